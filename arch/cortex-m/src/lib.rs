@@ -596,9 +596,12 @@ pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
         "ite   ne               /* check if the result of that bitwise AND was not 0 */",
         "movne r2, #1           /* BFSR & 0b00110000 != 0; r2 = 1 */",
         "moveq r2, #0           /* BFSR & 0b00110000 == 0; r2 = 0 */",
-        "and r5, r1, r2         /* bitwise and r2 and r1, store in r5 */ ",
-        "cmp  r5, #1            /*  update condition codes to reflect if r2 == 1 && r1 == 1 */",
-        "itt  eq                /* if r5==1 run the next 2 instructions, else skip to branch */",
+        // Only use r0-r3, which the hardware stacked: a process whose fault
+        // the kernel handles may be resumed, and r4-r11 still hold its
+        // registers until the kernel saves them after the handler returns.
+        "and r3, r1, r2         /* bitwise and r2 and r1, store in r3 */ ",
+        "cmp  r3, #1            /*  update condition codes to reflect if r2 == 1 && r1 == 1 */",
+        "itt  eq                /* if r3==1 run the next 2 instructions, else skip to branch */",
         // if true, The hardware couldn't use the stack, so we have no saved data and
         // we cannot use the kernel stack as is. We just want to report that
         // the kernel's stack overflowed, since that is essential for
@@ -608,8 +611,8 @@ pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
         // kernel's original stack. This should in theory leave the bottom
         // of the stack where the problem occurred untouched should one want
         // to further debug.
-        "ldreq  r4, ={}       /* load _estack into r4 */",
-        "moveq  sp, r4        /* Set the stack pointer to _estack */",
+        "ldreq  r3, ={}       /* load _estack into r3 */",
+        "moveq  sp, r3        /* Set the stack pointer to _estack */",
         // finally, branch to non-naked handler
         // per ARM calling convention, faulting stack is passed in r0, kernel_stack in r1,
         // and whether there was a stack overflow in r2
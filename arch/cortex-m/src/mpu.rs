@@ -154,6 +154,12 @@ impl<const NUM_REGIONS: usize> MPU<NUM_REGIONS> {
 pub struct CortexMConfig<const NUM_REGIONS: usize> {
     /// The computed region configuration for this process.
     regions: [CortexMRegion; NUM_REGIONS],
+    /// Regions of this process that are not in the hardware, because all
+    /// hardware regions are in use. They are swapped in by `swap_in_region()`
+    /// when the process accesses them.
+    swapped_out: [Option<CortexMRegion>; NUM_SWAPPED_OUT_REGIONS],
+    /// The hardware region that is swapped out next.
+    next_victim: Cell<usize>,
     /// Has the configuration changed since the last time the this process
     /// configuration was written to hardware?
     is_dirty: Cell<bool>,
@@ -161,11 +167,16 @@ pub struct CortexMConfig<const NUM_REGIONS: usize> {
 
 const APP_MEMORY_REGION_NUM: usize = 0;
 
+/// Number of regions a process can have beyond the hardware regions.
+const NUM_SWAPPED_OUT_REGIONS: usize = 8;
+
 impl<const NUM_REGIONS: usize> Default for CortexMConfig<NUM_REGIONS> {
     fn default() -> Self {
         // a bit of a hack to initialize array without unsafe
         let mut ret = Self {
             regions: [CortexMRegion::empty(0); NUM_REGIONS],
+            swapped_out: [None; NUM_SWAPPED_OUT_REGIONS],
+            next_victim: Cell::new(0),
             is_dirty: Cell::new(true),
         };
         for i in 0..NUM_REGIONS {
@@ -225,6 +236,17 @@ impl<const NUM_REGIONS: usize> fmt::Display for CortexMConfig<NUM_REGIONS> {
                 write!(f, "\r\n  Region {}: Unused", i)?;
             }
         }
+        for region in self.swapped_out.iter().filter_map(|region| region.as_ref()) {
+            if let Some((start, size)) = region.location() {
+                write!(
+                    f,
+                    "\r\n  Swapped out: [{:#010X}:{:#010X}], length: {} bytes",
+                    start as usize,
+                    start as usize + size,
+                    size,
+                )?;
+            }
+        }
         write!(f, "\r\n")
     }
 }
@@ -241,6 +263,27 @@ impl<const NUM_REGIONS: usize> CortexMConfig<NUM_REGIONS> {
         }
         None
     }
+
+    fn overlaps(&self, start: *const u8, size: usize) -> bool {
+        self.regions
+            .iter()
+            .chain(self.swapped_out.iter().filter_map(|region| region.as_ref()))
+            .any(|region| region.overlaps(start, size))
+    }
+
+    /// Pick the hardware region to swap out, in turn among all regions but
+    /// the one covering app-owned memory.
+    fn next_victim(&self) -> Option<usize> {
+        let mut victim = self.next_victim.get();
+        for _ in 0..NUM_REGIONS {
+            victim = (victim + 1) % NUM_REGIONS;
+            if victim != APP_MEMORY_REGION_NUM {
+                self.next_victim.set(victim);
+                return Some(victim);
+            }
+        }
+        None
+    }
 }
 
 /// Struct storing configuration for a Cortex-M MPU region.
@@ -330,6 +373,22 @@ impl CortexMRegion {
         self.location
     }
 
+    /// The same region, placed in hardware region `region_num`.
+    fn renumbered(&self, region_num: usize) -> CortexMRegion {
+        CortexMRegion {
+            location: self.location,
+            base_address: RegionBaseAddress::ADDR
+                .val(self.base_address.read(RegionBaseAddress::ADDR))
+                + RegionBaseAddress::VALID::UseRBAR
+                + RegionBaseAddress::REGION.val(region_num as u32),
+            attributes: self.attributes,
+        }
+    }
+
+    fn contains(&self, address: *const u8) -> bool {
+        self.overlaps(address, 1)
+    }
+
     fn base_address(&self) -> FieldValue<u32, RegionBaseAddress::Register> {
         self.base_address
     }
@@ -393,13 +452,22 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
         config: &mut Self::MpuConfig,
     ) -> Option<mpu::Region> {
         // Check that no previously allocated regions overlap the unallocated memory.
-        for region in config.regions.iter() {
-            if region.overlaps(unallocated_memory_start, unallocated_memory_size) {
-                return None;
-            }
+        if config.overlaps(unallocated_memory_start, unallocated_memory_size) {
+            return None;
         }
 
-        let region_num = config.unused_region_number()?;
+        // If all hardware regions are in use, keep the region swapped out
+        // until the process accesses it.
+        let region_num = config.unused_region_number();
+        let swapped_out_index = match region_num {
+            Some(_) => None,
+            None => Some(
+                config
+                    .swapped_out
+                    .iter()
+                    .position(|region| region.is_none())?,
+            ),
+        };
 
         // Logical region
         let mut start = unallocated_memory_start as usize;
@@ -500,17 +568,81 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
             size,
             region_start as *const u8,
             region_size,
-            region_num,
+            region_num.unwrap_or(0),
             subregions,
             permissions,
         );
 
-        config.regions[region_num] = region;
-        config.is_dirty.set(true);
+        match (region_num, swapped_out_index) {
+            (Some(region_num), _) => {
+                config.regions[region_num] = region;
+                config.is_dirty.set(true);
+            }
+            (None, Some(index)) => config.swapped_out[index] = Some(region),
+            (None, None) => return None,
+        }
 
         Some(mpu::Region::new(start as *const u8, size))
     }
 
+    fn remove_memory_region(
+        &self,
+        region: mpu::Region,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        let location = Some((region.start_address(), region.size()));
+
+        if let Some(swapped_out) = config
+            .swapped_out
+            .iter_mut()
+            .find(|r| r.map_or(false, |r| r.location() == location))
+        {
+            *swapped_out = None;
+            return Ok(());
+        }
+
+        let (region_num, _) = config
+            .regions
+            .iter()
+            .enumerate()
+            .find(|(number, r)| *number != APP_MEMORY_REGION_NUM && r.location() == location)
+            .ok_or(())?;
+
+        // Use the freed hardware region for a swapped out region, if any.
+        config.regions[region_num] = config
+            .swapped_out
+            .iter_mut()
+            .find(|r| r.is_some())
+            .and_then(|r| r.take())
+            .map_or(CortexMRegion::empty(region_num), |r| {
+                r.renumbered(region_num)
+            });
+        config.is_dirty.set(true);
+
+        Ok(())
+    }
+
+    fn swap_in_region(
+        &self,
+        fault_address: *const u8,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        let index = config
+            .swapped_out
+            .iter()
+            .position(|r| r.map_or(false, |r| r.contains(fault_address)))
+            .ok_or(())?;
+        let victim = config.next_victim().ok_or(())?;
+
+        let region = config.swapped_out[index].ok_or(())?;
+        let swapped_out = config.regions[victim];
+        config.regions[victim] = region.renumbered(victim);
+        config.swapped_out[index] = swapped_out.location().map(|_| swapped_out);
+        config.is_dirty.set(true);
+
+        Ok(())
+    }
+
     fn allocate_app_memory_region(
         &self,
         unallocated_memory_start: *const u8,
@@ -522,10 +654,8 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
         config: &mut Self::MpuConfig,
     ) -> Option<(*const u8, usize)> {
         // Check that no previously allocated regions overlap the unallocated memory.
-        for region in config.regions.iter() {
-            if region.overlaps(unallocated_memory_start, unallocated_memory_size) {
-                return None;
-            }
+        if config.overlaps(unallocated_memory_start, unallocated_memory_size) {
+            return None;
         }
 
         // Make sure there is enough memory for app memory and kernel memory.
@@ -290,6 +290,17 @@ pub unsafe fn reset() {
     );
 }
 
+/// Clear the status bits of the memory management faults, so that the status
+/// of the next fault is not mixed with the previous one.
+///
+/// Tock does not enable the MemManage handler, so these faults escalate to a
+/// hard fault and also set the `FORCED` bit of the HFSR, which is cleared too.
+pub unsafe fn clear_memory_management_fault_status() {
+    // The status bits are cleared by writing ones.
+    SCB.cfsr.write(ConfigurableFaultStatus::MemManage.val(0xFF));
+    SCB.hfsr.write(HardFaultStatus::FORCED::SET);
+}
+
 /// relocate interrupt vector table
 pub unsafe fn set_vector_table_offset(offset: *const ()) {
     SCB.vtor.set(offset as u32);
@@ -5,6 +5,8 @@ use core::fmt::Write;
 use core::mem;
 use core::ptr::{read_volatile, write_volatile};
 
+use kernel::ErrorCode;
+
 /// This is used in the syscall handler. When set to 1 this means the
 /// svc_handler was called. Marked `pub` because it is used in the cortex-m*
 /// specific handler.
@@ -37,6 +39,9 @@ extern "C" {
 // Space for 8 u32s: r0-r3, r12, lr, pc, and xPSR
 const SVC_FRAME_SIZE: usize = 32;
 
+// Number of 32-bit words in the stored state: r4-r11, yield_pc, psr, and psp.
+const STORED_STATE_WORDS: usize = 11;
+
 /// This holds all of the state that the kernel must keep for the process when
 /// the process is not executing.
 #[derive(Default)]
@@ -219,6 +224,11 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         let switch_reason = if app_fault == 1 || invalid_stack_pointer {
             // APP_HARD_FAULT takes priority. This means we hit the hardfault
             // handler and this process faulted.
+            if app_fault == 0 {
+                // The registers of the last hard fault do not describe this
+                // fault.
+                write_volatile(&mut SCB_REGISTERS, [0; 5]);
+            }
             kernel::syscall::ContextSwitchReason::Fault
         } else if syscall_fired == 1 {
             // Save these fields after a syscall. If this is a synchronous
@@ -344,4 +354,80 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             },
         ));
     }
+
+    fn store_context(
+        &self,
+        state: &CortexMStoredState,
+        out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let size = STORED_STATE_WORDS * 4;
+        let out = out.get_mut(..size).ok_or(ErrorCode::SIZE)?;
+        let special = [state.yield_pc, state.psr, state.psp];
+        let words = state.regs.iter().chain(special.iter());
+        for (chunk, word) in out.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&(*word as u32).to_le_bytes());
+        }
+        Ok(size)
+    }
+
+    fn restore_context(
+        &self,
+        state: &mut CortexMStoredState,
+        input: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let input = input.get(..STORED_STATE_WORDS * 4).ok_or(ErrorCode::SIZE)?;
+        let mut words = input
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize);
+        for reg in state.regs.iter_mut() {
+            *reg = words.next().unwrap_or(0);
+        }
+        state.yield_pc = words.next().unwrap_or(0);
+        state.psr = words.next().unwrap_or(0);
+        state.psp = words.next().unwrap_or(0);
+        Ok(())
+    }
+
+    fn store_fault_status(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        // Safety: `SCB_REGISTERS` is only written by the hard fault handler,
+        // which cannot run while the kernel is reading it.
+        let registers = unsafe { read_volatile(&SCB_REGISTERS) };
+        let size = registers.len() * 4;
+        let out = out.get_mut(..size).ok_or(ErrorCode::SIZE)?;
+        for (chunk, register) in out.chunks_exact_mut(4).zip(registers.iter()) {
+            chunk.copy_from_slice(&register.to_le_bytes());
+        }
+        Ok(size)
+    }
+
+    unsafe fn memory_fault_address(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &CortexMStoredState,
+    ) -> Option<*const u8> {
+        // MemManage status bits of the CFSR.
+        const IACCVIOL: u32 = 1 << 0;
+        const MMARVALID: u32 = 1 << 7;
+
+        let registers = read_volatile(&SCB_REGISTERS);
+        let cfsr = registers[1];
+        let mmfar = registers[3];
+        crate::scb::clear_memory_management_fault_status();
+
+        if cfsr & MMARVALID != 0 {
+            Some(mmfar as *const u8)
+        } else if cfsr & IACCVIOL != 0 {
+            // The core does not record the address of an instruction fetch
+            // that faults, but it is the stacked pc.
+            if state.psp < accessible_memory_start as usize
+                || (state.psp + SVC_FRAME_SIZE) > app_brk as usize
+            {
+                return None;
+            }
+            Some(read_volatile((state.psp as *const usize).offset(6)) as *const u8)
+        } else {
+            None
+        }
+    }
 }
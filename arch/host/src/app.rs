@@ -0,0 +1,295 @@
+//! Apps compiled for the host.
+//!
+//! An app is a function that runs as the `main()` of a process and calls
+//! syscalls through its `AppContext`:
+//!
+//! ```rust
+//! use host::app::{AppContext, HostApp};
+//!
+//! fn hello(ctx: &AppContext) {
+//!     let buffer = ctx.allocate(6);
+//!     buffer.copy_from_slice(b"hello\n");
+//!     ctx.allow_readonly(1, 1, buffer.as_ptr(), buffer.len());
+//!     ctx.command(1, 1, buffer.len(), 0);
+//! }
+//!
+//! const APPS: &[HostApp] = &[HostApp {
+//!     name: "hello",
+//!     main: hello,
+//!     minimum_ram_size: 4096,
+//! }];
+//! ```
+//!
+//! Buffers shared with the kernel must be in process memory, which the app
+//! gets from `AppContext::allocate()`. When `main` returns, the process
+//! exits. If the app panics, the process faults, as long as the simulator is
+//! built with `panic = "unwind"`.
+
+use std::cell::Cell;
+use std::mem;
+use std::slice;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use kernel::syscall::{Syscall, SyscallReturn};
+
+use crate::syscall::{Event, Resume};
+
+/// The `main()` of an app.
+pub type AppMain = fn(&AppContext);
+
+/// An upcall, called with the three arguments the capsule passes and the
+/// application data given to `subscribe`.
+pub type Upcall = fn(&AppContext, usize, usize, usize, usize);
+
+/// An app to load.
+pub struct HostApp {
+    /// Package name of the app.
+    pub name: &'static str,
+    pub main: AppMain,
+    /// RAM the kernel reserves for the process, including the memory the
+    /// kernel uses for grants.
+    pub minimum_ram_size: usize,
+}
+
+impl HostApp {
+    /// The TBF object of the app, with `tlvs` at the end of its header, to be
+    /// stored in a file and loaded with `SysCall::load_tbfs()`.
+    pub fn tbf(&self, tlvs: &[u8]) -> Vec<u8> {
+        crate::syscall::tbf_image(self, tlvs).0
+    }
+}
+
+/// The syscall interface of a process, and its memory.
+pub struct AppContext {
+    resumes: Receiver<Resume>,
+    events: Sender<Event>,
+    flash_start: usize,
+    memory_start: usize,
+    memory_len: usize,
+    app_break: Cell<usize>,
+    /// Start of the memory not yet given out by `allocate()`.
+    next_free: Cell<usize>,
+    /// Flag `yield_no_wait()` asks the kernel to set.
+    yield_flag: Cell<Option<usize>>,
+}
+
+impl AppContext {
+    /// Run `main` once the kernel starts the process. Called on the thread of
+    /// the process.
+    pub(crate) fn run(main: AppMain, resumes: Receiver<Resume>, events: Sender<Event>) {
+        // The first function call is the init function, with the process
+        // memory as arguments.
+        let init = match resumes.recv() {
+            Ok(Resume {
+                call: Some(init), ..
+            }) => init,
+            _ => return,
+        };
+        let ctx = AppContext {
+            resumes,
+            events,
+            flash_start: init.argument0,
+            memory_start: init.argument1,
+            memory_len: init.argument2,
+            app_break: Cell::new(init.argument3),
+            next_free: Cell::new(init.argument1),
+            yield_flag: Cell::new(None),
+        };
+        main(&ctx);
+        ctx.exit(0);
+    }
+
+    /// Start of the flash of the app, after its TBF header.
+    pub fn flash_start(&self) -> usize {
+        self.flash_start
+    }
+
+    /// Start and length of the memory of the process.
+    pub fn memory(&self) -> (usize, usize) {
+        (self.memory_start, self.memory_len)
+    }
+
+    /// End of the memory the process can access.
+    pub fn app_break(&self) -> usize {
+        self.app_break.get()
+    }
+
+    /// Allocate a zeroed buffer of `len` bytes in process memory, moving the
+    /// app break if needed. Panics if the process is out of memory.
+    pub fn allocate(&self, len: usize) -> &'static mut [u8] {
+        let start = (self.next_free.get() + 7) & !7;
+        let end = start + len;
+        if end > self.app_break.get() {
+            match self.memop(0, end) {
+                SyscallReturn::Success => self.app_break.set(end),
+                _ => panic!("process out of memory"),
+            }
+        }
+        self.next_free.set(end);
+        // Safety: the memory between `start` and `end` belongs to the process
+        // and is not given out again.
+        unsafe {
+            let buffer = slice::from_raw_parts_mut(start as *mut u8, len);
+            for byte in buffer.iter_mut() {
+                *byte = 0;
+            }
+            buffer
+        }
+    }
+
+    pub fn command(
+        &self,
+        driver: usize,
+        command: usize,
+        arg0: usize,
+        arg1: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::Command {
+            driver_number: driver,
+            subdriver_number: command,
+            arg0,
+            arg1,
+        })
+    }
+
+    /// Subscribe `upcall` to upcall `subscribe` of `driver`, or unsubscribe if
+    /// `upcall` is `None`.
+    pub fn subscribe(
+        &self,
+        driver: usize,
+        subscribe: usize,
+        upcall: Option<Upcall>,
+        appdata: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::Subscribe {
+            driver_number: driver,
+            subdriver_number: subscribe,
+            upcall_ptr: upcall.map_or(0, |upcall| upcall as usize) as *mut (),
+            appdata,
+        })
+    }
+
+    pub fn allow_readwrite(
+        &self,
+        driver: usize,
+        allow: usize,
+        buffer: *mut u8,
+        len: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::ReadWriteAllow {
+            driver_number: driver,
+            subdriver_number: allow,
+            allow_address: buffer,
+            allow_size: len,
+        })
+    }
+
+    pub fn allow_readonly(
+        &self,
+        driver: usize,
+        allow: usize,
+        buffer: *const u8,
+        len: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::ReadOnlyAllow {
+            driver_number: driver,
+            subdriver_number: allow,
+            allow_address: buffer,
+            allow_size: len,
+        })
+    }
+
+    /// Call memop `operand`. The kernel returns addresses as 32 bit values,
+    /// so those are extended to `SuccessU64` with the upper bits of the
+    /// address of process memory or flash.
+    pub fn memop(&self, operand: usize, arg0: usize) -> SyscallReturn {
+        let result = self.syscall(Syscall::Memop { operand, arg0 });
+        let region = match operand {
+            1 | 2 | 3 | 6 => self.memory_start,
+            4 | 5 | 8 | 9 => self.flash_start,
+            _ => return result,
+        };
+        match result {
+            SyscallReturn::SuccessU32(address) => {
+                let upper = (region as u64) & !(u32::MAX as u64);
+                SyscallReturn::SuccessU64(upper | address as u64)
+            }
+            _ => result,
+        }
+    }
+
+    /// Wait until an upcall ran.
+    pub fn yield_wait(&self) {
+        self.syscall(Syscall::Yield {
+            which: 1,
+            address: std::ptr::null_mut(),
+        });
+    }
+
+    /// Run an upcall if one is pending. Returns whether an upcall ran.
+    pub fn yield_no_wait(&self) -> bool {
+        let flag = match self.yield_flag.get() {
+            Some(flag) => flag,
+            None => {
+                let flag = self.allocate(1).as_mut_ptr() as usize;
+                self.yield_flag.set(Some(flag));
+                flag
+            }
+        };
+        self.syscall(Syscall::Yield {
+            which: 0,
+            address: flag as *mut u8,
+        });
+        // Safety: the flag is in process memory and only written by the
+        // kernel while this thread waits.
+        unsafe { *(flag as *const u8) != 0 }
+    }
+
+    /// Terminate the process.
+    pub fn exit(&self, completion_code: usize) -> ! {
+        let _ = self.events.send(Event::Syscall(Syscall::Exit {
+            which: 0,
+            completion_code,
+        }));
+        wait_forever()
+    }
+
+    /// Fault, like a process accessing memory it does not own.
+    pub fn fault(&self) -> ! {
+        let _ = self.events.send(Event::Fault);
+        wait_forever()
+    }
+
+    /// Hand `syscall` to the kernel and wait until it resumes the process.
+    fn syscall(&self, syscall: Syscall) -> SyscallReturn {
+        if self.events.send(Event::Syscall(syscall)).is_err() {
+            wait_forever();
+        }
+        let resume = match self.resumes.recv() {
+            Ok(resume) => resume,
+            // The kernel restarted or removed the process.
+            Err(_) => wait_forever(),
+        };
+        self.app_break.set(resume.app_break);
+        if let Some(call) = resume.call {
+            // Safety: the kernel only calls functions the process subscribed.
+            let upcall: Upcall = unsafe { mem::transmute(call.pc) };
+            upcall(
+                self,
+                call.argument0,
+                call.argument1,
+                call.argument2,
+                call.argument3,
+            );
+        }
+        // Yield does not return a value.
+        resume.return_value.unwrap_or(SyscallReturn::Success)
+    }
+}
+
+fn wait_forever() -> ! {
+    loop {
+        thread::park();
+    }
+}
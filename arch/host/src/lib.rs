@@ -0,0 +1,22 @@
+//! Support for running Tock as a process on a host operating system.
+//!
+//! This crate implements the `UserspaceKernelBoundary` for apps that are
+//! compiled for the host together with the kernel. Each process runs in its
+//! own host thread, and only one of the kernel and the processes runs at any
+//! time: a process runs when the kernel switches to it, and the kernel runs
+//! again once the process calls a syscall. Processes are not preempted.
+//!
+//! Apps are ordinary Rust functions that take an `app::AppContext`, which
+//! provides the syscalls. `syscall::SysCall::load_apps()` builds a TBF image
+//! for them that the kernel loads like apps in flash, and `load_tbfs()` loads
+//! TBF objects, such as files, that name the app to run.
+//!
+//! This is meant for running and testing the kernel and capsules without
+//! hardware, for example with the `host_sim` chip. It offers no isolation
+//! between the kernel and the processes.
+
+#![crate_name = "host"]
+#![crate_type = "rlib"]
+
+pub mod app;
+pub mod syscall;
@@ -0,0 +1,339 @@
+//! Implementation of the `UserspaceKernelBoundary` for apps running in host
+//! threads.
+
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use kernel::procs::FunctionCall;
+use kernel::syscall::{ContextSwitchReason, Syscall, SyscallReturn};
+use kernel::ErrorCode;
+
+use crate::app::{AppContext, AppMain, HostApp};
+
+/// Message from the kernel that resumes a process.
+pub(crate) struct Resume {
+    /// Function the process calls before it continues, if any.
+    pub(crate) call: Option<FunctionCall>,
+    /// Return value of the syscall the process called last, if any.
+    pub(crate) return_value: Option<SyscallReturn>,
+    /// Current app break of the process.
+    pub(crate) app_break: usize,
+}
+
+// The raw pointers in `FunctionCall` and `SyscallReturn` point into process
+// memory, which the kernel and the process thread never access at the same
+// time.
+unsafe impl Send for Resume {}
+
+/// Message from a process that returns control to the kernel.
+pub(crate) enum Event {
+    Syscall(Syscall),
+    Fault,
+}
+
+unsafe impl Send for Event {}
+
+/// The thread running a process.
+pub struct ProcessThread {
+    resume: Sender<Resume>,
+    events: Receiver<Event>,
+}
+
+/// Process state the kernel stores between context switches.
+#[derive(Default)]
+pub struct HostStoredState {
+    /// `None` until the process runs for the first time.
+    thread: Option<ProcessThread>,
+    call: Option<FunctionCall>,
+    return_value: Option<SyscallReturn>,
+    last_syscall: Option<Syscall>,
+}
+
+/// Switches between the kernel and processes running in host threads.
+pub struct SysCall {
+    /// The `main()` of each loaded app, keyed by the address of its init
+    /// function in the TBF image.
+    apps: RefCell<Vec<(usize, AppMain)>>,
+}
+
+impl SysCall {
+    pub fn new() -> SysCall {
+        SysCall {
+            apps: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Build the TBF image for `apps`, to be passed to
+    /// `kernel::procs::load_processes()` as the app flash.
+    ///
+    /// Each app gets a TBF header with its name and the minimum RAM size it
+    /// requests. When the kernel starts the process, its `main` runs in a new
+    /// thread.
+    pub fn load_apps(&self, apps: &[HostApp]) -> &'static [u8] {
+        let apps: Vec<(&HostApp, &[u8])> = apps.iter().map(|app| (app, &[][..])).collect();
+        self.load_apps_with_tlvs(&apps)
+    }
+
+    /// Like `load_apps()`, but appends the TLVs that come with each app to its
+    /// TBF header, for example to give it permissions. The TLVs of each app
+    /// must be a multiple of four bytes long.
+    pub fn load_apps_with_tlvs(&self, apps: &[(&HostApp, &[u8])]) -> &'static [u8] {
+        let images: Vec<(Vec<u8>, usize, AppMain)> = apps
+            .iter()
+            .map(|(app, tlvs)| {
+                let (image, header_size) = tbf_image(app, tlvs);
+                (image, header_size, app.main)
+            })
+            .collect();
+        let mut flash = Vec::new();
+        for (image, _, _) in images.iter() {
+            flash.extend_from_slice(image);
+        }
+        // An invalid header marks the end of the apps. The rest of the flash
+        // is free for apps installed at runtime.
+        flash.extend_from_slice(&[0; FREE_FLASH_LEN]);
+        let flash: &'static [u8] = Box::leak(flash.into_boxed_slice());
+
+        let mut offset = 0;
+        let mut registry = self.apps.borrow_mut();
+        for (image, header_size, main) in images.iter() {
+            registry.push((flash.as_ptr() as usize + offset + header_size, *main));
+            offset += image.len();
+        }
+        flash
+    }
+
+    /// Build the app flash from the TBF objects in `tbfs`, for example read
+    /// from files, to be passed to `kernel::procs::load_processes()` like the
+    /// image of `load_apps()`.
+    ///
+    /// The binaries in the TBF objects are never executed. Each process runs
+    /// the `main` of the app in `apps` named like the package in its TBF
+    /// header instead, and faults when it starts if there is no such app.
+    pub fn load_tbfs(&self, tbfs: &[&[u8]], apps: &[HostApp]) -> &'static [u8] {
+        let mut flash = Vec::new();
+        for tbf in tbfs.iter() {
+            flash.extend_from_slice(tbf);
+        }
+        flash.extend_from_slice(&[0; FREE_FLASH_LEN]);
+        let flash: &'static [u8] = Box::leak(flash.into_boxed_slice());
+
+        let mut offset = 0;
+        let mut registry = self.apps.borrow_mut();
+        for tbf in tbfs.iter() {
+            let image = &flash[offset..offset + tbf.len()];
+            let header = image
+                .get(0..8)
+                .and_then(|lengths| lengths.try_into().ok())
+                .and_then(|lengths| tock_tbf::parse::parse_tbf_header_lengths(lengths).ok())
+                .and_then(|(version, header_len, _)| {
+                    let header = image.get(0..header_len as usize)?;
+                    tock_tbf::parse::parse_tbf_header(header, version).ok()
+                });
+            if let Some(header) = header {
+                let name = header.get_package_name();
+                if let Some(app) = apps.iter().find(|app| Some(app.name) == name) {
+                    let init_fn =
+                        image.as_ptr() as usize + header.get_init_function_offset() as usize;
+                    registry.push((init_fn, app.main));
+                }
+            }
+            offset += tbf.len();
+        }
+        flash
+    }
+
+    /// Allocate `len` bytes of memory for processes, to be passed to
+    /// `kernel::procs::load_processes()`. The kernel places its structures for
+    /// each process in this memory, so it is aligned for them.
+    pub fn app_memory(&self, len: usize) -> &'static mut [u8] {
+        let words = (len + 7) / 8;
+        let memory: &'static mut [u64] = Box::leak(vec![0u64; words].into_boxed_slice());
+        // Safety: the memory is `8 * words` bytes long and never freed.
+        unsafe { std::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, words * 8) }
+    }
+
+    fn find_app(&self, init_pc: usize) -> Option<AppMain> {
+        self.apps
+            .borrow()
+            .iter()
+            .find(|(pc, _)| *pc == init_pc)
+            .map(|(_, main)| *main)
+    }
+}
+
+/// The length of the free flash after the apps.
+const FREE_FLASH_LEN: usize = 4096;
+
+/// Build the TBF image of `app`, with `tlvs` at the end of its header.
+/// Returns the image and the length of its header.
+pub(crate) fn tbf_image(app: &HostApp, tlvs: &[u8]) -> (Vec<u8>, usize) {
+    const BASE_HEADER_LEN: usize = 16;
+    const MAIN_TLV_LEN: usize = 4 + 12;
+    // The binary is never executed, the kernel only needs it to point the
+    // init function at.
+    const BINARY_LEN: usize = 4;
+
+    let name = app.name.as_bytes();
+    let name_tlv_len = 4 + ((name.len() + 3) & !3);
+    let header_size = BASE_HEADER_LEN + MAIN_TLV_LEN + name_tlv_len + tlvs.len();
+    let total_size = header_size + BINARY_LEN;
+
+    let mut image = Vec::with_capacity(total_size);
+    image.extend_from_slice(&2u16.to_le_bytes());
+    image.extend_from_slice(&(header_size as u16).to_le_bytes());
+    image.extend_from_slice(&(total_size as u32).to_le_bytes());
+    // Flags: enabled.
+    image.extend_from_slice(&1u32.to_le_bytes());
+    // Checksum, filled in below.
+    image.extend_from_slice(&0u32.to_le_bytes());
+
+    // Main TLV: init function offset, protected size, minimum RAM size.
+    image.extend_from_slice(&1u16.to_le_bytes());
+    image.extend_from_slice(&12u16.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&(app.minimum_ram_size as u32).to_le_bytes());
+
+    // Package name TLV.
+    image.extend_from_slice(&3u16.to_le_bytes());
+    image.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    image.extend_from_slice(name);
+    image.resize(header_size - tlvs.len(), 0);
+    image.extend_from_slice(tlvs);
+
+    let checksum = image
+        .chunks(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+        });
+    image[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    image.resize(total_size, 0);
+    (image, header_size)
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        // Nothing is stored in process memory, the stack of the process is
+        // the stack of its thread.
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        // Dropping the channels of a previous thread leaves that thread
+        // blocked forever.
+        *state = HostStoredState::default();
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        state.return_value = Some(return_value);
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        upcall: FunctionCall,
+    ) -> Result<(), ()> {
+        state.call = Some(upcall);
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        if state.thread.is_none() {
+            // The first function a process calls is its init function, which
+            // identifies the app.
+            let main = match state.call.and_then(|call| self.find_app(call.pc)) {
+                Some(main) => main,
+                None => return (ContextSwitchReason::Fault, None),
+            };
+            let (resume_sender, resume_receiver) = mpsc::channel();
+            let (event_sender, event_receiver) = mpsc::channel();
+            let spawned = thread::Builder::new().spawn(move || {
+                AppContext::run(main, resume_receiver, event_sender);
+            });
+            if spawned.is_err() {
+                return (ContextSwitchReason::Fault, None);
+            }
+            state.thread = Some(ProcessThread {
+                resume: resume_sender,
+                events: event_receiver,
+            });
+        }
+
+        let resume = Resume {
+            call: state.call.take(),
+            return_value: state.return_value.take(),
+            app_break: app_brk as usize,
+        };
+        let event = state.thread.as_ref().and_then(|thread| {
+            thread.resume.send(resume).ok()?;
+            thread.events.recv().ok()
+        });
+        match event {
+            Some(Event::Syscall(syscall)) => {
+                state.last_syscall = Some(syscall);
+                (ContextSwitchReason::SyscallFired { syscall }, None)
+            }
+            // The thread also stops if the app panics.
+            Some(Event::Fault) | None => (ContextSwitchReason::Fault, None),
+        }
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = match state.last_syscall {
+            Some(syscall) => writer.write_fmt(format_args!("\r\n Last syscall: {:?}\r\n", syscall)),
+            None => writer.write_str("\r\n No syscall yet\r\n"),
+        };
+    }
+
+    fn store_context(
+        &self,
+        _state: &Self::StoredState,
+        _out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        // The context of a process is its thread, which cannot be stored.
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn restore_context(
+        &self,
+        _state: &mut Self::StoredState,
+        _input: &[u8],
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
@@ -328,8 +328,17 @@ pub struct PMPConfig<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> {
     is_dirty: Cell<bool>,
     /// Which region index is used for app memory (if it has been configured).
     app_memory_region: OptionalCell<usize>,
+    /// Regions that are not in the hardware, because all hardware regions are
+    /// in use. They are swapped in by `swap_in_region()` when the process
+    /// accesses them.
+    swapped_out: [Option<PMPRegion>; NUM_SWAPPED_OUT_REGIONS],
+    /// The region that is swapped out next.
+    next_victim: Cell<usize>,
 }
 
+/// Number of regions a process can have beyond the hardware regions.
+const NUM_SWAPPED_OUT_REGIONS: usize = 8;
+
 impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> Default
     for PMPConfig<MAX_AVAILABLE_REGIONS_OVER_TWO>
 {
@@ -343,6 +352,8 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> Default
             regions: [None; MAX_AVAILABLE_REGIONS_OVER_TWO],
             is_dirty: Cell::new(true),
             app_memory_region: OptionalCell::empty(),
+            swapped_out: [None; NUM_SWAPPED_OUT_REGIONS],
+            next_victim: Cell::new(0),
         }
     }
 }
@@ -358,14 +369,18 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> fmt::Display
                 Some(region) => write!(f, "  [{}]: {}\r\n", n, region)?,
             }
         }
+        for region in self.swapped_out.iter().filter_map(|region| region.as_ref()) {
+            write!(f, "  swapped out: {}\r\n", region)?;
+        }
         Ok(())
     }
 }
 
 impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> PMPConfig<MAX_AVAILABLE_REGIONS_OVER_TWO> {
-    /// Get the first unused region
-    fn unused_region_number(&self, locked_region_mask: u64) -> Option<usize> {
-        for (number, region) in self.regions.iter().enumerate() {
+    /// Get the first unused region of the `num_regions` regions the hardware
+    /// supports
+    fn unused_region_number(&self, locked_region_mask: u64, num_regions: usize) -> Option<usize> {
+        for (number, region) in self.regions.iter().enumerate().take(num_regions) {
             if self.app_memory_region.contains(&number) {
                 continue;
             }
@@ -380,6 +395,32 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> PMPConfig<MAX_AVAILABLE_REGION
         None
     }
 
+    /// Pick the region to swap out, in turn among the `num_regions` regions
+    /// the hardware supports, except for the app memory region and locked
+    /// regions.
+    fn next_victim(&self, locked_region_mask: u64, num_regions: usize) -> Option<usize> {
+        let mut victim = self.next_victim.get();
+        for _ in 0..num_regions {
+            victim = (victim + 1) % num_regions;
+            if !self.app_memory_region.contains(&victim)
+                && locked_region_mask & (1 << victim) == 0
+                && self.regions[victim].is_some()
+            {
+                self.next_victim.set(victim);
+                return Some(victim);
+            }
+        }
+        None
+    }
+
+    fn overlaps(&self, start: *const u8, size: usize) -> bool {
+        self.regions
+            .iter()
+            .chain(self.swapped_out.iter())
+            .filter_map(|region| region.as_ref())
+            .any(|region| region.overlaps(start, size))
+    }
+
     /// Get the last unused region
     /// The app regions need to be lower then the kernel to ensure they
     /// match before the kernel ones.
@@ -498,18 +539,23 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
         permissions: mpu::Permissions,
         config: &mut Self::MpuConfig,
     ) -> Option<mpu::Region> {
-        for region in config.regions.iter() {
-            if region.is_some() {
-                if region
-                    .unwrap()
-                    .overlaps(unallocated_memory_start, unallocated_memory_size)
-                {
-                    return None;
-                }
-            }
+        if config.overlaps(unallocated_memory_start, unallocated_memory_size) {
+            return None;
         }
 
-        let region_num = config.unused_region_number(self.locked_region_mask.get())?;
+        // If all hardware regions are in use, keep the region swapped out
+        // until the process accesses it.
+        let region_num =
+            config.unused_region_number(self.locked_region_mask.get(), self.num_regions / 2);
+        let swapped_out_index = match region_num {
+            Some(_) => None,
+            None => Some(
+                config
+                    .swapped_out
+                    .iter()
+                    .position(|region| region.is_none())?,
+            ),
+        };
 
         // Logical region
         let mut start = unallocated_memory_start as usize;
@@ -536,12 +582,55 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
             return None;
         }
 
-        config.regions[region_num] = region;
-        config.is_dirty.set(true);
+        match (region_num, swapped_out_index) {
+            (Some(region_num), _) => {
+                config.regions[region_num] = region;
+                config.is_dirty.set(true);
+            }
+            (None, Some(index)) => config.swapped_out[index] = region,
+            (None, None) => return None,
+        }
 
         Some(mpu::Region::new(start as *const u8, size))
     }
 
+    fn remove_memory_region(
+        &self,
+        region: mpu::Region,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        let location = (region.start_address(), region.size());
+
+        if let Some(swapped_out) = config
+            .swapped_out
+            .iter_mut()
+            .find(|r| r.map_or(false, |r| r.location() == location))
+        {
+            *swapped_out = None;
+            return Ok(());
+        }
+
+        let (region_num, _) = config
+            .regions
+            .iter()
+            .enumerate()
+            .find(|(number, r)| {
+                !config.app_memory_region.contains(number)
+                    && r.map_or(false, |r| r.location() == location)
+            })
+            .ok_or(())?;
+
+        // Use the freed hardware region for a swapped out region, if any.
+        config.regions[region_num] = config
+            .swapped_out
+            .iter_mut()
+            .find(|r| r.is_some())
+            .and_then(|r| r.take());
+        config.is_dirty.set(true);
+
+        Ok(())
+    }
+
     fn allocate_app_memory_region(
         &self,
         unallocated_memory_start: *const u8,
@@ -567,7 +656,7 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
         let region_num = if config.app_memory_region.is_some() {
             config.app_memory_region.unwrap_or(0)
         } else {
-            config.unused_region_number(self.locked_region_mask.get())?
+            config.unused_region_number(self.locked_region_mask.get(), self.num_regions / 2)?
         };
 
         // App memory size is what we actual set the region to. So this region
@@ -656,6 +745,28 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
         Ok(())
     }
 
+    fn swap_in_region(
+        &self,
+        fault_address: *const u8,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        let index = config
+            .swapped_out
+            .iter()
+            .position(|r| r.map_or(false, |r| r.overlaps(fault_address, 1)))
+            .ok_or(())?;
+        let victim = config
+            .next_victim(self.locked_region_mask.get(), self.num_regions / 2)
+            .ok_or(())?;
+
+        let swapped_out = config.regions[victim];
+        config.regions[victim] = config.swapped_out[index];
+        config.swapped_out[index] = swapped_out;
+        config.is_dirty.set(true);
+
+        Ok(())
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &ProcessId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
@@ -853,3 +964,99 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::KernelMPU
         csr::CSR.mseccfg.modify(csr::mseccfg::mseccfg::mml::SET);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::mpu::MPU;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    /// A PMP with four regions, none of them locked.
+    fn pmp() -> PMP<4> {
+        PMP {
+            last_configured_for: MapCell::empty(),
+            locked_region_mask: Cell::new(0),
+            num_regions: 8,
+        }
+    }
+
+    fn in_hardware(config: &PMPConfig<4>, region: mpu::Region) -> bool {
+        let location = (region.start_address(), region.size());
+        config
+            .regions
+            .iter()
+            .any(|r| r.map_or(false, |r| r.location() == location))
+    }
+
+    fn swapped_out(config: &PMPConfig<4>, region: mpu::Region) -> bool {
+        let location = (region.start_address(), region.size());
+        config
+            .swapped_out
+            .iter()
+            .any(|r| r.map_or(false, |r| r.location() == location))
+    }
+
+    /// Give the process memory and share `count` buffers of 64 bytes with it.
+    fn share(pmp: &PMP<4>, config: &mut PMPConfig<4>, count: usize) -> Vec<mpu::Region> {
+        pmp.allocate_app_memory_region(
+            0x8000_0000 as *const u8,
+            0x1000,
+            0x1000,
+            0x800,
+            0x400,
+            mpu::Permissions::ReadWriteOnly,
+            config,
+        )
+        .unwrap();
+        (0..count)
+            .map(|i| {
+                let start = (0x8001_0000 + 0x100 * i) as *const u8;
+                pmp.allocate_region(start, 64, 64, mpu::Permissions::ReadOnly, config)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared_region_is_removed() {
+        let pmp = pmp();
+        let mut config = PMPConfig::default();
+        let regions = share(&pmp, &mut config, 1);
+        assert_eq!(regions[0].start_address(), 0x8001_0000 as *const u8);
+        assert_eq!(regions[0].size(), 64);
+        assert!(in_hardware(&config, regions[0]));
+
+        config.is_dirty.set(false);
+        assert_eq!(pmp.remove_memory_region(regions[0], &mut config), Ok(()));
+        assert!(!in_hardware(&config, regions[0]));
+        assert!(config.is_dirty.get());
+
+        // The region is gone, and the app memory region cannot be removed.
+        assert_eq!(pmp.remove_memory_region(regions[0], &mut config), Err(()));
+        let app_memory = mpu::Region::new(0x8000_0000 as *const u8, 0x800);
+        assert_eq!(pmp.remove_memory_region(app_memory, &mut config), Err(()));
+        assert!(in_hardware(&config, app_memory));
+    }
+
+    #[test]
+    fn removing_a_region_swaps_in_another() {
+        let pmp = pmp();
+        let mut config = PMPConfig::default();
+        // The app memory region and three shared regions fill the hardware.
+        let regions = share(&pmp, &mut config, 5);
+        assert!(swapped_out(&config, regions[3]));
+        assert!(swapped_out(&config, regions[4]));
+
+        // A swapped out region is removed without touching the hardware.
+        assert_eq!(pmp.remove_memory_region(regions[4], &mut config), Ok(()));
+        assert!(!swapped_out(&config, regions[4]));
+        assert!(!in_hardware(&config, regions[4]));
+
+        assert_eq!(pmp.remove_memory_region(regions[0], &mut config), Ok(()));
+        assert!(!in_hardware(&config, regions[0]));
+        assert!(in_hardware(&config, regions[3]));
+        assert!(!swapped_out(&config, regions[3]));
+    }
+}
@@ -5,6 +5,7 @@ use core::fmt::Write;
 use crate::csr::mcause;
 use kernel;
 use kernel::syscall::ContextSwitchReason;
+use kernel::ErrorCode;
 
 /// This holds all of the state that the kernel must keep for the process when
 /// the process is not executing.
@@ -40,6 +41,10 @@ const R_A2: usize = 11;
 const R_A3: usize = 12;
 const R_A4: usize = 13;
 
+// Number of 32-bit words in the stored state: all registers, pc, mcause, and
+// mtval.
+const STORED_STATE_WORDS: usize = 34;
+
 /// Implementation of the `UserspaceKernelBoundary` for the RISC-V architecture.
 pub struct SysCall(());
 
@@ -507,4 +512,55 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             state.mtval,
         ));
     }
+
+    fn store_context(
+        &self,
+        state: &Riscv32iStoredState,
+        out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let size = STORED_STATE_WORDS * 4;
+        let out = out.get_mut(..size).ok_or(ErrorCode::SIZE)?;
+        let special = [state.pc, state.mcause, state.mtval];
+        let words = state.regs.iter().chain(special.iter());
+        for (chunk, word) in out.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(size)
+    }
+
+    fn restore_context(
+        &self,
+        state: &mut Riscv32iStoredState,
+        input: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let input = input.get(..STORED_STATE_WORDS * 4).ok_or(ErrorCode::SIZE)?;
+        let mut words = input
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        for reg in state.regs.iter_mut() {
+            *reg = words.next().unwrap_or(0);
+        }
+        state.pc = words.next().unwrap_or(0);
+        state.mcause = words.next().unwrap_or(0);
+        state.mtval = words.next().unwrap_or(0);
+        Ok(())
+    }
+
+    unsafe fn memory_fault_address(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Riscv32iStoredState,
+    ) -> Option<*const u8> {
+        // The pc is not advanced on an access fault, so the process retries
+        // the access when it is resumed.
+        match mcause::Trap::from(state.mcause as usize) {
+            mcause::Trap::Exception(mcause::Exception::InstructionFault)
+            | mcause::Trap::Exception(mcause::Exception::LoadFault)
+            | mcause::Trap::Exception(mcause::Exception::StoreFault) => {
+                Some(state.mtval as *const u8)
+            }
+            _ => None,
+        }
+    }
 }
@@ -496,7 +496,7 @@ pub unsafe fn main() {
 
     let serial_num_bottom_16 = u16::from_le_bytes([serial_num[0], serial_num[1]]);
 
-    let (ieee802154_radio, _mux_mac) = components::ieee802154::Ieee802154Component::new(
+    let (ieee802154_radio, _mux_mac, _) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         &base_peripherals.ieee802154_radio,
         aes_mux,
@@ -0,0 +1,207 @@
+//! Component to initialize the userland CoAP driver.
+//!
+//! This provides one Component, CoapComponent, which creates the CoapNode
+//! that sends the CoAP requests of processes, and the driver processes use
+//! it through. The node sends and receives messages on the CoAP port,
+//! through the UDP component.
+//!
+//! The port table of the UDP component only binds ports once the UDP driver
+//! component was finalized, so the driver has to be finalized first.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxComponent::new(
+//!        ...
+//!    )
+//!    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+//!    let udp_driver = UDPDriverComponent::new(
+//!        ...
+//!    )
+//!    .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
+//!    let coap_driver = CoapComponent::new(
+//!        board_kernel,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!        rng,
+//!    )
+//!    .finalize(components::coap_component_helper!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules;
+use capsules::net::coap::coap_node::CoapNode;
+use capsules::net::coap::{CoapDriver, COAP_PORT};
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::rng::Random;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+// The CoAP node requires buffers of its own:
+//
+//   1. TX_BUF: Buffer the CoapNode builds messages in. It fits a block of
+//      the payload of a request, with its header and options.
+//   2. PATH_BUF: Buffer holding the path of the current request.
+//   3. PAYLOAD_BUF: Buffer holding the payload of the current request, which
+//      is sent in blocks if it does not fit in one message.
+
+const MAX_MESSAGE_LEN: usize = 256; // The max size of a CoAP message sent by this device
+const MAX_PATH_LEN: usize = 64;
+const MAX_PAYLOAD_LEN: usize = 1024;
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut PATH_BUF: [u8; MAX_PATH_LEN] = [0; MAX_PATH_LEN];
+static mut PAYLOAD_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::coap::coap_node::CoapNode;
+        use capsules::net::coap::CoapDriver;
+        use capsules::net::udp::udp_recv::UDPReceiver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<UDPReceiver<'static>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<CoapNode<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4)
+    };};
+}
+
+pub struct CoapComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Random<'static>,
+}
+
+impl<A: Alarm<'static> + 'static> CoapComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Random<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+            rng,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for CoapComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<CoapNode<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static CoapDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init_half!(
+            static_buffer.0,
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init_half!(static_buffer.1, UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        // Requests go to any server, from the CoAP port.
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::Any,
+                PortRange::Port(COAP_PORT),
+                &create_cap
+            )
+        );
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no socket available for CoAP");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, COAP_PORT, net_cap)
+            .expect("failed to bind the CoAP port");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let coap_virtual_alarm = static_init_half!(
+            static_buffer.2,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let coap_node = static_init_half!(
+            static_buffer.3,
+            CoapNode<'static, VirtualMuxAlarm<'static, A>>,
+            CoapNode::new(
+                udp_send,
+                coap_virtual_alarm,
+                self.rng,
+                &mut TX_BUF,
+                &mut PATH_BUF,
+                &mut PAYLOAD_BUF,
+                net_cap,
+            )
+        );
+        coap_virtual_alarm.set_alarm_client(coap_node);
+        udp_send.set_client(coap_node);
+        udp_recv.set_client(coap_node);
+
+        let coap_driver = static_init_half!(
+            static_buffer.4,
+            CoapDriver<'static, VirtualMuxAlarm<'static, A>>,
+            CoapDriver::new(coap_node, self.board_kernel.create_grant(&grant_cap))
+        );
+        coap_node.set_client(coap_driver);
+        coap_driver
+    }
+}
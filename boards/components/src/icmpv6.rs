@@ -0,0 +1,287 @@
+//! Component to initialize the ICMPv6/6LoWPAN interface.
+//!
+//! This provides one Component, ICMP6Component. This component sets up an
+//! IPv6 sender and receiver for ICMPv6, on a MAC user of their own, and the
+//! ICMP6Node that answers pings and registers with a router. It exposes the
+//! node, and the MuxICMP6Receiver that other capsules handling ICMPv6
+//! messages can be added to.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (icmp6_node, icmp6_recv_mux) = ICMP6Component::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::icmp6_component_helper!(sam4l::ast::Ast));
+//!    icmp6_node.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::net::icmpv6::icmpv6_node::ICMP6Node;
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::routing::RouteLookup;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+// The ICMPv6 stack requires its own packet buffers, like the UDP stack:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
+//   3. ICMP_MESSAGE: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   4. TX_BUF: Buffer the ICMP6Node builds messages in.
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+
+pub const MAX_MESSAGE_LEN: usize = 128; //The max payload of an ICMPv6 message sent by this device
+static mut ICMP_MESSAGE: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! icmp6_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::icmpv6::icmpv6_node::ICMP6Node;
+        use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<ICMP6Node<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<MuxICMP6Receiver<'static>> = MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<ICMP6Receiver<'static>> = MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8,
+        )
+    };};
+}
+
+pub struct ICMP6Component<A: Alarm<'static> + 'static> {
+    mux_mac: &'static MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    routes: Option<&'static dyn RouteLookup>,
+}
+
+impl<A: Alarm<'static> + 'static> ICMP6Component<A> {
+    pub fn new(
+        mux_mac: &'static MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+            routes: None,
+        }
+    }
+
+    /// Sends packets to the next hop `routes` returns, rather than always to
+    /// `dst_mac_addr`.
+    pub fn with_routes(mut self, routes: &'static dyn RouteLookup) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for ICMP6Component<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<ICMP6Node<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<MuxICMP6Receiver<'static>>,
+        &'static mut MaybeUninit<ICMP6Receiver<'static>>,
+    );
+    type Output = (
+        &'static ICMP6Node<'static, VirtualMuxAlarm<'static, A>>,
+        &'static MuxICMP6Receiver<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // The IP sender and receiver only support a single client each, so
+        // ICMPv6 gets a MAC user of its own rather than sharing the one of
+        // UDP.
+        let icmp_mac = static_init_half!(
+            static_buffer.1,
+            MacUser<'static>,
+            MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(icmp_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.2,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        icmp_mac.set_receive_client(sixlowpan);
+
+        let tr_hdr = TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128));
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut ICMP_MESSAGE,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.4,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                icmp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_addr(self.interface_list[0]);
+        icmp_mac.set_transmit_client(ip_send);
+        if let Some(routes) = self.routes {
+            ip_send.set_routes(routes);
+        }
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        // The node answers pings and solicitations from any node.
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let icmp_virtual_alarm = static_init_half!(
+            static_buffer.5,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let icmp_node = static_init_half!(
+            static_buffer.6,
+            ICMP6Node<'static, VirtualMuxAlarm<'static, A>>,
+            ICMP6Node::new(
+                ip_send,
+                icmp_virtual_alarm,
+                self.interface_list[0],
+                self.src_mac_addr,
+                &mut TX_BUF,
+                net_cap,
+            )
+        );
+        icmp_virtual_alarm.set_alarm_client(icmp_node);
+        ip_send.set_client(icmp_node);
+
+        let icmp_recv_mux = static_init_half!(
+            static_buffer.7,
+            MuxICMP6Receiver<'static>,
+            MuxICMP6Receiver::new()
+        );
+        ip_receive.set_client(icmp_recv_mux);
+        let icmp_node_receiver = static_init_half!(
+            static_buffer.8,
+            ICMP6Receiver<'static>,
+            ICMP6Receiver::new()
+        );
+        icmp_node_receiver.set_client(icmp_node);
+        icmp_recv_mux.add_client(icmp_node_receiver);
+
+        (icmp_node, icmp_recv_mux)
+    }
+}
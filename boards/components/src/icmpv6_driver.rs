@@ -0,0 +1,70 @@
+//! Component to initialize the userland ICMPv6 driver.
+//!
+//! This provides one Component, ICMP6DriverComponent. This component creates
+//! the driver that lets apps ping other nodes through an ICMP6Node, and see
+//! whether it registered with a router.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let icmp6_driver = ICMP6DriverComponent::new(board_kernel, icmp6_node)
+//!        .finalize(components::icmp6_driver_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::net::icmpv6::icmpv6_node::ICMP6Node;
+use capsules::net::icmpv6::ICMP6Driver;
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init_half};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! icmp6_driver_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::icmpv6::ICMP6Driver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<ICMP6Driver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct ICMP6DriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    icmp6_node: &'static ICMP6Node<'static, VirtualMuxAlarm<'static, A>>,
+}
+
+impl<A: Alarm<'static>> ICMP6DriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        icmp6_node: &'static ICMP6Node<'static, VirtualMuxAlarm<'static, A>>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            icmp6_node,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for ICMP6DriverComponent<A> {
+    type StaticInput = &'static mut MaybeUninit<ICMP6Driver<'static, VirtualMuxAlarm<'static, A>>>;
+    type Output = &'static ICMP6Driver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let icmp6_driver = static_init_half!(
+            static_buffer,
+            ICMP6Driver<'static, VirtualMuxAlarm<'static, A>>,
+            ICMP6Driver::new(self.icmp6_node, self.board_kernel.create_grant(&grant_cap))
+        );
+        self.icmp6_node.set_ping_client(icmp6_driver);
+        icmp6_driver
+    }
+}
@@ -7,7 +7,7 @@
 //! Usage
 //! -----
 //! ```rust
-//! let (radio, mux_mac) = components::ieee802154::Ieee802154Component::new(
+//! let (radio, mux_mac, framer) = components::ieee802154::Ieee802154Component::new(
 //!     board_kernel,
 //!     &nrf52::ieee802154_radio::RADIO,
 //!     &nrf52::aes::AESECB,
@@ -119,6 +119,11 @@ impl<
     type Output = (
         &'static capsules::ieee802154::RadioDriver<'static>,
         &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        &'static capsules::ieee802154::framer::Framer<
+            'static,
+            AwakeMac<'static, R>,
+            capsules::virtual_aes_ccm::VirtualAES128CCM<'static, A>,
+        >,
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -191,6 +196,6 @@ impl<
                 .expect("no deferred call slot available for ieee802154 driver"),
         );
 
-        (radio_driver, mux_mac)
+        (radio_driver, mux_mac, mac_device)
     }
 }
@@ -8,6 +8,7 @@ pub mod app_flash_driver;
 pub mod bus;
 pub mod button;
 pub mod cdc;
+pub mod coap;
 pub mod console;
 pub mod crc;
 pub mod ctap;
@@ -21,6 +22,8 @@ pub mod hmac;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
+pub mod icmpv6;
+pub mod icmpv6_driver;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod l3gd20;
@@ -37,6 +40,7 @@ pub mod nrf51822;
 pub mod panic_button;
 pub mod process_console;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
@@ -45,11 +49,14 @@ pub mod si7021;
 pub mod sound_pressure;
 pub mod spi;
 pub mod st77xx;
+pub mod tcp_driver;
+pub mod tcp_mux;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
 pub mod test;
 pub mod text_screen;
+pub mod thread;
 pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
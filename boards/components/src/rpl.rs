@@ -0,0 +1,309 @@
+//! Components to initialize IPv6 routing with RPL.
+//!
+//! This provides two Components:
+//!
+//! - RoutingTableComponent, which creates the RoutingTable that the IP
+//!   senders of the board choose the next hop of packets with. It should be
+//!   passed to the `with_routes()` method of the UDP, TCP, and ICMPv6
+//!   components.
+//! - RplComponent, which sets up an IPv6 sender for RPL, on a MAC user of its
+//!   own, and the RplNode that joins a DODAG and fills in the routing table.
+//!   The node receives RPL messages through the MuxICMP6Receiver of the
+//!   ICMPv6 component.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let routes = RoutingTableComponent::new()
+//!        .finalize(components::routing_table_component_helper!(4));
+//!    let (icmp6_node, icmp6_recv_mux) = ICMP6Component::new(
+//!        ...
+//!    )
+//!    .with_routes(routes)
+//!    .finalize(components::icmp6_component_helper!(sam4l::ast::Ast));
+//!    let rpl_node = RplComponent::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!        icmp6_recv_mux,
+//!        routes,
+//!    )
+//!    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+//!    rpl_node.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::routing::{Route, RoutingTable};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::rpl::rpl_node::RplNode;
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+// RPL only sends, so it needs fewer buffers than the other stacks:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. RPL_MESSAGE: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   3. TX_BUF: Buffer the RplNode builds messages in.
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+pub const MAX_MESSAGE_LEN: usize = 128; //The max payload of a RPL message sent by this device
+static mut RPL_MESSAGE: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// Setup static space for the routing table, with room for `$N` prefix
+// routes.
+#[macro_export]
+macro_rules! routing_table_component_helper {
+    ($N:expr $(,)?) => {{
+        use capsules::net::ipv6::routing::{Route, RoutingTable};
+        use core::cell::Cell;
+        use core::mem::MaybeUninit;
+        const EMPTY: Cell<Option<Route>> = Cell::new(None);
+        static mut ROUTES: [Cell<Option<Route>>; $N] = [EMPTY; $N];
+        static mut BUF: MaybeUninit<RoutingTable<'static>> = MaybeUninit::uninit();
+        (&ROUTES[..], &mut BUF)
+    };};
+}
+
+pub struct RoutingTableComponent {}
+
+impl RoutingTableComponent {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Component for RoutingTableComponent {
+    type StaticInput = (
+        &'static [Cell<Option<Route>>],
+        &'static mut MaybeUninit<RoutingTable<'static>>,
+    );
+    type Output = &'static RoutingTable<'static>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        static_init_half!(
+            static_buffer.1,
+            RoutingTable<'static>,
+            RoutingTable::new(static_buffer.0)
+        )
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::icmpv6::icmpv6_recv::ICMP6Receiver;
+        use capsules::net::rpl::rpl_node::RplNode;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<ICMP6Receiver<'static>> = MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6,
+        )
+    };};
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    mux_mac: &'static MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    icmp6_recv_mux: &'static MuxICMP6Receiver<'static>,
+    routes: &'static RoutingTable<'static>,
+}
+
+impl<A: Alarm<'static> + 'static> RplComponent<A> {
+    pub fn new(
+        mux_mac: &'static MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        icmp6_recv_mux: &'static MuxICMP6Receiver<'static>,
+        routes: &'static RoutingTable<'static>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+            icmp6_recv_mux,
+            routes,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<ICMP6Receiver<'static>>,
+    );
+    type Output = &'static RplNode<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // The IP sender only supports a single client, so RPL gets a MAC
+        // user of its own. RPL messages are received through the ICMPv6
+        // stack, so the MAC user has no receive client.
+        let rpl_mac = static_init_half!(
+            static_buffer.1,
+            MacUser<'static>,
+            MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(rpl_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.2,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let tr_hdr = TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type155));
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut RPL_MESSAGE,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.3,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                rpl_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_addr(self.interface_list[0]);
+        ip_send.set_routes(self.routes);
+        rpl_mac.set_transmit_client(ip_send);
+
+        // RPL messages go to all RPL nodes and to the root of the DODAG,
+        // whose address is only known once the node joins it.
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let rpl_virtual_alarm = static_init_half!(
+            static_buffer.4,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let rpl_node = static_init_half!(
+            static_buffer.5,
+            RplNode<'static, VirtualMuxAlarm<'static, A>>,
+            RplNode::new(
+                ip_send,
+                rpl_virtual_alarm,
+                self.routes,
+                self.interface_list[0],
+                &mut TX_BUF,
+                net_cap,
+            )
+        );
+        rpl_virtual_alarm.set_alarm_client(rpl_node);
+        ip_send.set_client(rpl_node);
+
+        let rpl_receiver = static_init_half!(
+            static_buffer.6,
+            ICMP6Receiver<'static>,
+            ICMP6Receiver::new()
+        );
+        rpl_receiver.set_client(rpl_node);
+        self.icmp6_recv_mux.add_client(rpl_receiver);
+
+        rpl_node
+    }
+}
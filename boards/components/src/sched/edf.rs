@@ -0,0 +1,88 @@
+//! Component for an earliest deadline first scheduler.
+//!
+//! This provides one Component, EDFComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let scheduler = components::sched::edf::EDFComponent::new(mux_alarm, &PROCESSES)
+//!     .finalize(components::edf_component_helper!(
+//!         nrf52832::rtc::Rtc<'static>,
+//!         NUM_PROCS
+//!     ));
+//! // Processes are loaded before they can be configured.
+//! scheduler.configure_process("control", 10000, 2000).unwrap();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time;
+use kernel::procs::Process;
+use kernel::static_init_half;
+use kernel::{EDFProcessNode, EDFSched};
+
+#[macro_export]
+macro_rules! edf_component_helper {
+    ($A:ty, $N:expr $(,)?) => {{
+        use core::mem::MaybeUninit;
+        use kernel::static_init;
+        use kernel::{EDFProcessNode, EDFSched};
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<EDFSched<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        const UNINIT: MaybeUninit<EDFProcessNode<'static>> = MaybeUninit::uninit();
+        static mut BUF3: [MaybeUninit<EDFProcessNode<'static>>; $N] = [UNINIT; $N];
+        (&mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct EDFComponent<A: 'static + time::Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    processes: &'static [Option<&'static dyn Process>],
+}
+
+impl<A: 'static + time::Alarm<'static>> EDFComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        processes: &'static [Option<&'static dyn Process>],
+    ) -> EDFComponent<A> {
+        EDFComponent {
+            alarm_mux,
+            processes,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for EDFComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<EDFSched<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut [MaybeUninit<EDFProcessNode<'static>>],
+    );
+    type Output = &'static mut EDFSched<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let (alarm_buf, sched_buf, proc_nodes) = static_buffer;
+        let scheduler_alarm = static_init_half!(
+            alarm_buf,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let scheduler = static_init_half!(
+            sched_buf,
+            EDFSched<'static, VirtualMuxAlarm<'static, A>>,
+            EDFSched::new(scheduler_alarm)
+        );
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                EDFProcessNode<'static>,
+                EDFProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
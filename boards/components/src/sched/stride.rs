@@ -0,0 +1,61 @@
+//! Component for a stride scheduler.
+//!
+//! This provides one Component, StrideComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let scheduler = components::sched::stride::StrideComponent::new(&PROCESSES)
+//!     .finalize(components::stride_component_helper!(NUM_PROCS));
+//! // Processes are loaded before they can be configured. The logger gets 10%
+//! // of the CPU time, the two other apps share the rest.
+//! scheduler.configure_process("logger", 10).unwrap();
+//! scheduler.configure_process("sensing", 45).unwrap();
+//! scheduler.configure_process("radio", 45).unwrap();
+//! ```
+
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::procs::Process;
+use kernel::{static_init, static_init_half};
+use kernel::{StrideProcessNode, StrideSched};
+
+#[macro_export]
+macro_rules! stride_component_helper {
+    ($N:expr $(,)?) => {{
+        use core::mem::MaybeUninit;
+        use kernel::StrideProcessNode;
+        const UNINIT: MaybeUninit<StrideProcessNode<'static>> = MaybeUninit::uninit();
+        static mut BUF: [MaybeUninit<StrideProcessNode<'static>>; $N] = [UNINIT; $N];
+        &mut BUF
+    };};
+}
+
+pub struct StrideComponent {
+    processes: &'static [Option<&'static dyn Process>],
+}
+
+impl StrideComponent {
+    pub fn new(processes: &'static [Option<&'static dyn Process>]) -> StrideComponent {
+        StrideComponent { processes }
+    }
+}
+
+impl Component for StrideComponent {
+    type StaticInput = &'static mut [MaybeUninit<StrideProcessNode<'static>>];
+    type Output = &'static mut StrideSched<'static>;
+
+    unsafe fn finalize(self, proc_nodes: Self::StaticInput) -> Self::Output {
+        let scheduler = static_init!(StrideSched<'static>, StrideSched::new());
+
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                StrideProcessNode<'static>,
+                StrideProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
@@ -0,0 +1,107 @@
+//! Component to initialize the userland TCP driver.
+//!
+//! This provides one Component, TCPDriverComponent. This component creates
+//! the sockets of the userspace TCP driver on a MuxTcp, and the driver that
+//! hands them out to apps.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TCPDriverComponent::new(board_kernel, tcp_mux)
+//!        .finalize(components::tcp_driver_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::tcp::tcp_mux::MuxTcp;
+use capsules::net::tcp::tcp_socket::TcpSocket;
+use capsules::net::tcp::TcpDriver;
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+/// Number of sockets, and so of apps that can use TCP at the same time.
+pub const NUM_SOCKETS: usize = 2;
+/// Size of the send and receive buffers of each socket.
+const SOCKET_BUF_LEN: usize = 512;
+
+static mut TX_BUF: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+static mut RX_BUF: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_driver_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::tcp::tcp_socket::TcpSocket;
+        use capsules::net::tcp::TcpDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            [TcpSocket<'static, VirtualMuxAlarm<'static, $A>>; $crate::tcp_driver::NUM_SOCKETS],
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<TcpDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct TCPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    tcp_mux: &'static MuxTcp<'static, VirtualMuxAlarm<'static, A>>,
+}
+
+impl<A: Alarm<'static>> TCPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        tcp_mux: &'static MuxTcp<'static, VirtualMuxAlarm<'static, A>>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            tcp_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for TCPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[TcpSocket<'static, VirtualMuxAlarm<'static, A>>; NUM_SOCKETS]>,
+        &'static mut MaybeUninit<TcpDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static TcpDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let [tx0, tx1] = &mut TX_BUF;
+        let [rx0, rx1] = &mut RX_BUF;
+        let sockets = static_init_half!(
+            static_buffer.0,
+            [TcpSocket<'static, VirtualMuxAlarm<'static, A>>; NUM_SOCKETS],
+            [
+                TcpSocket::new(self.tcp_mux, 0, tx0, rx0),
+                TcpSocket::new(self.tcp_mux, 1, tx1, rx1),
+            ]
+        );
+
+        let tcp_driver = static_init_half!(
+            static_buffer.1,
+            TcpDriver<'static, VirtualMuxAlarm<'static, A>>,
+            TcpDriver::new(sockets, self.board_kernel.create_grant(&grant_cap), net_cap,)
+        );
+        for socket in sockets.iter() {
+            socket.set_client(tcp_driver);
+            self.tcp_mux.add_socket(socket);
+        }
+        tcp_driver
+    }
+}
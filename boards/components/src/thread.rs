@@ -0,0 +1,228 @@
+//! Component to attach to a Thread network as a Minimal End Device.
+//!
+//! This provides one Component, ThreadComponent, which creates the MleNode
+//! that finds a parent in the network and becomes its child. The node sends
+//! and receives MLE messages on a UDP port of its own, through the UDP
+//! component, and secures frames of the network with the key the board
+//! configures, through the framer of the IEEE 802.15.4 component.
+//!
+//! The UDP component has to secure the frames it sends with the same key,
+//! and route packets through the routing table the node fills in. The port
+//! table of the UDP component only binds ports once the UDP driver
+//! component was finalized, so the driver has to be finalized first.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (_, mux_mac, framer) = Ieee802154Component::new(
+//!        ...
+//!    )
+//!    .finalize(components::ieee802154_component_helper!(...));
+//!    let routes = RoutingTableComponent::new()
+//!        .finalize(components::routing_table_component_helper!(1));
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxComponent::new(
+//!        ...
+//!    )
+//!    .with_routes(routes)
+//!    .with_security(mle::SECURITY_LEVEL, mle::key_id(KEY_SEQUENCE))
+//!    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+//!    let udp_driver = UDPDriverComponent::new(
+//!        ...
+//!    )
+//!    .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
+//!    let mle_node = ThreadComponent::new(
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        framer,
+//!        mux_alarm,
+//!        rng,
+//!        routes,
+//!        NETWORK_KEY,
+//!        KEY_SEQUENCE,
+//!    )
+//!    .finalize(components::thread_component_helper!(nrf52840::rtc::Rtc));
+//!    mle_node.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::Mac;
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::ipv6::routing::RoutingTable;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::thread::mle::MLE_PORT;
+use capsules::net::thread::mle_node::MleNode;
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+const MAX_MESSAGE_LEN: usize = 128; // The max size of an MLE message sent by this device
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! thread_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::thread::mle_node::MleNode;
+        use capsules::net::udp::udp_recv::UDPReceiver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<UDPReceiver<'static>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<MleNode<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct ThreadComponent<
+    A: Alarm<'static> + 'static,
+    M: Mac + 'static,
+    C: AES128CCM<'static> + 'static,
+> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    framer: &'static Framer<'static, M, C>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Random<'static>,
+    routes: &'static RoutingTable<'static>,
+    key: [u8; 16],
+    key_sequence: u32,
+}
+
+impl<A: Alarm<'static> + 'static, M: Mac + 'static, C: AES128CCM<'static> + 'static>
+    ThreadComponent<A, M, C>
+{
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        framer: &'static Framer<'static, M, C>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Random<'static>,
+        routes: &'static RoutingTable<'static>,
+        key: [u8; 16],
+        key_sequence: u32,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            framer,
+            alarm_mux,
+            rng,
+            routes,
+            key,
+            key_sequence,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, M: Mac + 'static, C: AES128CCM<'static> + 'static> Component
+    for ThreadComponent<A, M, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MleNode<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static MleNode<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init_half!(
+            static_buffer.0,
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init_half!(static_buffer.1, UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        // MLE messages go to neighbors, by link-local unicast or multicast,
+        // from and to the MLE port.
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::Port(MLE_PORT),
+                PortRange::Port(MLE_PORT),
+                &create_cap
+            )
+        );
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no socket available for MLE");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, MLE_PORT, net_cap)
+            .expect("failed to bind the MLE port");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let mle_virtual_alarm = static_init_half!(
+            static_buffer.2,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let mle_node = static_init_half!(
+            static_buffer.3,
+            MleNode<'static, VirtualMuxAlarm<'static, A>>,
+            MleNode::new(
+                udp_send,
+                mle_virtual_alarm,
+                self.rng,
+                self.framer,
+                self.routes,
+                self.key,
+                self.key_sequence,
+                &mut TX_BUF,
+                net_cap,
+            )
+        );
+        mle_virtual_alarm.set_alarm_client(mle_node);
+        udp_send.set_client(mle_node);
+        udp_recv.set_client(mle_node);
+
+        // Frames of the network are secured with the key of the node,
+        // rather than keys userspace configures through the radio driver.
+        self.framer.set_key_procedure(mle_node);
+        self.framer.set_device_procedure(mle_node);
+
+        mle_node
+    }
+}
@@ -25,11 +25,12 @@
 
 use capsules;
 use capsules::ieee802154::device::MacDevice;
-use capsules::net::ieee802154::MacAddress;
+use capsules::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::routing::RouteLookup;
 use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
@@ -120,6 +121,8 @@ pub struct UDPMuxComponent<A: Alarm<'static> + 'static> {
     src_mac_addr: MacAddress,
     interface_list: &'static [IPAddr],
     alarm_mux: &'static MuxAlarm<'static, A>,
+    routes: Option<&'static dyn RouteLookup>,
+    security: Option<(SecurityLevel, KeyId)>,
 }
 
 impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
@@ -140,8 +143,24 @@ impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
             src_mac_addr,
             interface_list,
             alarm_mux,
+            routes: None,
+            security: None,
         }
     }
+
+    /// Sends packets to the next hop `routes` returns, rather than always to
+    /// `dst_mac_addr`.
+    pub fn with_routes(mut self, routes: &'static dyn RouteLookup) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Secures the frames packets are sent in with `level` and the key
+    /// `key_id` names, rather than sending them unsecured.
+    pub fn with_security(mut self, level: SecurityLevel, key_id: KeyId) -> Self {
+        self.security = Some((level, key_id));
+        self
+    }
 }
 
 impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
@@ -258,6 +277,10 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         // userland or capsules.
         ip_send.set_addr(self.interface_list[0]);
         udp_mac.set_transmit_client(ip_send);
+        if let Some(routes) = self.routes {
+            ip_send.set_routes(routes);
+        }
+        ip_send.set_security(self.security);
 
         let ip_receive = static_init!(
             capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
@@ -0,0 +1,225 @@
+//! Board file for running Tock as a process on the host, on the simulated
+//! chip.
+//!
+//! The board loads the TBF objects given on the command line and runs the
+//! kernel loop until it is interrupted, with the console on stdin and stdout.
+//! Apps are compiled into the board, see `APPS`, and each TBF object names
+//! the app to run in its package name. `host-board --tbf NAME FILE` writes the
+//! TBF object of the app `NAME` to `FILE`.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use capsules::alarm::{self, AlarmDriver};
+use capsules::console::{self, Console};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use host::app::{AppContext, HostApp};
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::{HostChip, SimPeripheral};
+use host_sim::uart::SimUart;
+use kernel::capabilities;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::procs::PanicFaultPolicy;
+use kernel::{create_capability, static_init};
+use kernel::{Chip, Kernel, Platform, RoundRobinProcessNode, RoundRobinSched};
+
+/// Number of concurrent processes this platform supports.
+const NUM_PROCS: usize = 4;
+
+/// Memory for the processes, including the kernel memory of their grants.
+const APP_MEMORY_LEN: usize = 65536;
+
+static mut PROCESSES: [Option<&'static dyn kernel::procs::Process>; NUM_PROCS] = [None; NUM_PROCS];
+
+/// The apps of the board, which TBF objects name in their package name.
+const APPS: &[HostApp] = &[
+    HostApp {
+        name: "hello",
+        main: hello,
+        minimum_ram_size: 8192,
+    },
+    HostApp {
+        name: "ticker",
+        main: ticker,
+        minimum_ram_size: 8192,
+    },
+];
+
+/// Print a greeting.
+fn hello(ctx: &AppContext) {
+    let message = ctx.allocate(14);
+    message.copy_from_slice(b"Hello World!\r\n");
+    print(ctx, message);
+}
+
+/// Print a line every second, five times.
+fn ticker(ctx: &AppContext) {
+    let message = ctx.allocate(6);
+    message.copy_from_slice(b"tick\r\n");
+    ctx.subscribe(alarm::DRIVER_NUM, 0, Some(done), 0);
+    for _ in 0..5 {
+        ctx.command(alarm::DRIVER_NUM, 5, 1000, 0);
+        ctx.yield_wait();
+        print(ctx, message);
+    }
+}
+
+/// Write `message`, which is in process memory, to the console and wait until
+/// it is written.
+fn print(ctx: &AppContext, message: &[u8]) {
+    ctx.subscribe(console::DRIVER_NUM, 1, Some(done), 0);
+    ctx.allow_readonly(console::DRIVER_NUM, 1, message.as_ptr(), message.len());
+    ctx.command(console::DRIVER_NUM, 1, message.len(), 0);
+    ctx.yield_wait();
+}
+
+fn done(_ctx: &AppContext, _: usize, _: usize, _: usize, _appdata: usize) {}
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct HostBoard {
+    console: &'static Console<'static>,
+    alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, SimAlarm<'static>>>,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for HostBoard {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            console::DRIVER_NUM => f(Some(self.console)),
+            alarm::DRIVER_NUM => f(Some(self.alarm)),
+            _ => f(None),
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [option, name, path] if option == "--tbf" => write_tbf(name, path),
+        [] => exit_with_usage(),
+        paths => {
+            let tbfs: Vec<Vec<u8>> = paths
+                .iter()
+                .map(|path| {
+                    fs::read(path).unwrap_or_else(|error| {
+                        eprintln!("host-board: cannot read {}: {}", path, error);
+                        process::exit(1)
+                    })
+                })
+                .collect();
+            let tbfs: Vec<&[u8]> = tbfs.iter().map(|tbf| &tbf[..]).collect();
+            unsafe { run(&tbfs) }
+        }
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("usage: host-board APP.tbf...");
+    eprintln!("       host-board --tbf NAME FILE");
+    eprintln!();
+    eprintln!("apps of the board:");
+    for app in APPS.iter() {
+        eprintln!("  {}", app.name);
+    }
+    process::exit(2)
+}
+
+/// Write the TBF object of the app called `name` to `path`.
+fn write_tbf(name: &str, path: &str) {
+    let app = APPS
+        .iter()
+        .find(|app| app.name == name)
+        .unwrap_or_else(|| exit_with_usage());
+    if let Err(error) = fs::write(path, app.tbf(&[])) {
+        eprintln!("host-board: cannot write {}: {}", path, error);
+        process::exit(1);
+    }
+}
+
+/// Set up the board and run the processes of `tbfs`.
+unsafe fn run(tbfs: &[&[u8]]) -> ! {
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+    let board_kernel = static_init!(Kernel, Kernel::new(&PROCESSES));
+
+    let uart = static_init!(SimUart<'static>, SimUart::stdio());
+    let sim_alarm = static_init!(SimAlarm<'static>, SimAlarm::new());
+    let peripherals = static_init!([&'static dyn SimPeripheral; 2], [uart, sim_alarm]);
+    let chip = static_init!(HostChip, HostChip::new(peripherals));
+
+    let console = static_init!(
+        Console<'static>,
+        Console::new(
+            uart,
+            static_init!([u8; 64], [0; 64]),
+            static_init!([u8; 64], [0; 64]),
+            board_kernel.create_grant(&memory_allocation_cap),
+        )
+    );
+    uart.set_transmit_client(console);
+    uart.set_receive_client(console);
+
+    let mux_alarm = static_init!(
+        MuxAlarm<'static, SimAlarm<'static>>,
+        MuxAlarm::new(sim_alarm)
+    );
+    sim_alarm.set_alarm_client(mux_alarm);
+    let virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, SimAlarm<'static>>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let alarm = static_init!(
+        AlarmDriver<'static, VirtualMuxAlarm<'static, SimAlarm<'static>>>,
+        AlarmDriver::new(
+            virtual_alarm,
+            board_kernel.create_grant(&memory_allocation_cap)
+        )
+    );
+    virtual_alarm.set_alarm_client(alarm);
+
+    let scheduler = static_init!(RoundRobinSched<'static>, RoundRobinSched::new());
+    let nodes = static_init!(
+        [RoundRobinProcessNode<'static>; NUM_PROCS],
+        [
+            RoundRobinProcessNode::new(&PROCESSES[0]),
+            RoundRobinProcessNode::new(&PROCESSES[1]),
+            RoundRobinProcessNode::new(&PROCESSES[2]),
+            RoundRobinProcessNode::new(&PROCESSES[3]),
+        ]
+    );
+    for node in nodes.iter() {
+        scheduler.processes.push_tail(node);
+    }
+
+    let apps = chip.userspace_kernel_boundary();
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        apps.load_tbfs(tbfs, APPS),
+        apps.app_memory(APP_MEMORY_LEN),
+        &mut PROCESSES,
+        &PanicFaultPolicy {},
+        &process_management_cap,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("host-board: cannot load the apps: {:?}", error);
+        process::exit(1)
+    });
+
+    let board = HostBoard { console, alarm };
+    board_kernel.kernel_loop(
+        &board,
+        chip,
+        None::<&kernel::ipc::IPC<NUM_PROCS>>,
+        scheduler,
+        &main_loop_cap,
+    )
+}
@@ -5,6 +5,7 @@ use kernel::debug;
 use kernel::debug::IoWrite;
 use kernel::hil::led;
 use kernel::hil::uart::{self, Configure};
+use kernel::panic_record::PanicRecord;
 use sam4l;
 
 use crate::CHIP;
@@ -16,6 +17,18 @@ struct Writer {
 
 static mut WRITER: Writer = Writer { initialized: false };
 
+/// Record of the last panic, which the kernel does not zero on boot so that
+/// it survives the reset after a panic.
+#[link_section = ".noinit"]
+pub static mut PANIC_RECORD: PanicRecord = PanicRecord::new();
+
+/// Where the linker script placed the kernel code and stack.
+extern "C" {
+    static _stext: u8;
+    static _etext: u8;
+    static _estack: u8;
+}
+
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
         self.write(s.as_bytes());
@@ -57,6 +70,12 @@ pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
     let led_pin = sam4l::gpio::GPIOPin::new(sam4l::gpio::Pin::PC22);
     let led = &mut led::LedLow::new(&led_pin);
     let writer = &mut WRITER;
+    PANIC_RECORD.record(
+        pi,
+        &PROCESSES,
+        (&_stext as *const u8, &_etext as *const u8),
+        &_estack as *const u8,
+    );
     debug::panic(
         &mut [led],
         writer,
@@ -136,6 +136,7 @@ struct Imix {
     >,
     nrf51822: &'static capsules::nrf51822_serialization::Nrf51822Serialization<'static>,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    last_panic: &'static capsules::last_panic::LastPanic,
 }
 
 // The RF233 radio stack requires our buffers for its SPI operations:
@@ -175,6 +176,7 @@ impl kernel::Platform for Imix {
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules::last_panic::DRIVER_NUM => f(Some(self.last_panic)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -315,6 +317,7 @@ pub unsafe fn main() {
         UartMuxComponent::new(&peripherals.usart3, 115200, dynamic_deferred_caller).finalize(());
 
     let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    pconsole.set_panic_record(&io::PANIC_RECORD);
     let console = ConsoleComponent::new(board_kernel, uart_mux).finalize(());
     DebugWriterComponent::new(uart_mux).finalize(());
 
@@ -456,7 +459,7 @@ pub unsafe fn main() {
 
     // Can this initialize be pushed earlier, or into component? -pal
     let _ = rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
-    let (_, mux_mac) = components::ieee802154::Ieee802154Component::new(
+    let (_, mux_mac, _) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         rf233,
         aes_mux,
@@ -528,6 +531,17 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    // Let the app manager read the record of the panic that caused the last
+    // reset, if there was one.
+    let last_panic = static_init!(
+        capsules::last_panic::LastPanic,
+        capsules::last_panic::LastPanic::new(
+            &io::PANIC_RECORD,
+            "app_manager",
+            board_kernel.create_grant(&grant_cap)
+        )
+    );
+
     let imix = Imix {
         pconsole,
         console,
@@ -549,6 +563,7 @@ pub unsafe fn main() {
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
+        last_panic,
     };
 
     // Need to initialize the UART for the nRF51 serialization.
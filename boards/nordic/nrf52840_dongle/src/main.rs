@@ -288,7 +288,7 @@ pub unsafe fn main() {
             .expect("no deferred call slot available for ccm mux"),
     );
 
-    let (ieee802154_radio, _mux_mac) = components::ieee802154::Ieee802154Component::new(
+    let (ieee802154_radio, _mux_mac, _) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         &base_peripherals.ieee802154_radio,
         aes_mux,
@@ -400,7 +400,7 @@ pub unsafe fn main() {
     let serial_num = nrf52840::ficr::FICR_INSTANCE.address();
     let serial_num_bottom_16 = serial_num[0] as u16 + ((serial_num[1] as u16) << 8);
     let src_mac_from_serial_num: MacAddress = MacAddress::Short(serial_num_bottom_16);
-    let (ieee802154_radio, mux_mac) = components::ieee802154::Ieee802154Component::new(
+    let (ieee802154_radio, mux_mac, _) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         &base_peripherals.ieee802154_radio,
         aes_mux,
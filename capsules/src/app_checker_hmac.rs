@@ -0,0 +1,210 @@
+//! Check app credentials with an HMAC-SHA256 keyed by the board.
+//!
+//! This implements `AppCredentialsChecker` for apps whose TBF footer contains
+//! an `HmacSha256` credential. The HMAC is computed over the TBF object (from
+//! the start of the header to the end of the application binary) with the
+//! key provided by the board, using any `hil::digest` implementation that
+//! supports HMAC-SHA256. The app is accepted if the computed HMAC matches the
+//! one in its footer and rejected otherwise. Other credential formats are not
+//! supported by this checker.
+//!
+//! Since the app binary is in flash and the digest HIL needs a mutable
+//! buffer, the binary is copied into `data_buffer` one chunk at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let checker = static_init!(
+//!     capsules::app_checker_hmac::AppCheckerHmacSha256<'static, VirtualMuxHmac<'static, lowrisc::hmac::Hmac, 32>>,
+//!     capsules::app_checker_hmac::AppCheckerHmacSha256::new(
+//!         virtual_hmac,
+//!         &APP_KEY,
+//!         true,
+//!         static_init!([u8; 64], [0; 64]),
+//!         static_init!([u8; 32], [0; 32]),
+//!     )
+//! );
+//! digest::Digest::set_client(virtual_hmac, checker);
+//!
+//! let checker_machine = static_init!(
+//!     kernel::process_checker::ProcessCheckerMachine,
+//!     kernel::process_checker::ProcessCheckerMachine::new(board_kernel, checker)
+//! );
+//! checker.set_client(checker_machine);
+//!
+//! kernel::procs::load_and_check_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     &FAULT_RESPONSE,
+//!     checker_machine,
+//!     &process_mgmt_cap,
+//! )
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::process_checker::{
+    AppCredentialsChecker, CheckResult, Client, TbfFooterV2Credentials, TbfFooterV2CredentialsType,
+};
+use kernel::ErrorCode;
+
+pub struct AppCheckerHmacSha256<'a, H: digest::Digest<'a, 32> + digest::HMACSha256> {
+    hmac: &'a H,
+    key: &'static [u8],
+    require_credentials: bool,
+    client: OptionalCell<&'a dyn Client<'a>>,
+
+    data_buffer: TakeCell<'static, [u8]>,
+    hash_buffer: TakeCell<'static, [u8; 32]>,
+
+    /// The credentials and binary being checked.
+    credentials: OptionalCell<TbfFooterV2Credentials<'a>>,
+    binary: OptionalCell<&'a [u8]>,
+    /// How many bytes of the binary have been added to the HMAC.
+    binary_offset: Cell<usize>,
+}
+
+impl<'a, H: digest::Digest<'a, 32> + digest::HMACSha256> AppCheckerHmacSha256<'a, H> {
+    pub fn new(
+        hmac: &'a H,
+        key: &'static [u8],
+        require_credentials: bool,
+        data_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; 32],
+    ) -> AppCheckerHmacSha256<'a, H> {
+        AppCheckerHmacSha256 {
+            hmac,
+            key,
+            require_credentials,
+            client: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            hash_buffer: TakeCell::new(hash_buffer),
+            credentials: OptionalCell::empty(),
+            binary: OptionalCell::empty(),
+            binary_offset: Cell::new(0),
+        }
+    }
+
+    /// Copy the next chunk of the binary into the data buffer and add it to
+    /// the HMAC, or compute the HMAC if the whole binary has been added.
+    fn add_next_chunk(&self) -> Result<(), ErrorCode> {
+        let binary = self.binary.extract().ok_or(ErrorCode::FAIL)?;
+        let offset = self.binary_offset.get();
+
+        if offset >= binary.len() {
+            let hash = self.hash_buffer.take().ok_or(ErrorCode::BUSY)?;
+            return self.hmac.run(hash).map_err(|(e, hash)| {
+                self.hash_buffer.replace(hash);
+                e
+            });
+        }
+
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let length = core::cmp::min(buffer.len(), binary.len() - offset);
+        buffer[..length].copy_from_slice(&binary[offset..offset + length]);
+        self.binary_offset.set(offset + length);
+
+        let mut lease_buf = LeasableBuffer::new(buffer);
+        lease_buf.slice(..length);
+        self.hmac
+            .add_data(lease_buf)
+            .map(|_| ())
+            .map_err(|(e, buffer)| {
+                self.data_buffer.replace(buffer);
+                e
+            })
+    }
+
+    /// Finish the current check and report the result to the client.
+    fn check_finished(&self, result: Result<CheckResult, ErrorCode>) {
+        self.hmac.clear_data();
+        let credentials = self.credentials.take();
+        let binary = self.binary.take();
+        if let (Some(credentials), Some(binary)) = (credentials, binary) {
+            self.client
+                .map(|client| client.check_done(result, credentials, binary));
+        }
+    }
+}
+
+impl<'a, H: digest::Digest<'a, 32> + digest::HMACSha256> AppCredentialsChecker<'a>
+    for AppCheckerHmacSha256<'a, H>
+{
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials<'a>,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials<'a>, &'a [u8])> {
+        if credentials.format() != TbfFooterV2CredentialsType::HmacSha256 {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        if self.credentials.is_some() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+
+        if let Err(e) = self.hmac.set_mode_hmacsha256(self.key) {
+            return Err((e, credentials, binary));
+        }
+
+        self.credentials.set(credentials);
+        self.binary.set(binary);
+        self.binary_offset.set(0);
+
+        self.add_next_chunk().map_err(|e| {
+            self.hmac.clear_data();
+            self.credentials.clear();
+            self.binary.clear();
+            (e, credentials, binary)
+        })
+    }
+}
+
+impl<'a, H: digest::Digest<'a, 32> + digest::HMACSha256> digest::Client<'a, 32>
+    for AppCheckerHmacSha256<'a, H>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+
+        if let Err(e) = result.and_then(|()| self.add_next_chunk()) {
+            self.check_finished(Err(e));
+        }
+    }
+
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        let check = result.map(|()| {
+            let expected = self.credentials.map_or(&[][..], |c| c.data());
+
+            // Compare every byte so the time taken does not depend on where
+            // the first mismatch is.
+            let matches = expected.len() == digest.len()
+                && expected
+                    .iter()
+                    .zip(digest.iter())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0;
+
+            if matches {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        });
+
+        self.hash_buffer.replace(digest);
+        self.check_finished(check);
+    }
+}
@@ -0,0 +1,517 @@
+//! Install and remove apps while the kernel is running.
+//!
+//! This capsule lets a userspace app write a new TBF object into app flash,
+//! start it as a new process, and later stop a process and reclaim its flash.
+//! The kernel side of this is `kernel::procs::DynamicProcessLoader`, which
+//! decides where new apps go and creates the processes; this capsule does the
+//! flash writes through a `NonvolatileStorage` interface to the app flash
+//! (e.g. `NonvolatileToPages` on top of the chip's `hil::flash::Flash`).
+//!
+//! The app linked list in flash must stay valid at all times, as the next
+//! boot will walk it. While a new app is being written its space is covered by
+//! a padding entry, and the real TBF header (the first 16 bytes of the TBF
+//! object) is only written once the rest of the app is in flash and the app
+//! is loaded. If loading fails, or the app is uninstalled, its space is marked
+//! as padding again and can be reused.
+//!
+//! Only one app can be installed at a time, and only the process that started
+//! an install can continue it. If that process stops or restarts before it
+//! finishes, the install is abandoned when another process starts one.
+//!
+//! Installing and removing apps is privileged. Only processes whose TBF
+//! header has a syscall permissions section that lists this driver may use
+//! it, and the kernel checks which commands they may call. Every other
+//! process gets `NODEVICE`, as if the driver did not exist. If the board
+//! checks app credentials, it passes its `ProcessCheckerMachine` to the
+//! `DynamicProcessLoader`, and installed apps only run once their credentials
+//! are approved.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let loader = static_init!(
+//!     kernel::procs::DynamicProcessLoader<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>>,
+//!     kernel::procs::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         core::slice::from_raw_parts(
+//!             &_sapps as *const u8,
+//!             &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+//!         ),
+//!         &mut APP_MEMORY,
+//!         &mut PROCESSES,
+//!         &FAULT_RESPONSE,
+//!         Some(checker),
+//!         &process_management_capability,
+//!     )
+//! );
+//! loader.load_processes().unwrap_or_else(|err| {
+//!     debug!("Error loading processes!");
+//!     debug!("{:?}", err);
+//! });
+//!
+//! pub static mut APP_LOADER_BUFFER: [u8; 512] = [0; 512];
+//! let app_loader = static_init!(
+//!     capsules::app_loader::AppLoader<'static>,
+//!     capsules::app_loader::AppLoader::new(
+//!         loader,
+//!         nv_to_page,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut APP_LOADER_BUFFER
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, app_loader);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Read-only allow `0`: the data to write with command `2`.
+//! - Subscribe `0`: called when a command finishes with the command number,
+//!   the status code, and a value (the flash address of the app for command
+//!   `1`, the process identifier for command `3`).
+//! - Command `0`: driver check.
+//! - Command `1`: start installing an app that is `arg1` bytes long.
+//! - Command `2`: write `arg2` bytes from the allowed buffer at offset `arg1`
+//!   in the app. Fails with `SIZE` if the bytes that go to flash do not fit
+//!   the buffer the board gives the capsule, so large apps are written in
+//!   several parts.
+//! - Command `3`: finish installing the app and start it.
+//! - Command `4`: abandon the install.
+//! - Command `5`: stop the process with identifier `arg1` and remove its app.
+//!   A process cannot remove itself.
+
+use core::cell::Cell;
+use core::mem;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::procs::{
+    create_padding_header, AppFlashLocation, CommandPermissions, DynamicProcessLoading,
+};
+use kernel::ErrorCode;
+use kernel::{CommandReturn, Driver, Grant, ProcessId, Read, ReadOnlyAppSlice, Upcall};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AppLoader as usize;
+
+/// Length of a TBF padding header, and of the part of the TBF header that is
+/// written last.
+const HEADER_LEN: usize = 16;
+
+const COMMAND_SETUP: usize = 1;
+const COMMAND_WRITE: usize = 2;
+const COMMAND_LOAD: usize = 3;
+const COMMAND_UNINSTALL: usize = 5;
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    buffer: ReadOnlyAppSlice,
+}
+
+/// An app that is being installed.
+#[derive(Clone, Copy)]
+struct Session {
+    app: ProcessId,
+    location: AppFlashLocation,
+    length: usize,
+    /// The start of the TBF header, which is written when the app is loaded.
+    header: [u8; HEADER_LEN],
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    SetupPaddingBefore,
+    SetupPlaceholder,
+    Write,
+    LoadPaddingAfter,
+    LoadHeader,
+    LoadRestore(ErrorCode),
+    Uninstall,
+}
+
+pub struct AppLoader<'a> {
+    loader: &'a dyn DynamicProcessLoading,
+    driver: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    apps: Grant<App>,
+    session: OptionalCell<Session>,
+    state: Cell<State>,
+    /// The process to notify when the current flash operation finishes.
+    current_app: OptionalCell<ProcessId>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a> AppLoader<'a> {
+    pub fn new(
+        loader: &'a dyn DynamicProcessLoading,
+        driver: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        grant: Grant<App>,
+        buffer: &'static mut [u8],
+    ) -> AppLoader<'a> {
+        AppLoader {
+            loader,
+            driver,
+            apps: grant,
+            session: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            current_app: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    /// Write a padding header at `address` for an entry of `length` bytes.
+    fn write_padding(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        let header = create_padding_header(length as u32);
+        self.write_header(address, &header)
+    }
+
+    fn write_header(&self, address: usize, header: &[u8; HEADER_LEN]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[..HEADER_LEN].copy_from_slice(header);
+            self.driver.write(buffer, address, HEADER_LEN)
+        })
+    }
+
+    /// Start writing the space for a new app.
+    fn setup(&self, appid: ProcessId, length: usize) -> Result<(), ErrorCode> {
+        // The install of a process that stopped or restarted can never
+        // finish, so it does not keep others from installing apps.
+        let installing = self.session.map_or(false, |session| {
+            self.apps.enter(session.app, |_| ()).is_ok()
+        });
+        if installing {
+            return Err(ErrorCode::BUSY);
+        }
+        let location = self.loader.find_flash_for_app(length)?;
+        let session = Session {
+            app: appid,
+            location,
+            length,
+            header: [0; HEADER_LEN],
+        };
+
+        match location.padding_before {
+            Some((address, padding_length)) => {
+                self.write_padding(address, padding_length)?;
+                self.state.set(State::SetupPaddingBefore);
+            }
+            None => {
+                self.write_placeholder(&session)?;
+                self.state.set(State::SetupPlaceholder);
+            }
+        }
+        self.session.set(session);
+        self.current_app.set(appid);
+        Ok(())
+    }
+
+    /// Cover the whole space the app is installed in with one padding entry.
+    fn write_placeholder(&self, session: &Session) -> Result<(), ErrorCode> {
+        let padding_after = session.location.padding_after.map_or(0, |(_, len)| len);
+        self.write_padding(session.location.address, session.length + padding_after)
+    }
+
+    /// Write part of the app from the allowed buffer. Returns `Ok(false)` if
+    /// there was nothing to write to flash as all of the data is part of the
+    /// TBF header.
+    fn write(&self, appid: ProcessId, offset: usize, length: usize) -> Result<bool, ErrorCode> {
+        let mut session = self
+            .session
+            .extract()
+            .filter(|session| session.app == appid)
+            .ok_or(ErrorCode::RESERVE)?;
+        let end = offset.checked_add(length).ok_or(ErrorCode::INVAL)?;
+        if end > session.length {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let result = self
+            .apps
+            .enter(appid, |app| {
+                app.buffer.map_or(Err(ErrorCode::RESERVE), |app_buffer| {
+                    let data = app_buffer.get(..length).ok_or(ErrorCode::SIZE)?;
+
+                    // The start of the TBF header is kept until the app is
+                    // loaded.
+                    for (i, byte) in data.iter().enumerate() {
+                        if offset + i < HEADER_LEN {
+                            session.header[offset + i] = *byte;
+                        }
+                    }
+
+                    let skip = HEADER_LEN.saturating_sub(offset);
+                    if skip >= length {
+                        return Ok(false);
+                    }
+                    self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+                        let flash_data = &data[skip..];
+                        if flash_data.len() > buffer.len() {
+                            self.buffer.replace(buffer);
+                            return Err(ErrorCode::SIZE);
+                        }
+                        buffer[..flash_data.len()].copy_from_slice(flash_data);
+                        self.driver
+                            .write(
+                                buffer,
+                                session.location.address + offset + skip,
+                                flash_data.len(),
+                            )
+                            .map(|()| true)
+                    })
+                })
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if result.is_ok() {
+            self.session.set(session);
+        }
+        result
+    }
+
+    /// Finish writing the app to flash so it can be loaded.
+    fn load(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let session = self
+            .session
+            .extract()
+            .filter(|session| session.app == appid)
+            .ok_or(ErrorCode::RESERVE)?;
+
+        match session.location.padding_after {
+            Some((address, length)) => {
+                self.write_padding(address, length)?;
+                self.state.set(State::LoadPaddingAfter);
+            }
+            None => {
+                self.write_header(session.location.address, &session.header)?;
+                self.state.set(State::LoadHeader);
+            }
+        }
+        self.current_app.set(appid);
+        Ok(())
+    }
+
+    /// Stop a process and mark its flash as padding.
+    fn uninstall(&self, appid: ProcessId, identifier: usize) -> Result<(), ErrorCode> {
+        // The loader frees the memory of the process, which the kernel still
+        // uses until this syscall returns.
+        if identifier == appid.id() {
+            return Err(ErrorCode::INVAL);
+        }
+        let (address, length) = self.loader.uninstall_process(identifier)?;
+        self.write_padding(address, length)?;
+        self.state.set(State::Uninstall);
+        self.current_app.set(appid);
+        Ok(())
+    }
+
+    /// Tell the process that started an operation that it finished.
+    fn finish(&self, command: usize, result: Result<(), ErrorCode>, value: usize) {
+        self.state.set(State::Idle);
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app| {
+                app.callback
+                    .schedule(command, kernel::into_statuscode(result), value);
+            });
+        });
+    }
+}
+
+impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for AppLoader<'_> {
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {}
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+
+        match self.state.get() {
+            State::Idle => {}
+            State::SetupPaddingBefore => {
+                let result = self.session.map_or(Err(ErrorCode::FAIL), |session| {
+                    self.write_placeholder(session)
+                });
+                match result {
+                    Ok(()) => self.state.set(State::SetupPlaceholder),
+                    Err(e) => {
+                        self.session.clear();
+                        self.finish(COMMAND_SETUP, Err(e), 0);
+                    }
+                }
+            }
+            State::SetupPlaceholder => {
+                let address = self.session.map_or(0, |session| session.location.address);
+                self.finish(COMMAND_SETUP, Ok(()), address);
+            }
+            State::Write => self.finish(COMMAND_WRITE, Ok(()), 0),
+            State::LoadPaddingAfter => {
+                let result = self.session.map_or(Err(ErrorCode::FAIL), |session| {
+                    self.write_header(session.location.address, &session.header)
+                });
+                match result {
+                    Ok(()) => self.state.set(State::LoadHeader),
+                    Err(e) => {
+                        self.session.clear();
+                        self.finish(COMMAND_LOAD, Err(e), 0);
+                    }
+                }
+            }
+            State::LoadHeader => {
+                let session = self.session.take();
+                let result = session.map_or(Err(ErrorCode::FAIL), |session| {
+                    self.loader.load_process(session.location.address)
+                });
+                match result {
+                    Ok(processid) => self.finish(COMMAND_LOAD, Ok(()), processid.id()),
+                    Err(e) => {
+                        // Don't leave a broken app in flash: mark the space as
+                        // padding again.
+                        let restore = session.map_or(Err(ErrorCode::FAIL), |session| {
+                            self.write_padding(session.location.address, session.length)
+                        });
+                        match restore {
+                            Ok(()) => self.state.set(State::LoadRestore(e)),
+                            Err(_) => self.finish(COMMAND_LOAD, Err(e), 0),
+                        }
+                    }
+                }
+            }
+            State::LoadRestore(e) => self.finish(COMMAND_LOAD, Err(e), 0),
+            State::Uninstall => self.finish(COMMAND_UNINSTALL, Ok(()), 0),
+        }
+    }
+}
+
+impl Driver for AppLoader<'_> {
+    /// Setup buffer to write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer with the app data to write.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app| {
+                    mem::swap(&mut app.buffer, &mut slice);
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for finished commands.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// App loader control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start installing an app of `arg1` bytes.
+    /// - `2`: Write `arg2` bytes from the allowed buffer at offset `arg1` in
+    ///   the app, or fail with `SIZE` if they do not fit the kernel buffer.
+    /// - `3`: Load the app and start it.
+    /// - `4`: Abandon the install.
+    /// - `5`: Uninstall the process with identifier `arg1`.
+    ///
+    /// Returns NODEVICE unless the syscall permissions of the process list
+    /// this driver.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match appid.get_command_permissions(DRIVER_NUM, command_num / 64) {
+            CommandPermissions::Mask(_) => {}
+            _ => return CommandReturn::failure(ErrorCode::NODEVICE),
+        }
+
+        if command_num != 0 && self.state.get() != State::Idle {
+            return CommandReturn::failure(ErrorCode::BUSY);
+        }
+
+        let res = match command_num {
+            0 /* This driver exists. */ => Ok(()),
+
+            COMMAND_SETUP => self.setup(appid, arg1),
+
+            COMMAND_WRITE => {
+                self.write(appid, arg1, arg2).map(|started| {
+                    if started {
+                        self.state.set(State::Write);
+                        self.current_app.set(appid);
+                    } else {
+                        // Nothing to write to flash, so we are already done.
+                        let _ = self.apps.enter(appid, |app| {
+                            app.callback.schedule(COMMAND_WRITE, 0, 0);
+                        });
+                    }
+                })
+            }
+
+            COMMAND_LOAD => self.load(appid),
+
+            4 /* Abandon the install */ => {
+                // The space is still covered by the placeholder padding entry,
+                // so the install can just be forgotten.
+                if self.session.map_or(false, |session| session.app == appid) {
+                    self.session.clear();
+                    Ok(())
+                } else {
+                    Err(ErrorCode::RESERVE)
+                }
+            }
+
+            COMMAND_UNINSTALL => self.uninstall(appid, arg1),
+
+            _ /* Unknown command num */ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
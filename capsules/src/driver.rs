@@ -19,6 +19,10 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    AppLoader             = 0x10001,
+    ProcessManager        = 0x10002,
+    MessageIpc            = 0x10003,
+    LastPanic             = 0x10004,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -31,6 +35,9 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
+    Icmp6                 = 0x30004,
+    Coap                  = 0x30005,
 
     // Cryptography
     Rng                   = 0x40001,
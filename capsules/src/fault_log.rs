@@ -0,0 +1,231 @@
+//! Persistent log of process faults.
+//!
+//! When a process faults the kernel only prints its state to the debug
+//! console, which is lost on deployed devices. This capsule is a
+//! `ProcessFaultPolicy` that wraps the policy of the board: it lets the wrapped
+//! policy decide what to do with the faulted process, and appends a record of
+//! the fault to a log, for example a circular `capsules::log::Log` in flash.
+//! The records can be read back later with the `LogRead` interface of the log.
+//!
+//! Once a record is written, a client, such as
+//! `capsules::process_manager::ProcessManager`, is told about the fault.
+//!
+//! Only one record is written at a time. Faults that happen while a record is
+//! being written are not recorded, but counted in the next record. If the
+//! wrapped policy panics the board, the record is not written;
+//! `kernel::debug::panic` prints the process instead.
+//!
+//! Record Format
+//! -------------
+//!
+//! All numbers are little-endian.
+//!
+//! ```text
+//! 0      1      2         4            8               12
+//! +------+------+---------+------------+---------------+
+//! | ver  |action| dropped | process id | restart count |
+//! +------+------+---------+------------+---------------+
+//! ```
+//!
+//! - `ver`: `FAULT_RECORD_VERSION`.
+//! - `action`: `0` panic, `1` restart, `2` stop.
+//! - `dropped`: number of faults before this one that were not recorded.
+//!
+//! Four sections follow, each a `u16` length followed by that many bytes:
+//!
+//! 1. The package name of the process.
+//! 2. The registers describing the fault (`Process::debug_fault_status()`).
+//!    This is empty if the kernel faulted the process, e.g. because of an
+//!    invalid syscall, rather than the hardware.
+//! 3. The registers of the process, including the program counter and the
+//!    stack pointer (`Process::debug_stored_context()`).
+//! 4. The top of the stack of the process (`Process::debug_stack_excerpt()`),
+//!    as much as fits in the record buffer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let fault_log_buffer = static_init!([u8; 256], [0; 256]);
+//! let fault_log = static_init!(
+//!     capsules::fault_log::FaultLog<'static, capsules::log::Log<'static, Flash>>,
+//!     capsules::fault_log::FaultLog::new(
+//!         log,
+//!         &kernel::procs::RestartFaultPolicy {},
+//!         fault_log_buffer,
+//!     )
+//! );
+//! log.set_append_client(fault_log);
+//! fault_log.set_client(process_manager);
+//! // Load processes with `fault_log` as their fault policy.
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::log::{LogWrite, LogWriteClient};
+use kernel::procs::{FaultAction, Process, ProcessFaultPolicy};
+use kernel::{ErrorCode, ProcessId};
+
+/// Version of the record format.
+pub const FAULT_RECORD_VERSION: u8 = 1;
+
+/// Length of the fixed part of a record.
+const HEADER_LEN: usize = 12;
+
+/// Told about faults once they are recorded.
+pub trait FaultLogClient {
+    /// `process` faulted, and the kernel took `action`. `result` is whether the
+    /// record of the fault was appended to the log.
+    fn fault_recorded(
+        &self,
+        process: ProcessId,
+        action: FaultAction,
+        result: Result<(), ErrorCode>,
+    );
+}
+
+pub struct FaultLog<'a, L: LogWrite<'a>> {
+    log: &'a L,
+    policy: &'a dyn ProcessFaultPolicy,
+    buffer: TakeCell<'static, [u8]>,
+    /// The fault whose record is being appended.
+    recording: OptionalCell<(ProcessId, FaultAction)>,
+    /// Faults not recorded since the last record.
+    dropped: Cell<u16>,
+    client: OptionalCell<&'a dyn FaultLogClient>,
+}
+
+impl<'a, L: LogWrite<'a>> FaultLog<'a, L> {
+    pub fn new(
+        log: &'a L,
+        policy: &'a dyn ProcessFaultPolicy,
+        buffer: &'static mut [u8],
+    ) -> FaultLog<'a, L> {
+        FaultLog {
+            log,
+            policy,
+            buffer: TakeCell::new(buffer),
+            recording: OptionalCell::empty(),
+            dropped: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn FaultLogClient) {
+        self.client.set(client);
+    }
+
+    fn record(&self, process: &dyn Process, action: FaultAction) {
+        let buffer = match self.buffer.take() {
+            Some(buffer) if buffer.len() >= HEADER_LEN => buffer,
+            buffer => {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.dropped.set(self.dropped.get().saturating_add(1));
+                return;
+            }
+        };
+
+        let processid = process.processid();
+        buffer[0] = FAULT_RECORD_VERSION;
+        buffer[1] = action_code(action);
+        buffer[2..4].copy_from_slice(&self.dropped.get().to_le_bytes());
+        buffer[4..8].copy_from_slice(&(processid.id() as u32).to_le_bytes());
+        buffer[8..12].copy_from_slice(&(process.get_restart_count() as u32).to_le_bytes());
+
+        let mut length = HEADER_LEN;
+        length += write_section(&mut buffer[length..], |out| {
+            let name = process.get_process_name().as_bytes();
+            let name_len = cmp::min(name.len(), out.len());
+            out[..name_len].copy_from_slice(&name[..name_len]);
+            name_len
+        });
+        length += write_section(&mut buffer[length..], |out| {
+            process.debug_fault_status(out).unwrap_or(0)
+        });
+        length += write_section(&mut buffer[length..], |out| {
+            process.debug_stored_context(out).unwrap_or(0)
+        });
+        length += write_section(&mut buffer[length..], |out| {
+            process.debug_stack_excerpt(out)
+        });
+
+        match self.log.append(buffer, length) {
+            Ok(()) => {
+                self.dropped.set(0);
+                self.recording.set((processid, action));
+            }
+            Err((_, buffer)) => {
+                self.buffer.replace(buffer);
+                self.dropped.set(self.dropped.get().saturating_add(1));
+            }
+        }
+    }
+}
+
+/// Write a section of a record to the start of `out`: the length `fill`
+/// returns, followed by the bytes `fill` wrote. Returns the length of the
+/// section.
+fn write_section<F>(out: &mut [u8], fill: F) -> usize
+where
+    F: FnOnce(&mut [u8]) -> usize,
+{
+    if out.len() < 2 {
+        return 0;
+    }
+    let (length, body) = out.split_at_mut(2);
+    let max_len = cmp::min(body.len(), u16::MAX as usize);
+    let written = cmp::min(fill(&mut body[..max_len]), max_len);
+    length.copy_from_slice(&(written as u16).to_le_bytes());
+    2 + written
+}
+
+/// The number a record stores for each fault action.
+pub fn action_code(action: FaultAction) -> u8 {
+    match action {
+        FaultAction::Panic => 0,
+        FaultAction::Restart => 1,
+        FaultAction::Stop => 2,
+    }
+}
+
+impl<'a, L: LogWrite<'a>> ProcessFaultPolicy for FaultLog<'a, L> {
+    fn action(&self, process: &dyn Process) -> FaultAction {
+        let action = self.policy.action(process);
+        // A panic never returns to the kernel loop, so the record would not
+        // be written.
+        if let FaultAction::Panic = action {
+            return action;
+        }
+        self.record(process, action);
+        action
+    }
+}
+
+impl<'a, L: LogWrite<'a>> LogWriteClient for FaultLog<'a, L> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        _records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        if error.is_ok() {
+            // Make the record persistent right away, the device may reset
+            // before the log is synced otherwise.
+            let _ = self.log.sync();
+        }
+        self.recording.take().map(|(process, action)| {
+            self.client
+                .map(|client| client.fault_recorded(process, action, error));
+        });
+    }
+
+    fn sync_done(&self, _error: Result<(), ErrorCode>) {}
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+}
@@ -0,0 +1,192 @@
+//! Give apps the record of the last kernel panic.
+//!
+//! If the board keeps a `kernel::panic_record::PanicRecord` across resets,
+//! this capsule lets an app read it after the device restarted, for example to
+//! report the panic to a server.
+//!
+//! Only one app, the supervisor, may read the record, as the panic message and
+//! the names of the processes may tell an app more about the others than it
+//! should know. The supervisor is identified by the package name in its TBF
+//! header, which the board passes to `LastPanic::new()`, and the driver
+//! behaves as if it did not exist for all other apps. Like for
+//! `capsules::process_manager`, the package name is not authenticated, so
+//! boards that may run apps they do not trust should check the credentials of
+//! the supervisor when they load it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let last_panic = static_init!(
+//!     capsules::last_panic::LastPanic,
+//!     capsules::last_panic::LastPanic::new(
+//!         &io::PANIC_RECORD,
+//!         "app_manager",
+//!         board_kernel.create_grant(&grant_cap),
+//!     )
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Read-write allow `0`: buffer commands `1` and `3` copy text to.
+//! - Command `0`: driver check.
+//! - Command `1`: returns the number of panics recorded, the length of the
+//!   panic message, and the number of stack trace entries, and copies as much
+//!   of the message as fits to the allowed buffer. The number of panics changes
+//!   with each panic. Fails with `FAIL` if there is no record.
+//! - Command `2`: returns the stack trace entry at index `arg1`, newest first.
+//! - Command `3`: returns the state (numbered like in
+//!   `capsules::process_manager`) and the name length of the process at index
+//!   `arg1` at the time of the panic, and copies as much of its name as fits to
+//!   the allowed buffer.
+
+use core::cmp;
+use core::mem;
+
+use kernel::common::cells::OptionalCell;
+use kernel::panic_record::PanicRecord;
+use kernel::{CommandReturn, Driver, ErrorCode, Grant, ProcessId};
+use kernel::{ReadWrite, ReadWriteAppSlice};
+
+use crate::process_manager::state_code;
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::LastPanic as usize;
+
+#[derive(Default)]
+pub struct App {
+    buffer: ReadWriteAppSlice,
+}
+
+pub struct LastPanic {
+    record: &'static PanicRecord,
+    /// Package name of the app that may use this driver.
+    supervisor: &'static str,
+    /// The last process found to be the supervisor.
+    supervisor_id: OptionalCell<ProcessId>,
+    apps: Grant<App>,
+}
+
+impl LastPanic {
+    pub fn new(
+        record: &'static PanicRecord,
+        supervisor: &'static str,
+        grant: Grant<App>,
+    ) -> LastPanic {
+        LastPanic {
+            record,
+            supervisor,
+            supervisor_id: OptionalCell::empty(),
+            apps: grant,
+        }
+    }
+
+    fn is_supervisor(&self, appid: ProcessId) -> bool {
+        if self.supervisor_id.contains(&appid) {
+            return true;
+        }
+        // The supervisor has a new identifier each time it restarts.
+        let supervisor = appid.get_process_name() == Some(self.supervisor);
+        if supervisor {
+            self.supervisor_id.set(appid);
+        }
+        supervisor
+    }
+
+    /// Copy as much of `text` as fits to the buffer of `appid`.
+    fn copy_to_app(&self, appid: ProcessId, text: &str) {
+        let _ = self.apps.enter(appid, |app| {
+            app.buffer.mut_map_or((), |buffer| {
+                let length = cmp::min(buffer.len(), text.len());
+                buffer[..length].copy_from_slice(&text.as_bytes()[..length]);
+            });
+        });
+    }
+}
+
+impl Driver for LastPanic {
+    /// Setup buffer to copy text to.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer for the panic message and process names.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            _ if !self.is_supervisor(appid) => Err(ErrorCode::NODEVICE),
+            0 => self
+                .apps
+                .enter(appid, |app| {
+                    mem::swap(&mut app.buffer, &mut slice);
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Read the last panic.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the panic count, message, and stack trace length.
+    /// - `2`: Get stack trace entry `arg1`.
+    /// - `3`: Get the state and name of process `arg1`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        if !self.is_supervisor(appid) {
+            return CommandReturn::failure(ErrorCode::NODEVICE);
+        }
+
+        match command_num {
+            0 /* This driver exists. */ => CommandReturn::success(),
+
+            1 => match (self.record.count(), self.record.message()) {
+                (Some(count), Some(message)) => {
+                    self.copy_to_app(appid, message);
+                    CommandReturn::success_u32_u32_u32(
+                        count,
+                        message.len() as u32,
+                        self.record.stack_trace().len() as u32,
+                    )
+                }
+                _ => CommandReturn::failure(ErrorCode::FAIL),
+            },
+
+            2 => match self.record.stack_trace().get(arg1) {
+                Some(address) => CommandReturn::success_u32(*address),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            3 => match self.record.process(arg1) {
+                Some((name, state)) => {
+                    self.copy_to_app(appid, name);
+                    CommandReturn::success_u32_u32(state_code(state), name.len() as u32)
+                }
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            _ /* Unknown command num */ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
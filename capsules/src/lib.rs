@@ -14,7 +14,9 @@ pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
+pub mod app_checker_hmac;
 pub mod app_flash_driver;
+pub mod app_loader;
 pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
@@ -25,6 +27,7 @@ pub mod ctap;
 pub mod dac;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fault_log;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -39,6 +42,7 @@ pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod l3gd20;
+pub mod last_panic;
 pub mod led;
 pub mod led_matrix;
 pub mod log;
@@ -50,6 +54,7 @@ pub mod lsm303xx;
 pub mod ltc294x;
 pub mod max17205;
 pub mod mcp230xx;
+pub mod message_ipc;
 pub mod mlx90614;
 pub mod mx25r6435f;
 pub mod ninedof;
@@ -58,7 +63,9 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod process_checkpoint;
 pub mod process_console;
+pub mod process_manager;
 pub mod proximity;
 pub mod rf233;
 pub mod rf233_const;
@@ -92,3 +99,4 @@ pub mod virtual_rng;
 pub mod virtual_spi;
 pub mod virtual_timer;
 pub mod virtual_uart;
+pub mod watchdog_monitor;
@@ -0,0 +1,521 @@
+//! Synchronous message passing between apps.
+//!
+//! This capsule lets an app offer a service that other apps send requests to
+//! and receive replies from, e.g. a crypto service app. Unlike
+//! `kernel::ipc`, the apps do not need to agree on a protocol over a shared
+//! buffer: the kernel copies each request from the client to the service and
+//! each reply back, and queues requests while the service is busy.
+//!
+//! A service registers under the package name in its TBF header. A client
+//! looks the service up by name, sends it a request, and waits (yields) for
+//! the reply. A client has at most one request outstanding. A service handles
+//! one request at a time; up to `QUEUE_LEN` further requests wait in its
+//! grant in the order they were sent.
+//!
+//! Requests are bounded by the size of the buffer the service allows for
+//! them, replies by the size of the buffer the client allows for them (longer
+//! replies are truncated).
+//!
+//! Access Control
+//! --------------
+//!
+//! The board can restrict which clients may use a service with a list of
+//! `ServiceAccess` entries. A service that has an entry only accepts requests
+//! from the clients listed in it, and is not found by other clients. Services
+//! without an entry accept requests from any client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! static SERVICE_ACCESS: [capsules::message_ipc::ServiceAccess; 1] =
+//!     [capsules::message_ipc::ServiceAccess {
+//!         service: "crypto",
+//!         clients: &["wallet", "updater"],
+//!     }];
+//! let message_ipc = static_init!(
+//!     capsules::message_ipc::MessageIpc<ProcessMgmtCap>,
+//!     capsules::message_ipc::MessageIpc::new(
+//!         board_kernel,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &SERVICE_ACCESS,
+//!         ProcessMgmtCap,
+//!     )
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Read-only allow `0`: the request to send (clients).
+//! - Read-only allow `1`: the reply to send (services).
+//! - Read-only allow `2`: the package name of the service to look up.
+//! - Read-write allow `0`: buffer for incoming requests (services).
+//! - Read-write allow `1`: buffer for incoming replies (clients).
+//! - Subscribe `0`: called with the client identifier and the request length
+//!   when a request was copied to the request buffer (services).
+//! - Subscribe `1`: called with the service identifier and the reply length
+//!   when a reply was copied to the reply buffer (clients).
+//! - Command `0`: driver check.
+//! - Command `1`: look up the service named in read-only allow `2`, and return
+//!   its identifier.
+//! - Command `2`: send the first `arg2` bytes of read-only allow `0` to the
+//!   service with identifier `arg1`.
+//! - Command `3`: register as a service.
+//! - Command `4`: reply to the current request with the first `arg1` bytes of
+//!   read-only allow `1`.
+//! - Command `5`: cancel the outstanding request.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::{CommandReturn, Driver, ErrorCode, Grant, Kernel, ProcessId, Upcall};
+use kernel::{Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::MessageIpc as usize;
+
+/// Number of requests that can wait for a busy service.
+pub const QUEUE_LEN: usize = 4;
+
+/// The clients that may use a service.
+pub struct ServiceAccess {
+    /// Package name of the service.
+    pub service: &'static str,
+    /// Package names of the clients that may send requests to the service.
+    pub clients: &'static [&'static str],
+}
+
+#[derive(Default)]
+pub struct App {
+    request_callback: Upcall,
+    reply_callback: Upcall,
+    request: ReadOnlyAppSlice,
+    reply: ReadOnlyAppSlice,
+    service_name: ReadOnlyAppSlice,
+    request_buffer: ReadWriteAppSlice,
+    reply_buffer: ReadWriteAppSlice,
+    /// Whether this app has registered as a service.
+    service: bool,
+    /// The service this app, as a client, is waiting for a reply from, and the
+    /// length of its request.
+    outstanding: Option<(ProcessId, usize)>,
+    /// The client whose request this app, as a service, is handling.
+    current_client: Option<ProcessId>,
+    /// Clients whose requests wait for this app, as a service.
+    queue: [Option<ProcessId>; QUEUE_LEN],
+}
+
+pub struct MessageIpc<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    apps: Grant<App>,
+    access: &'static [ServiceAccess],
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> MessageIpc<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        grant: Grant<App>,
+        access: &'static [ServiceAccess],
+        capability: C,
+    ) -> MessageIpc<C> {
+        MessageIpc {
+            kernel,
+            apps: grant,
+            access,
+            capability,
+        }
+    }
+
+    /// Find the process `predicate` is true for and return its identifier and
+    /// package name.
+    fn find_process<P>(&self, predicate: P) -> Option<(ProcessId, &'static str)>
+    where
+        P: Fn(ProcessId, &'static str) -> bool,
+    {
+        let found = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                let processid = process.processid();
+                let name = process.get_process_name();
+                if found.get().is_none() && predicate(processid, name) {
+                    found.set(Some((processid, name)));
+                }
+            });
+        found.get()
+    }
+
+    fn process_name(&self, appid: ProcessId) -> Option<&'static str> {
+        self.find_process(|processid, _| processid == appid)
+            .map(|(_, name)| name)
+    }
+
+    /// Whether `client` may send requests to the service named `service`.
+    fn allowed(&self, service: &str, client: ProcessId) -> bool {
+        match self.access.iter().find(|access| access.service == service) {
+            Some(access) => self.process_name(client).map_or(false, |client| {
+                access.clients.iter().any(|allowed| *allowed == client)
+            }),
+            None => true,
+        }
+    }
+
+    fn is_service(&self, appid: ProcessId) -> bool {
+        self.apps.enter(appid, |app| app.service).unwrap_or(false)
+    }
+
+    fn lookup(&self, appid: ProcessId) -> Result<u32, ErrorCode> {
+        let (service, name) = self
+            .apps
+            .enter(appid, |app| {
+                app.service_name.map_or(None, |requested| {
+                    self.find_process(|processid, name| {
+                        name.as_bytes() == requested && self.is_service(processid)
+                    })
+                })
+            })
+            .map_err(ErrorCode::from)?
+            .ok_or(ErrorCode::NODEVICE)?;
+        if !self.allowed(name, appid) {
+            return Err(ErrorCode::NODEVICE);
+        }
+        Ok(service.id() as u32)
+    }
+
+    fn send(&self, appid: ProcessId, service_id: usize, length: usize) -> Result<(), ErrorCode> {
+        let (service, name) = self
+            .find_process(|processid, _| processid.id() == service_id)
+            .ok_or(ErrorCode::INVAL)?;
+        if service == appid || !self.is_service(service) {
+            return Err(ErrorCode::INVAL);
+        }
+        if !self.allowed(name, appid) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        let outstanding = self
+            .apps
+            .enter(appid, |app| app.outstanding)
+            .map_err(ErrorCode::from)?;
+        if let Some((previous, _)) = outstanding {
+            // A request to a service that no longer exists will never get a
+            // reply, so it does not keep the client from sending another.
+            if self.apps.enter(previous, |_| ()).is_ok() {
+                return Err(ErrorCode::BUSY);
+            }
+        }
+
+        self.apps
+            .enter(service, |service_app| {
+                let request_buffer_len = service_app.request_buffer.len();
+                self.apps
+                    .enter(appid, |app| {
+                        if length > app.request.len() || length > request_buffer_len {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        // An idle service gets the request from the queue
+                        // right away.
+                        enqueue(&mut service_app.queue, appid)?;
+                        app.outstanding = Some((service, length));
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.deliver(service);
+        Ok(())
+    }
+
+    /// If `service` is idle, give it the next request that waits for it.
+    fn deliver(&self, service: ProcessId) {
+        let _ = self.apps.enter(service, |service_app| {
+            while service_app.current_client.is_none() {
+                let client = match dequeue(&mut service_app.queue) {
+                    Some(client) => client,
+                    None => return,
+                };
+                // Skip clients that no longer exist or cancelled.
+                let _ = self.apps.enter(client, |app| match app.outstanding {
+                    Some((to, length)) if to == service => {
+                        let copied = app.request.map_or(0, |request| {
+                            service_app.request_buffer.mut_map_or(0, |buffer| {
+                                let length =
+                                    cmp::min(length, cmp::min(request.len(), buffer.len()));
+                                buffer[..length].copy_from_slice(&request[..length]);
+                                length
+                            })
+                        });
+                        service_app.current_client = Some(client);
+                        service_app
+                            .request_callback
+                            .schedule(client.id(), copied, 0);
+                    }
+                    _ => {}
+                });
+            }
+        });
+    }
+
+    fn reply(&self, appid: ProcessId, length: usize) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |service_app| {
+                let client = service_app.current_client.take().ok_or(ErrorCode::INVAL)?;
+                if length > service_app.reply.len() {
+                    service_app.current_client = Some(client);
+                    return Err(ErrorCode::SIZE);
+                }
+                // The client may have cancelled the request or gone away, in
+                // which case the reply is dropped.
+                let _ = self.apps.enter(client, |app| match app.outstanding {
+                    Some((to, _)) if to == appid => {
+                        app.outstanding = None;
+                        let copied = service_app.reply.map_or(0, |reply| {
+                            app.reply_buffer.mut_map_or(0, |buffer| {
+                                let length = cmp::min(length, buffer.len());
+                                buffer[..length].copy_from_slice(&reply[..length]);
+                                length
+                            })
+                        });
+                        app.reply_callback.schedule(appid.id(), copied, 0);
+                    }
+                    _ => {}
+                });
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.deliver(appid);
+        Ok(())
+    }
+
+    fn cancel(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let (service, _) = self
+            .apps
+            .enter(appid, |app| app.outstanding.take())
+            .map_err(ErrorCode::from)?
+            .ok_or(ErrorCode::ALREADY)?;
+        // Remove the request from the queue of the service. If the service is
+        // handling it already, its reply is dropped.
+        let _ = self.apps.enter(service, |service_app| {
+            remove(&mut service_app.queue, appid);
+        });
+        Ok(())
+    }
+}
+
+/// Add `client` after the clients waiting in `queue`. Fails with `BUSY` if
+/// the queue is full.
+fn enqueue<T: Copy>(queue: &mut [Option<T>], client: T) -> Result<(), ErrorCode> {
+    let slot = queue
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ErrorCode::BUSY)?;
+    *slot = Some(client);
+    Ok(())
+}
+
+/// Take the first waiting client from `queue`, and move the others up.
+fn dequeue<T: Copy>(queue: &mut [Option<T>]) -> Option<T> {
+    let client = queue.first_mut()?.take();
+    queue.rotate_left(1);
+    client
+}
+
+/// Remove `client` from `queue`, and move the clients after it up.
+fn remove<T: Copy + PartialEq>(queue: &mut [Option<T>], client: T) {
+    let mut waiting = 0;
+    for index in 0..queue.len() {
+        match queue[index].take() {
+            Some(other) if other != client => {
+                queue[waiting] = Some(other);
+                waiting += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability> Driver for MessageIpc<C> {
+    /// Setup outgoing messages.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the request to send.
+    /// - `1`: Set the reply to send.
+    /// - `2`: Set the name of the service to look up.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| {
+                match allow_num {
+                    0 => mem::swap(&mut app.request, &mut slice),
+                    1 => mem::swap(&mut app.reply, &mut slice),
+                    2 => mem::swap(&mut app.service_name, &mut slice),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup buffers for incoming messages.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer for incoming requests.
+    /// - `1`: Set the buffer for incoming replies.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| {
+                match allow_num {
+                    0 => mem::swap(&mut app.request_buffer, &mut slice),
+                    1 => mem::swap(&mut app.reply_buffer, &mut slice),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for incoming requests.
+    /// - `1`: Set the callback for incoming replies.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app| {
+                match subscribe_num {
+                    0 => mem::swap(&mut app.request_callback, &mut callback),
+                    1 => mem::swap(&mut app.reply_callback, &mut callback),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Message passing.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Look up a service by name.
+    /// - `2`: Send `arg2` bytes to the service with identifier `arg1`.
+    /// - `3`: Register as a service.
+    /// - `4`: Reply to the current request with `arg1` bytes.
+    /// - `5`: Cancel the outstanding request.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 /* This driver exists. */ => Ok(()),
+
+            1 => match self.lookup(appid) {
+                Ok(service_id) => return CommandReturn::success_u32(service_id),
+                Err(e) => Err(e),
+            },
+
+            2 => self.send(appid, arg1, arg2),
+
+            3 => self
+                .apps
+                .enter(appid, |app| app.service = true)
+                .map_err(ErrorCode::from),
+
+            4 => self.reply(appid, arg1),
+
+            5 => self.cancel(appid),
+
+            _ /* Unknown command num */ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_serves_clients_in_order() {
+        let mut queue = [None; QUEUE_LEN];
+        for client in 1..=QUEUE_LEN {
+            assert_eq!(enqueue(&mut queue, client), Ok(()));
+        }
+        assert_eq!(enqueue(&mut queue, 9), Err(ErrorCode::BUSY));
+
+        assert_eq!(dequeue(&mut queue), Some(1));
+        assert_eq!(enqueue(&mut queue, 5), Ok(()));
+        assert_eq!(queue, [Some(2), Some(3), Some(4), Some(5)]);
+        for client in 2..=5 {
+            assert_eq!(dequeue(&mut queue), Some(client));
+        }
+        assert_eq!(dequeue(&mut queue), None);
+        assert_eq!(queue, [None; QUEUE_LEN]);
+    }
+
+    #[test]
+    fn queue_closes_the_gap_of_removed_clients() {
+        let mut queue = [Some(1), Some(2), Some(3), None];
+        remove(&mut queue, 2);
+        assert_eq!(queue, [Some(1), Some(3), None, None]);
+        remove(&mut queue, 7);
+        assert_eq!(queue, [Some(1), Some(3), None, None]);
+
+        assert_eq!(enqueue(&mut queue, 4), Ok(()));
+        remove(&mut queue, 1);
+        assert_eq!(queue, [Some(3), Some(4), None, None]);
+        assert_eq!(dequeue(&mut queue), Some(3));
+        assert_eq!(dequeue(&mut queue), Some(4));
+        assert_eq!(dequeue(&mut queue), None);
+    }
+}
@@ -0,0 +1,486 @@
+//! This file contains the encoding and decoding of CoAP messages (RFC 7252,
+//! section 3), and of the Block1 and Block2 options of block-wise transfers
+//! (RFC 7959, section 2.2).
+//!
+//! A CoAP message starts with a fixed 4 byte header and a token of up to 8
+//! bytes, which together make a `CoapHeader`. Options follow, in ascending
+//! order of their number. Each option holds the difference between its number
+//! and the number of the previous option, rather than its number, so options
+//! are encoded with `encode_option()` given the number of the previous one,
+//! and decoded with the `CoapOptions` iterator. The payload, if there is one,
+//! follows a payload marker after the options.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// UDP port of CoAP servers.
+pub const COAP_PORT: u16 = 5683;
+
+/// Codes of the messages, made of a 3 bit class and a 5 bit detail.
+pub mod coap_code {
+    pub const EMPTY: u8 = 0x00;
+
+    // Requests
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    // Responses
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+
+    /// Returns the class of `code`: 0 for requests, 2 for successful
+    /// responses, and 4 and 5 for client and server errors.
+    pub fn class(code: u8) -> u8 {
+        code >> 5
+    }
+
+    pub fn is_request(code: u8) -> bool {
+        code != EMPTY && class(code) == 0
+    }
+
+    pub fn is_response(code: u8) -> bool {
+        class(code) >= 2
+    }
+}
+
+/// Numbers of the options.
+pub mod coap_opt {
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const URI_QUERY: u16 = 15;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+
+    /// Returns whether a message with an option of number `number` that is
+    /// not understood must be rejected.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 != 0
+    }
+}
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+pub const MAX_TOKEN_LEN: usize = 8;
+
+/// Values of the delta and length nibbles of options that mean the value is
+/// in one or two extra bytes.
+const EXT_8BIT: u8 = 13;
+const EXT_16BIT: u8 = 14;
+const EXT_8BIT_BASE: u16 = 13;
+const EXT_16BIT_BASE: u16 = 269;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MessageType {
+    /// Retransmitted until the peer acknowledges or resets it.
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl From<u8> for MessageType {
+    fn from(msg_type: u8) -> Self {
+        match msg_type & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// Token by which responses are matched with requests.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Token {
+    len: u8,
+    bytes: [u8; MAX_TOKEN_LEN],
+}
+
+impl Token {
+    /// Returns None if `bytes` is longer than `MAX_TOKEN_LEN`.
+    pub fn new(bytes: &[u8]) -> Option<Token> {
+        if bytes.len() > MAX_TOKEN_LEN {
+            return None;
+        }
+        let mut token = Token {
+            len: bytes.len() as u8,
+            bytes: [0; MAX_TOKEN_LEN],
+        };
+        token.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(token)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// The fixed header and token of a message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CoapHeader {
+    pub msg_type: MessageType,
+    pub code: u8,
+    /// Matches acknowledgements and resets with the message they answer,
+    /// and detects duplicates.
+    pub message_id: u16,
+    pub token: Token,
+}
+
+impl CoapHeader {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let first = VERSION << 6 | (self.msg_type as u8) << 4 | self.token.len;
+        let off = enc_consume!(buf, 0; encode_u8, first);
+        let off = enc_consume!(buf, off; encode_u8, self.code);
+        let off = enc_consume!(buf, off; encode_u16, self.message_id);
+        let off = enc_consume!(buf, off; encode_bytes, self.token.as_bytes());
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<CoapHeader> {
+        let (off, first) = dec_try!(buf; decode_u8);
+        stream_cond!(first >> 6 == VERSION);
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(token_len <= MAX_TOKEN_LEN);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        let mut token = Token::default();
+        token.len = token_len as u8;
+        let off = dec_consume!(buf, off; decode_bytes, &mut token.bytes[..token_len]);
+        // Empty messages are only a header.
+        stream_cond!(code != coap_code::EMPTY || (token_len == 0 && off == buf.len()));
+        stream_done!(
+            off,
+            CoapHeader {
+                msg_type: MessageType::from(first >> 4),
+                code: code,
+                message_id: message_id,
+                token: token,
+            }
+        );
+    }
+}
+
+/// Splits a delta or length into its nibble and its extra bytes.
+fn option_nibble(value: u16) -> (u8, usize, u16) {
+    if value < EXT_8BIT_BASE {
+        (value as u8, 0, 0)
+    } else if value < EXT_16BIT_BASE {
+        (EXT_8BIT, 1, value - EXT_8BIT_BASE)
+    } else {
+        (EXT_16BIT, 2, value - EXT_16BIT_BASE)
+    }
+}
+
+fn encode_extended(buf: &mut [u8], len: usize, value: u16) -> SResult {
+    match len {
+        0 => stream_done!(0),
+        1 => stream_done!(enc_consume!(buf; encode_u8, value as u8)),
+        _ => stream_done!(enc_consume!(buf; encode_u16, value)),
+    }
+}
+
+fn decode_extended(buf: &[u8], nibble: u8) -> SResult<u16> {
+    match nibble {
+        EXT_8BIT => {
+            let (off, value) = dec_try!(buf; decode_u8);
+            stream_done!(off, value as u16 + EXT_8BIT_BASE);
+        }
+        EXT_16BIT => {
+            let (off, value) = dec_try!(buf; decode_u16);
+            stream_cond!(value <= u16::MAX - EXT_16BIT_BASE);
+            stream_done!(off, value + EXT_16BIT_BASE);
+        }
+        // The nibble 15 is reserved for the payload marker.
+        15 => stream_err!(),
+        _ => stream_done!(0, nibble as u16),
+    }
+}
+
+/// Serializes option `number` with `value`, after option `prev`, which is 0
+/// for the first option. `number` must not be less than `prev`.
+pub fn encode_option(buf: &mut [u8], prev: u16, number: u16, value: &[u8]) -> SResult<usize> {
+    stream_cond!(number >= prev && value.len() <= u16::MAX as usize);
+    let (delta, delta_len, delta_ext) = option_nibble(number - prev);
+    let (len, len_len, len_ext) = option_nibble(value.len() as u16);
+    let off = enc_consume!(buf; encode_u8, delta << 4 | len);
+    let off = enc_consume!(buf, off; encode_extended, delta_len, delta_ext);
+    let off = enc_consume!(buf, off; encode_extended, len_len, len_ext);
+    let off = enc_consume!(buf, off; encode_bytes, value);
+    stream_done!(off, off);
+}
+
+/// Serializes option `number` with an unsigned integer value, in as few
+/// bytes as it takes.
+pub fn encode_uint_option(buf: &mut [u8], prev: u16, number: u16, value: u32) -> SResult<usize> {
+    let bytes = value.to_be_bytes();
+    let len = (32 - value.leading_zeros() as usize + 7) / 8;
+    encode_option(buf, prev, number, &bytes[bytes.len() - len..])
+}
+
+/// Serializes the payload marker and `payload`, unless it is empty.
+pub fn encode_payload(buf: &mut [u8], payload: &[u8]) -> SResult<usize> {
+    if payload.is_empty() {
+        stream_done!(0, 0);
+    }
+    let off = enc_consume!(buf; encode_u8, PAYLOAD_MARKER);
+    let off = enc_consume!(buf, off; encode_bytes, payload);
+    stream_done!(off, off);
+}
+
+/// Returns the unsigned integer value of an option.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, &b| acc << 8 | b as u32))
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CoapOption<'a> {
+    pub number: u16,
+    pub value: &'a [u8],
+}
+
+/// Iterator over the options of a message. It stops at the payload marker,
+/// or at the first option that cannot be decoded.
+#[derive(Copy, Clone)]
+pub struct CoapOptions<'a> {
+    buf: &'a [u8],
+    number: u16,
+    /// Whether the options were decoded up to the payload marker, or the
+    /// end of the message.
+    valid: bool,
+}
+
+impl<'a> CoapOptions<'a> {
+    /// `buf` holds the rest of the message after the header.
+    pub fn new(buf: &'a [u8]) -> CoapOptions<'a> {
+        CoapOptions {
+            buf: buf,
+            number: 0,
+            valid: true,
+        }
+    }
+
+    /// Returns the payload of the message, which is empty if it has none.
+    /// Returns None if an option cannot be decoded, or if a payload marker
+    /// is followed by no payload.
+    pub fn payload(mut self) -> Option<&'a [u8]> {
+        while self.next().is_some() {}
+        if !self.valid {
+            return None;
+        }
+        match self.buf.split_first() {
+            None => Some(&[]),
+            Some((_, [])) => None,
+            Some((_, payload)) => Some(payload),
+        }
+    }
+
+    fn decode(&self) -> SResult<CoapOption<'a>> {
+        let buf = self.buf;
+        let (off, first) = dec_try!(buf; decode_u8);
+        let (off, delta) = dec_try!(buf, off; decode_extended, first >> 4);
+        let (off, len) = dec_try!(buf, off; decode_extended, first & 0xf);
+        let end = off + len as usize;
+        stream_len_cond!(buf, end);
+        stream_cond!(self.number as u32 + delta as u32 <= u16::MAX as u32);
+        stream_done!(
+            end,
+            CoapOption {
+                number: self.number + delta,
+                value: &buf[off..end],
+            }
+        );
+    }
+}
+
+impl<'a> Iterator for CoapOptions<'a> {
+    type Item = CoapOption<'a>;
+
+    fn next(&mut self) -> Option<CoapOption<'a>> {
+        if self.buf.first().map_or(true, |&b| b == PAYLOAD_MARKER) {
+            return None;
+        }
+        match self.decode().done() {
+            Some((len, option)) => {
+                self.buf = &self.buf[len..];
+                self.number = option.number;
+                Some(option)
+            }
+            None => {
+                self.buf = &[];
+                self.valid = false;
+                None
+            }
+        }
+    }
+}
+
+/// The value of a Block1 or Block2 option: the number of a block of a
+/// payload split into blocks of 2^(`szx` + 4) bytes, and whether more blocks
+/// follow it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    pub szx: u8,
+}
+
+impl Block {
+    /// Largest size exponent, for blocks of 1024 bytes.
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// Returns the offset of the block in the payload.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    pub fn to_uint(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    /// Returns None if the size exponent is the reserved value 7, or the
+    /// block number does not fit in 20 bits.
+    pub fn from_uint(value: &[u8]) -> Option<Block> {
+        if value.len() > 3 {
+            return None;
+        }
+        let value = decode_uint(value)?;
+        let szx = (value & 0x7) as u8;
+        if szx > Block::MAX_SZX {
+            return None;
+        }
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx: szx,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trips() {
+        let mut buf = [0; 512];
+        let header = CoapHeader {
+            msg_type: MessageType::NonConfirmable,
+            code: coap_code::POST,
+            message_id: 0x1234,
+            token: Token::new(&[1, 2, 3]).unwrap(),
+        };
+        let mut len = header.encode(&mut buf).done().unwrap().0;
+        assert_eq!(&buf[..len], &[0x53, 0x02, 0x12, 0x34, 1, 2, 3]);
+
+        // Deltas and lengths that take no, one and two extra bytes.
+        let long = [b'x'; 300];
+        let start = len;
+        len += encode_option(&mut buf[len..], 0, coap_opt::URI_PATH, b"a")
+            .done()
+            .unwrap()
+            .0;
+        assert_eq!(&buf[start..len], &[0xb1, b'a']);
+        let start = len;
+        len += encode_option(&mut buf[len..], 11, 24, &long[..20])
+            .done()
+            .unwrap()
+            .0;
+        assert_eq!(&buf[start..start + 3], &[0xdd, 0, 7]);
+        let start = len;
+        len += encode_option(&mut buf[len..], 24, 400, &long)
+            .done()
+            .unwrap()
+            .0;
+        assert_eq!(&buf[start..start + 5], &[0xee, 0, 107, 0, 31]);
+        len += encode_uint_option(&mut buf[len..], 400, 400, 0x10000)
+            .done()
+            .unwrap()
+            .0;
+        len += encode_payload(&mut buf[len..], b"hi").done().unwrap().0;
+
+        let (offset, decoded) = CoapHeader::decode(&buf[..len]).done().unwrap();
+        assert_eq!(decoded, header);
+        let options = CoapOptions::new(&buf[offset..len]);
+        let expected = [
+            CoapOption {
+                number: coap_opt::URI_PATH,
+                value: b"a",
+            },
+            CoapOption {
+                number: 24,
+                value: &long[..20],
+            },
+            CoapOption {
+                number: 400,
+                value: &long,
+            },
+            CoapOption {
+                number: 400,
+                value: &[1, 0, 0],
+            },
+        ];
+        assert!(options.eq(expected.iter().copied()));
+        assert_eq!(options.payload(), Some(&b"hi"[..]));
+    }
+
+    #[test]
+    fn payload_markers() {
+        // A payload marker needs a payload, and the nibble 15 is reserved for
+        // the marker.
+        assert_eq!(CoapOptions::new(&[0xff]).payload(), None);
+        assert_eq!(CoapOptions::new(&[0xf1, 0]).payload(), None);
+        assert_eq!(CoapOptions::new(&[]).payload(), Some(&[][..]));
+    }
+
+    #[test]
+    fn decode_rejects_malformed_headers() {
+        // Version 1 only, tokens of up to 8 bytes, and empty messages without
+        // a token.
+        assert!(CoapHeader::decode(&[0x80, 0x01, 0, 0]).done().is_none());
+        assert!(
+            CoapHeader::decode(&[0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+                .done()
+                .is_none()
+        );
+        assert!(CoapHeader::decode(&[0x41, 0x00, 0, 0, 0]).done().is_none());
+        assert!(CoapHeader::decode(&[0x60, 0x00, 0, 7]).done().is_some());
+    }
+
+    #[test]
+    fn block_options() {
+        let block = Block {
+            num: 4,
+            more: true,
+            szx: 6,
+        };
+        assert_eq!(block.to_uint(), 0x4e);
+        assert_eq!(block.size(), 1024);
+        assert_eq!(block.offset(), 4096);
+        assert_eq!(Block::from_uint(&[0x4e]), Some(block));
+        assert_eq!(Block::from_uint(&[]).map(|block| block.size()), Some(16));
+        assert_eq!(Block::from_uint(&[0x0f]), None);
+    }
+}
@@ -0,0 +1,778 @@
+//! This file contains the definition and implementation of `CoapNode`, a CoAP
+//! client (RFC 7252) that sends one request at a time to a server and hands
+//! the response to a `CoapClient`:
+//!
+//! - Requests are sent in Confirmable messages, and retransmitted with
+//!   exponential back-off until the server acknowledges them. A request
+//!   fails with NOACK if the server never acknowledges it, or never sends
+//!   the response it promised with an empty Acknowledgement.
+//! - Responses are matched with the request by their token. The node
+//!   acknowledges Confirmable responses, and resets responses it does not
+//!   expect.
+//! - Payloads larger than a block are sent in Block1 blocks, and responses
+//!   the server splits in Block2 blocks are fetched block by block (RFC
+//!   7959). The client gets each block of the response as it arrives.
+//! - A GET request can register the node as an observer of the resource
+//!   (RFC 7641). The client then gets the first block of the fresh
+//!   notifications, until the server ends the observation or the client
+//!   cancels it. Cancelling is passive: the node resets the next
+//!   notification.
+//!
+//! The node is not a server. It answers requests with 4.04 Not Found, and
+//! pings with a Reset.
+//!
+//! The node sends messages with a `UDPSender`, and receives them with a
+//! `UDPReceiver` bound to the same port, usually `COAP_PORT`. The CoAP
+//! component (`components::coap`) sets this up.
+
+use crate::net::coap::{coap_code, coap_opt};
+use crate::net::coap::{decode_uint, encode_option, encode_payload, encode_uint_option};
+use crate::net::coap::{Block, CoapHeader, CoapOptions, MessageType, Token, MAX_TOKEN_LEN};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::rng::Random;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ErrorCode;
+
+/// Time to wait for the acknowledgement of the first transmission of a
+/// message, which is randomized up to ACK_TIMEOUT * ACK_RANDOM_FACTOR
+/// (Section 4.8).
+const ACK_TIMEOUT_MS: u32 = 2000;
+const ACK_RANDOM_MS: u32 = 1000;
+const MAX_RETRANSMIT: u8 = 4;
+
+/// Time to wait for a separate response once the server acknowledged the
+/// request, MAX_TRANSMIT_WAIT (Section 4.8.2).
+const SEPARATE_RESPONSE_TIMEOUT_S: u32 = 93;
+
+/// Time after which a notification is fresh whatever its sequence number
+/// (RFC 7641, Section 3.4).
+const NOTIFICATION_FRESHNESS_S: u32 = 128;
+
+/// Size exponent of the blocks the payload of requests is sent in, for
+/// blocks of 128 bytes.
+const BLOCK_SZX: u8 = 3;
+
+/// Length of the tokens of requests.
+const TOKEN_LEN: usize = 4;
+
+/// Maximum length of a Uri-Path or Uri-Query option.
+const MAX_URI_OPTION_LEN: usize = 255;
+
+/// Receives the outcome of the requests of a `CoapNode`, and the
+/// notifications of the resource it observes.
+pub trait CoapClient {
+    /// A response to the current request arrived. `payload` is at `offset`
+    /// in the payload of the whole response, which is not 0 for the blocks
+    /// after the first.
+    fn response(&self, code: u8, offset: usize, payload: &[u8]);
+
+    /// The current request is complete. Returns the code of the last
+    /// response, or an error if there was no response.
+    fn request_done(&self, result: Result<u8, ErrorCode>);
+
+    /// A notification of the observed resource arrived. `payload` is its
+    /// first block. `last` is set if the notification ends the observation.
+    fn notification(&self, code: u8, payload: &[u8], last: bool);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Exchange {
+    /// The message of the request was not acknowledged yet.
+    AwaitingAck,
+    /// The server acknowledged the message, and sends the response later.
+    AwaitingResponse,
+}
+
+/// The current request, and the message that carries it.
+#[derive(Copy, Clone)]
+struct Request {
+    method: u8,
+    dst: IPAddr,
+    dst_port: u16,
+    /// Whether to register as an observer of the resource.
+    observe: bool,
+    /// Lengths of the path and payload in their buffers.
+    path_len: usize,
+    payload_len: usize,
+    /// Block of the payload the message carries, if the payload is sent in
+    /// blocks.
+    block1: Option<Block>,
+    /// Block of the response the message asks for, once the response is
+    /// fetched in blocks.
+    block2: Option<Block>,
+    message_id: u16,
+    token: Token,
+    exchange: Exchange,
+    retransmissions: u8,
+    timeout_ms: u32,
+}
+
+impl Request {
+    fn encode(&self, buf: &mut [u8], path: &[u8], payload: &[u8]) -> SResult<usize> {
+        let header = CoapHeader {
+            msg_type: MessageType::Confirmable,
+            code: self.method,
+            message_id: self.message_id,
+            token: self.token,
+        };
+        let mut off = enc_consume!(buf; header; encode);
+        let mut prev = 0;
+        // Requests for the blocks after the first do not register again
+        // (RFC 7959, Section 3.4).
+        if self.observe && self.block2.is_none() {
+            off = enc_consume!(buf, off; encode_uint_option, prev, coap_opt::OBSERVE, 0);
+            prev = coap_opt::OBSERVE;
+        }
+        let (segments, queries) = split_path(path);
+        for segment in segments.split(|&b| b == b'/').filter(|s| !s.is_empty()) {
+            off = enc_consume!(buf, off; encode_option, prev, coap_opt::URI_PATH, segment);
+            prev = coap_opt::URI_PATH;
+        }
+        for query in queries.split(|&b| b == b'&').filter(|s| !s.is_empty()) {
+            off = enc_consume!(buf, off; encode_option, prev, coap_opt::URI_QUERY, query);
+            prev = coap_opt::URI_QUERY;
+        }
+        if let Some(block2) = self.block2 {
+            off = enc_consume!(buf, off; encode_uint_option, prev, coap_opt::BLOCK2, block2.to_uint());
+            prev = coap_opt::BLOCK2;
+        }
+        let body = match self.block1 {
+            Some(block1) => {
+                off = enc_consume!(buf, off; encode_uint_option, prev, coap_opt::BLOCK1, block1.to_uint());
+                let start = block1.offset();
+                &payload[start..cmp::min(start + block1.size(), payload.len())]
+            }
+            None => payload,
+        };
+        let off = enc_consume!(buf, off; encode_payload, body);
+        stream_done!(off, off);
+    }
+}
+
+/// The options of a response the node makes use of.
+#[derive(Copy, Clone, Default)]
+struct ResponseOptions {
+    observe: Option<u32>,
+    block1: Option<Block>,
+    block2: Option<Block>,
+}
+
+impl ResponseOptions {
+    /// Returns None if an option cannot be decoded, or if an option the node
+    /// does not understand must not be ignored.
+    fn parse(options: CoapOptions) -> Option<ResponseOptions> {
+        let mut parsed = ResponseOptions::default();
+        for option in options {
+            match option.number {
+                coap_opt::OBSERVE => {
+                    parsed.observe = Some(decode_uint(option.value).filter(|&v| v < 1 << 24)?)
+                }
+                coap_opt::BLOCK1 => parsed.block1 = Some(Block::from_uint(option.value)?),
+                coap_opt::BLOCK2 => parsed.block2 = Some(Block::from_uint(option.value)?),
+                number if coap_opt::is_critical(number) => return None,
+                _ => {}
+            }
+        }
+        Some(parsed)
+    }
+}
+
+/// The resource the node observes.
+#[derive(Copy, Clone)]
+struct Observation {
+    addr: IPAddr,
+    port: u16,
+    token: Token,
+    /// Sequence number of the freshest notification.
+    sequence: u32,
+}
+
+/// Returns whether the notification with sequence number `v2` is newer than
+/// the one with `v1` (RFC 7641, Section 3.4).
+fn is_newer(v1: u32, v2: u32) -> bool {
+    (v1 < v2 && v2 - v1 < 1 << 23) || (v1 > v2 && v1 - v2 > 1 << 23)
+}
+
+/// Splits a path into its segments, separated by '/', and its query, after
+/// '?' and with arguments separated by '&'.
+fn split_path(path: &[u8]) -> (&[u8], &[u8]) {
+    match path.iter().position(|&b| b == b'?') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => (path, &[]),
+    }
+}
+
+/// Returns an upper bound of the length of a request for `path` with
+/// `body_len` bytes of payload.
+fn max_request_len(path: &[u8], body_len: usize) -> usize {
+    let (segments, queries) = split_path(path);
+    let uri_options =
+        segments.split(|&b| b == b'/').count() + queries.split(|&b| b == b'&').count();
+    // The Uri-Path and Uri-Query options take at most 3 bytes besides
+    // their value, and the Observe, Block2 and Block1 options at most 6
+    // bytes with theirs.
+    4 + MAX_TOKEN_LEN + 3 * uri_options + path.len() + 3 * 6 + 1 + body_len
+}
+
+pub struct CoapNode<'a, A: Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Random<'a>,
+    client: OptionalCell<&'a dyn CoapClient>,
+    /// Buffer messages are built in. The `UDPSender` holds it while it
+    /// sends a message.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    tx_len: usize,
+    /// Path and payload of the current request, kept for the
+    /// retransmissions and the blocks after the first.
+    path_buffer: TakeCell<'static, [u8]>,
+    payload_buffer: TakeCell<'static, [u8]>,
+    request: OptionalCell<Request>,
+    /// Whether to send the message of the current request.
+    request_pending: Cell<bool>,
+    /// Acknowledgement or Reset to send, and its destination.
+    reply: OptionalCell<(IPAddr, u16, CoapHeader)>,
+    /// Answer to the last Confirmable message, sent again if the message is
+    /// retransmitted.
+    last_reply: OptionalCell<(IPAddr, u16, CoapHeader)>,
+    /// The observed resource, and when its freshest notification arrived.
+    observation: OptionalCell<(Observation, A::Ticks)>,
+    /// Message ID of the next message, from a random start.
+    message_id: OptionalCell<u16>,
+    /// Reference and duration of the timer.
+    timer: OptionalCell<(A::Ticks, A::Ticks)>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> CoapNode<'a, A> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Random<'a>,
+        tx_buffer: &'static mut [u8],
+        path_buffer: &'static mut [u8],
+        payload_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> CoapNode<'a, A> {
+        CoapNode {
+            udp_sender: udp_sender,
+            alarm: alarm,
+            rng: rng,
+            client: OptionalCell::empty(),
+            tx_len: tx_buffer.len(),
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            path_buffer: TakeCell::new(path_buffer),
+            payload_buffer: TakeCell::new(payload_buffer),
+            request: OptionalCell::empty(),
+            request_pending: Cell::new(false),
+            reply: OptionalCell::empty(),
+            last_reply: OptionalCell::empty(),
+            observation: OptionalCell::empty(),
+            message_id: OptionalCell::empty(),
+            timer: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn CoapClient) {
+        self.client.set(client);
+    }
+
+    /// Sends a `method` request for `path` to `dst_port` on `dst`, with
+    /// `payload`. The path is relative to the root of the server, and may
+    /// end with a query after '?'. If `observe` is set, the GET request
+    /// registers the node as an observer of the resource, instead of the
+    /// resource it observes.
+    ///
+    /// Returns BUSY if a request is in progress, INVAL if `method` is not a
+    /// request code or `observe` is set for another method than GET, and
+    /// SIZE if the path or payload do not fit in the buffers of the node.
+    pub fn request(
+        &self,
+        method: u8,
+        dst: IPAddr,
+        dst_port: u16,
+        path: &[u8],
+        payload: &[u8],
+        observe: bool,
+    ) -> Result<(), ErrorCode> {
+        if self.request.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !coap_code::is_request(method) || (observe && method != coap_code::GET) {
+            return Err(ErrorCode::INVAL);
+        }
+        let (segments, queries) = split_path(path);
+        if segments
+            .split(|&b| b == b'/')
+            .chain(queries.split(|&b| b == b'&'))
+            .any(|s| s.len() > MAX_URI_OPTION_LEN)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        let first_block = Block {
+            num: 0,
+            more: true,
+            szx: BLOCK_SZX,
+        };
+        let block1 = if payload.len() > first_block.size() {
+            Some(first_block)
+        } else {
+            None
+        };
+        let body_len = cmp::min(payload.len(), first_block.size());
+        if max_request_len(path, body_len) > self.tx_len {
+            return Err(ErrorCode::SIZE);
+        }
+        let copy = |buffer: &TakeCell<'static, [u8]>, bytes: &[u8]| {
+            buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+                if bytes.len() > buf.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                buf[..bytes.len()].copy_from_slice(bytes);
+                Ok(())
+            })
+        };
+        copy(&self.path_buffer, path)?;
+        copy(&self.payload_buffer, payload)?;
+        self.send_message(Request {
+            method: method,
+            dst: dst,
+            dst_port: dst_port,
+            observe: observe,
+            path_len: path.len(),
+            payload_len: payload.len(),
+            block1: block1,
+            block2: None,
+            message_id: 0,
+            token: Token::default(),
+            exchange: Exchange::AwaitingAck,
+            retransmissions: 0,
+            timeout_ms: 0,
+        });
+        Ok(())
+    }
+
+    /// Returns whether the node observes a resource.
+    pub fn observing(&self) -> bool {
+        self.observation.is_some()
+    }
+
+    /// Stops observing the resource, or stops the current request from
+    /// registering as an observer. Returns ALREADY if the node observes no
+    /// resource.
+    pub fn cancel_observe(&self) -> Result<(), ErrorCode> {
+        let registering = self.request.extract().filter(|request| request.observe);
+        if let Some(mut request) = registering {
+            request.observe = false;
+            self.request.set(request);
+        }
+        if self.observation.take().is_none() && registering.is_none() {
+            return Err(ErrorCode::ALREADY);
+        }
+        Ok(())
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let message_id = self
+            .message_id
+            .extract()
+            .unwrap_or_else(|| self.rng.random() as u16);
+        self.message_id.set(message_id.wrapping_add(1));
+        message_id
+    }
+
+    fn start_timer(&self, dt: A::Ticks) {
+        self.timer.set((self.alarm.now(), dt));
+        let dt = cmp::max(dt.into_u32(), self.alarm.minimum_dt().into_u32());
+        self.alarm.set_alarm(self.alarm.now(), A::Ticks::from(dt));
+    }
+
+    /// Sends `request` in a new message, and sets the timer for its
+    /// acknowledgement.
+    fn send_message(&self, mut request: Request) {
+        let random = self.rng.random();
+        request.message_id = self.next_message_id();
+        request.token = Token::new(&random.to_be_bytes()[..TOKEN_LEN]).unwrap_or_default();
+        request.exchange = Exchange::AwaitingAck;
+        request.retransmissions = 0;
+        request.timeout_ms = ACK_TIMEOUT_MS + (random >> 8) % ACK_RANDOM_MS;
+        self.request.set(request);
+        self.start_timer(A::ticks_from_ms(request.timeout_ms));
+        self.request_pending.set(true);
+        self.transmit();
+    }
+
+    /// Ends the current request.
+    fn finish(&self, result: Result<u8, ErrorCode>) {
+        self.timer.clear();
+        self.request.clear();
+        self.request_pending.set(false);
+        self.client.map(|client| client.request_done(result));
+    }
+
+    /// Sends `reply` to `dst_port` on `dst`. If it answers a Confirmable
+    /// message, it is sent again when the message is retransmitted.
+    fn send_reply(&self, dst: IPAddr, dst_port: u16, reply: CoapHeader, confirmable: bool) {
+        if confirmable {
+            self.last_reply.set((dst, dst_port, reply));
+        }
+        self.reply.set((dst, dst_port, reply));
+        self.transmit();
+    }
+
+    /// Acknowledges `header` if it is Confirmable.
+    fn acknowledge(&self, dst: IPAddr, dst_port: u16, header: &CoapHeader) {
+        if header.msg_type == MessageType::Confirmable {
+            let ack = CoapHeader {
+                msg_type: MessageType::Acknowledgement,
+                code: coap_code::EMPTY,
+                message_id: header.message_id,
+                token: Token::default(),
+            };
+            self.send_reply(dst, dst_port, ack, true);
+        }
+    }
+
+    fn reset(&self, dst: IPAddr, dst_port: u16, header: &CoapHeader) {
+        let rst = CoapHeader {
+            msg_type: MessageType::Reset,
+            code: coap_code::EMPTY,
+            message_id: header.message_id,
+            token: Token::default(),
+        };
+        self.send_reply(
+            dst,
+            dst_port,
+            rst,
+            header.msg_type == MessageType::Confirmable,
+        );
+    }
+
+    /// Writes the next message into `buf`, and returns its destination and
+    /// length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, u16, usize)> {
+        if let Some((dst, dst_port, reply)) = self.reply.take() {
+            let (len, _) = reply.encode(buf).done()?;
+            return Some((dst, dst_port, len));
+        }
+        if !self.request_pending.take() {
+            return None;
+        }
+        let request = self.request.extract()?;
+        let encoded = self.path_buffer.map_or(None, |path| {
+            self.payload_buffer.map_or(None, |payload| {
+                request
+                    .encode(
+                        buf,
+                        &path[..request.path_len],
+                        &payload[..request.payload_len],
+                    )
+                    .done()
+            })
+        });
+        match encoded {
+            Some((len, _)) => Some((request.dst, request.dst_port, len)),
+            None => {
+                debug!("[CoAP] request too long");
+                None
+            }
+        }
+    }
+
+    /// Sends the next message, if the `UDPSender` is idle.
+    fn transmit(&self) {
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        buf.reset();
+        match self.next_message(&mut buf[..]) {
+            Some((dst, dst_port, len)) => {
+                buf.slice(0..len);
+                if let Err(mut buf) = self.udp_sender.send_to(dst, dst_port, buf, self.net_cap) {
+                    debug!("[CoAP] send failed");
+                    buf.reset();
+                    self.tx_buffer.replace(buf);
+                }
+            }
+            None => {
+                self.tx_buffer.replace(buf);
+            }
+        }
+    }
+
+    /// Handles an Acknowledgement or Reset, which may answer the message of
+    /// the current request.
+    fn reply_arrived(
+        &self,
+        src: IPAddr,
+        src_port: u16,
+        header: &CoapHeader,
+        options: CoapOptions,
+        body: &[u8],
+    ) {
+        let mut request = match self.request.extract() {
+            Some(request)
+                if request.exchange == Exchange::AwaitingAck
+                    && request.message_id == header.message_id
+                    && request.dst == src
+                    && request.dst_port == src_port =>
+            {
+                request
+            }
+            _ => return,
+        };
+        if header.msg_type == MessageType::Reset {
+            self.finish(Err(ErrorCode::FAIL));
+        } else if header.code == coap_code::EMPTY {
+            // The response follows in a message of its own.
+            request.exchange = Exchange::AwaitingResponse;
+            self.request.set(request);
+            self.start_timer(A::ticks_from_seconds(SEPARATE_RESPONSE_TIMEOUT_S));
+        } else if coap_code::is_response(header.code) && header.token == request.token {
+            if let Some(options) = ResponseOptions::parse(options) {
+                self.response_arrived(request, header.code, options, body);
+            }
+        }
+    }
+
+    /// Handles a Confirmable or Non-confirmable response, which may be the
+    /// separate response to the current request or a notification.
+    fn message_arrived(
+        &self,
+        src: IPAddr,
+        src_port: u16,
+        header: &CoapHeader,
+        options: CoapOptions,
+        body: &[u8],
+    ) {
+        let options = match ResponseOptions::parse(options) {
+            Some(options) => options,
+            None => return self.reset(src, src_port, header),
+        };
+        let request = self.request.extract().filter(|request| {
+            request.token == header.token && request.dst == src && request.dst_port == src_port
+        });
+        let observation = self.observation.extract().filter(|(observation, _)| {
+            observation.token == header.token
+                && observation.addr == src
+                && observation.port == src_port
+        });
+        if let Some(request) = request {
+            self.acknowledge(src, src_port, header);
+            self.response_arrived(request, header.code, options, body);
+        } else if let Some(observation) = observation {
+            self.acknowledge(src, src_port, header);
+            self.notification_arrived(observation, header.code, options, body);
+        } else {
+            self.reset(src, src_port, header);
+        }
+    }
+
+    /// Handles the response to the message of the current request: sends
+    /// the next block of the payload, or asks for the next block of the
+    /// response, or completes the request.
+    fn response_arrived(
+        &self,
+        mut request: Request,
+        code: u8,
+        options: ResponseOptions,
+        body: &[u8],
+    ) {
+        self.timer.clear();
+        self.request_pending.set(false);
+
+        if let (Some(sent), Some(acked)) = (request.block1, options.block1) {
+            if code == coap_code::CONTINUE && sent.more {
+                // The server may ask for smaller blocks than the ones sent
+                // (RFC 7959, Section 2.5).
+                let szx = cmp::min(sent.szx, acked.szx);
+                let offset = sent.offset() + sent.size();
+                let size = 1 << (szx + 4);
+                request.block1 = Some(Block {
+                    num: (offset / size) as u32,
+                    more: offset + size < request.payload_len,
+                    szx: szx,
+                });
+                return self.send_message(request);
+            }
+        }
+
+        let expected = request.block2.map_or(0, |block| block.offset());
+        let offset = options.block2.map_or(0, |block| block.offset());
+        if offset != expected {
+            return self.finish(Err(ErrorCode::FAIL));
+        }
+        self.client
+            .map(|client| client.response(code, offset, body));
+
+        if request.observe && request.block2.is_none() && coap_code::class(code) == 2 {
+            if let Some(sequence) = options.observe {
+                let observation = Observation {
+                    addr: request.dst,
+                    port: request.dst_port,
+                    token: request.token,
+                    sequence: sequence,
+                };
+                self.observation.set((observation, self.alarm.now()));
+            }
+        }
+
+        // Only GET requests fetch the rest of the response.
+        let next = options.block2.filter(|block| {
+            block.more && request.method == coap_code::GET && coap_code::class(code) == 2
+        });
+        match next {
+            Some(block) => {
+                request.block1 = None;
+                request.block2 = Some(Block {
+                    num: block.num + 1,
+                    more: false,
+                    szx: block.szx,
+                });
+                self.send_message(request);
+            }
+            None => self.finish(Ok(code)),
+        }
+    }
+
+    fn notification_arrived(
+        &self,
+        (mut observation, received): (Observation, A::Ticks),
+        code: u8,
+        options: ResponseOptions,
+        body: &[u8],
+    ) {
+        let sequence = match options.observe {
+            Some(sequence) if coap_code::class(code) == 2 => sequence,
+            _ => {
+                // The server ended the observation.
+                self.observation.clear();
+                self.client
+                    .map(|client| client.notification(code, body, true));
+                return;
+            }
+        };
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(received).into_u32();
+        if !is_newer(observation.sequence, sequence)
+            && elapsed <= A::ticks_from_seconds(NOTIFICATION_FRESHNESS_S).into_u32()
+        {
+            return;
+        }
+        observation.sequence = sequence;
+        self.observation.set((observation, now));
+        self.client
+            .map(|client| client.notification(code, body, false));
+    }
+
+    /// Called when the timer expires.
+    fn timer_expired(&self) {
+        self.timer.clear();
+        let mut request = match self.request.extract() {
+            Some(request) => request,
+            None => return,
+        };
+        if request.exchange == Exchange::AwaitingAck && request.retransmissions < MAX_RETRANSMIT {
+            request.retransmissions += 1;
+            request.timeout_ms *= 2;
+            self.request.set(request);
+            self.start_timer(A::ticks_from_ms(request.timeout_ms));
+            self.request_pending.set(true);
+            self.transmit();
+        } else {
+            self.finish(Err(ErrorCode::NOACK));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for CoapNode<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let (offset, header) = match CoapHeader::decode(payload).done() {
+            Some(header) => header,
+            None => return,
+        };
+        let confirmable = header.msg_type == MessageType::Confirmable;
+        if confirmable {
+            // A retransmission of the last Confirmable message gets the
+            // same answer, and is not handled again.
+            let duplicate = self.last_reply.extract().filter(|(addr, port, reply)| {
+                *addr == src_addr && *port == src_port && reply.message_id == header.message_id
+            });
+            if let Some((_, _, reply)) = duplicate {
+                return self.send_reply(src_addr, src_port, reply, false);
+            }
+        }
+        let options = CoapOptions::new(&payload[offset..]);
+        let body = match options.payload() {
+            Some(body) => body,
+            None => return self.reset(src_addr, src_port, &header),
+        };
+        match header.msg_type {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                self.reply_arrived(src_addr, src_port, &header, options, body)
+            }
+            _ if coap_code::is_request(header.code) => {
+                if confirmable {
+                    let not_found = CoapHeader {
+                        msg_type: MessageType::Acknowledgement,
+                        code: coap_code::NOT_FOUND,
+                        message_id: header.message_id,
+                        token: header.token,
+                    };
+                    self.send_reply(src_addr, src_port, not_found, true);
+                }
+            }
+            _ if coap_code::is_response(header.code) => {
+                self.message_arrived(src_addr, src_port, &header, options, body)
+            }
+            // Pings, and messages with a reserved code.
+            _ => {
+                if confirmable {
+                    self.reset(src_addr, src_port, &header);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for CoapNode<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>, mut dgram: LeasableBuffer<'static, u8>) {
+        if result != Ok(()) {
+            debug!("[CoAP] send_done: {:?}", result);
+        }
+        dgram.reset();
+        self.tx_buffer.replace(dgram);
+        self.transmit();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for CoapNode<'a, A> {
+    fn alarm(&self) {
+        if let Some((reference, dt)) = self.timer.extract() {
+            let elapsed = self.alarm.now().wrapping_sub(reference);
+            if elapsed.into_u32() >= dt.into_u32() {
+                self.timer_expired();
+            } else {
+                let _ = self.alarm.set_alarm(reference, dt);
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! CoAP userspace interface.
+//!
+//! Lets processes send CoAP requests through a `CoapNode`, and observe a
+//! resource. The node sends one request at a time, so a process that sends
+//! a request while the request of another process is in progress gets
+//! BUSY. Likewise, one process at a time observes a resource.
+//!
+//! Endpoints in the config buffer are a 16 byte IPv6 address followed by a
+//! port in host byte order, like those of the UDP driver. Paths in the path
+//! buffer are relative to the root of the server, such as
+//! `sensors/temp?unit=C`.
+
+use crate::net::coap::coap_code;
+use crate::net::coap::coap_node::{CoapClient, CoapNode};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::util::host_slice_to_u16;
+use core::cmp;
+use core::mem::{self, size_of};
+use kernel::hil::time::Alarm;
+use kernel::{
+    CommandReturn, Driver, ErrorCode, Grant, ProcessId, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, Upcall,
+};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// Length of an endpoint in the config buffer.
+const ENDPOINT_LEN: usize = size_of::<IPAddr>() + size_of::<u16>();
+
+#[derive(Default)]
+pub struct App {
+    request_callback: Upcall,
+    notification_callback: Upcall,
+    app_response: ReadWriteAppSlice,
+    app_cfg: ReadWriteAppSlice,
+    app_notification: ReadWriteAppSlice,
+    app_path: ReadOnlyAppSlice,
+    app_payload: ReadOnlyAppSlice,
+    /// Whether the request in progress is the one of the process.
+    request: bool,
+    /// Length of the response to the request of the process so far.
+    response_len: usize,
+    /// Whether the process observes the resource the node observes.
+    observer: bool,
+}
+
+pub struct CoapDriver<'a, A: Alarm<'a>> {
+    node: &'a CoapNode<'a, A>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> CoapDriver<'a, A> {
+    pub fn new(node: &'a CoapNode<'a, A>, grant: Grant<App>) -> CoapDriver<'a, A> {
+        CoapDriver {
+            node: node,
+            apps: grant,
+        }
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> Option<(IPAddr, u16)> {
+        if buf.len() != ENDPOINT_LEN {
+            return None;
+        }
+        let (a, p) = buf.split_at(size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        Some((addr, host_slice_to_u16(p)))
+    }
+
+    /// Sends the request of `appid` for the path in its path buffer, with
+    /// the first `len` bytes of its payload buffer.
+    fn request(
+        &self,
+        appid: ProcessId,
+        method: u8,
+        len: usize,
+        observe: bool,
+    ) -> Result<(), ErrorCode> {
+        if observe {
+            // A process that exited does not hold the observation anymore.
+            let other_observer = self
+                .apps
+                .iter()
+                .any(|app| app.processid() != appid && app.enter(|app| app.observer));
+            if other_observer {
+                return Err(ErrorCode::BUSY);
+            }
+        }
+        self.apps
+            .enter(appid, |app| {
+                let (dst, dst_port) = app
+                    .app_cfg
+                    .map_or(None, |cfg| Self::parse_endpoint(cfg.as_ref()))
+                    .ok_or(ErrorCode::INVAL)?;
+                app.app_path.map_or(Err(ErrorCode::INVAL), |path| {
+                    let request = |payload: &[u8]| {
+                        let payload = &payload[..cmp::min(len, payload.len())];
+                        self.node
+                            .request(method, dst, dst_port, path, payload, observe)
+                    };
+                    // Requests without a payload need no payload buffer.
+                    app.app_payload
+                        .map_or(None, |payload| Some(request(payload)))
+                        .unwrap_or_else(|| request(&[]))
+                })?;
+                app.request = true;
+                app.response_len = 0;
+                if observe {
+                    app.observer = true;
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for CoapDriver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Response buffer. Receives the payload of the response to the
+    ///        request of the process, truncated to the buffer.
+    /// - `1`: Config buffer. Holds the endpoint of the server.
+    /// - `2`: Notification buffer. Receives the payload of the notifications
+    ///        of the observed resource, truncated to the buffer.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| match allow_num {
+                0 => {
+                    mem::swap(&mut app.app_response, &mut slice);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.app_cfg, &mut slice);
+                    Ok(())
+                }
+                2 => {
+                    mem::swap(&mut app.app_notification, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Path buffer. Holds the path of the resource to request.
+    /// - `1`: Payload buffer. Holds the payload of the request.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| match allow_num {
+                0 => {
+                    mem::swap(&mut app.app_path, &mut slice);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.app_payload, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: The request of the process is complete. Called with the status
+    ///        code, the code of the response, and the length of its payload.
+    ///        The status is NOACK if the server did not answer, and FAIL if
+    ///        it rejected the request.
+    /// - `1`: A notification of the observed resource arrived. Called with
+    ///        its code, the length of its payload, and `1` if it ends the
+    ///        observation.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.request_callback, &mut callback);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.notification_callback, &mut callback);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = res {
+            Err((callback, e))
+        } else {
+            Ok(callback)
+        }
+    }
+
+    /// CoAP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send a request with method `arg1` (`1` for GET, `2` for POST,
+    ///        `3` for PUT and `4` for DELETE) for the path in the path
+    ///        buffer, to the endpoint in the config buffer, with the first
+    ///        `arg2` bytes of the payload buffer. Returns INVAL if a buffer
+    ///        does not hold what it should. Otherwise, returns the result of
+    ///        `CoapNode::request()`.
+    /// - `2`: Send a GET request for the path in the path buffer, to the
+    ///        endpoint in the config buffer, that registers the process as
+    ///        an observer of the resource. Returns BUSY if another process
+    ///        observes a resource.
+    /// - `3`: Stop observing the resource. Returns ALREADY if the process
+    ///        observes no resource.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 if arg1 > u8::MAX as usize => Err(ErrorCode::INVAL),
+            1 => self.request(appid, arg1 as u8, arg2, false),
+            2 => self.request(appid, coap_code::GET, 0, true),
+            3 => self
+                .apps
+                .enter(appid, |app| {
+                    if !app.observer {
+                        return Err(ErrorCode::ALREADY);
+                    }
+                    app.observer = false;
+                    self.node.cancel_observe()
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        CommandReturn::from(result)
+    }
+}
+
+impl<'a, A: Alarm<'a>> CoapClient for CoapDriver<'a, A> {
+    fn response(&self, _code: u8, offset: usize, payload: &[u8]) {
+        self.apps.each(|_, app| {
+            if app.request {
+                app.response_len = offset + payload.len();
+                app.app_response.mut_map_or((), |buf| {
+                    if offset < buf.len() {
+                        let len = cmp::min(payload.len(), buf.len() - offset);
+                        buf[offset..offset + len].copy_from_slice(&payload[..len]);
+                    }
+                });
+            }
+        });
+    }
+
+    fn request_done(&self, result: Result<u8, ErrorCode>) {
+        let observing = self.node.observing();
+        self.apps.each(|_, app| {
+            if app.request {
+                app.request = false;
+                if app.observer && !observing {
+                    // The server did not register the process.
+                    app.observer = false;
+                }
+                let len = app.response_len;
+                app.request_callback.schedule(
+                    kernel::into_statuscode(result.map(|_| ())),
+                    result.unwrap_or(0) as usize,
+                    len,
+                );
+            }
+        });
+    }
+
+    fn notification(&self, code: u8, payload: &[u8], last: bool) {
+        self.apps.each(|_, app| {
+            if app.observer {
+                if last {
+                    app.observer = false;
+                }
+                app.app_notification.mut_map_or((), |buf| {
+                    let len = cmp::min(payload.len(), buf.len());
+                    buf[..len].copy_from_slice(&payload[..len]);
+                });
+                app.notification_callback
+                    .schedule(code as usize, payload.len(), last as usize);
+            }
+        });
+    }
+}
@@ -0,0 +1,13 @@
+pub mod coap_node;
+pub mod driver;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
+
+// Reexport the exports of the [`coap`] module, to avoid redundant
+// module paths (e.g. `capsules::net::coap::coap::CoapHeader`)
+mod coap;
+pub use coap::{coap_code, coap_opt};
+pub use coap::{decode_uint, encode_option, encode_payload, encode_uint_option};
+pub use coap::{Block, CoapHeader, CoapOption, CoapOptions, MessageType, Token};
+pub use coap::{COAP_PORT, MAX_TOKEN_LEN};
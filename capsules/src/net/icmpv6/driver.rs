@@ -0,0 +1,180 @@
+//! ICMPv6 userspace interface.
+//!
+//! Lets processes ping other nodes through an `ICMP6Node`, and see whether
+//! the node registered with a router. The node sends one ping at a time, so
+//! a process that pings while the ping of another process is outstanding
+//! gets BUSY.
+//!
+//! Addresses in the address buffer are 16 byte IPv6 addresses.
+
+use crate::net::icmpv6::icmpv6_node::{ICMP6Node, PingClient};
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::mem::{self, size_of};
+use kernel::hil::time::Alarm;
+use kernel::{CommandReturn, Driver, ErrorCode, Grant, ProcessId, Read, ReadWrite};
+use kernel::{ReadWriteAppSlice, Upcall};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Icmp6 as usize;
+
+#[derive(Default)]
+pub struct App {
+    ping_callback: Upcall,
+    app_addr: ReadWriteAppSlice,
+    /// Sequence number of the outstanding ping of the process.
+    ping: Option<u16>,
+}
+
+pub struct ICMP6Driver<'a, A: Alarm<'a>> {
+    node: &'a ICMP6Node<'a, A>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> ICMP6Driver<'a, A> {
+    pub fn new(node: &'a ICMP6Node<'a, A>, grant: Grant<App>) -> ICMP6Driver<'a, A> {
+        ICMP6Driver {
+            node: node,
+            apps: grant,
+        }
+    }
+
+    fn ping(&self, appid: ProcessId, len: usize) -> Result<u16, ErrorCode> {
+        self.apps
+            .enter(appid, |app| {
+                let dst = app.app_addr.map_or(None, |buf| {
+                    if buf.len() != size_of::<IPAddr>() {
+                        return None;
+                    }
+                    let mut addr = IPAddr::new();
+                    addr.0.copy_from_slice(buf.as_ref());
+                    Some(addr)
+                });
+                let seqno = self.node.ping(dst.ok_or(ErrorCode::INVAL)?, len)?;
+                app.ping = Some(seqno);
+                Ok(seqno)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for ICMP6Driver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Address buffer. Holds the address to ping with command `1`,
+    ///        and receives the address of the router with command `3`.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app| {
+                    mem::swap(&mut app.app_addr, &mut slice);
+                })
+                .map_err(ErrorCode::from),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A ping finished. Called with the status code, the sequence
+    ///        number of the ping, and the round-trip time in milliseconds.
+    ///        The status is NOACK if no reply arrived in time.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app| {
+                    mem::swap(&mut app.ping_callback, &mut callback);
+                })
+                .map_err(ErrorCode::from),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        if let Err(e) = res {
+            Err((callback, e))
+        } else {
+            Ok(callback)
+        }
+    }
+
+    /// ICMPv6 control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Ping the address in the address buffer with `arg1` bytes of
+    ///        data. Returns the sequence number of the ping. Returns INVAL
+    ///        if the address buffer does not hold an address, and BUSY if a
+    ///        ping is outstanding.
+    /// - `2`: Get the state of the registration with a router, as a
+    ///        `RegistrationState`.
+    /// - `3`: Write the address of the router into the address buffer.
+    ///        Returns OFF if no router advertised itself, and SIZE if the
+    ///        buffer does not fit an address.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self
+                .ping(appid, arg1)
+                .map_or_else(CommandReturn::failure, |seqno| {
+                    CommandReturn::success_u32(seqno as u32)
+                }),
+            2 => CommandReturn::success_u32(self.node.registration_state() as u32),
+            3 => CommandReturn::from(self.node.router().ok_or(ErrorCode::OFF).and_then(|router| {
+                self.apps
+                    .enter(appid, |app| {
+                        app.app_addr.mut_map_or(Err(ErrorCode::SIZE), |buf| {
+                            if buf.len() != size_of::<IPAddr>() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buf.copy_from_slice(&router.0);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> PingClient for ICMP6Driver<'a, A> {
+    fn ping_done(&self, seqno: u16, result: Result<u32, ErrorCode>) {
+        self.apps.each(|_, app| {
+            if app.ping == Some(seqno) {
+                app.ping = None;
+                app.ping_callback.schedule(
+                    kernel::into_statuscode(result.map(|_| ())),
+                    seqno as usize,
+                    result.unwrap_or(0) as usize,
+                );
+            }
+        });
+    }
+}
@@ -2,11 +2,17 @@
 //! ICMPv6 header, including getter and setter methods and encode/decode
 //! functionality necessary for transmission.
 //!
+//! Besides error and echo messages, the header covers the Neighbor Discovery
+//! messages of RFC 4861. Their fixed fields, including the target address of
+//! Neighbor Solicitations and Advertisements, are part of the header; their
+//! options follow as payload (see `nd`).
+//!
 //! - Author: Conor McAvity <cmcavity@stanford.edu>
 
+use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_u16, decode_u32, decode_u8};
-use crate::net::stream::{encode_u16, encode_u32, encode_u8};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
 
 /// A struct representing an ICMPv6 header.
 #[derive(Copy, Clone)]
@@ -19,18 +25,59 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    Type133 {
+        reserved: u32,
+    },
+    Type134 {
+        cur_hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+        reachable_time: u32,
+        retrans_timer: u32,
+    },
+    Type135 {
+        reserved: u32,
+        target: IPAddr,
+    },
+    Type136 {
+        flags: u8,
+        target: IPAddr,
+    },
+    Type155,
 }
 
-#[derive(Copy, Clone)]
+/// Flags of Neighbor Advertisements (`ICMP6HeaderOptions::Type136`).
+pub mod na_flags {
+    pub const ROUTER: u8 = 0x80;
+    pub const SOLICITED: u8 = 0x40;
+    pub const OVERRIDE: u8 = 0x20;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ICMP6Type {
     Type1,   // Destination Unreachable
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
 impl ICMP6Header {
@@ -40,6 +87,23 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+                reachable_time: 0,
+                retrans_timer: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 {
+                reserved: 0,
+                target: IPAddr::new(),
+            },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 {
+                flags: 0,
+                target: IPAddr::new(),
+            },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155,
         };
 
         ICMP6Header {
@@ -51,12 +115,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(ICMP6Header::new(icmp_type).options);
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -81,6 +140,11 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 => ICMP6Type::Type155,
         }
     }
 
@@ -90,6 +154,11 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
         }
     }
 
@@ -109,8 +178,16 @@ impl ICMP6Header {
         self.len
     }
 
+    /// Returns the size of the header, which includes the fixed fields of
+    /// Neighbor Discovery messages. The code of RPL control messages selects
+    /// the message, whose fields follow as payload.
     pub fn get_hdr_size(&self) -> usize {
-        8
+        match self.get_type() {
+            ICMP6Type::Type155 => 4,
+            ICMP6Type::Type134 => 16,
+            ICMP6Type::Type135 | ICMP6Type::Type136 => 24,
+            _ => 8,
+        }
     }
 
     /// Serializes an `ICMP6Header` into a buffer.
@@ -125,6 +202,8 @@ impl ICMP6Header {
     /// This function returns the new offset into the buffer,
     /// wrapped in an SResult
     pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
         let mut off = offset;
 
         off = enc_consume!(buf, off; encode_u8, self.get_type_as_int());
@@ -132,7 +211,9 @@ impl ICMP6Header {
         off = enc_consume!(buf, off; encode_u16, self.cksum);
 
         match self.options {
-            ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+            ICMP6HeaderOptions::Type1 { unused }
+            | ICMP6HeaderOptions::Type3 { unused }
+            | ICMP6HeaderOptions::Type133 { reserved: unused } => {
                 off = enc_consume!(buf, off; encode_u32, unused);
             }
             ICMP6HeaderOptions::Type128 { id, seqno }
@@ -140,6 +221,28 @@ impl ICMP6Header {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit,
+                flags,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, cur_hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, router_lifetime);
+                off = enc_consume!(buf, off; encode_u32, reachable_time);
+                off = enc_consume!(buf, off; encode_u32, retrans_timer);
+            }
+            ICMP6HeaderOptions::Type135 { reserved, target } => {
+                off = enc_consume!(buf, off; encode_u32, reserved);
+                off = enc_consume!(buf, off; encode_bytes, &target.0);
+            }
+            ICMP6HeaderOptions::Type136 { flags, target } => {
+                off = enc_consume!(buf, off; encode_u32, (flags as u32) << 24);
+                off = enc_consume!(buf, off; encode_bytes, &target.0);
+            }
+            ICMP6HeaderOptions::Type155 => {}
         }
 
         stream_done!(off, off);
@@ -163,6 +266,11 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -171,35 +279,170 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
+        let (off, options) = match icmp_type {
             ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type1 { unused })
             }
             ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type3 { unused })
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type128 { id, seqno })
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type129 { id, seqno })
             }
-        }
+            ICMP6Type::Type133 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type133 { reserved })
+            }
+            ICMP6Type::Type134 => {
+                let (off, cur_hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, router_lifetime) = dec_try!(buf, off; decode_u16);
+                let (off, reachable_time) = dec_try!(buf, off; decode_u32);
+                let (off, retrans_timer) = dec_try!(buf, off; decode_u32);
+                let options = ICMP6HeaderOptions::Type134 {
+                    cur_hop_limit,
+                    flags,
+                    router_lifetime,
+                    reachable_time,
+                    retrans_timer,
+                };
+                (off, options)
+            }
+            ICMP6Type::Type135 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                (off, ICMP6HeaderOptions::Type135 { reserved, target })
+            }
+            ICMP6Type::Type136 => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                let flags = (flags >> 24) as u8;
+                (off, ICMP6HeaderOptions::Type136 { flags, target })
+            }
+            ICMP6Type::Type155 => (off, ICMP6HeaderOptions::Type155),
+        };
+        icmp_header.set_options(options);
+        icmp_header.set_len(buf.len() as u16);
 
         stream_done!(off, icmp_header);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(header: ICMP6Header, expected: &[u8]) -> ICMP6Header {
+        let mut buf = [0; 24];
+        let len = header.get_hdr_size();
+        assert_eq!(len, expected.len());
+        assert_eq!(header.encode(&mut buf, 0).done(), Some((len, len)));
+        assert_eq!(&buf[..len], expected);
+        let (off, decoded) = ICMP6Header::decode(&buf[..len]).done().unwrap();
+        assert_eq!(off, len);
+        assert_eq!(decoded.get_type(), header.get_type());
+        decoded
+    }
+
+    #[test]
+    fn echo_header() {
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_cksum(0xabcd);
+        header.set_options(ICMP6HeaderOptions::Type128 { id: 7, seqno: 258 });
+        let decoded = round_trip(header, &[128, 0, 0xab, 0xcd, 0, 7, 1, 2]);
+        assert_eq!(decoded.get_cksum(), 0xabcd);
+        assert!(matches!(
+            decoded.get_options(),
+            ICMP6HeaderOptions::Type128 { id: 7, seqno: 258 }
+        ));
+    }
+
+    #[test]
+    fn router_advertisement_header() {
+        let mut header = ICMP6Header::new(ICMP6Type::Type134);
+        header.set_options(ICMP6HeaderOptions::Type134 {
+            cur_hop_limit: 64,
+            flags: 0x80,
+            router_lifetime: 1800,
+            reachable_time: 1,
+            retrans_timer: 2,
+        });
+        let decoded = round_trip(
+            header,
+            &[134, 0, 0, 0, 64, 0x80, 0x07, 0x08, 0, 0, 0, 1, 0, 0, 0, 2],
+        );
+        assert!(matches!(
+            decoded.get_options(),
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 64,
+                flags: 0x80,
+                router_lifetime: 1800,
+                reachable_time: 1,
+                retrans_timer: 2,
+            }
+        ));
+    }
+
+    #[test]
+    fn neighbor_messages_carry_their_target() {
+        let target = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 1]);
+        let mut expected = [0; 24];
+        expected[0] = 136;
+        expected[4] = na_flags::SOLICITED | na_flags::OVERRIDE;
+        expected[8..].copy_from_slice(&target.0);
+
+        let mut header = ICMP6Header::new(ICMP6Type::Type136);
+        header.set_options(ICMP6HeaderOptions::Type136 {
+            flags: na_flags::SOLICITED | na_flags::OVERRIDE,
+            target,
+        });
+        match round_trip(header, &expected).get_options() {
+            ICMP6HeaderOptions::Type136 { flags, target: t } => {
+                assert_eq!(flags, na_flags::SOLICITED | na_flags::OVERRIDE);
+                assert_eq!(t, target);
+            }
+            _ => panic!("not a neighbor advertisement"),
+        }
+
+        let mut header = ICMP6Header::new(ICMP6Type::Type135);
+        header.set_options(ICMP6HeaderOptions::Type135 {
+            reserved: 0,
+            target,
+        });
+        expected[0] = 135;
+        expected[4] = 0;
+        match round_trip(header, &expected).get_options() {
+            ICMP6HeaderOptions::Type135 { target: t, .. } => assert_eq!(t, target),
+            _ => panic!("not a neighbor solicitation"),
+        }
+    }
+
+    #[test]
+    fn rpl_header_is_followed_by_the_base_object() {
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        header.set_code(1);
+        let decoded = round_trip(header, &[155, 1, 0, 0]);
+        assert_eq!(decoded.get_code(), 1);
+    }
+
+    #[test]
+    fn decode_rejects_unknown_types_and_short_headers() {
+        assert!(ICMP6Header::decode(&[2, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        let mut buf = [0; 16];
+        buf[0] = 135;
+        assert!(ICMP6Header::decode(&buf).done().is_none());
+    }
+}
@@ -0,0 +1,655 @@
+//! This file contains the definition and implementation of `ICMP6Node`, which
+//! handles the ICMPv6 messages a host has to answer and sends those it needs
+//! to take part in a 6LoWPAN network:
+//!
+//! - It answers Echo Requests with Echo Replies, and sends Echo Requests for
+//!   its `PingClient`.
+//! - It answers Neighbor Solicitations for its addresses with Neighbor
+//!   Advertisements (RFC 4861, section 7.2.4).
+//! - Once started, it registers with a router as 6LoWPAN-ND prescribes
+//!   (RFC 6775, section 5): it sends Router Solicitations until a router
+//!   answers with a Router Advertisement, then registers its address with a
+//!   Neighbor Solicitation carrying an Address Registration option, and
+//!   refreshes the registration before it expires. If the Router
+//!   Advertisement holds a prefix for autoconfiguration, the address
+//!   registered is the one formed from that prefix, which the node answers
+//!   Neighbor Solicitations for as well.
+//!
+//! The node sends one message at a time. Messages that cannot be sent right
+//! away wait for the previous one to be sent; only one message of each kind
+//! waits, others are dropped and left to the timers of their senders.
+//!
+//! The node should get its own `IP6Sender`, and its own `IP6Receiver` on a
+//! MAC user of its own, since both only support a single client. The ICMPv6
+//! component (`components::icmpv6`) sets this up.
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::nd::{aro_status, prefix_flags, NDOption, NDOptions};
+use crate::net::icmpv6::{na_flags, ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::ErrorCode;
+
+/// All-nodes multicast address (ff02::1).
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+/// All-routers multicast address (ff02::2).
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// Neighbor Discovery messages must be received with this hop limit, which
+/// proves they were not forwarded (RFC 4861, section 6.1).
+const ND_HOP_LIMIT: u8 = 255;
+
+/// Router Solicitations sent at `RTR_SOLICITATION_INTERVAL_S` before backing
+/// off (RFC 6775, section 5.3).
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+const RTR_SOLICITATION_INTERVAL_S: u32 = 10;
+const MAX_RTR_SOLICITATION_INTERVAL_S: u32 = 60;
+
+/// Registrations sent before the router is considered gone, and the time to
+/// wait for the answer to each (RFC 4861, section 10).
+const MAX_REGISTRATIONS: u8 = 3;
+const RETRANS_TIMER_MS: u32 = 1000;
+
+/// Lifetime of registrations, in units of 60 seconds. Registrations are
+/// refreshed after three quarters of their lifetime.
+const REGISTRATION_LIFETIME: u16 = 60;
+const MAX_REFRESH_S: u32 = 45 * 60;
+
+/// Identifier of the Echo Requests sent for the `PingClient`, and the time to
+/// wait for their reply.
+const PING_ID: u16 = 0x7463;
+const PING_TIMEOUT_MS: u32 = 5000;
+
+/// Progress of the registration with a router.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RegistrationState {
+    /// The node was not started.
+    Idle = 0,
+    /// Sending Router Solicitations.
+    Soliciting = 1,
+    /// Waiting for a router to accept the registration.
+    Registering = 2,
+    /// Registered with a router.
+    Registered = 3,
+    /// The router refused the registration.
+    Failed = 4,
+}
+
+/// The client of pings sent with `ICMP6Node::ping()`.
+pub trait PingClient {
+    /// Called once the Echo Reply of the ping with sequence number `seqno`
+    /// arrived, with the round-trip time in milliseconds, or with NOACK if
+    /// none arrived in time.
+    fn ping_done(&self, seqno: u16, result: Result<u32, ErrorCode>);
+}
+
+/// An Echo Request sent, or waiting to be sent.
+#[derive(Copy, Clone)]
+struct Ping<T: Ticks> {
+    dst: IPAddr,
+    seqno: u16,
+    len: usize,
+    sent: T,
+}
+
+pub struct ICMP6Node<'a, A: Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    /// Address the `IP6Sender` sends from.
+    addr: IPAddr,
+    /// Link-layer address, advertised in Neighbor Discovery messages.
+    link_addr: MacAddress,
+    /// Buffer messages are built in. It holds the data of `echo_reply` while
+    /// that waits to be sent.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Whether the `IP6Sender` is sending a message.
+    sending: Cell<bool>,
+    /// Destination, identifier, sequence number, and data length of the
+    /// Echo Reply to send.
+    echo_reply: OptionalCell<(IPAddr, u16, u16, usize)>,
+    /// Destination and target of the Neighbor Advertisement to send.
+    advertisement: OptionalCell<(IPAddr, IPAddr)>,
+    /// Whether to send the Router Solicitation or registration of the
+    /// current state.
+    solicitation: Cell<bool>,
+    state: Cell<RegistrationState>,
+    /// Solicitations or registrations sent in the current state.
+    attempts: Cell<u8>,
+    /// Address and link-layer address of the router.
+    router: OptionalCell<(IPAddr, Option<MacAddress>)>,
+    /// Prefix advertised for autoconfiguration, and its length.
+    prefix: OptionalCell<(IPAddr, u8)>,
+    /// Reference and duration of the Neighbor Discovery timer.
+    nd_timer: OptionalCell<(A::Ticks, A::Ticks)>,
+    ping: OptionalCell<Ping<A::Ticks>>,
+    /// Whether `ping` waits to be sent.
+    ping_pending: Cell<bool>,
+    next_seqno: Cell<u16>,
+    ping_client: OptionalCell<&'a dyn PingClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> ICMP6Node<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        addr: IPAddr,
+        link_addr: MacAddress,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6Node<'a, A> {
+        ICMP6Node {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            addr: addr,
+            link_addr: link_addr,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            echo_reply: OptionalCell::empty(),
+            advertisement: OptionalCell::empty(),
+            solicitation: Cell::new(false),
+            state: Cell::new(RegistrationState::Idle),
+            attempts: Cell::new(0),
+            router: OptionalCell::empty(),
+            prefix: OptionalCell::empty(),
+            nd_timer: OptionalCell::empty(),
+            ping: OptionalCell::empty(),
+            ping_pending: Cell::new(false),
+            next_seqno: Cell::new(0),
+            ping_client: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_ping_client(&self, client: &'a dyn PingClient) {
+        self.ping_client.set(client);
+    }
+
+    /// Starts looking for a router to register with. Returns ALREADY if the
+    /// node was started before.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != RegistrationState::Idle {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.solicit_router();
+        Ok(())
+    }
+
+    pub fn registration_state(&self) -> RegistrationState {
+        self.state.get()
+    }
+
+    /// Returns the address of the router, once one advertised itself.
+    pub fn router(&self) -> Option<IPAddr> {
+        self.router.extract().map(|(addr, _)| addr)
+    }
+
+    /// Returns the address the node registers: the one formed from the
+    /// advertised prefix, or the address of the `IP6Sender` if there is none.
+    pub fn registered_addr(&self) -> IPAddr {
+        self.prefix
+            .extract()
+            .map_or(self.addr, |(prefix, prefix_len)| {
+                let mut addr = self.addr;
+                addr.set_prefix(&prefix.0, prefix_len);
+                addr
+            })
+    }
+
+    /// Sends an Echo Request with `len` bytes of data to `dst`. Returns the
+    /// sequence number of the request, which the `PingClient` is called with
+    /// once the reply arrives. Returns BUSY if the reply of the previous
+    /// request did not arrive yet, and SIZE if the data does not fit the
+    /// buffer of the node.
+    pub fn ping(&self, dst: IPAddr, len: usize) -> Result<u16, ErrorCode> {
+        if self.ping.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if len > self.tx_buffer.map_or(0, |buf| buf.len()) {
+            return Err(ErrorCode::SIZE);
+        }
+        let seqno = self.next_seqno.get();
+        self.next_seqno.set(seqno.wrapping_add(1));
+        self.ping.set(Ping {
+            dst: dst,
+            seqno: seqno,
+            len: len,
+            sent: self.alarm.now(),
+        });
+        self.ping_pending.set(true);
+        self.transmit();
+        self.update_alarm();
+        Ok(seqno)
+    }
+
+    fn is_own_addr(&self, addr: IPAddr) -> bool {
+        addr == self.addr || addr == self.registered_addr()
+    }
+
+    /// The EUI-64 identifying the node in registrations: the interface
+    /// identifier of its link-local address, with the universal/local bit
+    /// flipped back.
+    fn eui64(&self) -> [u8; 8] {
+        let mut eui64 = [0; 8];
+        eui64.copy_from_slice(&IPAddr::generate_from_mac(self.link_addr).0[8..]);
+        eui64[0] ^= 0x02;
+        eui64
+    }
+
+    fn start_nd_timer(&self, dt: A::Ticks) {
+        self.nd_timer.set((self.alarm.now(), dt));
+        self.update_alarm();
+    }
+
+    /// Sends the next Router Solicitation, and sets the timer for the one
+    /// after.
+    fn solicit_router(&self) {
+        self.state.set(RegistrationState::Soliciting);
+        let attempts = self.attempts.get().saturating_add(1);
+        self.attempts.set(attempts);
+        let interval = if attempts <= MAX_RTR_SOLICITATIONS {
+            RTR_SOLICITATION_INTERVAL_S
+        } else {
+            let backoff = cmp::min(attempts - MAX_RTR_SOLICITATIONS, 3) as u32;
+            cmp::min(
+                RTR_SOLICITATION_INTERVAL_S << backoff,
+                MAX_RTR_SOLICITATION_INTERVAL_S,
+            )
+        };
+        self.start_nd_timer(A::ticks_from_seconds(interval));
+        self.solicitation.set(true);
+        self.transmit();
+    }
+
+    /// Sends the next registration to the router, and sets the timer for the
+    /// one after.
+    fn register(&self) {
+        self.state.set(RegistrationState::Registering);
+        self.attempts.set(self.attempts.get() + 1);
+        self.start_nd_timer(A::ticks_from_ms(RETRANS_TIMER_MS));
+        self.solicitation.set(true);
+        self.transmit();
+    }
+
+    /// Starts over with Router Solicitations, for example because the
+    /// router is gone.
+    fn restart(&self) {
+        self.router.clear();
+        self.attempts.set(0);
+        self.solicit_router();
+    }
+
+    /// Returns the remaining ticks until `timer` expires.
+    fn remaining(timer: (A::Ticks, A::Ticks), now: A::Ticks) -> A::Ticks {
+        let (reference, dt) = timer;
+        let elapsed = now.wrapping_sub(reference);
+        if elapsed.into_u32() >= dt.into_u32() {
+            A::Ticks::from(0)
+        } else {
+            dt.wrapping_sub(elapsed)
+        }
+    }
+
+    /// Sets the alarm for the timer that expires first.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let ping_timer = self
+            .ping
+            .extract()
+            .map(|ping| (ping.sent, A::ticks_from_ms(PING_TIMEOUT_MS)));
+        let next = self
+            .nd_timer
+            .extract()
+            .iter()
+            .chain(ping_timer.iter())
+            .map(|timer| Self::remaining(*timer, now).into_u32())
+            .min();
+        match next {
+            Some(remaining) => {
+                let dt = cmp::max(remaining, self.alarm.minimum_dt().into_u32());
+                self.alarm.set_alarm(now, A::Ticks::from(dt));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Writes the next message to send into `buf`, and returns its
+    /// destination, header, and data length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, ICMP6Header, usize)> {
+        // The data of the Echo Reply is in the buffer already, so the reply
+        // has to go first.
+        if let Some((dst, id, seqno, len)) = self.echo_reply.take() {
+            let mut header = ICMP6Header::new(ICMP6Type::Type129);
+            header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+            return Some((dst, header, len));
+        }
+        if let Some((dst, target)) = self.advertisement.take() {
+            let mut header = ICMP6Header::new(ICMP6Type::Type136);
+            header.set_options(ICMP6HeaderOptions::Type136 {
+                flags: na_flags::SOLICITED | na_flags::OVERRIDE,
+                target: target,
+            });
+            let len =
+                Self::encode_options(buf, &[NDOption::TargetLinkLayerAddress(self.link_addr)])?;
+            return Some((dst, header, len));
+        }
+        if self.solicitation.take() {
+            match self.state.get() {
+                RegistrationState::Soliciting => {
+                    let header = ICMP6Header::new(ICMP6Type::Type133);
+                    let len = Self::encode_options(
+                        buf,
+                        &[NDOption::SourceLinkLayerAddress(self.link_addr)],
+                    )?;
+                    return Some((ALL_ROUTERS, header, len));
+                }
+                RegistrationState::Registering => {
+                    if let Some((router, _)) = self.router.extract() {
+                        let mut header = ICMP6Header::new(ICMP6Type::Type135);
+                        header.set_options(ICMP6HeaderOptions::Type135 {
+                            reserved: 0,
+                            target: self.registered_addr(),
+                        });
+                        let registration = NDOption::AddressRegistration {
+                            status: aro_status::SUCCESS,
+                            lifetime: REGISTRATION_LIFETIME,
+                            eui64: self.eui64(),
+                        };
+                        let len = Self::encode_options(
+                            buf,
+                            &[
+                                registration,
+                                NDOption::SourceLinkLayerAddress(self.link_addr),
+                            ],
+                        )?;
+                        return Some((router, header, len));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if self.ping_pending.take() {
+            if let Some(mut ping) = self.ping.extract() {
+                for (i, byte) in buf[..ping.len].iter_mut().enumerate() {
+                    *byte = i as u8;
+                }
+                ping.sent = self.alarm.now();
+                self.ping.set(ping);
+                let mut header = ICMP6Header::new(ICMP6Type::Type128);
+                header.set_options(ICMP6HeaderOptions::Type128 {
+                    id: PING_ID,
+                    seqno: ping.seqno,
+                });
+                return Some((ping.dst, header, ping.len));
+            }
+        }
+        None
+    }
+
+    /// Writes `options` into `buf`, and returns their length.
+    fn encode_options(buf: &mut [u8], options: &[NDOption]) -> Option<usize> {
+        let mut off = 0;
+        for option in options {
+            let (len, _) = option.encode(&mut buf[off..]).done()?;
+            off += len;
+        }
+        Some(off)
+    }
+
+    /// Sends the next message, if the `IP6Sender` is idle.
+    fn transmit(&self) {
+        if self.sending.get() {
+            return;
+        }
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        buf.reset();
+        if let Some((dst, header, len)) = self.next_message(&mut buf[..]) {
+            buf.slice(0..len);
+            // The sender copies the data, so the buffer can be used again
+            // right away.
+            self.sending.set(true);
+            if let Err(e) =
+                self.ip_sender
+                    .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap)
+            {
+                debug!("[ICMPv6] send failed: {:?}", e);
+                self.sending.set(false);
+            }
+        }
+        buf.reset();
+        self.tx_buffer.replace(buf);
+    }
+
+    /// Whether a Neighbor Discovery message passes the checks common to all
+    /// types (RFC 4861, sections 6.1 and 7.1).
+    fn nd_valid(ip_header: &IP6Header, icmp_header: &ICMP6Header, options: NDOptions) -> bool {
+        ip_header.get_hop_limit() == ND_HOP_LIMIT
+            && icmp_header.get_code() == 0
+            && options.is_valid()
+    }
+
+    fn echo_request_arrived(&self, src: IPAddr, id: u16, seqno: u16, data: &[u8]) {
+        if self.echo_reply.is_some() {
+            return;
+        }
+        let copied = self.tx_buffer.map_or(false, |buf| {
+            if data.len() > buf.len() {
+                return false;
+            }
+            buf[..data.len()].copy_from_slice(data);
+            true
+        });
+        if copied {
+            self.echo_reply.set((src, id, seqno, data.len()));
+            self.transmit();
+        }
+    }
+
+    fn echo_reply_arrived(&self, src: IPAddr, id: u16, seqno: u16) {
+        let ping = match self.ping.extract() {
+            Some(ping) => ping,
+            None => return,
+        };
+        if self.ping_pending.get()
+            || id != PING_ID
+            || seqno != ping.seqno
+            || (src != ping.dst && !ping.dst.is_multicast())
+        {
+            return;
+        }
+        self.ping.clear();
+        self.update_alarm();
+        let ticks = self.alarm.now().wrapping_sub(ping.sent).into_u32() as u64;
+        let rtt = (ticks * 1000 / A::Frequency::frequency() as u64) as u32;
+        self.ping_client
+            .map(|client| client.ping_done(seqno, Ok(rtt)));
+    }
+
+    fn router_advertisement_arrived(&self, src: IPAddr, router_lifetime: u16, options: NDOptions) {
+        // Routers advertise from their link-local address (RFC 4861, section
+        // 6.1.2).
+        if !src.is_unicast_link_local() {
+            return;
+        }
+        let state = self.state.get();
+        if router_lifetime == 0 {
+            // The router stops being a router.
+            if self.router() == Some(src)
+                && (state == RegistrationState::Registering
+                    || state == RegistrationState::Registered)
+            {
+                self.restart();
+            }
+            return;
+        }
+        if state != RegistrationState::Soliciting {
+            return;
+        }
+
+        let mut link_addr = None;
+        for option in options {
+            match option {
+                NDOption::SourceLinkLayerAddress(addr) => link_addr = Some(addr),
+                NDOption::PrefixInformation {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    prefix,
+                    ..
+                } => {
+                    // Only 64 bit prefixes make an address with the
+                    // interface identifier of the node.
+                    if flags & prefix_flags::AUTONOMOUS != 0
+                        && prefix_len == 64
+                        && valid_lifetime > 0
+                        && !prefix.is_unicast_link_local()
+                    {
+                        self.prefix.set((prefix, prefix_len));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.router.set((src, link_addr));
+        self.attempts.set(0);
+        self.register();
+    }
+
+    fn neighbor_solicitation_arrived(&self, src: IPAddr, target: IPAddr) {
+        if !self.is_own_addr(target) || self.advertisement.is_some() {
+            return;
+        }
+        // Duplicate address detection solicits from the unspecified
+        // address, and is answered to all nodes (RFC 4861, section 7.2.4).
+        let dst = if src.is_unspecified() { ALL_NODES } else { src };
+        self.advertisement.set((dst, target));
+        self.transmit();
+    }
+
+    fn neighbor_advertisement_arrived(&self, src: IPAddr, target: IPAddr, options: NDOptions) {
+        if self.state.get() != RegistrationState::Registering
+            || self.router() != Some(src)
+            || target != self.registered_addr()
+        {
+            return;
+        }
+        let eui64 = self.eui64();
+        let registration = options.filter_map(|option| match option {
+            NDOption::AddressRegistration {
+                status,
+                lifetime,
+                eui64: owner,
+            } if owner == eui64 => Some((status, lifetime)),
+            _ => None,
+        });
+        if let Some((status, lifetime)) = registration.last() {
+            self.attempts.set(0);
+            if status == aro_status::SUCCESS && lifetime > 0 {
+                self.state.set(RegistrationState::Registered);
+                let refresh = cmp::min(lifetime as u32 * 60 / 4 * 3, MAX_REFRESH_S);
+                self.start_nd_timer(A::ticks_from_seconds(cmp::max(refresh, 1)));
+            } else {
+                debug!("[ICMPv6] registration refused: {}", status);
+                self.state.set(RegistrationState::Failed);
+                self.nd_timer.clear();
+                self.update_alarm();
+            }
+        }
+    }
+
+    /// Called when the Neighbor Discovery timer expires.
+    fn nd_timer_expired(&self) {
+        self.nd_timer.clear();
+        match self.state.get() {
+            RegistrationState::Soliciting => self.solicit_router(),
+            RegistrationState::Registering => {
+                if self.attempts.get() >= MAX_REGISTRATIONS {
+                    self.restart();
+                } else {
+                    self.register();
+                }
+            }
+            RegistrationState::Registered => {
+                self.attempts.set(0);
+                self.register();
+            }
+            RegistrationState::Idle | RegistrationState::Failed => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> ICMP6RecvClient for ICMP6Node<'a, A> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let src = ip_header.get_src_addr();
+        let options = NDOptions::new(payload);
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.echo_request_arrived(src, id, seqno, payload)
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } => self.echo_reply_arrived(src, id, seqno),
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => {
+                if Self::nd_valid(&ip_header, &icmp_header, options) {
+                    self.router_advertisement_arrived(src, router_lifetime, options);
+                }
+            }
+            ICMP6HeaderOptions::Type135 { target, .. } => {
+                if Self::nd_valid(&ip_header, &icmp_header, options) {
+                    self.neighbor_solicitation_arrived(src, target);
+                }
+            }
+            ICMP6HeaderOptions::Type136 { target, .. } => {
+                if Self::nd_valid(&ip_header, &icmp_header, options) {
+                    self.neighbor_advertisement_arrived(src, target, options);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6SendClient for ICMP6Node<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if result != Ok(()) {
+            debug!("[ICMPv6] send_done: {:?}", result);
+        }
+        self.sending.set(false);
+        self.transmit();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for ICMP6Node<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        if let Some(ping) = self.ping.extract() {
+            let timer = (ping.sent, A::ticks_from_ms(PING_TIMEOUT_MS));
+            if Self::remaining(timer, now).into_u32() == 0 {
+                self.ping.clear();
+                self.ping_pending.set(false);
+                self.ping_client
+                    .map(|client| client.ping_done(ping.seqno, Err(ErrorCode::NOACK)));
+            }
+        }
+        if let Some(timer) = self.nd_timer.extract() {
+            if Self::remaining(timer, now).into_u32() == 0 {
+                self.nd_timer_expired();
+            }
+        }
+        self.update_alarm();
+    }
+}
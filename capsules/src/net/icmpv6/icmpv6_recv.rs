@@ -0,0 +1,78 @@
+//! This file contains the definition and implementation of the ICMPv6
+//! reception interface. Like for UDP, received messages are dispatched right
+//! away, so no queueing is needed: the `MuxICMP6Receiver` decodes the ICMPv6
+//! header of each message and passes the message to every `ICMP6Receiver`
+//! added to it, whose clients pick the message types they handle.
+
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ipv6::ip_utils::ip6_nh;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::IP6Header;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+
+/// Capsules that handle ICMPv6 messages implement this trait.
+pub trait ICMP6RecvClient {
+    /// Called for every ICMPv6 message received. `payload` is the part of the
+    /// message after the header, for example the options of Neighbor
+    /// Discovery messages or the data of echo messages.
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+pub struct MuxICMP6Receiver<'a> {
+    rcvr_list: List<'a, ICMP6Receiver<'a>>,
+}
+
+impl<'a> MuxICMP6Receiver<'a> {
+    pub fn new() -> MuxICMP6Receiver<'a> {
+        MuxICMP6Receiver {
+            rcvr_list: List::new(),
+        }
+    }
+
+    pub fn add_client(&self, rcvr: &'a ICMP6Receiver<'a>) {
+        self.rcvr_list.push_tail(rcvr);
+    }
+}
+
+impl<'a> IP6RecvClient for MuxICMP6Receiver<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Other transport protocols share the IP receiver.
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        // Messages of types the header does not support are dropped.
+        if let Some((offset, icmp_header)) = ICMP6Header::decode(payload).done() {
+            for rcvr in self.rcvr_list.iter() {
+                rcvr.client
+                    .map(|client| client.receive(ip_header, icmp_header, &payload[offset..]));
+            }
+        }
+    }
+}
+
+/// This struct is added to the `MuxICMP6Receiver`, and passes the messages
+/// it receives to its `ICMP6RecvClient`.
+pub struct ICMP6Receiver<'a> {
+    client: OptionalCell<&'a dyn ICMP6RecvClient>,
+    next: ListLink<'a, ICMP6Receiver<'a>>,
+}
+
+impl<'a> ListNode<'a, ICMP6Receiver<'a>> for ICMP6Receiver<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, ICMP6Receiver<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> ICMP6Receiver<'a> {
+    pub fn new() -> ICMP6Receiver<'a> {
+        ICMP6Receiver {
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ICMP6RecvClient) {
+        self.client.set(client);
+    }
+}
@@ -1,8 +1,16 @@
+pub mod driver;
+pub mod icmpv6_node;
+pub mod icmpv6_recv;
 pub mod icmpv6_send;
+pub mod nd;
+
+pub use self::driver::ICMP6Driver;
+pub use self::driver::DRIVER_NUM;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::icmpv6::icmpv6::ICMP6Header`)
 mod icmpv6;
+pub use icmpv6::na_flags;
 pub use icmpv6::ICMP6Header;
 pub use icmpv6::ICMP6HeaderOptions;
 pub use icmpv6::ICMP6Type;
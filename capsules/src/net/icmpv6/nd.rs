@@ -0,0 +1,457 @@
+//! Options of Neighbor Discovery messages.
+//!
+//! Neighbor Discovery (RFC 4861) messages carry options after their fixed
+//! fields: a type, a length in units of 8 bytes, and a value. This module
+//! encodes and decodes the options used over 6LoWPAN: the link-layer address
+//! options in the format of RFC 4944 (section 8), the Prefix Information and
+//! MTU options, and the Address Registration, 6LoWPAN Context and
+//! Authoritative Border Router options of RFC 6775 (section 4).
+//!
+//! Options of other types are decoded as `NDOption::Unknown`, so that
+//! receivers can skip them.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// Type numbers of the options.
+pub mod nd_opt {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+    pub const MTU: u8 = 5;
+    pub const ADDR_REGISTRATION: u8 = 33;
+    pub const SIXLOWPAN_CONTEXT: u8 = 34;
+    pub const BORDER_ROUTER: u8 = 35;
+}
+
+/// Flags of the Prefix Information option.
+pub mod prefix_flags {
+    /// The prefix can be used to decide whether an address is on-link.
+    pub const ON_LINK: u8 = 0x80;
+    /// The prefix can be used for stateless address autoconfiguration.
+    pub const AUTONOMOUS: u8 = 0x40;
+}
+
+/// Status of the Address Registration option (RFC 6775, section 4.1).
+pub mod aro_status {
+    pub const SUCCESS: u8 = 0;
+    pub const DUPLICATE: u8 = 1;
+    pub const CACHE_FULL: u8 = 2;
+}
+
+/// Options are a multiple of 8 bytes long.
+const UNIT: usize = 8;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NDOption<'a> {
+    SourceLinkLayerAddress(MacAddress),
+    TargetLinkLayerAddress(MacAddress),
+    PrefixInformation {
+        prefix_len: u8,
+        flags: u8,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: IPAddr,
+    },
+    Mtu(u32),
+    /// Lifetime in units of 60 seconds.
+    AddressRegistration {
+        status: u8,
+        lifetime: u16,
+        eui64: [u8; 8],
+    },
+    /// `flags` holds the compression flag and the context identifier.
+    /// Lifetime in units of 60 seconds.
+    SixlowpanContext {
+        context_len: u8,
+        flags: u8,
+        valid_lifetime: u16,
+        prefix: IPAddr,
+    },
+    /// Lifetime in units of 60 seconds.
+    AuthoritativeBorderRouter {
+        version: u32,
+        valid_lifetime: u16,
+        addr: IPAddr,
+    },
+    /// An option this module does not interpret, with its value.
+    Unknown {
+        option_type: u8,
+        value: &'a [u8],
+    },
+}
+
+impl<'a> NDOption<'a> {
+    /// Returns the length of the option in bytes, once encoded.
+    pub fn get_len(&self) -> usize {
+        match *self {
+            NDOption::SourceLinkLayerAddress(addr) | NDOption::TargetLinkLayerAddress(addr) => {
+                match addr {
+                    MacAddress::Short(_) => UNIT,
+                    MacAddress::Long(_) => 2 * UNIT,
+                }
+            }
+            NDOption::PrefixInformation { .. } => 4 * UNIT,
+            NDOption::Mtu(_) => UNIT,
+            NDOption::AddressRegistration { .. } => 2 * UNIT,
+            NDOption::SixlowpanContext { context_len, .. } => {
+                if context_len > 64 {
+                    3 * UNIT
+                } else {
+                    2 * UNIT
+                }
+            }
+            NDOption::AuthoritativeBorderRouter { .. } => 3 * UNIT,
+            NDOption::Unknown { value, .. } => (value.len() + 2 + UNIT - 1) / UNIT * UNIT,
+        }
+    }
+
+    fn get_type(&self) -> u8 {
+        match *self {
+            NDOption::SourceLinkLayerAddress(_) => nd_opt::SOURCE_LL_ADDR,
+            NDOption::TargetLinkLayerAddress(_) => nd_opt::TARGET_LL_ADDR,
+            NDOption::PrefixInformation { .. } => nd_opt::PREFIX_INFO,
+            NDOption::Mtu(_) => nd_opt::MTU,
+            NDOption::AddressRegistration { .. } => nd_opt::ADDR_REGISTRATION,
+            NDOption::SixlowpanContext { .. } => nd_opt::SIXLOWPAN_CONTEXT,
+            NDOption::AuthoritativeBorderRouter { .. } => nd_opt::BORDER_ROUTER,
+            NDOption::Unknown { option_type, .. } => option_type,
+        }
+    }
+
+    /// Serializes the option into `buf`, padding it with zeros to a multiple
+    /// of 8 bytes.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let len = self.get_len();
+        stream_len_cond!(buf, len);
+        for byte in buf[..len].iter_mut() {
+            *byte = 0;
+        }
+
+        let off = enc_consume!(buf, 0; encode_u8, self.get_type());
+        let off = enc_consume!(buf, off; encode_u8, (len / UNIT) as u8);
+        match *self {
+            NDOption::SourceLinkLayerAddress(addr) | NDOption::TargetLinkLayerAddress(addr) => {
+                // Unlike in frames, addresses are in their canonical order.
+                match addr {
+                    MacAddress::Short(short_addr) => {
+                        enc_consume!(buf, off; encode_u16, short_addr);
+                    }
+                    MacAddress::Long(long_addr) => {
+                        enc_consume!(buf, off; encode_bytes, &long_addr);
+                    }
+                }
+            }
+            NDOption::PrefixInformation {
+                prefix_len,
+                flags,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, prefix_len);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u32, valid_lifetime);
+                let off = enc_consume!(buf, off; encode_u32, preferred_lifetime);
+                let off = enc_consume!(buf, off; encode_u32, 0);
+                enc_consume!(buf, off; encode_bytes, &prefix.0);
+            }
+            NDOption::Mtu(mtu) => {
+                let off = enc_consume!(buf, off; encode_u16, 0);
+                enc_consume!(buf, off; encode_u32, mtu);
+            }
+            NDOption::AddressRegistration {
+                status,
+                lifetime,
+                eui64,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, status);
+                let off = enc_consume!(buf, off; encode_u8, 0);
+                let off = enc_consume!(buf, off; encode_u16, 0);
+                let off = enc_consume!(buf, off; encode_u16, lifetime);
+                enc_consume!(buf, off; encode_bytes, &eui64);
+            }
+            NDOption::SixlowpanContext {
+                context_len,
+                flags,
+                valid_lifetime,
+                prefix,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, context_len);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u16, 0);
+                let off = enc_consume!(buf, off; encode_u16, valid_lifetime);
+                enc_consume!(buf, off; encode_bytes, &prefix.0[..len - off]);
+            }
+            NDOption::AuthoritativeBorderRouter {
+                version,
+                valid_lifetime,
+                addr,
+            } => {
+                let off = enc_consume!(buf, off; encode_u16, version as u16);
+                let off = enc_consume!(buf, off; encode_u16, (version >> 16) as u16);
+                let off = enc_consume!(buf, off; encode_u16, valid_lifetime);
+                enc_consume!(buf, off; encode_bytes, &addr.0);
+            }
+            NDOption::Unknown { value, .. } => {
+                enc_consume!(buf, off; encode_bytes, value);
+            }
+        }
+        stream_done!(len, len);
+    }
+
+    /// Deserializes the option at the start of `buf`. The offset returned is
+    /// that of the next option. Fails if the length of the option is zero or
+    /// exceeds `buf`, in which case the message must be dropped.
+    pub fn decode(buf: &'a [u8]) -> SResult<NDOption<'a>> {
+        let (off, option_type) = dec_try!(buf, 0; decode_u8);
+        let (off, units) = dec_try!(buf, off; decode_u8);
+        let len = units as usize * UNIT;
+        stream_cond!(len > 0 && len <= buf.len(), ());
+        let buf = &buf[..len];
+
+        let option = match (option_type, units) {
+            (nd_opt::SOURCE_LL_ADDR, 1) | (nd_opt::TARGET_LL_ADDR, 1) => {
+                let (_, short_addr) = dec_try!(buf, off; decode_u16);
+                Self::ll_addr_option(option_type, MacAddress::Short(short_addr))
+            }
+            (nd_opt::SOURCE_LL_ADDR, 2) | (nd_opt::TARGET_LL_ADDR, 2) => {
+                let mut long_addr = [0; 8];
+                dec_consume!(buf, off; decode_bytes, &mut long_addr);
+                Self::ll_addr_option(option_type, MacAddress::Long(long_addr))
+            }
+            (nd_opt::PREFIX_INFO, 4) => {
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off + 4; decode_bytes, &mut prefix.0);
+                NDOption::PrefixInformation {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix,
+                }
+            }
+            (nd_opt::MTU, 1) => {
+                let (_, mtu) = dec_try!(buf, off + 2; decode_u32);
+                NDOption::Mtu(mtu)
+            }
+            (nd_opt::ADDR_REGISTRATION, 2) => {
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                let (off, lifetime) = dec_try!(buf, off + 3; decode_u16);
+                let mut eui64 = [0; 8];
+                dec_consume!(buf, off; decode_bytes, &mut eui64);
+                NDOption::AddressRegistration {
+                    status,
+                    lifetime,
+                    eui64,
+                }
+            }
+            (nd_opt::SIXLOWPAN_CONTEXT, 2) | (nd_opt::SIXLOWPAN_CONTEXT, 3) => {
+                let (off, context_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off + 2; decode_u16);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off; decode_bytes, &mut prefix.0[..len - off]);
+                NDOption::SixlowpanContext {
+                    context_len,
+                    flags,
+                    valid_lifetime,
+                    prefix,
+                }
+            }
+            (nd_opt::BORDER_ROUTER, 3) => {
+                let (off, version_low) = dec_try!(buf, off; decode_u16);
+                let (off, version_high) = dec_try!(buf, off; decode_u16);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u16);
+                let mut addr = IPAddr::new();
+                dec_consume!(buf, off; decode_bytes, &mut addr.0);
+                NDOption::AuthoritativeBorderRouter {
+                    version: (version_high as u32) << 16 | version_low as u32,
+                    valid_lifetime,
+                    addr,
+                }
+            }
+            _ => NDOption::Unknown {
+                option_type,
+                value: &buf[off..],
+            },
+        };
+        stream_done!(len, option);
+    }
+
+    fn ll_addr_option(option_type: u8, addr: MacAddress) -> NDOption<'a> {
+        if option_type == nd_opt::SOURCE_LL_ADDR {
+            NDOption::SourceLinkLayerAddress(addr)
+        } else {
+            NDOption::TargetLinkLayerAddress(addr)
+        }
+    }
+}
+
+/// Iterator over the options of a Neighbor Discovery message. It stops at
+/// the first option that cannot be decoded; see `is_valid()`.
+#[derive(Copy, Clone)]
+pub struct NDOptions<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> NDOptions<'a> {
+    /// `buf` holds the options, which follow the fixed fields of the message.
+    pub fn new(buf: &'a [u8]) -> NDOptions<'a> {
+        NDOptions { buf: buf }
+    }
+
+    /// Whether all options can be decoded. Messages with options that
+    /// cannot must be dropped (RFC 4861, section 4.6).
+    pub fn is_valid(&self) -> bool {
+        let mut buf = self.buf;
+        while !buf.is_empty() {
+            match NDOption::decode(buf).done() {
+                Some((len, _)) => buf = &buf[len..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl<'a> Iterator for NDOptions<'a> {
+    type Item = NDOption<'a>;
+
+    fn next(&mut self) -> Option<NDOption<'a>> {
+        match NDOption::decode(self.buf).done() {
+            Some((len, option)) => {
+                self.buf = &self.buf[len..];
+                Some(option)
+            }
+            None => {
+                self.buf = &[];
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `option`, and checks that it decodes to the same option.
+    fn round_trip(option: NDOption, len: usize) -> [u8; 32] {
+        let mut buf = [0xff; 32];
+        assert_eq!(option.get_len(), len);
+        assert_eq!(option.encode(&mut buf).done(), Some((len, len)));
+        assert_eq!(NDOption::decode(&buf[..len]).done(), Some((len, option)));
+        buf
+    }
+
+    #[test]
+    fn link_layer_address_options() {
+        // Short addresses are padded to 8 bytes, long ones to 16.
+        let buf = round_trip(
+            NDOption::SourceLinkLayerAddress(MacAddress::Short(0x1234)),
+            8,
+        );
+        assert_eq!(&buf[..8], &[1, 1, 0x12, 0x34, 0, 0, 0, 0]);
+        let buf = round_trip(
+            NDOption::TargetLinkLayerAddress(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8])),
+            16,
+        );
+        assert_eq!(
+            &buf[..16],
+            &[2, 2, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn prefix_and_registration_options() {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0]);
+        let buf = round_trip(
+            NDOption::PrefixInformation {
+                prefix_len: 64,
+                flags: prefix_flags::ON_LINK | prefix_flags::AUTONOMOUS,
+                valid_lifetime: 3600,
+                preferred_lifetime: 1800,
+                prefix,
+            },
+            32,
+        );
+        assert_eq!(
+            &buf[..16],
+            &[3, 4, 64, 0xc0, 0, 0, 0x0e, 0x10, 0, 0, 0x07, 0x08, 0, 0, 0, 0]
+        );
+
+        let buf = round_trip(
+            NDOption::AddressRegistration {
+                status: aro_status::DUPLICATE,
+                lifetime: 30,
+                eui64: [1, 2, 3, 4, 5, 6, 7, 8],
+            },
+            16,
+        );
+        assert_eq!(&buf[..8], &[33, 2, 1, 0, 0, 0, 0, 30]);
+        round_trip(NDOption::Mtu(1280), 8);
+    }
+
+    #[test]
+    fn context_and_border_router_options() {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0]);
+        // Contexts of up to 64 bits hold half the prefix.
+        round_trip(
+            NDOption::SixlowpanContext {
+                context_len: 64,
+                flags: 0x11,
+                valid_lifetime: 60,
+                prefix,
+            },
+            16,
+        );
+        prefix.0[8] = 0xaa;
+        round_trip(
+            NDOption::SixlowpanContext {
+                context_len: 72,
+                flags: 0x10,
+                valid_lifetime: 60,
+                prefix,
+            },
+            24,
+        );
+        round_trip(
+            NDOption::AuthoritativeBorderRouter {
+                version: 0x0001_0002,
+                valid_lifetime: 10,
+                addr: prefix,
+            },
+            24,
+        );
+    }
+
+    #[test]
+    fn unknown_and_malformed_options() {
+        let buf = [200, 1, 1, 2, 3, 4, 5, 6, 1, 0, 0, 0, 0, 0, 0, 0];
+        let mut options = NDOptions::new(&buf[..8]);
+        assert!(options.is_valid());
+        assert_eq!(
+            options.next(),
+            Some(NDOption::Unknown {
+                option_type: 200,
+                value: &[1, 2, 3, 4, 5, 6],
+            })
+        );
+        assert_eq!(options.next(), None);
+
+        // Options of length zero, or longer than the message, are invalid.
+        assert!(!NDOptions::new(&buf[8..]).is_valid());
+        assert!(NDOption::decode(&[1, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(!NDOptions::new(&[1, 2, 0, 0, 0, 0, 0, 0]).is_valid());
+    }
+}
@@ -2,9 +2,10 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
+use crate::net::tcp::TCPHeader;
 use crate::net::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -74,6 +75,19 @@ impl IPAddr {
         ip_addr
     }
 
+    /// Returns the 15.4 MAC address the interface identifier of this address
+    /// was generated from, as by `generate_from_mac()`.
+    pub fn mac_from_iid(&self) -> MacAddress {
+        if self.0[8..14] == [0, 0, 0, 0xff, 0xfe, 0] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..]);
+            long_addr[0] ^= 0b00000010;
+            MacAddress::Long(long_addr)
+        }
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -180,48 +194,92 @@ pub fn compute_udp_checksum(
     sum as u16 //Return result as u16 in host byte order */
 }
 
+/// Computes the checksum of an ICMPv6 message to send, from its header and
+/// payload. The checksum field of the header is not part of the sum.
 pub fn compute_icmp_checksum(
     ipv6_header: &IP6Header,
     icmp_header: &ICMP6Header,
     payload: &[u8],
 ) -> u16 {
-    let mut sum: u32 = 0;
+    let mut header = [0; 24];
+    let hdr_size = icmp_header.get_hdr_size();
+    let _ = icmp_header.encode(&mut header, 0);
+    header[2] = 0;
+    header[3] = 0;
+    let payload_len = icmp_header.get_len() as usize - hdr_size;
+
+    let mut sum = compute_transport_ph_sum(ipv6_header, ip6_nh::ICMP, icmp_header.get_len());
+    sum += compute_odd_sum(&header[..hdr_size]);
+    sum += compute_odd_sum(&payload[..payload_len]);
+    fold_checksum(sum)
+}
 
-    // add ipv6 pseudo-header
-    sum += compute_ipv6_ph_sum(ipv6_header);
+/// Computes the checksum of a received ICMPv6 message, header included. The
+/// result is zero if the checksum of the message is correct.
+pub fn compute_icmp_message_checksum(ip6_header: &IP6Header, message: &[u8]) -> u16 {
+    let sum = compute_transport_ph_sum(ip6_header, ip6_nh::ICMP, message.len() as u16)
+        + compute_odd_sum(message);
+    fold_checksum(sum)
+}
 
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
+/// Computes the checksum of a TCP segment to send, from its header and
+/// payload. The checksum field of the header should be zero.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut header = [0; 60];
+    let hdr_size = tcp_header.get_hdr_size();
+    let _ = tcp_header.encode(&mut header, 0);
+    let payload_len = tcp_header.get_len() as usize - hdr_size;
+
+    let mut sum = compute_transport_ph_sum(ip6_header, ip6_nh::TCP, tcp_header.get_len());
+    // The header length is a multiple of 4, so the payload starts at an even
+    // offset.
+    sum += compute_odd_sum(&header[..hdr_size]);
+    sum += compute_odd_sum(&payload[..payload_len]);
+    fold_checksum(sum)
+}
 
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
+/// Computes the checksum of a received TCP segment, header included. The
+/// result is zero if the checksum of the segment is correct.
+pub fn compute_tcp_segment_checksum(ip6_header: &IP6Header, segment: &[u8]) -> u16 {
+    let sum = compute_transport_ph_sum(ip6_header, ip6_nh::TCP, segment.len() as u16)
+        + compute_odd_sum(segment);
+    fold_checksum(sum)
+}
+
+/// Sum of the IPv6 pseudo-header of a transport message of `len` bytes, with
+/// `next_header` as its protocol.
+fn compute_transport_ph_sum(ip6_header: &IP6Header, next_header: u8, len: u16) -> u32 {
+    let addresses = ip6_header
+        .src_addr
+        .0
+        .iter()
+        .chain(ip6_header.dst_addr.0.iter());
+    let mut sum: u32 = 0;
+    let mut high = true;
+    for byte in addresses {
+        sum += if high {
+            (*byte as u32) << 8
+        } else {
+            *byte as u32
+        };
+        high = !high;
     }
+    sum + len as u32 + next_header as u32
+}
 
-    // add icmp payload
-    let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
-    sum += compute_sum(payload, payload_len);
+/// Like `compute_sum()`, but pads buffers of odd length with a zero byte.
+fn compute_odd_sum(buf: &[u8]) -> u32 {
+    buf.chunks(2).fold(0, |sum, word| {
+        sum + ((word[0] as u32) << 8) + word.get(1).map_or(0, |lsb| *lsb as u32)
+    })
+}
 
-    // carry overflow
+/// Folds the carries of `sum` into 16 bits and returns its complement.
+fn fold_checksum(mut sum: u32) -> u16 {
     while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = (sum >> 16) + (sum & 0xffff);
     }
-
-    sum = !sum;
-    sum = sum & 0xffff;
-
-    sum as u16
+    !(sum as u16)
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
@@ -241,7 +299,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,7 +311,7 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        let lsb = buf.get(i + 1).map_or(0, |lsb| *lsb as u32);
         sum += msb + lsb;
         i += 2;
     }
@@ -67,7 +67,9 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
+use crate::net::ipv6::ip_utils::compute_icmp_message_checksum;
 use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{compute_tcp_checksum, compute_tcp_segment_checksum};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
@@ -281,17 +283,17 @@ impl IP6Header {
                 Ok(())
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
-                    }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
-                };
-                if checksum != 0 {
+                // Like for TCP, the checksum covers the whole message, whose
+                // header length depends on its type.
+                if compute_icmp_message_checksum(&self, buf) != 0 {
+                    return Err(ErrorCode::FAIL); //Incorrect cksum
+                }
+                Ok(())
+            }
+            ip6_nh::TCP => {
+                // The checksum covers all options of the header, so it is
+                // computed over the received bytes rather than a `TCPHeader`.
+                if compute_tcp_segment_checksum(&self, buf) != 0 {
                     return Err(ErrorCode::FAIL); //Incorrect cksum
                 }
                 Ok(())
@@ -360,16 +362,21 @@ impl<'a> IPPayload<'a> {
             TransportHeader::UDP(mut udp_header) => {
                 let length = (payload.len() + udp_header.get_hdr_size()) as u16;
                 udp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::UDP(udp_header);
                 (ip6_nh::UDP, length)
             }
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
         }
     }
 
@@ -388,9 +395,7 @@ impl<'a> IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -405,8 +410,8 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
         }
     }
@@ -454,7 +459,7 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -477,11 +482,14 @@ impl<'a> IP6Packet<'a> {
                 udp_header.set_cksum(cksum);
             }
             TransportHeader::ICMP(ref mut icmp_header) => {
+                icmp_header.set_cksum(0);
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(&self.header, &tcp_header, self.payload.payload);
+                tcp_header.set_cksum(cksum);
             }
         }
     }
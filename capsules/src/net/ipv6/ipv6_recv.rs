@@ -71,7 +71,7 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
                     debug!("cksum fail!: {:?}", checksum_result);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
                 self.client
@@ -5,7 +5,8 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. It sends each packet to the next hop
+//! its `RouteLookup` returns, or to its gateway if there is no route.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -17,8 +18,9 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
-use crate::net::ieee802154::MacAddress;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
 use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::routing::RouteLookup;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
@@ -59,7 +61,7 @@ pub trait IP6Sender<'a> {
     fn set_addr(&self, src_addr: IPAddr);
 
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// instance, for packets without a route.
     ///
     /// # Arguments
     /// `gateway` - MAC address to send the constructed packet to
@@ -101,8 +103,9 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    routes: OptionalCell<&'a dyn RouteLookup>,
+    security: Cell<Option<(SecurityLevel, KeyId)>>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
 }
@@ -135,11 +138,15 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return Err(ErrorCode::FAIL);
         }
+        let dst_mac_addr = self
+            .routes
+            .map_or(None, |routes| routes.next_hop(dst))
+            .unwrap_or(self.gateway.get());
         let _ = self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
+            dst_mac_addr,
             self.radio.get_pan(),
-            None,
+            self.security.get(),
         );
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
@@ -166,13 +173,25 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            routes: OptionalCell::empty(),
+            security: Cell::new(None),
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    /// Sets the routes used to choose the next hop of packets.
+    pub fn set_routes(&self, routes: &'a dyn RouteLookup) {
+        self.routes.set(routes);
+    }
+
+    /// Sets the security level and key ID of the frames packets are sent in,
+    /// or `None` to send them unsecured.
+    pub fn set_security(&self, security: Option<(SecurityLevel, KeyId)>) {
+        self.security.set(security);
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
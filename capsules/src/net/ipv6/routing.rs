@@ -0,0 +1,198 @@
+//! This file contains the routing interface of the IPv6 layer. An
+//! `IP6SendStruct` asks its `RouteLookup`, if it has one, for the MAC address
+//! of the next hop towards the destination of each packet, and sends packets
+//! without a route to its gateway.
+//!
+//! `RoutingTable` is a `RouteLookup` with a fixed number of prefix routes and
+//! a default route. Routing protocols such as RPL fill it in, and all the IP
+//! senders of a board can share it.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+use kernel::ErrorCode;
+
+/// Frames to this MAC address are received by all nodes in range.
+const BROADCAST: MacAddress = MacAddress::Short(0xffff);
+
+/// Chooses the next hop of packets.
+pub trait RouteLookup {
+    /// Returns the MAC address of the next hop towards `dst`, or `None` if
+    /// there is no route to it.
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress>;
+}
+
+/// A route to the addresses starting with `prefix_len` bits of `prefix`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub prefix: IPAddr,
+    pub prefix_len: u8,
+    pub next_hop: MacAddress,
+}
+
+impl Route {
+    /// Whether `addr` starts with the prefix of the route.
+    pub fn matches(&self, addr: IPAddr) -> bool {
+        if self.prefix_len > 128 {
+            return false;
+        }
+        let full_bytes = (self.prefix_len / 8) as usize;
+        let remaining = self.prefix_len % 8;
+        if addr.0[..full_bytes] != self.prefix.0[..full_bytes] {
+            return false;
+        }
+        remaining == 0 || {
+            let mask = 0xffu8 << (8 - remaining);
+            addr.0[full_bytes] & mask == self.prefix.0[full_bytes] & mask
+        }
+    }
+}
+
+pub struct RoutingTable<'a> {
+    routes: &'a [Cell<Option<Route>>],
+    default_route: Cell<Option<MacAddress>>,
+}
+
+impl<'a> RoutingTable<'a> {
+    /// `routes` holds the prefix routes of the table, and should be empty
+    /// initially.
+    pub fn new(routes: &'a [Cell<Option<Route>>]) -> RoutingTable<'a> {
+        RoutingTable {
+            routes: routes,
+            default_route: Cell::new(None),
+        }
+    }
+
+    /// Adds `route`, or updates the next hop of the route with the same
+    /// prefix. Returns NOMEM if the table is full.
+    pub fn add_route(&self, route: Route) -> Result<(), ErrorCode> {
+        let slot = self
+            .routes
+            .iter()
+            .find(|slot| {
+                slot.get().map_or(false, |other| {
+                    other.prefix_len == route.prefix_len && other.matches(route.prefix)
+                })
+            })
+            .or_else(|| self.routes.iter().find(|slot| slot.get().is_none()))
+            .ok_or(ErrorCode::NOMEM)?;
+        slot.set(Some(route));
+        Ok(())
+    }
+
+    /// Removes the route to `prefix_len` bits of `prefix`, if there is one.
+    pub fn remove_route(&self, prefix: IPAddr, prefix_len: u8) {
+        for slot in self.routes.iter() {
+            if slot.get().map_or(false, |route| {
+                route.prefix_len == prefix_len && route.matches(prefix)
+            }) {
+                slot.set(None);
+            }
+        }
+    }
+
+    /// Sets the next hop of packets that match no route, usually the router
+    /// towards the rest of the network.
+    pub fn set_default_route(&self, next_hop: Option<MacAddress>) {
+        self.default_route.set(next_hop);
+    }
+
+    pub fn default_route(&self) -> Option<MacAddress> {
+        self.default_route.get()
+    }
+}
+
+impl<'a> RouteLookup for RoutingTable<'a> {
+    /// Multicast packets are broadcast, and link-local packets sent straight
+    /// to their destination. Other packets follow the route with the longest
+    /// prefix that matches, or the default route.
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(BROADCAST);
+        }
+        if dst.is_unicast_link_local() {
+            return Some(dst.mac_from_iid());
+        }
+        self.routes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|route| route.matches(dst))
+            .max_by_key(|route| route.prefix_len)
+            .map(|route| route.next_hop)
+            .or_else(|| self.default_route.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0];
+
+    fn global(link_addr: MacAddress) -> IPAddr {
+        let mut addr = IPAddr::generate_from_mac(link_addr);
+        addr.set_prefix(&PREFIX, 64);
+        addr
+    }
+
+    #[test]
+    fn prefix_matches() {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&PREFIX);
+        let route = |prefix_len| Route {
+            prefix,
+            prefix_len,
+            next_hop: BROADCAST,
+        };
+        let mut addr = global(MacAddress::Short(7));
+        assert!(route(0).matches(addr));
+        assert!(route(64).matches(addr));
+        // Only the bits within the prefix length count.
+        addr.0[3] ^= 0x01;
+        assert!(route(31).matches(addr));
+        assert!(!route(32).matches(addr));
+        assert!(!route(129).matches(addr));
+    }
+
+    #[test]
+    fn lookup() {
+        let slots = [Cell::new(None), Cell::new(None)];
+        let routes = RoutingTable::new(&slots);
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&PREFIX);
+        let dst = global(MacAddress::Short(7));
+
+        assert_eq!(routes.next_hop(dst), None);
+        routes.set_default_route(Some(MacAddress::Short(1)));
+        assert_eq!(routes.next_hop(dst), Some(MacAddress::Short(1)));
+
+        // The longest matching prefix wins over shorter ones and the default
+        // route.
+        let route = |prefix_len, next_hop| Route {
+            prefix,
+            prefix_len,
+            next_hop: MacAddress::Short(next_hop),
+        };
+        assert_eq!(routes.add_route(route(32, 2)), Ok(()));
+        assert_eq!(routes.add_route(route(64, 3)), Ok(()));
+        assert_eq!(routes.next_hop(dst), Some(MacAddress::Short(3)));
+        assert_eq!(routes.add_route(route(48, 4)), Err(ErrorCode::NOMEM));
+        assert_eq!(routes.add_route(route(64, 5)), Ok(()));
+        assert_eq!(routes.next_hop(dst), Some(MacAddress::Short(5)));
+        routes.remove_route(prefix, 64);
+        assert_eq!(routes.next_hop(dst), Some(MacAddress::Short(2)));
+
+        // Link-local destinations are neighbors, and multicast is broadcast.
+        let neighbor = MacAddress::Long([8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(
+            routes.next_hop(IPAddr::generate_from_mac(neighbor)),
+            Some(neighbor)
+        );
+        assert_eq!(
+            routes.next_hop(IPAddr::generate_from_mac(MacAddress::Short(9))),
+            Some(MacAddress::Short(9))
+        );
+        let all_nodes = IPAddr([0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(routes.next_hop(all_nodes), Some(BROADCAST));
+    }
+}
@@ -5,10 +5,12 @@ pub mod sixlowpan;
 pub mod util;
 #[macro_use]
 pub mod stream;
+pub mod coap;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
 pub mod network_capabilities;
+pub mod rpl;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
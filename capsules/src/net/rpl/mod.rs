@@ -0,0 +1,8 @@
+//! RPL, the IPv6 Routing Protocol for Low-Power and Lossy Networks (RFC
+//! 6550), in non-storing mode.
+
+mod rpl;
+pub mod rpl_node;
+
+pub use self::rpl::{encode_dis, Dao, DaoAck, Dio, RplOption, RplOptions};
+pub use self::rpl::{rpl_code, rpl_mop, rpl_opt};
@@ -0,0 +1,627 @@
+//! This file contains the encoding and decoding of the RPL control messages
+//! (RFC 6550, section 6) that nodes of a non-storing mode DODAG exchange:
+//! DODAG Information Solicitations (DIS), DODAG Information Objects (DIO),
+//! Destination Advertisement Objects (DAO), and their acknowledgements
+//! (DAO-ACK).
+//!
+//! RPL control messages are ICMPv6 messages of type 155, whose code selects
+//! the message. The base object of the message follows the ICMPv6 header,
+//! and options follow the base object. Unlike Neighbor Discovery options, the
+//! length of RPL options is in bytes, and does not include the type and
+//! length fields.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// Codes of the RPL control messages.
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+/// Modes of operation of a DODAG.
+pub mod rpl_mop {
+    /// No downward routes.
+    pub const NO_DOWNWARD_ROUTES: u8 = 0;
+    /// Downward routes through source routes from the root.
+    pub const NON_STORING: u8 = 1;
+}
+
+/// Type numbers of the options.
+pub mod rpl_opt {
+    pub const PAD1: u8 = 0;
+    pub const PADN: u8 = 1;
+    pub const DODAG_CONFIG: u8 = 4;
+    pub const TARGET: u8 = 5;
+    pub const TRANSIT_INFO: u8 = 6;
+    pub const PREFIX_INFO: u8 = 8;
+}
+
+/// Size of the type and length fields of options.
+const TL_WIDTH: usize = 2;
+
+const DIO_GROUNDED: u8 = 0x80;
+const DIO_MOP_SHIFT: u8 = 3;
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAG_ID_PRESENT: u8 = 0x40;
+const DAO_ACK_DODAG_ID_PRESENT: u8 = 0x80;
+
+/// The base object of a DIO, by which nodes advertise the DODAG they belong
+/// to and their rank in it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mop: u8,
+    pub preference: u8,
+    /// Destination Advertisement Trigger Sequence Number. Children send new
+    /// DAOs when their parent increments it.
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+}
+
+impl Dio {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let flags = (self.grounded as u8) << 7
+            | (self.mop & 0x7) << DIO_MOP_SHIFT
+            | (self.preference & 0x7);
+        let off = enc_consume!(buf, 0; encode_u8, self.instance_id);
+        let off = enc_consume!(buf, off; encode_u8, self.version);
+        let off = enc_consume!(buf, off; encode_u16, self.rank);
+        let off = enc_consume!(buf, off; encode_u8, flags);
+        let off = enc_consume!(buf, off; encode_u8, self.dtsn);
+        let off = enc_consume!(buf, off; encode_u16, 0);
+        let off = enc_consume!(buf, off; encode_bytes, &self.dodag_id.0);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dio> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, version) = dec_try!(buf, off; decode_u8);
+        let (off, rank) = dec_try!(buf, off; decode_u16);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, dtsn) = dec_try!(buf, off; decode_u8);
+        let (off, _) = dec_try!(buf, off; decode_u16);
+        let mut dodag_id = IPAddr::new();
+        let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+        let dio = Dio {
+            instance_id: instance_id,
+            version: version,
+            rank: rank,
+            grounded: flags & DIO_GROUNDED != 0,
+            mop: (flags >> DIO_MOP_SHIFT) & 0x7,
+            preference: flags & 0x7,
+            dtsn: dtsn,
+            dodag_id: dodag_id,
+        };
+        stream_done!(off, dio);
+    }
+}
+
+/// The base object of a DAO, by which nodes advertise the addresses they
+/// can be reached at, and their parents, to the root of a non-storing mode
+/// DODAG.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Dao {
+    pub instance_id: u8,
+    pub ack_requested: bool,
+    pub sequence: u8,
+    /// Only needed if the RPL instance has several DODAGs.
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl Dao {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let flags = if self.ack_requested {
+            DAO_ACK_REQUESTED
+        } else {
+            0
+        } | if self.dodag_id.is_some() {
+            DAO_DODAG_ID_PRESENT
+        } else {
+            0
+        };
+        let off = enc_consume!(buf, 0; encode_u8, self.instance_id);
+        let off = enc_consume!(buf, off; encode_u8, flags);
+        let off = enc_consume!(buf, off; encode_u8, 0);
+        let mut off = enc_consume!(buf, off; encode_u8, self.sequence);
+        if let Some(dodag_id) = self.dodag_id {
+            off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+        }
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dao> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, _) = dec_try!(buf, off; decode_u8);
+        let (mut off, sequence) = dec_try!(buf, off; decode_u8);
+        let mut dodag_id = None;
+        if flags & DAO_DODAG_ID_PRESENT != 0 {
+            let mut addr = IPAddr::new();
+            off = dec_consume!(buf, off; decode_bytes, &mut addr.0);
+            dodag_id = Some(addr);
+        }
+        let dao = Dao {
+            instance_id: instance_id,
+            ack_requested: flags & DAO_ACK_REQUESTED != 0,
+            sequence: sequence,
+            dodag_id: dodag_id,
+        };
+        stream_done!(off, dao);
+    }
+}
+
+/// The base object of a DAO-ACK. A status below 128 means the DAO was
+/// accepted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DaoAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    pub status: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl DaoAck {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let flags = if self.dodag_id.is_some() {
+            DAO_ACK_DODAG_ID_PRESENT
+        } else {
+            0
+        };
+        let off = enc_consume!(buf, 0; encode_u8, self.instance_id);
+        let off = enc_consume!(buf, off; encode_u8, flags);
+        let off = enc_consume!(buf, off; encode_u8, self.sequence);
+        let mut off = enc_consume!(buf, off; encode_u8, self.status);
+        if let Some(dodag_id) = self.dodag_id {
+            off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+        }
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DaoAck> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (mut off, status) = dec_try!(buf, off; decode_u8);
+        let mut dodag_id = None;
+        if flags & DAO_ACK_DODAG_ID_PRESENT != 0 {
+            let mut addr = IPAddr::new();
+            off = dec_consume!(buf, off; decode_bytes, &mut addr.0);
+            dodag_id = Some(addr);
+        }
+        let ack = DaoAck {
+            instance_id: instance_id,
+            sequence: sequence,
+            status: status,
+            dodag_id: dodag_id,
+        };
+        stream_done!(off, ack);
+    }
+
+    pub fn accepted(&self) -> bool {
+        self.status < 128
+    }
+}
+
+/// The base object of a DIS, which has no fields of interest.
+pub fn encode_dis(buf: &mut [u8]) -> SResult<usize> {
+    let off = enc_consume!(buf, 0; encode_u16, 0);
+    stream_done!(off, off);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RplOption<'a> {
+    /// The parameters of a DODAG, which only its root sets.
+    DodagConfiguration {
+        flags: u8,
+        dio_interval_doublings: u8,
+        dio_interval_min: u8,
+        dio_redundancy: u8,
+        max_rank_increase: u16,
+        min_hop_rank_increase: u16,
+        objective_code_point: u16,
+        default_lifetime: u8,
+        lifetime_unit: u16,
+    },
+    /// An address or prefix reachable through the sender of a DAO.
+    Target { prefix_len: u8, prefix: IPAddr },
+    /// The parent of the sender of a DAO, and the lifetime of the path
+    /// through it in units of the lifetime unit of the DODAG.
+    TransitInformation {
+        path_control: u8,
+        path_sequence: u8,
+        path_lifetime: u8,
+        parent: Option<IPAddr>,
+    },
+    /// The prefix of the DODAG, in the format of the Neighbor Discovery
+    /// option.
+    PrefixInformation {
+        prefix_len: u8,
+        flags: u8,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: IPAddr,
+    },
+    /// An option this module does not interpret, padding included.
+    Unknown { option_type: u8, value: &'a [u8] },
+}
+
+impl<'a> RplOption<'a> {
+    /// Returns the length of the option in bytes, once encoded.
+    pub fn get_len(&self) -> usize {
+        TL_WIDTH
+            + match *self {
+                RplOption::DodagConfiguration { .. } => 14,
+                RplOption::Target { prefix_len, .. } => 2 + (prefix_len as usize + 7) / 8,
+                RplOption::TransitInformation { parent, .. } => 4 + parent.map_or(0, |_| 16),
+                RplOption::PrefixInformation { .. } => 30,
+                RplOption::Unknown { value, .. } => value.len(),
+            }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let len = self.get_len();
+        stream_len_cond!(buf, len);
+        let option_type = match *self {
+            RplOption::DodagConfiguration { .. } => rpl_opt::DODAG_CONFIG,
+            RplOption::Target { .. } => rpl_opt::TARGET,
+            RplOption::TransitInformation { .. } => rpl_opt::TRANSIT_INFO,
+            RplOption::PrefixInformation { .. } => rpl_opt::PREFIX_INFO,
+            RplOption::Unknown { option_type, .. } => option_type,
+        };
+        let off = enc_consume!(buf, 0; encode_u8, option_type);
+        let off = enc_consume!(buf, off; encode_u8, (len - TL_WIDTH) as u8);
+        match *self {
+            RplOption::DodagConfiguration {
+                flags,
+                dio_interval_doublings,
+                dio_interval_min,
+                dio_redundancy,
+                max_rank_increase,
+                min_hop_rank_increase,
+                objective_code_point,
+                default_lifetime,
+                lifetime_unit,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u8, dio_interval_doublings);
+                let off = enc_consume!(buf, off; encode_u8, dio_interval_min);
+                let off = enc_consume!(buf, off; encode_u8, dio_redundancy);
+                let off = enc_consume!(buf, off; encode_u16, max_rank_increase);
+                let off = enc_consume!(buf, off; encode_u16, min_hop_rank_increase);
+                let off = enc_consume!(buf, off; encode_u16, objective_code_point);
+                let off = enc_consume!(buf, off; encode_u8, 0);
+                let off = enc_consume!(buf, off; encode_u8, default_lifetime);
+                enc_consume!(buf, off; encode_u16, lifetime_unit);
+            }
+            RplOption::Target { prefix_len, prefix } => {
+                let off = enc_consume!(buf, off; encode_u8, 0);
+                let off = enc_consume!(buf, off; encode_u8, prefix_len);
+                enc_consume!(buf, off; encode_bytes, &prefix.0[..len - off]);
+            }
+            RplOption::TransitInformation {
+                path_control,
+                path_sequence,
+                path_lifetime,
+                parent,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, 0);
+                let off = enc_consume!(buf, off; encode_u8, path_control);
+                let off = enc_consume!(buf, off; encode_u8, path_sequence);
+                let off = enc_consume!(buf, off; encode_u8, path_lifetime);
+                if let Some(parent) = parent {
+                    enc_consume!(buf, off; encode_bytes, &parent.0);
+                }
+            }
+            RplOption::PrefixInformation {
+                prefix_len,
+                flags,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                let off = enc_consume!(buf, off; encode_u8, prefix_len);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u32, valid_lifetime);
+                let off = enc_consume!(buf, off; encode_u32, preferred_lifetime);
+                let off = enc_consume!(buf, off; encode_u32, 0);
+                enc_consume!(buf, off; encode_bytes, &prefix.0);
+            }
+            RplOption::Unknown { value, .. } => {
+                enc_consume!(buf, off; encode_bytes, value);
+            }
+        }
+        stream_done!(len, len);
+    }
+
+    /// Deserializes the option at the start of `buf`. The offset returned is
+    /// that of the next option.
+    pub fn decode(buf: &'a [u8]) -> SResult<RplOption<'a>> {
+        let (off, option_type) = dec_try!(buf, 0; decode_u8);
+        // Pad1 is the only option without a length.
+        if option_type == rpl_opt::PAD1 {
+            stream_done!(
+                off,
+                RplOption::Unknown {
+                    option_type: option_type,
+                    value: &[],
+                }
+            );
+        }
+        let (off, value_len) = dec_try!(buf, off; decode_u8);
+        let len = off + value_len as usize;
+        stream_len_cond!(buf, len);
+        let buf = &buf[..len];
+
+        let option = match (option_type, value_len) {
+            (rpl_opt::DODAG_CONFIG, 14) => {
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, dio_interval_doublings) = dec_try!(buf, off; decode_u8);
+                let (off, dio_interval_min) = dec_try!(buf, off; decode_u8);
+                let (off, dio_redundancy) = dec_try!(buf, off; decode_u8);
+                let (off, max_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, min_hop_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, objective_code_point) = dec_try!(buf, off; decode_u16);
+                let (off, default_lifetime) = dec_try!(buf, off + 1; decode_u8);
+                let (_, lifetime_unit) = dec_try!(buf, off; decode_u16);
+                RplOption::DodagConfiguration {
+                    flags,
+                    dio_interval_doublings,
+                    dio_interval_min,
+                    dio_redundancy,
+                    max_rank_increase,
+                    min_hop_rank_increase,
+                    objective_code_point,
+                    default_lifetime,
+                    lifetime_unit,
+                }
+            }
+            (rpl_opt::TARGET, _) => {
+                let (off, prefix_len) = dec_try!(buf, off + 1; decode_u8);
+                let prefix_bytes = (prefix_len as usize + 7) / 8;
+                stream_cond!(prefix_len <= 128 && off + prefix_bytes <= len, ());
+                let mut prefix = IPAddr::new();
+                prefix.0[..prefix_bytes].copy_from_slice(&buf[off..off + prefix_bytes]);
+                RplOption::Target { prefix_len, prefix }
+            }
+            (rpl_opt::TRANSIT_INFO, 4) | (rpl_opt::TRANSIT_INFO, 20) => {
+                let (off, path_control) = dec_try!(buf, off + 1; decode_u8);
+                let (off, path_sequence) = dec_try!(buf, off; decode_u8);
+                let (off, path_lifetime) = dec_try!(buf, off; decode_u8);
+                let mut parent = None;
+                if value_len == 20 {
+                    let mut addr = IPAddr::new();
+                    dec_consume!(buf, off; decode_bytes, &mut addr.0);
+                    parent = Some(addr);
+                }
+                RplOption::TransitInformation {
+                    path_control,
+                    path_sequence,
+                    path_lifetime,
+                    parent,
+                }
+            }
+            (rpl_opt::PREFIX_INFO, 30) => {
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off + 4; decode_bytes, &mut prefix.0);
+                RplOption::PrefixInformation {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix,
+                }
+            }
+            _ => RplOption::Unknown {
+                option_type,
+                value: &buf[off..],
+            },
+        };
+        stream_done!(len, option);
+    }
+}
+
+/// Iterator over the options of a RPL control message. It stops at the
+/// first option that cannot be decoded.
+#[derive(Copy, Clone)]
+pub struct RplOptions<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> RplOptions<'a> {
+    /// `buf` holds the options, which follow the base object.
+    pub fn new(buf: &'a [u8]) -> RplOptions<'a> {
+        RplOptions { buf: buf }
+    }
+}
+
+impl<'a> Iterator for RplOptions<'a> {
+    type Item = RplOption<'a>;
+
+    fn next(&mut self) -> Option<RplOption<'a>> {
+        match RplOption::decode(self.buf).done() {
+            Some((len, option)) => {
+                self.buf = &self.buf[len..];
+                Some(option)
+            }
+            None => {
+                self.buf = &[];
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dodag_id() -> IPAddr {
+        IPAddr([
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 1,
+        ])
+    }
+
+    #[test]
+    fn dio_round_trips() {
+        let dio = Dio {
+            instance_id: 30,
+            version: 2,
+            rank: 0x0300,
+            grounded: true,
+            mop: rpl_mop::NON_STORING,
+            preference: 5,
+            dtsn: 9,
+            dodag_id: dodag_id(),
+        };
+        let mut buf = [0; 24];
+        assert_eq!(dio.encode(&mut buf).done(), Some((24, 24)));
+        assert_eq!(&buf[..8], &[30, 2, 3, 0, 0x8d, 9, 0, 0]);
+        assert_eq!(&buf[8..], &dodag_id().0);
+        assert_eq!(Dio::decode(&buf).done(), Some((24, dio)));
+        assert!(Dio::decode(&buf[..23]).done().is_none());
+    }
+
+    #[test]
+    fn dao_and_ack_carry_an_optional_dodag_id() {
+        let mut dao = Dao {
+            instance_id: 30,
+            ack_requested: true,
+            sequence: 4,
+            dodag_id: None,
+        };
+        let mut buf = [0; 20];
+        assert_eq!(dao.encode(&mut buf).done(), Some((4, 4)));
+        assert_eq!(&buf[..4], &[30, 0x80, 0, 4]);
+        assert_eq!(Dao::decode(&buf[..4]).done(), Some((4, dao)));
+        dao.dodag_id = Some(dodag_id());
+        assert_eq!(dao.encode(&mut buf).done(), Some((20, 20)));
+        assert_eq!(buf[1], 0xc0);
+        assert_eq!(Dao::decode(&buf).done(), Some((20, dao)));
+
+        let mut ack = DaoAck {
+            instance_id: 30,
+            sequence: 4,
+            status: 0,
+            dodag_id: Some(dodag_id()),
+        };
+        assert_eq!(ack.encode(&mut buf).done(), Some((20, 20)));
+        assert_eq!(&buf[..4], &[30, 0x80, 4, 0]);
+        assert_eq!(DaoAck::decode(&buf).done(), Some((20, ack)));
+        assert!(ack.accepted());
+        ack.status = 128;
+        assert!(!ack.accepted());
+    }
+
+    /// Encodes `option`, and checks that it decodes to the same option.
+    fn round_trip(option: RplOption, len: usize) {
+        let mut buf = [0; 32];
+        assert_eq!(option.get_len(), len);
+        assert_eq!(option.encode(&mut buf).done(), Some((len, len)));
+        assert_eq!(RplOption::decode(&buf[..len]).done(), Some((len, option)));
+    }
+
+    #[test]
+    fn options_round_trip() {
+        round_trip(
+            RplOption::DodagConfiguration {
+                flags: 0,
+                dio_interval_doublings: 20,
+                dio_interval_min: 3,
+                dio_redundancy: 10,
+                max_rank_increase: 0x0700,
+                min_hop_rank_increase: 0x0100,
+                objective_code_point: 0,
+                default_lifetime: 0x1e,
+                lifetime_unit: 60,
+            },
+            16,
+        );
+        // Only the bytes within the prefix length are sent.
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&dodag_id().0[..8]);
+        round_trip(
+            RplOption::Target {
+                prefix_len: 64,
+                prefix,
+            },
+            12,
+        );
+        round_trip(
+            RplOption::Target {
+                prefix_len: 128,
+                prefix: dodag_id(),
+            },
+            20,
+        );
+        round_trip(
+            RplOption::TransitInformation {
+                path_control: 0,
+                path_sequence: 1,
+                path_lifetime: 0x1e,
+                parent: None,
+            },
+            6,
+        );
+        round_trip(
+            RplOption::TransitInformation {
+                path_control: 0,
+                path_sequence: 1,
+                path_lifetime: 0x1e,
+                parent: Some(dodag_id()),
+            },
+            22,
+        );
+        round_trip(
+            RplOption::PrefixInformation {
+                prefix_len: 64,
+                flags: 0x40,
+                valid_lifetime: 3600,
+                preferred_lifetime: 1800,
+                prefix,
+            },
+            32,
+        );
+    }
+
+    #[test]
+    fn options_skip_padding_and_stop_at_truncated_options() {
+        let target = RplOption::Target {
+            prefix_len: 16,
+            prefix: IPAddr([0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+        };
+        // Pad1, PadN of two bytes, a target, and a truncated target.
+        let buf = [0, 1, 2, 0, 0, 5, 4, 0, 16, 0x20, 0x01, 5, 4, 0];
+        let mut options = RplOptions::new(&buf);
+        assert_eq!(
+            options.next(),
+            Some(RplOption::Unknown {
+                option_type: rpl_opt::PAD1,
+                value: &[],
+            })
+        );
+        assert_eq!(
+            options.next(),
+            Some(RplOption::Unknown {
+                option_type: rpl_opt::PADN,
+                value: &[0, 0],
+            })
+        );
+        assert_eq!(options.next(), Some(target));
+        assert_eq!(options.next(), None);
+
+        // A target longer than its option is malformed.
+        assert!(RplOption::decode(&[5, 3, 0, 64, 0x20]).is_err());
+    }
+}
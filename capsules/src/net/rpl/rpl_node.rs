@@ -0,0 +1,558 @@
+//! This file contains the definition and implementation of `RplNode`, which
+//! joins a RPL DODAG (RFC 6550) as a leaf, so that a node can reach a border
+//! router through a mesh of 802.15.4 nodes, and be reached from it:
+//!
+//! - Once started, it sends DODAG Information Solicitations until a node of
+//!   a DODAG answers with a DODAG Information Object.
+//! - It picks the neighbor with the lowest rank it heard DIOs from as its
+//!   preferred parent, using Objective Function Zero (RFC 6552), and makes
+//!   the parent the default route of its `RoutingTable`. It only changes
+//!   parents for a neighbor at least one hop closer to the root, so that
+//!   parents do not flap between neighbors of similar rank.
+//! - In non-storing mode DODAGs, it sends a Destination Advertisement Object
+//!   to the root, announcing its global address and its parent, so that the
+//!   root can source route packets to it. It retransmits the DAO until the
+//!   root acknowledges it, and refreshes it before the route expires.
+//! - It sends a new DAO when its parent changes, when the root starts a new
+//!   version of the DODAG, and when its parent increments its DTSN. It leaves
+//!   the DODAG and starts over when its parent advertises an infinite rank,
+//!   when the root refuses its DAO, or when the root does not acknowledge it.
+//!
+//! Tock does not forward packets, so the node never becomes a router: it
+//! does not send DIOs, answer DISs, or accept children.
+//!
+//! The node should get its own `IP6Sender`, which should use the same
+//! `RoutingTable`, and an `ICMP6Receiver` on the `MuxICMP6Receiver` of the
+//! ICMPv6 stack. The RPL component (`components::rpl`) sets this up.
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::{ICMP6Header, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::routing::RoutingTable;
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::rpl::rpl::{encode_dis, rpl_code, rpl_mop, Dao, DaoAck, Dio};
+use crate::net::rpl::rpl::{RplOption, RplOptions};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ErrorCode;
+
+/// All-RPL-nodes multicast address (ff02::1a).
+pub const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Rank of nodes that cannot reach the root.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// Values of the DODAG configuration before the root advertises it (RFC
+/// 6550, section 17).
+const DEFAULT_MIN_HOP_RANK_INCREASE: u16 = 256;
+const DEFAULT_LIFETIME: u8 = 0xff;
+const DEFAULT_LIFETIME_UNIT: u16 = 0xffff;
+
+/// Rank increase of each hop in units of MinHopRankIncrease, when links
+/// have no metric (RFC 6552, section 6.3).
+const STEP_OF_RANK: u16 = 3;
+
+/// Interval between DISs while the node is detached.
+const DIS_INTERVAL_S: u32 = 10;
+
+/// DAOs sent before the root is considered unreachable, and the time to wait
+/// for the acknowledgement of each.
+const MAX_DAO_ATTEMPTS: u8 = 4;
+const DAO_ACK_TIMEOUT_MS: u32 = 2000;
+
+/// DAOs are refreshed after three quarters of the lifetime of the route,
+/// but at least this often.
+const MAX_REFRESH_S: u32 = 15 * 60;
+
+/// Progress of the node in joining a DODAG.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RplState {
+    /// The node was not started.
+    Idle = 0,
+    /// Looking for a DODAG to join.
+    Detached = 1,
+    /// The node has a parent, but the root did not acknowledge its DAO yet.
+    Joined = 2,
+    /// The root acknowledged the DAO of the node, and can route to it.
+    Reachable = 3,
+}
+
+/// The DODAG the node belongs to.
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    dodag_id: IPAddr,
+    version: u8,
+    mop: u8,
+    min_hop_rank_increase: u16,
+    default_lifetime: u8,
+    lifetime_unit: u16,
+    /// 64 bit prefix of the global addresses of the DODAG.
+    prefix: IPAddr,
+}
+
+impl Dodag {
+    /// Forms the global address of the node with link-local address
+    /// `link_local`.
+    fn global_addr(&self, link_local: IPAddr) -> IPAddr {
+        let mut addr = link_local;
+        addr.set_prefix(&self.prefix.0, 64);
+        addr
+    }
+
+    /// Lifetime of the routes of the DODAG, in seconds.
+    fn lifetime_s(&self) -> u32 {
+        self.default_lifetime as u32 * self.lifetime_unit as u32
+    }
+}
+
+/// The preferred parent, by its link-local address.
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    dtsn: u8,
+}
+
+pub struct RplNode<'a, A: Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    routes: &'a RoutingTable<'a>,
+    /// Link-local address of the node.
+    addr: IPAddr,
+    /// Buffer messages are built in.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Whether the `IP6Sender` is sending a message.
+    sending: Cell<bool>,
+    /// Whether to send a DIS.
+    dis_pending: Cell<bool>,
+    /// Whether to send the DAO with sequence number `dao_sequence`.
+    dao_pending: Cell<bool>,
+    state: Cell<RplState>,
+    dodag: OptionalCell<Dodag>,
+    parent: OptionalCell<Parent>,
+    rank: Cell<u16>,
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+    /// DAOs sent with the current sequence number.
+    attempts: Cell<u8>,
+    /// Reference and duration of the timer.
+    timer: OptionalCell<(A::Ticks, A::Ticks)>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> RplNode<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        routes: &'a RoutingTable<'a>,
+        addr: IPAddr,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> RplNode<'a, A> {
+        RplNode {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            routes: routes,
+            addr: addr,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            dis_pending: Cell::new(false),
+            dao_pending: Cell::new(false),
+            state: Cell::new(RplState::Idle),
+            dodag: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            rank: Cell::new(INFINITE_RANK),
+            dao_sequence: Cell::new(0),
+            path_sequence: Cell::new(0),
+            attempts: Cell::new(0),
+            timer: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Starts looking for a DODAG to join. Returns ALREADY if the node was
+    /// started before.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != RplState::Idle {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.detach();
+        Ok(())
+    }
+
+    pub fn state(&self) -> RplState {
+        self.state.get()
+    }
+
+    /// Returns the rank of the node, which is `INFINITE_RANK` while it is
+    /// detached.
+    pub fn rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// Returns the DODAGID, the address of the root, of the DODAG the node
+    /// belongs to.
+    pub fn dodag_id(&self) -> Option<IPAddr> {
+        self.dodag.extract().map(|dodag| dodag.dodag_id)
+    }
+
+    /// Returns the link-local address of the preferred parent.
+    pub fn parent(&self) -> Option<IPAddr> {
+        self.parent.extract().map(|parent| parent.addr)
+    }
+
+    /// Returns the global address of the node in the DODAG it belongs to.
+    pub fn global_addr(&self) -> Option<IPAddr> {
+        self.dodag
+            .extract()
+            .map(|dodag| dodag.global_addr(self.addr))
+    }
+
+    /// Rank of the node through a parent of rank `parent_rank`.
+    fn rank_through(parent_rank: u16, min_hop_rank_increase: u16) -> u16 {
+        parent_rank.saturating_add(min_hop_rank_increase.saturating_mul(STEP_OF_RANK))
+    }
+
+    fn start_timer(&self, dt: A::Ticks) {
+        self.timer.set((self.alarm.now(), dt));
+        self.update_alarm();
+    }
+
+    /// Leaves the DODAG, if the node belongs to one, and starts looking for
+    /// another.
+    fn detach(&self) {
+        self.dodag.clear();
+        self.parent.clear();
+        self.rank.set(INFINITE_RANK);
+        self.routes.set_default_route(None);
+        self.dao_pending.set(false);
+        self.state.set(RplState::Detached);
+        self.solicit();
+    }
+
+    /// Sends a DIS, and sets the timer for the next one.
+    fn solicit(&self) {
+        self.start_timer(A::ticks_from_seconds(DIS_INTERVAL_S));
+        self.dis_pending.set(true);
+        self.transmit();
+    }
+
+    /// Advertises the route to the node to the root with a new DAO, in
+    /// non-storing mode DODAGs.
+    fn advertise(&self) {
+        let non_storing = self
+            .dodag
+            .extract()
+            .map_or(false, |dodag| dodag.mop == rpl_mop::NON_STORING);
+        self.state.set(RplState::Joined);
+        if !non_storing {
+            self.timer.clear();
+            self.update_alarm();
+            return;
+        }
+        self.dao_sequence
+            .set(self.dao_sequence.get().wrapping_add(1));
+        self.path_sequence
+            .set(self.path_sequence.get().wrapping_add(1));
+        self.attempts.set(0);
+        self.send_dao();
+    }
+
+    /// Sends the current DAO, and sets the timer for its retransmission.
+    fn send_dao(&self) {
+        self.attempts.set(self.attempts.get() + 1);
+        self.start_timer(A::ticks_from_ms(DAO_ACK_TIMEOUT_MS));
+        self.dao_pending.set(true);
+        self.transmit();
+    }
+
+    /// Returns the remaining ticks until `timer` expires.
+    fn remaining(timer: (A::Ticks, A::Ticks), now: A::Ticks) -> A::Ticks {
+        let (reference, dt) = timer;
+        let elapsed = now.wrapping_sub(reference);
+        if elapsed.into_u32() >= dt.into_u32() {
+            A::Ticks::from(0)
+        } else {
+            dt.wrapping_sub(elapsed)
+        }
+    }
+
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        match self.timer.extract() {
+            Some(timer) => {
+                let remaining = Self::remaining(timer, now).into_u32();
+                let dt = cmp::max(remaining, self.alarm.minimum_dt().into_u32());
+                self.alarm.set_alarm(now, A::Ticks::from(dt));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Writes the next message to send into `buf`, and returns its source,
+    /// destination, header, and length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, IPAddr, ICMP6Header, usize)> {
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        if self.dis_pending.take() {
+            header.set_code(rpl_code::DIS);
+            let (len, _) = encode_dis(buf).done()?;
+            return Some((self.addr, ALL_RPL_NODES, header, len));
+        }
+        if self.dao_pending.take() {
+            let dodag = self.dodag.extract()?;
+            let parent = self.parent.extract()?;
+            header.set_code(rpl_code::DAO);
+            let dao = Dao {
+                instance_id: dodag.instance_id,
+                ack_requested: true,
+                sequence: self.dao_sequence.get(),
+                dodag_id: Some(dodag.dodag_id),
+            };
+            let global_addr = dodag.global_addr(self.addr);
+            let options = [
+                RplOption::Target {
+                    prefix_len: 128,
+                    prefix: global_addr,
+                },
+                RplOption::TransitInformation {
+                    path_control: 0,
+                    path_sequence: self.path_sequence.get(),
+                    path_lifetime: dodag.default_lifetime,
+                    parent: Some(dodag.global_addr(parent.addr)),
+                },
+            ];
+            let (mut off, _) = dao.encode(buf).done()?;
+            for option in options.iter() {
+                let (len, _) = option.encode(&mut buf[off..]).done()?;
+                off += len;
+            }
+            return Some((global_addr, dodag.dodag_id, header, off));
+        }
+        None
+    }
+
+    /// Sends the next message, if the `IP6Sender` is idle.
+    fn transmit(&self) {
+        if self.sending.get() {
+            return;
+        }
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        buf.reset();
+        if let Some((src, dst, header, len)) = self.next_message(&mut buf[..]) {
+            buf.slice(0..len);
+            // DAOs come from the global address of the node, the other
+            // messages from its link-local address. The sender copies the
+            // data, so the buffer can be used again right away.
+            self.ip_sender.set_addr(src);
+            self.sending.set(true);
+            if let Err(e) =
+                self.ip_sender
+                    .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap)
+            {
+                debug!("[RPL] send failed: {:?}", e);
+                self.sending.set(false);
+            }
+        }
+        buf.reset();
+        self.tx_buffer.replace(buf);
+    }
+
+    /// Returns the DODAG advertised by `dio`, with the configuration and
+    /// prefix in `options`. Values not advertised are those of `previous`,
+    /// or the defaults.
+    fn dodag_from(dio: &Dio, options: RplOptions, previous: Option<Dodag>) -> Dodag {
+        let mut dodag = previous.unwrap_or(Dodag {
+            instance_id: dio.instance_id,
+            dodag_id: dio.dodag_id,
+            version: dio.version,
+            mop: dio.mop,
+            min_hop_rank_increase: DEFAULT_MIN_HOP_RANK_INCREASE,
+            default_lifetime: DEFAULT_LIFETIME,
+            lifetime_unit: DEFAULT_LIFETIME_UNIT,
+            prefix: dio.dodag_id,
+        });
+        dodag.version = dio.version;
+        dodag.mop = dio.mop;
+        for option in options {
+            match option {
+                RplOption::DodagConfiguration {
+                    min_hop_rank_increase,
+                    default_lifetime,
+                    lifetime_unit,
+                    ..
+                } => {
+                    dodag.min_hop_rank_increase = cmp::max(min_hop_rank_increase, 1);
+                    dodag.default_lifetime = default_lifetime;
+                    dodag.lifetime_unit = lifetime_unit;
+                }
+                RplOption::PrefixInformation {
+                    prefix_len, prefix, ..
+                } => {
+                    // Only 64 bit prefixes make an address with the
+                    // interface identifier of the node.
+                    if prefix_len == 64 && !prefix.is_unicast_link_local() {
+                        dodag.prefix = prefix;
+                    }
+                }
+                _ => {}
+            }
+        }
+        dodag
+    }
+
+    fn dio_arrived(&self, src: IPAddr, dio: Dio, options: RplOptions) {
+        // Neighbors advertise from their link-local address, which the MAC
+        // address of the next hop is formed from.
+        if self.state.get() == RplState::Idle
+            || !src.is_unicast_link_local()
+            || (dio.mop != rpl_mop::NO_DOWNWARD_ROUTES && dio.mop != rpl_mop::NON_STORING)
+        {
+            return;
+        }
+        let current = self.dodag.extract();
+        let parent = self.parent.extract();
+        let from_parent = parent.map_or(false, |parent| parent.addr == src);
+        let new_version = match current {
+            Some(dodag) => {
+                if dio.instance_id != dodag.instance_id || dio.dodag_id != dodag.dodag_id {
+                    return;
+                }
+                // Versions are compared as sequence numbers.
+                let diff = dio.version.wrapping_sub(dodag.version) as i8;
+                if diff < 0 {
+                    return;
+                }
+                diff > 0
+            }
+            None => false,
+        };
+
+        if dio.rank == INFINITE_RANK {
+            // The parent lost its route to the root.
+            if from_parent && !new_version {
+                self.detach();
+            }
+            return;
+        }
+        let dodag = Self::dodag_from(&dio, options, current);
+        let rank = Self::rank_through(dio.rank, dodag.min_hop_rank_increase);
+        let switch = match parent {
+            None => true,
+            Some(_) if new_version || from_parent => true,
+            // Only a neighbor at least one hop closer to the root replaces
+            // the parent.
+            Some(_) => rank.saturating_add(dodag.min_hop_rank_increase) <= self.rank.get(),
+        };
+        if !switch {
+            return;
+        }
+
+        let dtsn_changed = parent.map_or(false, |parent| from_parent && parent.dtsn != dio.dtsn);
+        self.dodag.set(dodag);
+        self.parent.set(Parent {
+            addr: src,
+            dtsn: dio.dtsn,
+        });
+        self.rank.set(rank);
+        if !from_parent {
+            self.routes.set_default_route(Some(src.mac_from_iid()));
+        }
+        if !from_parent || new_version || dtsn_changed {
+            self.advertise();
+        }
+    }
+
+    fn dao_ack_arrived(&self, ack: DaoAck) {
+        let dodag = match self.dodag.extract() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        if self.state.get() != RplState::Joined
+            || ack.instance_id != dodag.instance_id
+            || ack.sequence != self.dao_sequence.get()
+        {
+            return;
+        }
+        if ack.accepted() {
+            self.state.set(RplState::Reachable);
+            self.attempts.set(0);
+            let refresh = cmp::min(dodag.lifetime_s() / 4 * 3, MAX_REFRESH_S);
+            self.start_timer(A::ticks_from_seconds(cmp::max(refresh, 1)));
+        } else {
+            debug!("[RPL] DAO refused: {}", ack.status);
+            self.detach();
+        }
+    }
+
+    /// Called when the timer expires.
+    fn timer_expired(&self) {
+        self.timer.clear();
+        match self.state.get() {
+            RplState::Detached => self.solicit(),
+            RplState::Joined => {
+                if self.attempts.get() >= MAX_DAO_ATTEMPTS {
+                    debug!("[RPL] root unreachable");
+                    self.detach();
+                } else {
+                    self.send_dao();
+                }
+            }
+            RplState::Reachable => self.advertise(),
+            RplState::Idle => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> ICMP6RecvClient for RplNode<'a, A> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        if icmp_header.get_type() != ICMP6Type::Type155 {
+            return;
+        }
+        match icmp_header.get_code() {
+            rpl_code::DIO => {
+                if let Some((off, dio)) = Dio::decode(payload).done() {
+                    let options = RplOptions::new(&payload[off..]);
+                    self.dio_arrived(ip_header.get_src_addr(), dio, options);
+                }
+            }
+            rpl_code::DAO_ACK => {
+                if let Some((_, ack)) = DaoAck::decode(payload).done() {
+                    self.dao_ack_arrived(ack);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6SendClient for RplNode<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if result != Ok(()) {
+            debug!("[RPL] send_done: {:?}", result);
+        }
+        self.sending.set(false);
+        self.transmit();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for RplNode<'a, A> {
+    fn alarm(&self) {
+        if let Some(timer) = self.timer.extract() {
+            if Self::remaining(timer, self.alarm.now()).into_u32() == 0 {
+                self.timer_expired();
+            }
+        }
+        self.update_alarm();
+    }
+}
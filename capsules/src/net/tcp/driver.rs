@@ -0,0 +1,370 @@
+//! TCP userspace interface.
+//!
+//! Gives processes TCP connections over the sockets of a `MuxTcp`. Each
+//! process uses at most one socket at a time, which the driver assigns when
+//! the process connects or listens, and takes back when the process aborts
+//! the connection or exits.
+//!
+//! Endpoints in the config buffer are a 16 byte IPv6 address followed by a
+//! port in host byte order, like those of the UDP driver.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp_socket::{TcpClient, TcpSocket, TcpState};
+use crate::net::util::host_slice_to_u16;
+use core::cmp;
+use core::mem::{self, size_of};
+use kernel::hil::time::Alarm;
+use kernel::{
+    CommandReturn, Driver, ErrorCode, Grant, ProcessId, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, Upcall,
+};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Length of an endpoint in the config buffer.
+const ENDPOINT_LEN: usize = size_of::<IPAddr>() + size_of::<u16>();
+
+/// Arguments of the upcalls of subscribe number 2.
+mod event {
+    pub const CONNECTED: usize = 0;
+    pub const PEER_CLOSED: usize = 1;
+    pub const CLOSED: usize = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Upcall,
+    tx_callback: Upcall,
+    event_callback: Upcall,
+    app_read: ReadWriteAppSlice,
+    app_write: ReadOnlyAppSlice,
+    app_cfg: ReadWriteAppSlice,
+    /// Index of the socket of the process, if it has one.
+    socket: Option<usize>,
+}
+
+pub struct TcpDriver<'a, A: Alarm<'a>> {
+    /// Sockets handed out to processes. The identifier of each socket must
+    /// be its index.
+    sockets: &'a [TcpSocket<'a, A>],
+    apps: Grant<App>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> TcpDriver<'a, A> {
+    pub fn new(
+        sockets: &'a [TcpSocket<'a, A>],
+        grant: Grant<App>,
+        net_cap: &'static NetworkCapability,
+    ) -> TcpDriver<'a, A> {
+        TcpDriver {
+            sockets: sockets,
+            apps: grant,
+            net_cap: net_cap,
+        }
+    }
+
+    /// Returns the socket of `appid`, if it has one.
+    fn socket(&self, appid: ProcessId) -> Result<&TcpSocket<'a, A>, ErrorCode> {
+        self.apps
+            .enter(appid, |app| app.socket)
+            .map_err(ErrorCode::from)?
+            .map(|index| &self.sockets[index])
+            .ok_or(ErrorCode::RESERVE)
+    }
+
+    /// Returns the socket of `appid`, and assigns it a free one first if it
+    /// has none. A socket is free if no process holds it; sockets of
+    /// processes that exited are aborted before they are used again.
+    fn assign_socket(&self, appid: ProcessId) -> Result<&TcpSocket<'a, A>, ErrorCode> {
+        if let Ok(socket) = self.socket(appid) {
+            return Ok(socket);
+        }
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| {
+                !self
+                    .apps
+                    .iter()
+                    .any(|app| app.enter(|app| app.socket == Some(socket.id())))
+            })
+            .ok_or(ErrorCode::NOMEM)?;
+        socket.abort();
+        self.apps
+            .enter(appid, |app| app.socket = Some(socket.id()))
+            .map_err(ErrorCode::from)?;
+        Ok(socket)
+    }
+
+    /// Runs `fun` on the process holding `socket`, if there is one.
+    fn with_holder<F>(&self, socket: usize, fun: F)
+    where
+        F: Fn(&mut App),
+    {
+        self.apps.each(|_, app| {
+            if app.socket == Some(socket) {
+                fun(app);
+            }
+        });
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> Option<(IPAddr, u16)> {
+        if buf.len() != ENDPOINT_LEN {
+            return None;
+        }
+        let (a, p) = buf.split_at(size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        Some((addr, host_slice_to_u16(p)))
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for TcpDriver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Receives the data read with command `4`.
+    /// - `1`: Config buffer. Holds the endpoint of the peer: the one to
+    ///        connect to, and the one that connected once a connection is
+    ///        established.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| match allow_num {
+                0 => {
+                    mem::swap(&mut app.app_read, &mut slice);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.app_cfg, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Write buffer. Holds the data sent with command `3`.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app| {
+                    mem::swap(&mut app.app_write, &mut slice);
+                })
+                .map_err(ErrorCode::from),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Data was received. Called with the number of bytes that can be
+    ///        read.
+    /// - `1`: The peer acknowledged data. Called with the number of bytes
+    ///        that can be sent.
+    /// - `2`: The state of the connection changed. Called with `0` once it is
+    ///        established, `1` once the peer closed its side, and `2` once it
+    ///        is closed, with the status code as second argument.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.rx_callback, &mut callback);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.tx_callback, &mut callback);
+                    Ok(())
+                }
+                2 => {
+                    mem::swap(&mut app.event_callback, &mut callback);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = res {
+            Err((callback, e))
+        } else {
+            Ok(callback)
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect to the endpoint in the config buffer, from local port
+    ///        `arg1`, or from a free port if it is zero. Returns INVAL if the
+    ///        config buffer does not hold an endpoint, and NOMEM if no socket
+    ///        is free. Otherwise, returns the result of
+    ///        `TcpSocket::connect()`.
+    /// - `2`: Wait for a peer to connect to local port `arg1`. Returns NOMEM
+    ///        if no socket is free. Otherwise, returns the result of
+    ///        `TcpSocket::listen()`.
+    /// - `3`: Send up to `arg1` bytes of the write buffer. Returns the number
+    ///        of bytes queued, which is less if the send buffer of the socket
+    ///        is full. Returns RESERVE if the process has no socket, and OFF
+    ///        if it cannot send.
+    /// - `4`: Move received data into the read buffer. Returns the number of
+    ///        bytes moved.
+    /// - `5`: Close this side of the connection, once the data queued is
+    ///        sent.
+    /// - `6`: Abort the connection, and give up the socket.
+    /// - `7`: Get the state of the connection, as a `TcpState`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 => self
+                .apps
+                .enter(appid, |app| {
+                    app.app_cfg
+                        .map_or(None, |cfg| Self::parse_endpoint(cfg.as_ref()))
+                })
+                .map_err(ErrorCode::from)
+                .and_then(|endpoint| endpoint.ok_or(ErrorCode::INVAL))
+                .and_then(|(addr, port)| {
+                    let socket = self.assign_socket(appid)?;
+                    socket.connect(addr, port, arg1 as u16, self.net_cap)
+                }),
+            2 => self
+                .assign_socket(appid)
+                .and_then(|socket| socket.listen(arg1 as u16, self.net_cap)),
+            3 => {
+                return self
+                    .socket(appid)
+                    .and_then(|socket| {
+                        self.apps
+                            .enter(appid, |app| {
+                                app.app_write.map_or(Ok(0), |data| {
+                                    let len = cmp::min(arg1, data.len());
+                                    socket.send(&data.as_ref()[..len])
+                                })
+                            })
+                            .unwrap_or_else(|err| Err(err.into()))
+                    })
+                    .map_or_else(CommandReturn::failure, |count| {
+                        CommandReturn::success_u32(count as u32)
+                    })
+            }
+            4 => {
+                return self
+                    .socket(appid)
+                    .and_then(|socket| {
+                        self.apps
+                            .enter(appid, |app| {
+                                app.app_read.mut_map_or(0, |buf| socket.recv(buf))
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .map_or_else(CommandReturn::failure, |count| {
+                        CommandReturn::success_u32(count as u32)
+                    })
+            }
+            5 => self.socket(appid).and_then(|socket| socket.close()),
+            6 => self.socket(appid).and_then(|socket| {
+                socket.abort();
+                self.apps
+                    .enter(appid, |app| app.socket = None)
+                    .map_err(ErrorCode::from)
+            }),
+            7 => {
+                return CommandReturn::success_u32(
+                    self.socket(appid)
+                        .map_or(TcpState::Closed, |socket| socket.state())
+                        as u32,
+                )
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        CommandReturn::from(result)
+    }
+}
+
+impl<'a, A: Alarm<'a>> TcpClient for TcpDriver<'a, A> {
+    fn connected(&self, socket: usize) {
+        let (addr, port) = self.sockets[socket].remote_endpoint();
+        self.with_holder(socket, |app| {
+            // Tell the process which peer connected.
+            app.app_cfg.mut_map_or((), |cfg| {
+                if cfg.len() == ENDPOINT_LEN {
+                    cfg[..size_of::<IPAddr>()].copy_from_slice(&addr.0);
+                    cfg[size_of::<IPAddr>()..].copy_from_slice(&port.to_ne_bytes());
+                }
+            });
+            app.event_callback.schedule(event::CONNECTED, 0, 0);
+        });
+    }
+
+    fn received(&self, socket: usize, available: usize) {
+        self.with_holder(socket, |app| {
+            app.rx_callback.schedule(available, 0, 0);
+        });
+    }
+
+    fn sent(&self, socket: usize, space: usize) {
+        self.with_holder(socket, |app| {
+            app.tx_callback.schedule(space, 0, 0);
+        });
+    }
+
+    fn peer_closed(&self, socket: usize) {
+        self.with_holder(socket, |app| {
+            app.event_callback.schedule(event::PEER_CLOSED, 0, 0);
+        });
+    }
+
+    fn closed(&self, socket: usize, result: Result<(), ErrorCode>) {
+        self.with_holder(socket, |app| {
+            app.event_callback
+                .schedule(event::CLOSED, kernel::into_statuscode(result), 0);
+        });
+    }
+}
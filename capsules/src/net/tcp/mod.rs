@@ -0,0 +1,11 @@
+pub mod driver;
+pub mod tcp_mux;
+pub mod tcp_socket;
+
+pub use self::driver::TcpDriver;
+pub use self::driver::DRIVER_NUM;
+
+// Reexport the exports of the [`tcp`] module, to avoid redundant
+// module paths (e.g. `capsules::net::tcp::tcp::TCPHeader`)
+mod tcp;
+pub use tcp::{seq_le, seq_lt, tcp_flags, TCPHeader};
@@ -0,0 +1,343 @@
+//! This file contains the structs and methods associated with the TCP header.
+//! This includes getters and setters for the various header fields, as well
+//! as the standard encode/decode functionality required for serializing
+//! the struct for transmission.
+//!
+//! The only option supported is the maximum segment size (MSS) option, which
+//! is sent with SYN segments. Other options of received segments are skipped.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_u16, encode_u32, encode_u8};
+
+/// Control bits of the TCP header.
+pub mod tcp_flags {
+    pub const FIN: u16 = 0x01;
+    pub const SYN: u16 = 0x02;
+    pub const RST: u16 = 0x04;
+    pub const PSH: u16 = 0x08;
+    pub const ACK: u16 = 0x10;
+    pub const URG: u16 = 0x20;
+}
+
+const MIN_HDR_LEN: usize = 20;
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+const OPTION_MSS_LEN: usize = 4;
+
+// Note: Unlike the `UDPHeader`, TCP header fields are stored in host byte
+// order, and converted when the header is encoded or decoded.
+
+/// The `TCPHeader` struct follows the layout of the TCP segment header.
+#[derive(Copy, Clone, Debug)]
+pub struct TCPHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq_num: u32,
+    pub ack_num: u32,
+    /// Data offset in the upper 4 bits, and the control bits in the lower
+    /// ones. The data offset is derived from the options when the header is
+    /// encoded.
+    pub offset_and_control: u16,
+    pub window: u16,
+    pub cksum: u16,
+    pub urg_ptr: u16,
+    /// Maximum segment size option, if present.
+    pub mss: Option<u16>,
+    pub len: u16, // Not a real TCP field, here for convenience
+}
+
+impl Default for TCPHeader {
+    fn default() -> TCPHeader {
+        TCPHeader {
+            src_port: 0,
+            dst_port: 0,
+            seq_num: 0,
+            ack_num: 0,
+            offset_and_control: 0,
+            window: 0,
+            cksum: 0,
+            urg_ptr: 0,
+            mss: None,
+            len: MIN_HDR_LEN as u16,
+        }
+    }
+}
+
+impl TCPHeader {
+    pub fn new() -> TCPHeader {
+        TCPHeader::default()
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.src_port = port;
+    }
+
+    pub fn set_dst_port(&mut self, port: u16) {
+        self.dst_port = port;
+    }
+
+    pub fn set_seq_num(&mut self, seq_num: u32) {
+        self.seq_num = seq_num;
+    }
+
+    pub fn set_ack_num(&mut self, ack_num: u32) {
+        self.ack_num = ack_num;
+    }
+
+    /// Sets the control bits, see `tcp_flags`.
+    pub fn set_flags(&mut self, flags: u16) {
+        self.offset_and_control = (self.offset_and_control & !0x3f) | (flags & 0x3f);
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn set_cksum(&mut self, cksum: u16) {
+        self.cksum = cksum;
+    }
+
+    pub fn set_mss(&mut self, mss: Option<u16>) {
+        self.mss = mss;
+    }
+
+    /// Sets the length of the segment, header included.
+    pub fn set_len(&mut self, len: u16) {
+        self.len = len;
+    }
+
+    pub fn get_src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    pub fn get_dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    pub fn get_seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    pub fn get_ack_num(&self) -> u32 {
+        self.ack_num
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.offset_and_control & 0x3f
+    }
+
+    /// Whether all control bits in `flags` are set.
+    pub fn has_flags(&self, flags: u16) -> bool {
+        self.get_flags() & flags == flags
+    }
+
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    pub fn get_cksum(&self) -> u16 {
+        self.cksum
+    }
+
+    pub fn get_mss(&self) -> Option<u16> {
+        self.mss
+    }
+
+    /// Returns the length of the segment, header included.
+    pub fn get_len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns the size of the encoded header, options included.
+    pub fn get_hdr_size(&self) -> usize {
+        match self.mss {
+            Some(_) => MIN_HDR_LEN + OPTION_MSS_LEN,
+            None => MIN_HDR_LEN,
+        }
+    }
+
+    /// Returns the data offset and control bits as they are encoded, with the
+    /// data offset matching the options of this header.
+    pub fn get_offset_and_control(&self) -> u16 {
+        ((self.get_hdr_size() / 4) as u16) << 12 | (self.offset_and_control & 0x0fff)
+    }
+
+    /// This function serializes the `TCPHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `TCPHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.src_port);
+        off = enc_consume!(buf, off; encode_u16, self.dst_port);
+        off = enc_consume!(buf, off; encode_u32, self.seq_num);
+        off = enc_consume!(buf, off; encode_u32, self.ack_num);
+        off = enc_consume!(buf, off; encode_u16, self.get_offset_and_control());
+        off = enc_consume!(buf, off; encode_u16, self.window);
+        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.urg_ptr);
+        if let Some(mss) = self.mss {
+            off = enc_consume!(buf, off; encode_u8, OPTION_MSS);
+            off = enc_consume!(buf, off; encode_u8, OPTION_MSS_LEN as u8);
+            off = enc_consume!(buf, off; encode_u16, mss);
+        }
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `TCPHeader` from the provided buffer.
+    /// The length of the segment is set to the length of the buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized `TCPHeader`,
+    /// followed by the payload of the segment
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `TCPHeader` struct wrapped in an SResult, with
+    /// the offset of the payload.
+    pub fn decode(buf: &[u8]) -> SResult<TCPHeader> {
+        stream_len_cond!(buf, MIN_HDR_LEN);
+        let mut tcp_header = Self::new();
+        let off = 0;
+        let (off, src_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.src_port = src_port;
+        let (off, dst_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.dst_port = dst_port;
+        let (off, seq_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.seq_num = seq_num;
+        let (off, ack_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.ack_num = ack_num;
+        let (off, offset_and_control) = dec_try!(buf, off; decode_u16);
+        tcp_header.offset_and_control = offset_and_control;
+        let (off, window) = dec_try!(buf, off; decode_u16);
+        tcp_header.window = window;
+        let (off, cksum) = dec_try!(buf, off; decode_u16);
+        tcp_header.cksum = cksum;
+        let (mut off, urg_ptr) = dec_try!(buf, off; decode_u16);
+        tcp_header.urg_ptr = urg_ptr;
+
+        let data_offset = (offset_and_control >> 12) as usize * 4;
+        stream_cond!(data_offset >= MIN_HDR_LEN);
+        stream_len_cond!(buf, data_offset);
+        while off < data_offset {
+            let (next, kind) = dec_try!(buf, off; decode_u8);
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => off = next,
+                _ => {
+                    let (_, len) = dec_try!(buf, next; decode_u8);
+                    let len = len as usize;
+                    stream_cond!(len >= 2 && off + len <= data_offset);
+                    if kind == OPTION_MSS && len == OPTION_MSS_LEN {
+                        let (_, mss) = dec_try!(buf, off + 2; decode_u16);
+                        tcp_header.mss = Some(mss);
+                    }
+                    off += len;
+                }
+            }
+        }
+        tcp_header.len = buf.len() as u16;
+        stream_done!(data_offset, tcp_header);
+    }
+}
+
+/// Returns whether sequence number `a` comes before `b`, modulo 2^32.
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Returns whether sequence number `a` comes before or is `b`, modulo 2^32.
+pub fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::stream::SResult;
+
+    #[test]
+    fn header_with_mss_round_trips() {
+        let mut header = TCPHeader::new();
+        header.set_src_port(49152);
+        header.set_dst_port(80);
+        header.set_seq_num(0x0102_0304);
+        header.set_ack_num(0xfffe_fdfc);
+        header.set_flags(tcp_flags::SYN | tcp_flags::ACK);
+        header.set_window(512);
+        header.set_mss(Some(1220));
+
+        let mut buf = [0; 28];
+        assert_eq!(header.encode(&mut buf, 4).done(), Some((28, 28)));
+        assert_eq!(
+            &buf[4..],
+            &[
+                0xc0, 0x00, 0x00, 0x50, 0x01, 0x02, 0x03, 0x04, 0xff, 0xfe, 0xfd, 0xfc, 0x60, 0x12,
+                0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x04, 0x04, 0xc4,
+            ]
+        );
+
+        let (offset, decoded) = TCPHeader::decode(&buf[4..]).done().unwrap();
+        assert_eq!(offset, 24);
+        assert_eq!(decoded.get_src_port(), 49152);
+        assert_eq!(decoded.get_dst_port(), 80);
+        assert_eq!(decoded.get_seq_num(), 0x0102_0304);
+        assert_eq!(decoded.get_ack_num(), 0xfffe_fdfc);
+        assert_eq!(decoded.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+        assert_eq!(decoded.get_window(), 512);
+        assert_eq!(decoded.get_mss(), Some(1220));
+        assert_eq!(decoded.get_len(), 24);
+    }
+
+    #[test]
+    fn decode_skips_other_options() {
+        // A NOP, a timestamp option, and the MSS option, then one byte of
+        // payload.
+        let mut buf = [0; 37];
+        buf[12] = 9 << 4;
+        buf[20] = OPTION_NOP;
+        buf[21..23].copy_from_slice(&[8, 10]);
+        buf[31..35].copy_from_slice(&[OPTION_MSS, 4, 0x01, 0x00]);
+        let (offset, decoded) = TCPHeader::decode(&buf).done().unwrap();
+        assert_eq!(offset, 36);
+        assert_eq!(decoded.get_mss(), Some(256));
+        assert_eq!(decoded.get_len(), 37);
+        // The header is sent without options.
+        assert_eq!(decoded.get_offset_and_control() >> 12, 6);
+    }
+
+    #[test]
+    fn decode_rejects_bad_lengths() {
+        assert!(matches!(TCPHeader::decode(&[0; 19]), SResult::Needed(_)));
+
+        // A data offset shorter than the fixed header.
+        let mut buf = [0; 24];
+        buf[12] = 4 << 4;
+        assert!(TCPHeader::decode(&buf).is_err());
+
+        // An option that runs past the data offset.
+        buf[12] = 6 << 4;
+        buf[20..22].copy_from_slice(&[OPTION_MSS, 8]);
+        assert!(TCPHeader::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        assert!(seq_lt(0xffff_fff0, 0x10));
+        assert!(!seq_lt(0x10, 0xffff_fff0));
+        assert!(seq_le(5, 5));
+        assert!(!seq_lt(5, 5));
+    }
+}
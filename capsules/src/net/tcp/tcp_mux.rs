@@ -0,0 +1,416 @@
+//! This file contains the definition and implementation of `MuxTcp`, which
+//! virtualizes the TCP sockets of the kernel and of the userspace driver on
+//! top of a single `IP6Sender`.
+//!
+//! The mux hands received TCP segments to the socket of their connection, or
+//! to a socket listening on their destination port, and answers segments for
+//! which there is neither with a reset. It sends one segment at a time: once
+//! the `IP6Sender` is done, it asks the sockets in turn for their next segment.
+//! It also runs the retransmission timers of all sockets with one alarm.
+//!
+//! Initial sequence numbers follow RFC 6528, so that off-path attackers cannot
+//! guess them: a clock plus a keyed hash of the connection. The key is a
+//! secret the mux gets from an RNG with `init_secret()`, and no connection
+//! can be opened until it arrived.
+//!
+//! The mux should get its own `IP6Sender`, and its own `IP6Receiver` on a MAC
+//! user of its own, since both only support a single client. The TCP
+//! component (`components::tcp_mux`) sets this up.
+
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::tcp::tcp_socket::TcpSocket;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::List;
+use kernel::debug;
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ErrorCode;
+
+/// First port given out to sockets that connect without choosing one
+/// (RFC 6335, section 6).
+const EPHEMERAL_PORTS_START: u16 = 49152;
+
+/// Maximum segment size advertised: the largest segment that fits an IPv6
+/// packet reassembled by 6LoWPAN, which holds up to 1280 bytes.
+const MAX_MSS: u16 = 1280 - 40 - 20;
+
+pub struct MuxTcp<'a, A: Alarm<'a>> {
+    sockets: List<'a, TcpSocket<'a, A>>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Rng<'a>,
+    /// Buffer the payload of segments is copied into for sending.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Whether the `IP6Sender` is sending a segment.
+    sending: Cell<bool>,
+    /// Reset to send before the segments of sockets.
+    reset: OptionalCell<(IPAddr, TCPHeader, &'static NetworkCapability)>,
+    next_port: Cell<u16>,
+    /// Key of the hash in initial sequence numbers, and how many of its words
+    /// arrived from the RNG.
+    secret: Cell<[u32; 4]>,
+    secret_words: Cell<usize>,
+    /// Capability to answer segments that match no socket with a reset.
+    net_cap: &'static NetworkCapability,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a, A: Alarm<'a>> MuxTcp<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Rng<'a>,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> MuxTcp<'a, A> {
+        MuxTcp {
+            sockets: List::new(),
+            ip_sender: ip_sender,
+            alarm: alarm,
+            rng: rng,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            reset: OptionalCell::empty(),
+            next_port: Cell::new(EPHEMERAL_PORTS_START),
+            secret: Cell::new([0; 4]),
+            secret_words: Cell::new(0),
+            net_cap: net_cap,
+            ip_vis: ip_vis,
+        }
+    }
+
+    pub fn add_socket(&self, socket: &'a TcpSocket<'a, A>) {
+        self.sockets.push_tail(socket);
+    }
+
+    /// Asks the RNG for the secret of initial sequence numbers. The mux must
+    /// be the client of the RNG.
+    pub fn init_secret(&self) -> Result<(), ErrorCode> {
+        self.secret_words.set(0);
+        self.rng.get()
+    }
+
+    pub(super) fn now(&self) -> A::Ticks {
+        self.alarm.now()
+    }
+
+    /// Whether a socket uses `port` as its local port.
+    pub(super) fn port_in_use(&self, port: u16) -> bool {
+        self.sockets.iter().any(|socket| socket.uses_port(port))
+    }
+
+    /// Returns an ephemeral port no socket uses, if there is one.
+    pub(super) fn free_port(&self) -> Option<u16> {
+        let count = (u16::MAX - EPHEMERAL_PORTS_START) as usize + 1;
+        for _ in 0..count {
+            let port = self.next_port.get();
+            self.next_port
+                .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORTS_START));
+            if !self.port_in_use(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    pub(super) fn remote_addr_valid(
+        &self,
+        addr: IPAddr,
+        net_cap: &'static NetworkCapability,
+    ) -> bool {
+        net_cap.remote_addr_valid(addr, self.ip_vis)
+    }
+
+    /// Returns the initial sequence number of a connection from `local_port`
+    /// to `remote_port` at `remote_addr`, or `None` if the secret did not
+    /// arrive yet. It follows a clock, offset by a keyed hash of the
+    /// connection (RFC 6528, section 3). The local address is the same for
+    /// all connections, and left out.
+    pub(super) fn initial_sequence_number(
+        &self,
+        local_port: u16,
+        remote_addr: IPAddr,
+        remote_port: u16,
+    ) -> Option<u32> {
+        if self.secret_words.get() < self.secret.get().len() {
+            return None;
+        }
+        let mut connection = [0; 20];
+        connection[..16].copy_from_slice(&remote_addr.0);
+        connection[16..18].copy_from_slice(&local_port.to_be_bytes());
+        connection[18..].copy_from_slice(&remote_port.to_be_bytes());
+        let offset = siphash24(self.secret.get(), &connection) as u32;
+        Some(self.now().into_u32().wrapping_mul(250).wrapping_add(offset))
+    }
+
+    /// Returns the maximum segment size to advertise for a receive buffer of
+    /// `rx_capacity` bytes.
+    pub(super) fn mss(&self, rx_capacity: usize) -> u16 {
+        cmp::min(rx_capacity, MAX_MSS as usize) as u16
+    }
+
+    /// Queues a reset to `dst`. Only one reset is queued at a time, others
+    /// are dropped.
+    pub(super) fn send_reset(
+        &self,
+        dst: IPAddr,
+        header: TCPHeader,
+        net_cap: &'static NetworkCapability,
+    ) {
+        if self.reset.is_none() {
+            self.reset.set((dst, header, net_cap));
+        }
+    }
+
+    /// Queues the reset answering a segment from `src` with `header` and
+    /// `data_len` bytes of payload (RFC 9293, section 3.10.7.1).
+    pub(super) fn reset_reply(
+        &self,
+        src: IPAddr,
+        header: &TCPHeader,
+        data_len: usize,
+        net_cap: &'static NetworkCapability,
+    ) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        let mut reset = TCPHeader::new();
+        reset.set_src_port(header.get_dst_port());
+        reset.set_dst_port(header.get_src_port());
+        if header.has_flags(tcp_flags::ACK) {
+            reset.set_seq_num(header.get_ack_num());
+            reset.set_flags(tcp_flags::RST);
+        } else {
+            let seg_len = data_len as u32
+                + header.has_flags(tcp_flags::SYN) as u32
+                + header.has_flags(tcp_flags::FIN) as u32;
+            reset.set_ack_num(header.get_seq_num().wrapping_add(seg_len));
+            reset.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.send_reset(src, reset, net_cap);
+    }
+
+    /// Sends the next segment, if the `IP6Sender` is idle.
+    pub(super) fn transmit(&self) {
+        if self.sending.get() {
+            return;
+        }
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        buf.reset();
+
+        let segment = self
+            .reset
+            .take()
+            .map(|(dst, header, net_cap)| (dst, header, 0, net_cap))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find_map(|socket| socket.next_segment(&mut buf[..]))
+            });
+        if let Some((dst, header, len, net_cap)) = segment {
+            buf.slice(0..len);
+            // The sender copies the payload, so the buffer can be used again
+            // right away.
+            match self
+                .ip_sender
+                .send_to(dst, TransportHeader::TCP(header), &buf, net_cap)
+            {
+                Ok(()) => self.sending.set(true),
+                // The segment is sent again once its retransmission timer
+                // expires.
+                Err(e) => debug!("[TCP] send failed: {:?}", e),
+            }
+        }
+        buf.reset();
+        self.tx_buffer.replace(buf);
+    }
+
+    /// Sets the alarm for the socket timer that expires first.
+    pub(super) fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .sockets
+            .iter()
+            .filter_map(|socket| socket.timer_remaining(now))
+            .min_by_key(|remaining| remaining.into_u32());
+        match next {
+            Some(remaining) => {
+                let dt = cmp::max(remaining.into_u32(), self.alarm.minimum_dt().into_u32());
+                self.alarm.set_alarm(now, A::Ticks::from(dt));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for MuxTcp<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Other transport protocols share the IP receiver.
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let src_addr = ip_header.get_src_addr();
+        let src_port = header.get_src_port();
+        let dst_port = header.get_dst_port();
+        let data = &payload[offset..];
+
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| socket.matches(src_addr, src_port, dst_port))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find(|socket| socket.is_listening(dst_port))
+            });
+        match socket {
+            Some(socket) => socket.segment_arrived(src_addr, &header, data),
+            None => {
+                if self.net_cap.remote_addr_valid(src_addr, self.ip_vis) {
+                    self.reset_reply(src_addr, &header, data.len(), self.net_cap);
+                }
+                self.transmit();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6SendClient for MuxTcp<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if result != Ok(()) {
+            debug!("[TCP] send_done: {:?}", result);
+        }
+        self.sending.set(false);
+        self.transmit();
+    }
+}
+
+impl<'a, A: Alarm<'a>> rng::Client for MuxTcp<'a, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        if error.is_err() {
+            return rng::Continue::Done;
+        }
+        let mut secret = self.secret.get();
+        let mut words = self.secret_words.get();
+        while words < secret.len() {
+            match randomness.next() {
+                Some(random) => {
+                    secret[words] = random;
+                    words += 1;
+                }
+                None => break,
+            }
+        }
+        self.secret.set(secret);
+        self.secret_words.set(words);
+        if words < secret.len() {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MuxTcp<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for socket in self.sockets.iter() {
+            if socket
+                .timer_remaining(now)
+                .map_or(false, |remaining| remaining.into_u32() == 0)
+            {
+                socket.timer_expired();
+            }
+        }
+        self.transmit();
+        self.update_alarm();
+    }
+}
+
+/// SipHash-2-4 of `data` with the 128 bit `key`, given as four words from the
+/// least significant one on.
+fn siphash24(key: [u32; 4], data: &[u8]) -> u64 {
+    let k0 = key[0] as u64 | (key[1] as u64) << 32;
+    let k1 = key[2] as u64 | (key[3] as u64) << 32;
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+
+    // The last word holds the remaining bytes, and the length in its top byte.
+    let chunks = data.chunks_exact(8);
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    for word in chunks.map(|chunk| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(chunk);
+        u64::from_le_bytes(bytes)
+    }) {
+        v[3] ^= word;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= word;
+    }
+    let word = u64::from_le_bytes(last);
+    v[3] ^= word;
+    round(&mut v);
+    round(&mut v);
+    v[0] ^= word;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn siphash24_matches_reference() {
+        // The test vector of the SipHash paper, appendix A.
+        let key = [0x03020100, 0x07060504, 0x0b0a0908, 0x0f0e0d0c];
+        let data: [u8; 15] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+        assert_eq!(siphash24(key, &data), 0xa129ca6149be45e5);
+        assert_ne!(siphash24([1, 0, 0, 0], &data), siphash24(key, &data));
+    }
+}
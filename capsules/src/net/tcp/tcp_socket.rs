@@ -0,0 +1,904 @@
+//! This file contains the definition and implementation of TCP sockets, which
+//! hold the state of one TCP connection each, following RFC 9293.
+//!
+//! A [TcpSocket](struct.TcpSocket.html) either actively opens a connection
+//! with `connect()`, or waits for a peer to open one with `listen()`. Each
+//! socket serves a single connection: once it is closed, the socket can open
+//! or wait for another one. Data is buffered in a send buffer until the peer
+//! acknowledges it, and in a receive buffer until the client reads it, so the
+//! size of these buffers bounds the data in flight in each direction. Received
+//! segments are only accepted in order; segments after a missing one are
+//! dropped and retransmitted by the peer.
+//!
+//! Sockets are virtualized by a `MuxTcp`, which sends their segments over an
+//! `IP6Sender`, passes them the segments it receives, and runs their timers.
+//! The [TcpClient](trait.TcpClient.html) of a socket is told when the
+//! connection opens and closes, and when data can be read or written.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp_mux::MuxTcp;
+use crate::net::tcp::{seq_le, seq_lt, tcp_flags, TCPHeader};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{ListLink, ListNode};
+use kernel::hil::time::{Alarm, Frequency, Ticks};
+use kernel::ErrorCode;
+
+/// Maximum segment size assumed for peers that do not send the option, which
+/// fills an IPv6 packet of the minimum MTU (RFC 9293, section 3.7.1).
+pub const DEFAULT_MSS: u16 = 1220;
+
+const INITIAL_RTO_MS: u32 = 1000;
+const MIN_RTO_MS: u32 = 1000;
+const MAX_RTO_MS: u32 = 60_000;
+/// Number of retransmissions of a segment before the connection is aborted.
+const MAX_RETRANSMISSIONS: u8 = 8;
+/// Time a connection closed by this side waits for retransmitted segments of
+/// the peer. This is much less than the 2 MSL of RFC 9293, so that sockets are
+/// available again soon; with the few connections of a device, segments of an
+/// old connection are unlikely to be mistaken for a new one.
+const TIME_WAIT_MS: u32 = 10_000;
+
+/// States of a TCP connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed = 0,
+    Listen = 1,
+    SynSent = 2,
+    SynReceived = 3,
+    Established = 4,
+    FinWait1 = 5,
+    FinWait2 = 6,
+    CloseWait = 7,
+    Closing = 8,
+    LastAck = 9,
+    TimeWait = 10,
+}
+
+/// The client of a `TcpSocket` implements this trait to be told about the
+/// connection. `socket` is the identifier the socket was created with, which
+/// tells apart the sockets of a client.
+pub trait TcpClient {
+    /// The connection is established, and data can be sent.
+    fn connected(&self, socket: usize);
+
+    /// Data was received, and `available` bytes can be read with
+    /// `TcpSocket::recv()`.
+    fn received(&self, socket: usize, available: usize);
+
+    /// The peer acknowledged data, and `space` bytes can be written to the
+    /// send buffer with `TcpSocket::send()`.
+    fn sent(&self, socket: usize, space: usize);
+
+    /// The peer closed its side of the connection. It receives the data still
+    /// sent until this side closes too.
+    fn peer_closed(&self, socket: usize);
+
+    /// The connection is closed. The result is `Ok(())` if both sides closed
+    /// it, `CANCEL` if the peer reset it, and `NOACK` if the peer stopped
+    /// acknowledging segments.
+    fn closed(&self, socket: usize, result: Result<(), ErrorCode>);
+}
+
+/// A byte queue in a buffer, wrapping around at its end.
+struct ByteRing {
+    buffer: TakeCell<'static, [u8]>,
+    start: Cell<usize>,
+    len: Cell<usize>,
+}
+
+impl ByteRing {
+    fn new(buffer: &'static mut [u8]) -> ByteRing {
+        ByteRing {
+            buffer: TakeCell::new(buffer),
+            start: Cell::new(0),
+            len: Cell::new(0),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.map_or(0, |buffer| buffer.len())
+    }
+
+    fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    fn space(&self) -> usize {
+        self.capacity() - self.len.get()
+    }
+
+    fn clear(&self) {
+        self.start.set(0);
+        self.len.set(0);
+    }
+
+    /// Appends as much of `data` as fits, and returns how much did.
+    fn push(&self, data: &[u8]) -> usize {
+        let count = cmp::min(data.len(), self.space());
+        self.buffer.map(|buffer| {
+            let end = self.start.get() + self.len.get();
+            for (i, byte) in data[..count].iter().enumerate() {
+                buffer[(end + i) % buffer.len()] = *byte;
+            }
+        });
+        self.len.set(self.len.get() + count);
+        count
+    }
+
+    /// Copies the bytes from `offset` in the queue into `out`, as many as
+    /// there are, and returns how many were copied.
+    fn peek(&self, offset: usize, out: &mut [u8]) -> usize {
+        let count = cmp::min(out.len(), self.len.get().saturating_sub(offset));
+        self.buffer.map(|buffer| {
+            let start = self.start.get() + offset;
+            for (i, byte) in out[..count].iter_mut().enumerate() {
+                *byte = buffer[(start + i) % buffer.len()];
+            }
+        });
+        count
+    }
+
+    /// Removes up to `count` bytes from the front of the queue.
+    fn consume(&self, count: usize) {
+        let count = cmp::min(count, self.len.get());
+        let capacity = self.capacity();
+        if capacity > 0 {
+            self.start.set((self.start.get() + count) % capacity);
+        }
+        self.len.set(self.len.get() - count);
+    }
+}
+
+/// A TCP connection endpoint. Created with a send and receive buffer, and
+/// registered with a `MuxTcp` with `MuxTcp::add_socket()`.
+pub struct TcpSocket<'a, A: Alarm<'a>> {
+    mux: &'a MuxTcp<'a, A>,
+    id: usize,
+    client: OptionalCell<&'a dyn TcpClient>,
+    next: ListLink<'a, TcpSocket<'a, A>>,
+    net_cap: OptionalCell<&'static NetworkCapability>,
+
+    state: Cell<TcpState>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+
+    // Send sequence variables (RFC 9293, section 3.3.1).
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    snd_wnd: Cell<u16>,
+    snd_mss: Cell<u16>,
+    /// Data from `snd_una` on, sent or not.
+    tx: ByteRing,
+    /// Whether the FIN was sent, after the last byte of data.
+    fin_sent: Cell<bool>,
+
+    // Receive sequence variables.
+    rcv_nxt: Cell<u32>,
+    /// Data received and not yet read by the client.
+    rx: ByteRing,
+    /// Whether to send an acknowledgement, even without data.
+    ack_pending: Cell<bool>,
+
+    /// Reference and duration of the retransmission or time-wait timer.
+    timer: OptionalCell<(A::Ticks, A::Ticks)>,
+    rto_ms: Cell<u32>,
+    srtt_ms: OptionalCell<u32>,
+    rttvar_ms: Cell<u32>,
+    /// Sequence number whose acknowledgement is timed, and when it was sent.
+    rtt_sample: OptionalCell<(u32, A::Ticks)>,
+    retransmissions: Cell<u8>,
+    /// Whether a byte may be sent beyond a zero window, to probe it.
+    probe: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> ListNode<'a, TcpSocket<'a, A>> for TcpSocket<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, TcpSocket<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: Alarm<'a>> TcpSocket<'a, A> {
+    pub fn new(
+        mux: &'a MuxTcp<'a, A>,
+        id: usize,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> TcpSocket<'a, A> {
+        TcpSocket {
+            mux: mux,
+            id: id,
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+            net_cap: OptionalCell::empty(),
+            state: Cell::new(TcpState::Closed),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            snd_mss: Cell::new(DEFAULT_MSS),
+            tx: ByteRing::new(tx_buffer),
+            fin_sent: Cell::new(false),
+            rcv_nxt: Cell::new(0),
+            rx: ByteRing::new(rx_buffer),
+            ack_pending: Cell::new(false),
+            timer: OptionalCell::empty(),
+            rto_ms: Cell::new(INITIAL_RTO_MS),
+            srtt_ms: OptionalCell::empty(),
+            rttvar_ms: Cell::new(0),
+            rtt_sample: OptionalCell::empty(),
+            retransmissions: Cell::new(0),
+            probe: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TcpClient) {
+        self.client.set(client);
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.state.get()
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port.get()
+    }
+
+    /// Returns the address and port of the peer.
+    pub fn remote_endpoint(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+
+    /// Waits for a peer to connect to `local_port`. The client is told once
+    /// the connection is established.
+    ///
+    /// Returns `ALREADY` if the socket is not closed, `INVAL` for port zero,
+    /// and `BUSY` if another socket uses the port.
+    pub fn listen(
+        &self,
+        local_port: u16,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != TcpState::Closed {
+            return Err(ErrorCode::ALREADY);
+        }
+        if local_port == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.mux.port_in_use(local_port) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.reset_connection();
+        self.net_cap.set(net_cap);
+        self.local_port.set(local_port);
+        self.state.set(TcpState::Listen);
+        Ok(())
+    }
+
+    /// Opens a connection to `remote_port` at `remote_addr`, from
+    /// `local_port`, or from a free port if it is zero. The client is told
+    /// once the connection is established. Data can already be written to
+    /// the send buffer before.
+    ///
+    /// Returns `ALREADY` if the socket is not closed, `INVAL` if `net_cap`
+    /// does not allow `remote_addr`, and `BUSY` if another socket uses the
+    /// local port, no port is free, or the mux has no secret for initial
+    /// sequence numbers yet.
+    pub fn connect(
+        &self,
+        remote_addr: IPAddr,
+        remote_port: u16,
+        local_port: u16,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != TcpState::Closed {
+            return Err(ErrorCode::ALREADY);
+        }
+        if remote_port == 0 || !self.mux.remote_addr_valid(remote_addr, net_cap) {
+            return Err(ErrorCode::INVAL);
+        }
+        let local_port = match local_port {
+            0 => self.mux.free_port().ok_or(ErrorCode::BUSY)?,
+            port if self.mux.port_in_use(port) => return Err(ErrorCode::BUSY),
+            port => port,
+        };
+        let iss = self
+            .mux
+            .initial_sequence_number(local_port, remote_addr, remote_port)
+            .ok_or(ErrorCode::BUSY)?;
+        self.reset_connection();
+        self.net_cap.set(net_cap);
+        self.local_port.set(local_port);
+        self.remote_addr.set(remote_addr);
+        self.remote_port.set(remote_port);
+        self.open(TcpState::SynSent, iss);
+        self.mux.transmit();
+        Ok(())
+    }
+
+    /// Appends as much of `data` to the send buffer as fits, and returns how
+    /// many bytes did. The client is told when the peer acknowledges them.
+    ///
+    /// Returns `OFF` if the connection is not open, or was closed by this
+    /// side.
+    pub fn send(&self, data: &[u8]) -> Result<usize, ErrorCode> {
+        match self.state.get() {
+            TcpState::SynSent
+            | TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::CloseWait => {
+                let count = self.tx.push(data);
+                self.mux.transmit();
+                Ok(count)
+            }
+            _ => Err(ErrorCode::OFF),
+        }
+    }
+
+    /// Moves as much received data into `buf` as fits, and returns how many
+    /// bytes it moved.
+    pub fn recv(&self, buf: &mut [u8]) -> usize {
+        let space_before = self.rx.space();
+        let count = self.rx.peek(0, buf);
+        self.rx.consume(count);
+        // Tell the peer about the larger window, if it could not send a full
+        // segment before.
+        if count > 0 && space_before < cmp::min(self.snd_mss.get() as usize, self.rx.capacity() / 2)
+        {
+            self.ack_pending.set(true);
+            self.mux.transmit();
+        }
+        count
+    }
+
+    /// Returns the number of bytes that can be read with `recv()`.
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Returns the number of bytes that can be written with `send()`.
+    pub fn send_space(&self) -> usize {
+        self.tx.space()
+    }
+
+    /// Closes this side of the connection, once the data in the send buffer
+    /// is sent. Data is still received until the peer closes its side. The
+    /// client is told once the connection is closed.
+    ///
+    /// A connection that is not established yet is aborted instead, and the
+    /// client is not told.
+    pub fn close(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            TcpState::Listen | TcpState::SynSent | TcpState::SynReceived => {
+                self.abort();
+                Ok(())
+            }
+            TcpState::Established => {
+                self.state.set(TcpState::FinWait1);
+                self.mux.transmit();
+                Ok(())
+            }
+            TcpState::CloseWait => {
+                self.state.set(TcpState::LastAck);
+                self.mux.transmit();
+                Ok(())
+            }
+            TcpState::Closed => Err(ErrorCode::OFF),
+            _ => Err(ErrorCode::ALREADY),
+        }
+    }
+
+    /// Closes the connection at once, telling the peer with a reset if it is
+    /// open. The client is not told.
+    pub fn abort(&self) {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::TimeWait => {}
+            _ => {
+                let mut header = self.header(tcp_flags::RST);
+                header.set_ack_num(0);
+                if let Some(net_cap) = self.net_cap.extract() {
+                    self.mux.send_reset(self.remote_addr.get(), header, net_cap);
+                }
+            }
+        }
+        self.state.set(TcpState::Closed);
+        self.timer.clear();
+        self.mux.transmit();
+    }
+
+    /// Whether the socket uses `port` as local port.
+    pub(super) fn uses_port(&self, port: u16) -> bool {
+        self.state.get() != TcpState::Closed && self.local_port.get() == port
+    }
+
+    /// Whether a segment from `port` at `addr` to `local_port` belongs to the
+    /// connection of this socket.
+    pub(super) fn matches(&self, addr: IPAddr, port: u16, local_port: u16) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => false,
+            _ => {
+                self.local_port.get() == local_port
+                    && self.remote_port.get() == port
+                    && self.remote_addr.get() == addr
+            }
+        }
+    }
+
+    /// Whether the socket waits for connections to `local_port`.
+    pub(super) fn is_listening(&self, local_port: u16) -> bool {
+        self.state.get() == TcpState::Listen && self.local_port.get() == local_port
+    }
+
+    fn reset_connection(&self) {
+        self.tx.clear();
+        self.rx.clear();
+        self.fin_sent.set(false);
+        self.ack_pending.set(false);
+        self.timer.clear();
+        self.rto_ms.set(INITIAL_RTO_MS);
+        self.srtt_ms.clear();
+        self.rttvar_ms.set(0);
+        self.rtt_sample.clear();
+        self.retransmissions.set(0);
+        self.probe.set(false);
+        self.snd_mss.set(DEFAULT_MSS);
+    }
+
+    /// Starts the handshake, in `SynSent` or `SynReceived`, with the initial
+    /// sequence number `iss`.
+    fn open(&self, state: TcpState, iss: u32) {
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.state.set(state);
+    }
+
+    fn rcv_wnd(&self) -> u16 {
+        cmp::min(self.rx.space(), u16::MAX as usize) as u16
+    }
+
+    /// Header of a segment of this connection, at `snd_nxt`.
+    fn header(&self, flags: u16) -> TCPHeader {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local_port.get());
+        header.set_dst_port(self.remote_port.get());
+        header.set_seq_num(self.snd_nxt.get());
+        header.set_ack_num(self.rcv_nxt.get());
+        header.set_flags(flags);
+        header.set_window(self.rcv_wnd());
+        header
+    }
+
+    /// Bytes of data sent and not yet acknowledged.
+    fn data_in_flight(&self) -> usize {
+        let mut in_flight = self.snd_nxt.get().wrapping_sub(self.snd_una.get()) as usize;
+        if self.fin_sent.get() {
+            in_flight = in_flight.saturating_sub(1);
+        }
+        cmp::min(in_flight, self.tx.len())
+    }
+
+    /// Builds the next segment to send, if any, with its payload in `buf`.
+    /// Returns the destination, header, payload length, and the network
+    /// capability to send it with.
+    pub(super) fn next_segment(
+        &self,
+        buf: &mut [u8],
+    ) -> Option<(IPAddr, TCPHeader, usize, &'static NetworkCapability)> {
+        let net_cap = self.net_cap.extract()?;
+        let state = self.state.get();
+        let (header, len) = match state {
+            TcpState::Closed | TcpState::Listen => return None,
+            TcpState::SynSent | TcpState::SynReceived => {
+                // The SYN is sent again after each retransmission timeout.
+                if self.snd_nxt.get() != self.iss.get() {
+                    return None;
+                }
+                let flags = match state {
+                    TcpState::SynSent => tcp_flags::SYN,
+                    _ => tcp_flags::SYN | tcp_flags::ACK,
+                };
+                let mut header = self.header(flags);
+                header.set_mss(Some(self.mux.mss(self.rx.capacity())));
+                self.snd_nxt.set(self.iss.get().wrapping_add(1));
+                self.start_retransmission();
+                (header, 0)
+            }
+            _ => {
+                let in_flight = self.data_in_flight();
+                let unsent = self.tx.len() - in_flight;
+                let mut window = (self.snd_wnd.get() as usize).saturating_sub(in_flight);
+                if self.probe.get() && in_flight == 0 {
+                    window = cmp::max(window, 1);
+                }
+                let max_len = cmp::min(buf.len(), self.snd_mss.get() as usize);
+                let len = cmp::min(cmp::min(unsent, window), max_len);
+                let closing = match state {
+                    TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck => true,
+                    _ => false,
+                };
+                let fin = closing && !self.fin_sent.get() && len == unsent;
+                if len == 0 && !fin && !self.ack_pending.get() {
+                    return None;
+                }
+
+                let mut flags = tcp_flags::ACK;
+                if len > 0 {
+                    flags |= tcp_flags::PSH;
+                }
+                if fin {
+                    flags |= tcp_flags::FIN;
+                }
+                let header = self.header(flags);
+                self.tx.peek(in_flight, &mut buf[..len]);
+                if len > 0 || fin {
+                    if self.rtt_sample.is_none() {
+                        self.rtt_sample.set((self.snd_nxt.get(), self.mux.now()));
+                    }
+                    self.snd_nxt
+                        .set(self.snd_nxt.get().wrapping_add(len as u32 + fin as u32));
+                    self.fin_sent.set(self.fin_sent.get() || fin);
+                    self.probe.set(false);
+                    if self.timer.is_none() {
+                        self.start_retransmission();
+                    }
+                }
+                (header, len)
+            }
+        };
+        self.ack_pending.set(false);
+        Some((self.remote_addr.get(), header, len, net_cap))
+    }
+
+    fn start_timer(&self, ms: u32) {
+        self.timer.set((self.mux.now(), A::ticks_from_ms(ms)));
+        self.mux.update_alarm();
+    }
+
+    fn start_retransmission(&self) {
+        self.start_timer(self.rto_ms.get());
+    }
+
+    /// Returns the ticks until the timer of this socket expires, if it runs.
+    pub(super) fn timer_remaining(&self, now: A::Ticks) -> Option<A::Ticks> {
+        self.timer.extract().map(|(reference, dt)| {
+            let elapsed = now.wrapping_sub(reference);
+            if elapsed.into_u32() >= dt.into_u32() {
+                A::Ticks::from(0)
+            } else {
+                dt.wrapping_sub(elapsed)
+            }
+        })
+    }
+
+    /// Called by the mux when the timer of this socket expires.
+    pub(super) fn timer_expired(&self) {
+        self.timer.clear();
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => {}
+            TcpState::TimeWait => self.set_closed(Ok(())),
+            _ => {
+                if self.retransmissions.get() >= MAX_RETRANSMISSIONS {
+                    self.abort();
+                    self.client
+                        .map(|client| client.closed(self.id, Err(ErrorCode::NOACK)));
+                    return;
+                }
+                self.retransmissions.set(self.retransmissions.get() + 1);
+                self.rto_ms.set(cmp::min(self.rto_ms.get() * 2, MAX_RTO_MS));
+                // Send everything that is not acknowledged again, and do not
+                // time the retransmitted segments (Karn's algorithm).
+                self.snd_nxt.set(self.snd_una.get());
+                self.fin_sent.set(false);
+                self.rtt_sample.clear();
+                self.probe.set(true);
+            }
+        }
+    }
+
+    fn set_closed(&self, result: Result<(), ErrorCode>) {
+        self.state.set(TcpState::Closed);
+        self.timer.clear();
+        self.client.map(|client| client.closed(self.id, result));
+    }
+
+    /// Updates the retransmission timeout with the round-trip time of the
+    /// timed segment, if `ack` acknowledges it (RFC 6298).
+    fn sample_rtt(&self, ack: u32) {
+        let sample = match self.rtt_sample.extract() {
+            Some((seq, sent)) if seq_lt(seq, ack) => sent,
+            _ => return,
+        };
+        self.rtt_sample.clear();
+        let ticks = self.mux.now().wrapping_sub(sample).into_u32() as u64;
+        let rtt = (ticks * 1000 / A::Frequency::frequency() as u64) as u32;
+        let (srtt, rttvar) = match self.srtt_ms.extract() {
+            None => (rtt, rtt / 2),
+            Some(srtt) => {
+                let delta = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                (
+                    srtt - srtt / 8 + rtt / 8,
+                    self.rttvar_ms.get() - self.rttvar_ms.get() / 4 + delta / 4,
+                )
+            }
+        };
+        self.srtt_ms.set(srtt);
+        self.rttvar_ms.set(rttvar);
+        let rto = srtt.saturating_add(cmp::max(1, rttvar.saturating_mul(4)));
+        self.rto_ms
+            .set(cmp::max(MIN_RTO_MS, cmp::min(rto, MAX_RTO_MS)));
+    }
+
+    /// Whether a segment starting at `seq`, `len` long in sequence space, is
+    /// at least partly in the receive window (RFC 9293, section 3.10.7.4).
+    fn acceptable(&self, seq: u32, len: u32) -> bool {
+        let rcv_nxt = self.rcv_nxt.get();
+        let wnd = self.rcv_wnd() as u32;
+        let in_window = |n: u32| seq_le(rcv_nxt, n) && seq_lt(n, rcv_nxt.wrapping_add(wnd));
+        match (len, wnd) {
+            (0, 0) => seq == rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+        }
+    }
+
+    /// Processes a segment of the connection of this socket, or a connection
+    /// request if it listens.
+    pub(super) fn segment_arrived(&self, src_addr: IPAddr, header: &TCPHeader, data: &[u8]) {
+        match self.state.get() {
+            TcpState::Closed => {}
+            TcpState::Listen => self.listen_segment(src_addr, header),
+            TcpState::SynSent => self.syn_sent_segment(header),
+            _ => self.synchronized_segment(header, data),
+        }
+        self.mux.transmit();
+    }
+
+    fn listen_segment(&self, src_addr: IPAddr, header: &TCPHeader) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        if header.has_flags(tcp_flags::ACK) {
+            self.reset_reply(src_addr, header);
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        let allowed = self.net_cap.extract().map_or(false, |net_cap| {
+            self.mux.remote_addr_valid(src_addr, net_cap)
+        });
+        if !allowed {
+            return;
+        }
+        // Without a secret for the initial sequence number, the SYN is
+        // dropped, and sent again by the peer.
+        let iss = match self.mux.initial_sequence_number(
+            self.local_port.get(),
+            src_addr,
+            header.get_src_port(),
+        ) {
+            Some(iss) => iss,
+            None => return,
+        };
+        // Data sent with the SYN is not accepted, and sent again by the peer.
+        self.remote_addr.set(src_addr);
+        self.remote_port.set(header.get_src_port());
+        self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        self.snd_wnd.set(header.get_window());
+        self.snd_mss.set(header.get_mss().unwrap_or(DEFAULT_MSS));
+        self.open(TcpState::SynReceived, iss);
+    }
+
+    fn syn_sent_segment(&self, header: &TCPHeader) {
+        let iss = self.iss.get();
+        let ack = header.get_ack_num();
+        let ack_acceptable =
+            header.has_flags(tcp_flags::ACK) && seq_lt(iss, ack) && seq_le(ack, self.snd_nxt.get());
+        if header.has_flags(tcp_flags::ACK) && !ack_acceptable {
+            if !header.has_flags(tcp_flags::RST) {
+                self.reset_reply(self.remote_addr.get(), header);
+            }
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            if ack_acceptable {
+                // The connection was refused.
+                self.set_closed(Err(ErrorCode::CANCEL));
+            }
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        self.snd_mss.set(header.get_mss().unwrap_or(DEFAULT_MSS));
+        self.ack_pending.set(true);
+        if ack_acceptable {
+            self.sample_rtt(ack);
+            self.snd_una.set(ack);
+            self.snd_wnd.set(header.get_window());
+            self.establish();
+        } else {
+            // Both sides opened the connection at the same time: send the
+            // SYN again, with an acknowledgement.
+            self.state.set(TcpState::SynReceived);
+            self.snd_nxt.set(iss);
+        }
+    }
+
+    fn establish(&self) {
+        self.state.set(TcpState::Established);
+        self.timer.clear();
+        self.retransmissions.set(0);
+        self.client.map(|client| client.connected(self.id));
+    }
+
+    fn synchronized_segment(&self, header: &TCPHeader, data: &[u8]) {
+        let seq = header.get_seq_num();
+        let syn = header.has_flags(tcp_flags::SYN);
+        let fin = header.has_flags(tcp_flags::FIN);
+        let seg_len = data.len() as u32 + syn as u32 + fin as u32;
+        if !self.acceptable(seq, seg_len) {
+            if !header.has_flags(tcp_flags::RST) {
+                self.ack_pending.set(true);
+            }
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            // Only a reset at the expected sequence number is accepted, others
+            // are answered with an acknowledgement (RFC 5961, section 3.2).
+            if seq == self.rcv_nxt.get() {
+                self.set_closed(Err(ErrorCode::CANCEL));
+            } else {
+                self.ack_pending.set(true);
+            }
+            return;
+        }
+        if syn {
+            // RFC 5961, section 4.2: answer with an acknowledgement.
+            self.ack_pending.set(true);
+            return;
+        }
+        if !header.has_flags(tcp_flags::ACK) {
+            return;
+        }
+        if !self.process_ack(header) {
+            return;
+        }
+
+        let mut data_done = true;
+        match self.state.get() {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                if !data.is_empty() {
+                    data_done = self.receive_data(seq, data);
+                }
+            }
+            _ => {}
+        }
+
+        let fin_seq = seq.wrapping_add(data.len() as u32);
+        if fin && data_done && fin_seq == self.rcv_nxt.get() {
+            self.rcv_nxt.set(fin_seq.wrapping_add(1));
+            self.ack_pending.set(true);
+            match self.state.get() {
+                TcpState::Established => self.state.set(TcpState::CloseWait),
+                TcpState::FinWait1 => self.state.set(TcpState::Closing),
+                TcpState::FinWait2 => self.enter_time_wait(),
+                _ => return,
+            }
+            self.client.map(|client| client.peer_closed(self.id));
+        }
+    }
+
+    /// Processes the acknowledgement of a segment. Returns false if the
+    /// segment should not be processed further.
+    fn process_ack(&self, header: &TCPHeader) -> bool {
+        let ack = header.get_ack_num();
+        let snd_una = self.snd_una.get();
+        let snd_nxt = self.snd_nxt.get();
+        if seq_lt(snd_nxt, ack) {
+            // Acknowledges something not sent yet.
+            self.ack_pending.set(true);
+            return false;
+        }
+        if self.state.get() == TcpState::SynReceived {
+            if !seq_lt(snd_una, ack) {
+                self.reset_reply(self.remote_addr.get(), header);
+                return false;
+            }
+            // The SYN takes one sequence number, but no data.
+            self.sample_rtt(ack);
+            self.snd_una.set(snd_una.wrapping_add(1));
+            self.snd_wnd.set(header.get_window());
+            self.establish();
+            return self.process_ack(header);
+        }
+        if seq_lt(ack, snd_una) {
+            // An old duplicate.
+            return true;
+        }
+
+        self.snd_wnd.set(header.get_window());
+        let acked = ack.wrapping_sub(snd_una) as usize;
+        if acked > 0 {
+            self.sample_rtt(ack);
+            let data_acked = cmp::min(acked, self.tx.len());
+            self.tx.consume(data_acked);
+            self.snd_una.set(ack);
+            self.retransmissions.set(0);
+            self.timer.clear();
+            if ack != snd_nxt || (self.snd_wnd.get() == 0 && self.tx.len() > 0) {
+                // Time the remaining data, or the probe of the zero window.
+                self.start_retransmission();
+            }
+            if data_acked > 0 {
+                self.client
+                    .map(|client| client.sent(self.id, self.tx.space()));
+            }
+        } else if self.snd_wnd.get() == 0 && self.tx.len() > 0 && self.timer.is_none() {
+            self.start_retransmission();
+        }
+
+        let fin_acked = self.fin_sent.get() && ack == self.snd_nxt.get();
+        match self.state.get() {
+            TcpState::FinWait1 if fin_acked => self.state.set(TcpState::FinWait2),
+            TcpState::Closing if fin_acked => self.enter_time_wait(),
+            TcpState::LastAck if fin_acked => {
+                self.set_closed(Ok(()));
+                return false;
+            }
+            TcpState::TimeWait => {
+                // A retransmitted FIN: acknowledge it again, and wait anew.
+                self.ack_pending.set(true);
+                self.start_timer(TIME_WAIT_MS);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Stores the data of a segment starting at `seq`. Returns whether all of
+    /// it was stored.
+    fn receive_data(&self, seq: u32, data: &[u8]) -> bool {
+        let rcv_nxt = self.rcv_nxt.get();
+        self.ack_pending.set(true);
+        if seq_lt(rcv_nxt, seq) {
+            // A segment is missing before this one.
+            return false;
+        }
+        let already_received = cmp::min(rcv_nxt.wrapping_sub(seq) as usize, data.len());
+        let new_data = &data[already_received..];
+        let count = self.rx.push(new_data);
+        self.rcv_nxt.set(rcv_nxt.wrapping_add(count as u32));
+        if count > 0 {
+            self.client
+                .map(|client| client.received(self.id, self.rx.len()));
+        }
+        count == new_data.len()
+    }
+
+    /// Answers an unacceptable segment from `addr` with a reset.
+    fn reset_reply(&self, addr: IPAddr, header: &TCPHeader) {
+        if let Some(net_cap) = self.net_cap.extract() {
+            self.mux.reset_reply(addr, header, 0, net_cap);
+        }
+    }
+
+    fn enter_time_wait(&self) {
+        self.state.set(TcpState::TimeWait);
+        self.start_timer(TIME_WAIT_MS);
+    }
+}
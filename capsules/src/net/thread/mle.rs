@@ -0,0 +1,132 @@
+//! Implements the framing of Mesh Link Establishment (MLE) messages as
+//! outlined in Chapter 4 of the Thread 1.1.1 Specification.
+//!
+//! MLE messages are sent over UDP to port 19788. They consist of a security
+//! suite, a command type, and a series of TLV parameters (see the `tlv`
+//! module).
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! The Thread specification secures MLE messages at the MLE layer, with a
+//! key derived from the master key of the network. This implementation
+//! instead relies on the IEEE 802.15.4 frame security of
+//! `ieee802154::framer`, in key index mode with the key of the current key
+//! sequence, which Thread uses for all other frames. MLE messages are thus
+//! sent with the security suite that denotes no MLE-layer security.
+
+use crate::net::ieee802154::{KeyId, SecurityLevel};
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u8, encode_u8};
+
+/// UDP port of MLE messages, both source and destination.
+pub const MLE_PORT: u16 = 19788;
+
+/// Security level of the frames of a Thread network.
+pub const SECURITY_LEVEL: SecurityLevel = SecurityLevel::EncMic32;
+
+/// Returns the key ID of the frames secured with the key of sequence number
+/// `key_sequence` (Section 7.2.2.2).
+pub fn key_id(key_sequence: u32) -> KeyId {
+    KeyId::Index(((key_sequence & 0x7f) + 1) as u8)
+}
+
+/// Value of the first byte of an MLE message.
+#[repr(u8)]
+pub enum SecuritySuite {
+    /// Secured at the MLE layer with an auxiliary security header and MIC.
+    Secured = 0,
+    /// Not secured at the MLE layer.
+    None = 255,
+}
+
+/// Value of the command type byte of an MLE message. Only the commands
+/// needed to attach an end device are listed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Command {
+    ParentRequest = 9,
+    ParentResponse = 10,
+    ChildIdRequest = 11,
+    ChildIdResponse = 12,
+    ChildUpdateRequest = 13,
+    ChildUpdateResponse = 14,
+    NotPresent,
+}
+
+impl From<u8> for Command {
+    fn from(command: u8) -> Self {
+        match command {
+            9 => Command::ParentRequest,
+            10 => Command::ParentResponse,
+            11 => Command::ChildIdRequest,
+            12 => Command::ChildIdResponse,
+            13 => Command::ChildUpdateRequest,
+            14 => Command::ChildUpdateResponse,
+            _ => Command::NotPresent,
+        }
+    }
+}
+
+/// Serializes the security suite and command type of a message without
+/// MLE-layer security. The TLVs of the message follow.
+pub fn encode_header(buf: &mut [u8], command: Command) -> SResult {
+    let offset = enc_consume!(buf; encode_u8, SecuritySuite::None as u8);
+    let offset = enc_consume!(buf, offset; encode_u8, command as u8);
+    stream_done!(offset)
+}
+
+/// Deserializes the security suite and command type of a message. Messages
+/// secured at the MLE layer are not supported, and yield an error.
+pub fn decode_header(buf: &[u8]) -> SResult<Command> {
+    let (offset, security_suite) = dec_try!(buf; decode_u8);
+    stream_cond!(security_suite == SecuritySuite::None as u8);
+    let (offset, command) = dec_try!(buf, offset; decode_u8);
+    stream_done!(offset, Command::from(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trips() {
+        let mut buf = [0; 2];
+        assert_eq!(
+            encode_header(&mut buf, Command::ChildIdRequest).done(),
+            Some((2, ()))
+        );
+        assert_eq!(buf, [255, 11]);
+        assert_eq!(
+            decode_header(&buf).done(),
+            Some((2, Command::ChildIdRequest))
+        );
+        assert_eq!(
+            decode_header(&[255, 0]).done(),
+            Some((2, Command::NotPresent))
+        );
+        assert!(encode_header(&mut buf[..1], Command::ParentRequest)
+            .done()
+            .is_none());
+    }
+
+    #[test]
+    fn secured_messages_are_rejected() {
+        assert!(decode_header(&[SecuritySuite::Secured as u8, 9]).is_err());
+    }
+
+    #[test]
+    fn key_ids_follow_the_key_sequence() {
+        assert_eq!(key_id(0), KeyId::Index(1));
+        assert_eq!(key_id(5), KeyId::Index(6));
+        assert_eq!(key_id(0x80), KeyId::Index(1));
+    }
+}
@@ -0,0 +1,580 @@
+//! This file contains the definition and implementation of `MleNode`, which
+//! attaches a node to an existing Thread network as a Minimal End Device
+//! (MED), a child that keeps its receiver on and relies on its parent for
+//! routing:
+//!
+//! - Once started, it multicasts Parent Requests, first to routers only and
+//!   then to routers and router-eligible end devices, and collects the
+//!   Parent Responses that answer its challenge.
+//! - It picks the parent with the best link margin, then parent priority,
+//!   then number of good links, and sends it a Child ID Request with the
+//!   parent's challenge.
+//! - Once the parent answers with a Child ID Response, the node takes the
+//!   RLOC16 the parent assigned as its short MAC address, and makes the
+//!   parent the default route of its `RoutingTable`.
+//! - While attached, it sends Child Update Requests before its timeout
+//!   expires, so the parent keeps it as a child. If the parent stops
+//!   answering, the node attaches again.
+//!
+//! Frames of the network are secured by the `ieee802154::framer` in key
+//! index mode. The node provides the framer with the key of the current key
+//! sequence and with the extended address of its parent, by implementing
+//! `KeyProcedure` and `DeviceProcedure`. Commissioning, which would give the
+//! node the key, is not implemented: the board configures the key.
+//!
+//! The node sends MLE messages with a `UDPSender` bound to `MLE_PORT`, and
+//! receives them with a `UDPReceiver` bound to the same port. The IP sender
+//! under the `UDPSender` should secure frames with `SECURITY_LEVEL` and
+//! `key_id()`. The Thread component (`components::thread`) sets this up.
+
+use crate::ieee802154::device::MacDevice;
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::routing::RoutingTable;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::thread::mle::{self, Command, MLE_PORT, SECURITY_LEVEL};
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType, Tlvs};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::rng::Random;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ErrorCode;
+
+/// All-routers multicast address (ff02::2).
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// Thread version advertised in the Version TLV.
+const THREAD_VERSION: u16 = 2;
+
+/// Mode of a Minimal End Device: receiver on when idle, and secure Data
+/// Requests.
+const MODE: u8 = LinkMode::ReceiverOnWhenIdle as u8 | LinkMode::SecureDataRequests as u8;
+
+/// Time the parent keeps the node as a child without hearing from it. The
+/// node sends a Child Update Request after half of it.
+const CHILD_TIMEOUT_S: u32 = 240;
+
+/// Time to wait for Parent Responses to Parent Requests to routers, and to
+/// routers and router-eligible end devices (Section 4.7.2).
+const PARENT_REQUEST_ROUTER_TIMEOUT_MS: u32 = 750;
+const PARENT_REQUEST_REED_TIMEOUT_MS: u32 = 1250;
+
+/// Requests sent before the parent is considered gone, and the time to
+/// wait for the answer to each.
+const MAX_REQUESTS: u8 = 3;
+const RESPONSE_TIMEOUT_MS: u32 = 1250;
+
+/// Time to wait before attaching again after no parent answered.
+const ATTACH_BACKOFF_S: u32 = 10;
+
+/// Progress of the node in attaching to a Thread network.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MleState {
+    /// The node was not started.
+    Disabled = 0,
+    /// No parent answered, the node attaches again later.
+    Detached = 1,
+    /// Sending Parent Requests and collecting Parent Responses.
+    ParentRequest = 2,
+    /// Waiting for the chosen parent to accept the node as a child.
+    ChildIdRequest = 3,
+    /// The node is the child of a parent.
+    Child = 4,
+}
+
+#[derive(Copy, Clone)]
+struct LeaderData {
+    partition_id: u32,
+    weighting: u8,
+    data_version: u8,
+    stable_data_version: u8,
+    leader_router_id: u8,
+}
+
+impl LeaderData {
+    fn tlv(&self) -> Tlv<'static> {
+        Tlv::LeaderData {
+            partition_id: self.partition_id,
+            weighting: self.weighting,
+            data_version: self.data_version,
+            stable_data_version: self.stable_data_version,
+            leader_router_id: self.leader_router_id,
+        }
+    }
+}
+
+/// A neighbor that answered a Parent Request, or the parent.
+#[derive(Copy, Clone)]
+struct Parent {
+    /// Link-local address, formed from the extended MAC address.
+    addr: IPAddr,
+    rloc16: u16,
+    /// Challenge to answer in the Child ID Request.
+    challenge: [u8; 8],
+    leader_data: LeaderData,
+    /// Ranks parents by link quality, parent priority, and number of links
+    /// of the best quality, in that order.
+    rank: (u8, i8, u8),
+}
+
+impl Parent {
+    fn mac_addr(&self) -> MacAddress {
+        self.addr.mac_from_iid()
+    }
+}
+
+pub struct MleNode<'a, A: Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Random<'a>,
+    mac: &'a dyn MacDevice<'a>,
+    routes: &'a RoutingTable<'a>,
+    /// Key of the frames of the network, and its sequence number.
+    key: [u8; 16],
+    key_sequence: u32,
+    /// Buffer messages are built in. The `UDPSender` holds it while it
+    /// sends a message.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Whether to send the request of the current state.
+    request_pending: Cell<bool>,
+    state: Cell<MleState>,
+    /// Parent Requests ask routers and router-eligible end devices to
+    /// answer once routers alone did not.
+    scan_mask: Cell<u8>,
+    /// Challenge the answer to the last request has to hold.
+    challenge: Cell<[u8; 8]>,
+    /// Requests sent in the current state.
+    attempts: Cell<u8>,
+    /// The best neighbor that answered the Parent Requests, then the parent.
+    parent: OptionalCell<Parent>,
+    rloc16: OptionalCell<u16>,
+    /// Reference and duration of the timer.
+    timer: OptionalCell<(A::Ticks, A::Ticks)>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> MleNode<'a, A> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Random<'a>,
+        mac: &'a dyn MacDevice<'a>,
+        routes: &'a RoutingTable<'a>,
+        key: [u8; 16],
+        key_sequence: u32,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> MleNode<'a, A> {
+        MleNode {
+            udp_sender: udp_sender,
+            alarm: alarm,
+            rng: rng,
+            mac: mac,
+            routes: routes,
+            key: key,
+            key_sequence: key_sequence,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            request_pending: Cell::new(false),
+            state: Cell::new(MleState::Disabled),
+            scan_mask: Cell::new(0),
+            challenge: Cell::new([0; 8]),
+            attempts: Cell::new(0),
+            parent: OptionalCell::empty(),
+            rloc16: OptionalCell::empty(),
+            timer: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Starts attaching to the network. Returns ALREADY if the node was
+    /// started before.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != MleState::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.attach();
+        Ok(())
+    }
+
+    pub fn state(&self) -> MleState {
+        self.state.get()
+    }
+
+    /// Returns the RLOC16 the parent assigned to the node, once attached.
+    pub fn rloc16(&self) -> Option<u16> {
+        self.rloc16.extract()
+    }
+
+    /// Returns the link-local address of the parent, once attached.
+    pub fn parent(&self) -> Option<IPAddr> {
+        if self.state.get() == MleState::Child {
+            self.parent.extract().map(|parent| parent.addr)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the key ID of the frames of the network.
+    pub fn key_id(&self) -> KeyId {
+        mle::key_id(self.key_sequence)
+    }
+
+    fn new_challenge(&self) {
+        let mut challenge = [0; 8];
+        challenge[..4].copy_from_slice(&self.rng.random().to_be_bytes());
+        challenge[4..].copy_from_slice(&self.rng.random().to_be_bytes());
+        self.challenge.set(challenge);
+    }
+
+    fn start_timer(&self, dt: A::Ticks) {
+        self.timer.set((self.alarm.now(), dt));
+        let dt = cmp::max(dt.into_u32(), self.alarm.minimum_dt().into_u32());
+        self.alarm.set_alarm(self.alarm.now(), A::Ticks::from(dt));
+    }
+
+    /// Forgets the parent, if there is one, and starts over with Parent
+    /// Requests to routers.
+    fn attach(&self) {
+        if self.rloc16.take().is_some() {
+            self.routes.set_default_route(None);
+        }
+        self.parent.clear();
+        self.state.set(MleState::ParentRequest);
+        self.scan_mask.set(MulticastResponder::Router as u8);
+        self.send_request(A::ticks_from_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS));
+    }
+
+    /// Sends the request of the current state with a new challenge, and sets
+    /// the timer for the answer.
+    fn send_request(&self, timeout: A::Ticks) {
+        self.attempts.set(self.attempts.get() + 1);
+        self.new_challenge();
+        self.start_timer(timeout);
+        self.request_pending.set(true);
+        self.transmit();
+    }
+
+    /// Sends a Child ID Request to the best neighbor that answered.
+    fn request_child_id(&self) {
+        self.state.set(MleState::ChildIdRequest);
+        self.attempts.set(0);
+        self.send_request(A::ticks_from_ms(RESPONSE_TIMEOUT_MS));
+    }
+
+    /// Sets the timer for the next Child Update Request.
+    fn keep_alive(&self) {
+        self.attempts.set(0);
+        self.start_timer(A::ticks_from_seconds(CHILD_TIMEOUT_S / 2));
+    }
+
+    /// Writes the request of the current state into `buf`, and returns its
+    /// destination and length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, usize)> {
+        if !self.request_pending.take() {
+            return None;
+        }
+        let challenge = Tlv::Challenge(self.challenge.get());
+        let mode = Tlv::Mode(MODE);
+        let version = Tlv::Version(THREAD_VERSION);
+        let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+        let (dst, command) = match self.state.get() {
+            MleState::ParentRequest => (ALL_ROUTERS, Command::ParentRequest),
+            MleState::ChildIdRequest => (self.parent.extract()?.addr, Command::ChildIdRequest),
+            MleState::Child => (self.parent.extract()?.addr, Command::ChildUpdateRequest),
+            MleState::Disabled | MleState::Detached => return None,
+        };
+        let (mut offset, _) = mle::encode_header(buf, command).done()?;
+        let mut encode = |tlv: Tlv| -> Option<()> {
+            let (len, _) = tlv.encode(&mut buf[offset..]).done()?;
+            offset += len;
+            Some(())
+        };
+        match command {
+            Command::ParentRequest => {
+                encode(mode)?;
+                encode(challenge)?;
+                encode(Tlv::ScanMask(self.scan_mask.get()))?;
+                encode(version)?;
+            }
+            Command::ChildIdRequest => {
+                let parent = self.parent.extract()?;
+                encode(Tlv::Response(parent.challenge))?;
+                // The framer does not keep frame counters, and MLE messages
+                // are not secured at the MLE layer.
+                encode(Tlv::LinkLayerFrameCounter(0))?;
+                encode(Tlv::MleFrameCounter(0))?;
+                encode(mode)?;
+                encode(Tlv::Timeout(CHILD_TIMEOUT_S))?;
+                encode(version)?;
+                encode(Tlv::TlvRequest(&requested))?;
+            }
+            _ => {
+                let parent = self.parent.extract()?;
+                encode(mode)?;
+                encode(challenge)?;
+                encode(parent.leader_data.tlv())?;
+                encode(Tlv::Timeout(CHILD_TIMEOUT_S))?;
+            }
+        }
+        Some((dst, offset))
+    }
+
+    /// Sends the next message, if the `UDPSender` is idle.
+    fn transmit(&self) {
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        buf.reset();
+        match self.next_message(&mut buf[..]) {
+            Some((dst, len)) => {
+                buf.slice(0..len);
+                if let Err(mut buf) = self.udp_sender.send_to(dst, MLE_PORT, buf, self.net_cap) {
+                    debug!("[MLE] send failed");
+                    buf.reset();
+                    self.tx_buffer.replace(buf);
+                }
+            }
+            None => {
+                self.tx_buffer.replace(buf);
+            }
+        }
+    }
+
+    fn parent_response_arrived(&self, src: IPAddr, tlvs: Tlvs) {
+        if self.state.get() != MleState::ParentRequest {
+            return;
+        }
+        let mut response = None;
+        let mut rloc16 = None;
+        let mut challenge = None;
+        let mut leader_data = None;
+        let mut link_margin = 0;
+        let mut connectivity = (0, 0);
+        for tlv in tlvs {
+            match tlv {
+                Tlv::Response(value) => response = Some(value),
+                Tlv::SourceAddress(value) => rloc16 = Some(value),
+                Tlv::Challenge(value) => challenge = Some(value),
+                Tlv::LeaderData {
+                    partition_id,
+                    weighting,
+                    data_version,
+                    stable_data_version,
+                    leader_router_id,
+                } => {
+                    leader_data = Some(LeaderData {
+                        partition_id,
+                        weighting,
+                        data_version,
+                        stable_data_version,
+                        leader_router_id,
+                    })
+                }
+                Tlv::LinkMargin(value) => link_margin = value,
+                Tlv::Connectivity {
+                    parent_priority,
+                    link_quality_3,
+                    ..
+                } => connectivity = ((parent_priority as i8) >> 6, link_quality_3),
+                _ => {}
+            }
+        }
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        let (rloc16, challenge, leader_data) = match (rloc16, challenge, leader_data) {
+            (Some(rloc16), Some(challenge), Some(leader_data)) => (rloc16, challenge, leader_data),
+            _ => return,
+        };
+        // Link quality as a function of link margin (Section 4.7.1.1).
+        let link_quality = match link_margin {
+            0..=2 => 0,
+            3..=10 => 1,
+            11..=20 => 2,
+            _ => 3,
+        };
+        let candidate = Parent {
+            addr: src,
+            rloc16: rloc16,
+            challenge: challenge,
+            leader_data: leader_data,
+            rank: (link_quality, connectivity.0, connectivity.1),
+        };
+        if self
+            .parent
+            .extract()
+            .map_or(true, |best| candidate.rank > best.rank)
+        {
+            self.parent.set(candidate);
+        }
+    }
+
+    fn child_id_response_arrived(&self, src: IPAddr, tlvs: Tlvs) {
+        let parent = match self.parent.extract() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if self.state.get() != MleState::ChildIdRequest || src != parent.addr {
+            return;
+        }
+        let address16 = tlvs
+            .filter_map(|tlv| match tlv {
+                Tlv::Address16(value) => Some(value),
+                _ => None,
+            })
+            .next();
+        if let Some(rloc16) = address16 {
+            self.rloc16.set(rloc16);
+            self.mac.set_address(rloc16);
+            self.mac.config_commit();
+            self.routes.set_default_route(Some(parent.mac_addr()));
+            self.state.set(MleState::Child);
+            self.keep_alive();
+        }
+    }
+
+    fn child_update_response_arrived(&self, src: IPAddr, tlvs: Tlvs) {
+        if self.state.get() != MleState::Child
+            || self.parent.extract().map(|parent| parent.addr) != Some(src)
+            || self.attempts.get() == 0
+        {
+            return;
+        }
+        let mut response = None;
+        let mut status = None;
+        for tlv in tlvs {
+            match tlv {
+                Tlv::Response(value) => response = Some(value),
+                Tlv::Status(value) => status = Some(value),
+                _ => {}
+            }
+        }
+        if status.is_some() {
+            // The parent no longer has the node as a child.
+            debug!("[MLE] parent refused update");
+            self.attach();
+        } else if response == Some(self.challenge.get()) {
+            self.keep_alive();
+        }
+    }
+
+    /// Called when the timer expires.
+    fn timer_expired(&self) {
+        self.timer.clear();
+        match self.state.get() {
+            MleState::Detached => self.attach(),
+            MleState::ParentRequest => {
+                if self.parent.is_some() {
+                    self.request_child_id();
+                } else if self.scan_mask.get() == MulticastResponder::Router as u8 {
+                    self.scan_mask.set(
+                        MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8,
+                    );
+                    self.send_request(A::ticks_from_ms(PARENT_REQUEST_REED_TIMEOUT_MS));
+                } else {
+                    self.state.set(MleState::Detached);
+                    self.attempts.set(0);
+                    self.start_timer(A::ticks_from_seconds(ATTACH_BACKOFF_S));
+                }
+            }
+            MleState::ChildIdRequest | MleState::Child => {
+                if self.attempts.get() >= MAX_REQUESTS {
+                    debug!("[MLE] parent unreachable");
+                    self.attempts.set(0);
+                    self.attach();
+                } else {
+                    self.send_request(A::ticks_from_ms(RESPONSE_TIMEOUT_MS));
+                }
+            }
+            MleState::Disabled => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for MleNode<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        // MLE messages are exchanged between neighbors only.
+        if src_port != MLE_PORT || !src_addr.is_unicast_link_local() {
+            return;
+        }
+        let (offset, command) = match mle::decode_header(payload).done() {
+            Some(header) => header,
+            None => return,
+        };
+        let tlvs = Tlvs::new(&payload[offset..]);
+        match command {
+            Command::ParentResponse => self.parent_response_arrived(src_addr, tlvs),
+            Command::ChildIdResponse => self.child_id_response_arrived(src_addr, tlvs),
+            Command::ChildUpdateResponse => self.child_update_response_arrived(src_addr, tlvs),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for MleNode<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>, mut dgram: LeasableBuffer<'static, u8>) {
+        if result != Ok(()) {
+            debug!("[MLE] send_done: {:?}", result);
+        }
+        dgram.reset();
+        self.tx_buffer.replace(dgram);
+        self.transmit();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MleNode<'a, A> {
+    fn alarm(&self) {
+        if let Some((reference, dt)) = self.timer.extract() {
+            let elapsed = self.alarm.now().wrapping_sub(reference);
+            if elapsed.into_u32() >= dt.into_u32() {
+                self.timer_expired();
+            } else {
+                let _ = self.alarm.set_alarm(reference, dt);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> KeyProcedure for MleNode<'a, A> {
+    /// Frames of the network are secured with the key of the current key
+    /// sequence.
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        if level == SECURITY_LEVEL && key_id == self.key_id() {
+            Some(self.key)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeviceProcedure for MleNode<'a, A> {
+    /// Any device of the network can send frames with its extended address,
+    /// but only the parent is known by its short address.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        match addr {
+            MacAddress::Long(addr) => Some(addr),
+            MacAddress::Short(short_addr) => self
+                .parent
+                .extract()
+                .filter(|parent| parent.rloc16 == short_addr)
+                .and_then(|parent| match parent.mac_addr() {
+                    MacAddress::Long(addr) => Some(addr),
+                    MacAddress::Short(_) => None,
+                }),
+        }
+    }
+}
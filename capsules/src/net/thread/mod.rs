@@ -1 +1,3 @@
+pub mod mle;
+pub mod mle_node;
 pub mod tlv;
@@ -9,16 +9,7 @@
 //! required to support MLE for attaching a Sleepy End Device (SED) to a
 //! Thread network.
 //!
-//! MLE for network attaching comprises a four-step handshake that works
-//! as follows:
-//!
-//! 1. A child device multicasts a Parent Request MLE command.
-//! 2. Each potential parent device on the network unicasts a Parent
-//!    Response MLE command.
-//! 3. The child device selects a parent based on a hierarchy of
-//!    connectivity metrics and unicasts a Child ID Request MLE
-//!    command.
-//! 4. The selected parent unicasts a Child ID Response MLE command.
+//! The MLE messages that use these TLVs are described in the `mle` module.
 //!
 //! A TLV is comprised of three parts:
 //!
@@ -47,10 +38,7 @@
 //!
 //! Author: Mateo Garcia <mateog@stanford.edu>
 
-// TODO: Move the MLE explanation above to the MLE module, when it is created.
-
 // NOTES FOR DEBUGGING:
-// - .to_be() may not have been called on values wider than one byte
 // - encode_bytes_be may have been used instead of encode_bytes
 // - decode_bytes_be may have been used instead of decode_bytes
 // - See 4.5.25 Active Operational Dataset TLV and 4.5.26 Pending Operational Dataset TLV
@@ -127,7 +115,7 @@ impl Tlv<'_> {
             Tlv::SourceAddress(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::Mode(ref mode) => {
@@ -139,7 +127,7 @@ impl Tlv<'_> {
             Tlv::Timeout(ref max_transmit_interval) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, max_transmit_interval.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *max_transmit_interval);
                 stream_done!(offset)
             }
             Tlv::Challenge(ref byte_str) => {
@@ -157,19 +145,19 @@ impl Tlv<'_> {
             Tlv::LinkLayerFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::MleFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::Address16(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::LeaderData {
@@ -185,7 +173,7 @@ impl Tlv<'_> {
                     + mem::size_of::<u8>()
                     + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, partition_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, partition_id);
                 offset = enc_consume!(buf, offset; encode_u8, weighting);
                 offset = enc_consume!(buf, offset; encode_u8, data_version);
                 offset = enc_consume!(buf, offset; encode_u8, stable_data_version);
@@ -246,7 +234,7 @@ impl Tlv<'_> {
                 offset = enc_consume!(buf, offset; encode_u8, id_sequence);
                 offset = enc_consume!(buf, offset; encode_u8, active_routers);
                 if let Some(ref buf_size) = sed_buffer_size {
-                    offset = enc_consume!(buf, offset; encode_u16, buf_size.to_be());
+                    offset = enc_consume!(buf, offset; encode_u16, *buf_size);
                 }
                 if let Some(ref datagram_cnt) = sed_datagram_count {
                     offset = enc_consume!(buf, offset; encode_u8, *datagram_cnt);
@@ -375,13 +363,13 @@ impl Tlv<'_> {
                 let (offset, active_routers) = dec_try!(buf, offset; decode_u8);
                 let mut offset = offset;
                 let mut sed_buffer_size = None;
-                if offset + mem::size_of::<u16>() < length as usize {
+                if offset + mem::size_of::<u16>() <= TL_WIDTH + length as usize {
                     let (new_offset, sed_buffer_size_raw) = dec_try!(buf, offset; decode_u16);
                     offset = new_offset;
                     sed_buffer_size = Some(sed_buffer_size_raw);
                 }
                 let mut sed_datagram_count = None;
-                if offset + mem::size_of::<u8>() < length as usize {
+                if offset + mem::size_of::<u8>() <= TL_WIDTH + length as usize {
                     let (new_offset, sed_datagram_count_raw) = dec_try!(buf, offset; decode_u8);
                     offset = new_offset;
                     sed_datagram_count = Some(sed_datagram_count_raw);
@@ -426,6 +414,40 @@ impl Tlv<'_> {
     }
 }
 
+/// Iterator over the TLVs of an MLE message. TLVs of types this module does
+/// not implement are skipped, and iteration stops at the first TLV that
+/// does not fit the buffer.
+#[derive(Copy, Clone)]
+pub struct Tlvs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Tlvs<'a> {
+    pub fn new(buf: &'a [u8]) -> Tlvs<'a> {
+        Tlvs { buf: buf }
+    }
+}
+
+impl<'a> Iterator for Tlvs<'a> {
+    type Item = Tlv<'a>;
+
+    fn next(&mut self) -> Option<Tlv<'a>> {
+        while self.buf.len() >= TL_WIDTH {
+            let len = TL_WIDTH + self.buf[1] as usize;
+            if len > self.buf.len() {
+                break;
+            }
+            let (tlv_buf, rest) = self.buf.split_at(len);
+            self.buf = rest;
+            if let Some((_, tlv)) = Tlv::decode(tlv_buf).done() {
+                return Some(tlv);
+            }
+        }
+        self.buf = &[];
+        None
+    }
+}
+
 /// Value encoded in the type field of a Type-Length-Value (TLV)
 /// structure.
 #[repr(u8)]
@@ -618,7 +640,7 @@ impl NetworkDataTlv<'_> {
                 };
                 let first_byte: u8 = t_bit | (0b1111 & s_id);
                 offset = enc_consume!(buf, offset; encode_u8, first_byte);
-                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number);
                 offset = enc_consume!(buf, offset; encode_u8, s_service_data_length);
                 offset = enc_consume!(buf, offset; encode_bytes_be, &s_service_data);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
@@ -883,7 +905,7 @@ impl HasRouteTlvValue {
     /// Serializes this Has Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 3);
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16);
         let last_byte = ((self.r_preference & 0b11) as u8) << 6;
         offset = enc_consume!(buf, offset; encode_u8, last_byte);
         stream_done!(offset)
@@ -930,8 +952,8 @@ impl BorderRouterTlvValue {
     /// Serializes this Border Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 4); // Each Border Router TLV value is 32 bits wide.
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16.to_be());
-        offset = enc_consume!(buf, offset; encode_u16, self.p_bits.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16);
+        offset = enc_consume!(buf, offset; encode_u16, self.p_bits);
         stream_done!(offset)
     }
 
@@ -969,7 +991,7 @@ impl ServiceSubTlv {
             } => {
                 let value_width = mem::size_of::<u16>() + s_server_data.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_u16, s_server_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, s_server_16);
                 offset = enc_consume!(buf, offset; encode_bytes_be, &s_server_data);
                 stream_done!(offset)
             }
@@ -1094,13 +1116,13 @@ impl NetworkManagementTlv<'_> {
                 let value_width = mem::size_of::<u8>() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
                 offset = enc_consume!(buf, offset; encode_u8, channel_page);
-                offset = enc_consume!(buf, offset; encode_u16, channel.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, channel);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PanId(ref pan_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, pan_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ExtendedPanId(ref extended_pan_id) => {
@@ -1151,7 +1173,7 @@ impl NetworkManagementTlv<'_> {
             NetworkManagementTlv::BorderAgentLocator(ref rloc_16) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rloc_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *rloc_16);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerId(ref commissioner_id) => {
@@ -1164,7 +1186,7 @@ impl NetworkManagementTlv<'_> {
             NetworkManagementTlv::CommissionerSessionId(ref session_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, session_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *session_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SecurityPolicy {
@@ -1173,7 +1195,7 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = mem::size_of::<u16>() + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rotation_time.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, rotation_time);
                 offset = enc_consume!(buf, offset; encode_u8, policy_bits);
                 stream_done!(offset)
             }
@@ -1187,13 +1209,13 @@ impl NetworkManagementTlv<'_> {
                 offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerUdpPort(ref udp_port) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, udp_port.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *udp_port);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PendingTimestamp {
@@ -1206,13 +1228,13 @@ impl NetworkManagementTlv<'_> {
                 offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::DelayTimer(ref time_remaining) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, time_remaining.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *time_remaining);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ChannelMask(ref entries) => {
@@ -1500,3 +1522,113 @@ impl ChannelMaskEntry {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `tlv` into `buf`, checks the encoding against `expected`, and
+    /// returns the TLV decoded from it.
+    fn round_trip<'a>(tlv: Tlv, buf: &'a mut [u8], expected: &[u8]) -> Tlv<'a> {
+        assert_eq!(tlv.encode(buf).done(), Some((expected.len(), ())));
+        assert_eq!(&buf[..expected.len()], expected);
+        let (offset, decoded) = Tlv::decode(&buf[..expected.len()]).done().unwrap();
+        assert_eq!(offset, expected.len());
+        decoded
+    }
+
+    #[test]
+    fn values_are_big_endian() {
+        let mut buf = [0; 16];
+        assert!(matches!(
+            round_trip(Tlv::SourceAddress(0x1234), &mut buf, &[0, 2, 0x12, 0x34]),
+            Tlv::SourceAddress(0x1234)
+        ));
+        assert!(matches!(
+            round_trip(Tlv::Timeout(0x0102_0304), &mut buf, &[2, 4, 1, 2, 3, 4]),
+            Tlv::Timeout(0x0102_0304)
+        ));
+        assert!(matches!(
+            round_trip(
+                Tlv::LinkLayerFrameCounter(0x0a0b_0c0d),
+                &mut buf,
+                &[5, 4, 0x0a, 0x0b, 0x0c, 0x0d]
+            ),
+            Tlv::LinkLayerFrameCounter(0x0a0b_0c0d)
+        ));
+        assert!(matches!(
+            round_trip(
+                Tlv::LeaderData {
+                    partition_id: 0x1122_3344,
+                    weighting: 64,
+                    data_version: 1,
+                    stable_data_version: 2,
+                    leader_router_id: 3,
+                },
+                &mut buf,
+                &[11, 8, 0x11, 0x22, 0x33, 0x44, 64, 1, 2, 3]
+            ),
+            Tlv::LeaderData {
+                partition_id: 0x1122_3344,
+                weighting: 64,
+                data_version: 1,
+                stable_data_version: 2,
+                leader_router_id: 3,
+            }
+        ));
+    }
+
+    #[test]
+    fn connectivity_fields_for_sleepy_children_are_optional() {
+        let connectivity = |sed_buffer_size, sed_datagram_count| Tlv::Connectivity {
+            parent_priority: ParentPriority::High as u8,
+            link_quality_3: 1,
+            link_quality_2: 2,
+            link_quality_1: 3,
+            leader_cost: 4,
+            id_sequence: 5,
+            active_routers: 6,
+            sed_buffer_size,
+            sed_datagram_count,
+        };
+        let mut buf = [0; 16];
+        assert!(matches!(
+            round_trip(
+                connectivity(None, None),
+                &mut buf,
+                &[15, 7, 0x40, 1, 2, 3, 4, 5, 6]
+            ),
+            Tlv::Connectivity {
+                active_routers: 6,
+                sed_buffer_size: None,
+                sed_datagram_count: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            round_trip(
+                connectivity(Some(1280), Some(4)),
+                &mut buf,
+                &[15, 10, 0x40, 1, 2, 3, 4, 5, 6, 0x05, 0x00, 4]
+            ),
+            Tlv::Connectivity {
+                active_routers: 6,
+                sed_buffer_size: Some(1280),
+                sed_datagram_count: Some(4),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn tlvs_skip_unknown_types_and_stop_at_truncated_tlvs() {
+        // A Mode TLV, a Route64 TLV this module does not implement, a Status
+        // TLV, and a truncated Version TLV.
+        let buf = [1, 1, 0x0f, 9, 2, 0xaa, 0xbb, 17, 1, 0, 18, 2, 0];
+        let mut tlvs = Tlvs::new(&buf);
+        assert!(matches!(tlvs.next(), Some(Tlv::Mode(0x0f))));
+        assert!(matches!(tlvs.next(), Some(Tlv::Status(0))));
+        assert!(tlvs.next().is_none());
+        assert!(tlvs.next().is_none());
+    }
+}
@@ -91,10 +91,10 @@ impl UDPHeader {
         stream_len_cond!(buf, self.get_hdr_size() + offset);
 
         let mut off = offset;
-        off = enc_consume!(buf, off; encode_u16, self.src_port);
-        off = enc_consume!(buf, off; encode_u16, self.dst_port);
-        off = enc_consume!(buf, off; encode_u16, self.len);
-        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.get_src_port());
+        off = enc_consume!(buf, off; encode_u16, self.get_dst_port());
+        off = enc_consume!(buf, off; encode_u16, self.get_len());
+        off = enc_consume!(buf, off; encode_u16, self.get_cksum());
         stream_done!(off, off);
     }
 
@@ -5,7 +5,7 @@
 //! by the UDP userspace driver, which must correctly check bindings of kernel apps to ensure
 //! correctness when dispatching received packets to the appropriate client.
 
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6_recv::IP6RecvClient;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
@@ -39,6 +39,10 @@ impl<'a> MuxUdpReceiver<'a> {
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Other transport protocols share the IP receiver.
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
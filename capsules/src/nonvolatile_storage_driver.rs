@@ -1,11 +1,18 @@
 //! This provides kernel and userspace access to nonvolatile memory.
 //!
-//! This is an initial implementation that does not provide safety for
-//! individual userland applications. Each application has full access to
-//! the entire memory space that has been provided to userland. Future revisions
-//! should update this to limit applications to only their allocated regions.
+//! The memory provided to userland is split into `NUM_APP_REGIONS` equally
+//! sized regions. Each region holds one object, which belongs to the storage
+//! ID (from the app's TBF header) that created it. The owner is stored in a
+//! small header at the start of the region, so ownership persists across
+//! reboots and app updates. An app selects which object it accesses by storage
+//! ID (by default its own), and the capsule checks the storage permissions of
+//! the app before every read or write. An app creates its object the first
+//! time it writes to it. Apps without storage permissions, which predate
+//! storage IDs, all share one object with the storage ID `SHARED_STORAGE_ID`,
+//! and cannot access any other object. Apps with storage permissions can
+//! access that object if they list its storage ID.
 //!
-//! However, the kernel accessible memory does not have to be the same range
+//! The kernel accessible memory does not have to be the same range
 //! as the userspace accessible address space. The kernel memory can overlap
 //! if desired, or can be a completely separate range.
 //!
@@ -56,6 +63,7 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::convert::TryInto;
 use core::mem;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil;
@@ -71,6 +79,21 @@ pub const DRIVER_NUM: usize = driver::NUM::NvmStorage as usize;
 
 pub static mut BUFFER: [u8; 512] = [0; 512];
 
+/// How many regions the userspace memory is split into. This is the maximum
+/// number of apps that can store an object.
+pub const NUM_APP_REGIONS: usize = 8;
+
+/// Marks the start of a region header. A region without this header is free.
+const REGION_MAGIC: u32 = 0x5354_4f52;
+/// The length of the region header: the magic value followed by the storage
+/// ID of the owner.
+const REGION_HEADER_LEN: usize = 8;
+/// The owner of a region that does not belong to any storage ID. Storage IDs
+/// are never 0.
+const REGION_FREE: u32 = 0;
+/// The storage ID of the object shared by apps without storage permissions.
+pub const SHARED_STORAGE_ID: u32 = 0xFFFF_FFFF;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum NonvolatileCommand {
     UserspaceRead,
@@ -81,8 +104,19 @@ pub enum NonvolatileCommand {
 
 #[derive(Clone, Copy)]
 pub enum NonvolatileUser {
-    App { app_id: ProcessId },
+    App {
+        app_id: ProcessId,
+    },
     Kernel,
+    /// Reading the header of a region to find out who owns it.
+    RegionScan {
+        region: usize,
+    },
+    /// Writing the header of a free region to give it to `owner`.
+    RegionClaim {
+        region: usize,
+        owner: u32,
+    },
 }
 
 pub struct App {
@@ -94,6 +128,9 @@ pub struct App {
     length: usize,
     buffer_read: ReadWriteAppSlice,
     buffer_write: ReadOnlyAppSlice,
+    // The storage ID of the object the app accesses, or `None` for the app's
+    // own object.
+    storage_id: Option<u32>,
 }
 
 impl Default for App {
@@ -107,6 +144,7 @@ impl Default for App {
             length: 0,
             buffer_read: ReadWriteAppSlice::default(),
             buffer_write: ReadOnlyAppSlice::default(),
+            storage_id: None,
         }
     }
 }
@@ -126,6 +164,10 @@ pub struct NonvolatileStorage<'a> {
     userspace_start_address: usize,
     // How many bytes allocated to userspace.
     userspace_length: usize,
+    // The storage ID that owns each region of the userspace memory.
+    region_owners: [Cell<u32>; NUM_APP_REGIONS],
+    // Whether `region_owners` has been read from storage yet.
+    regions_loaded: Cell<bool>,
     // The first byte that is accessible from the kernel.
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
@@ -164,6 +206,8 @@ impl<'a> NonvolatileStorage<'a> {
             current_user: OptionalCell::empty(),
             userspace_start_address: userspace_start_address,
             userspace_length: userspace_length,
+            region_owners: Default::default(),
+            regions_loaded: Cell::new(false),
             kernel_start_address: kernel_start_address,
             kernel_length: kernel_length,
             kernel_client: OptionalCell::empty(),
@@ -188,11 +232,12 @@ impl<'a> NonvolatileStorage<'a> {
         // Do bounds check.
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
-                // Userspace sees memory that starts at address 0 even if it
-                // is offset in the physical memory.
-                if offset >= self.userspace_length
-                    || length > self.userspace_length
-                    || offset + length > self.userspace_length
+                // Userspace sees each object as memory that starts at
+                // address 0.
+                let region_length = self.region_data_length();
+                if offset >= region_length
+                    || length > region_length
+                    || offset + length > region_length
                 {
                     return Err(ErrorCode::INVAL);
                 }
@@ -225,7 +270,7 @@ impl<'a> NonvolatileStorage<'a> {
                             };
 
                             // Check that it exists.
-                            if allow_buf_len == 0 || self.buffer.is_none() {
+                            if allow_buf_len == 0 {
                                 return Err(ErrorCode::RESERVE);
                             }
 
@@ -235,46 +280,23 @@ impl<'a> NonvolatileStorage<'a> {
 
                             // First need to determine if we can execute this or must
                             // queue it.
-                            if self.current_user.is_none() {
-                                // No app is currently using the underlying storage.
-                                // Mark this app as active, and then execute the command.
-                                self.current_user
-                                    .set(NonvolatileUser::App { app_id: appid });
-
-                                // Need to copy bytes if this is a write!
-                                if command == NonvolatileCommand::UserspaceWrite {
-                                    app.buffer_write.map_or((), |app_buffer| {
-                                        self.buffer.map(|kernel_buffer| {
-                                            // Check that the internal buffer and the buffer that was
-                                            // allowed are long enough.
-                                            let write_len =
-                                                cmp::min(active_len, kernel_buffer.len());
-
-                                            let d = &app_buffer[0..write_len];
-                                            for (i, c) in
-                                                kernel_buffer[0..write_len].iter_mut().enumerate()
-                                            {
-                                                *c = d[i];
-                                            }
-                                        });
-                                    });
-                                }
+                            if app.pending_command == true {
+                                // No more room in the queue, nowhere to store this
+                                // request.
+                                return Err(ErrorCode::NOMEM);
+                            }
+                            app.command = command;
+                            app.offset = offset;
+                            app.length = active_len;
 
-                                self.userspace_call_driver(command, offset, active_len)
+                            if self.current_user.is_none() {
+                                // No app is currently using the underlying storage,
+                                // so execute the command.
+                                self.start_userspace_command(appid, app)
                             } else {
                                 // Some app is using the storage, we must wait.
-                                if app.pending_command == true {
-                                    // No more room in the queue, nowhere to store this
-                                    // request.
-                                    Err(ErrorCode::NOMEM)
-                                } else {
-                                    // We can store this, so lets do it.
-                                    app.pending_command = true;
-                                    app.command = command;
-                                    app.offset = offset;
-                                    app.length = active_len;
-                                    Ok(())
-                                }
+                                app.pending_command = true;
+                                Ok(())
                             }
                         })
                         .unwrap_or_else(|err| Err(err.into()))
@@ -317,15 +339,151 @@ impl<'a> NonvolatileStorage<'a> {
         }
     }
 
+    /// The number of bytes of each region available to store data in.
+    fn region_data_length(&self) -> usize {
+        (self.userspace_length / NUM_APP_REGIONS).saturating_sub(REGION_HEADER_LEN)
+    }
+
+    /// The physical address of the start of a region.
+    fn region_address(&self, region: usize) -> usize {
+        self.userspace_start_address + region * (self.userspace_length / NUM_APP_REGIONS)
+    }
+
+    /// Find the region that belongs to `owner`.
+    fn find_region(&self, owner: u32) -> Option<usize> {
+        self.region_owners
+            .iter()
+            .position(|region_owner| region_owner.get() == owner)
+    }
+
+    /// Start reading the header of `region` to find out who owns it.
+    fn scan_region(&self, region: usize) -> Result<(), ErrorCode> {
+        self.buffer
+            .take()
+            .map_or(Err(ErrorCode::RESERVE), |buffer| {
+                self.current_user
+                    .set(NonvolatileUser::RegionScan { region });
+                let res = self
+                    .driver
+                    .read(buffer, self.region_address(region), REGION_HEADER_LEN);
+                if res.is_err() {
+                    self.current_user.clear();
+                }
+                res
+            })
+    }
+
+    /// Start writing the header of the free region `region` to give it to
+    /// `owner`.
+    fn claim_region(&self, region: usize, owner: u32) -> Result<(), ErrorCode> {
+        self.buffer
+            .take()
+            .map_or(Err(ErrorCode::RESERVE), |buffer| {
+                buffer[0..4].copy_from_slice(&REGION_MAGIC.to_le_bytes());
+                buffer[4..8].copy_from_slice(&owner.to_le_bytes());
+                self.current_user
+                    .set(NonvolatileUser::RegionClaim { region, owner });
+                let res = self
+                    .driver
+                    .write(buffer, self.region_address(region), REGION_HEADER_LEN);
+                if res.is_err() {
+                    self.current_user.clear();
+                }
+                res
+            })
+    }
+
+    /// Start the command stored in `app`, after checking that the app is
+    /// allowed to access the object it selected. This may first have to find
+    /// out who owns each region, or create the app's object, in which case
+    /// the command stays pending and is started once that finishes.
+    fn start_userspace_command(&self, appid: ProcessId, app: &mut App) -> Result<(), ErrorCode> {
+        if !self.regions_loaded.get() {
+            let res = self.scan_region(0);
+            app.pending_command = res.is_ok();
+            return res;
+        }
+
+        // The object the app accesses, and whether the app owns it.
+        let (storage_id, owner) = match appid.get_storage_permissions() {
+            Some(permissions) => {
+                let storage_id = app
+                    .storage_id
+                    .or(permissions.write_id())
+                    .ok_or(ErrorCode::NOSUPPORT)?;
+                let allowed = match app.command {
+                    NonvolatileCommand::UserspaceRead => {
+                        permissions.check_read_permission(storage_id)
+                    }
+                    NonvolatileCommand::UserspaceWrite => {
+                        permissions.check_modify_permission(storage_id)
+                    }
+                    _ => false,
+                };
+                if !allowed {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                (storage_id, permissions.write_id() == Some(storage_id))
+            }
+            None => {
+                // Apps without storage permissions share one object, and
+                // cannot select any other.
+                if app.storage_id.map_or(false, |id| id != SHARED_STORAGE_ID) {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                (SHARED_STORAGE_ID, true)
+            }
+        };
+
+        let region = match self.find_region(storage_id) {
+            Some(region) => region,
+            None => {
+                // The object does not exist yet. Only its owner can create it,
+                // by writing to it.
+                if app.command != NonvolatileCommand::UserspaceWrite || !owner {
+                    return Err(ErrorCode::INVAL);
+                }
+                let free_region = self.find_region(REGION_FREE).ok_or(ErrorCode::NOMEM)?;
+                let res = self.claim_region(free_region, storage_id);
+                app.pending_command = res.is_ok();
+                return res;
+            }
+        };
+
+        // Need to copy bytes if this is a write!
+        if app.command == NonvolatileCommand::UserspaceWrite {
+            let length = app.length;
+            app.length = app.buffer_write.map_or(0, |app_buffer| {
+                self.buffer.map_or(0, |kernel_buffer| {
+                    // Check that the internal buffer and the buffer that was
+                    // allowed are long enough.
+                    let write_len =
+                        cmp::min(length, cmp::min(app_buffer.len(), kernel_buffer.len()));
+                    kernel_buffer[0..write_len].copy_from_slice(&app_buffer[0..write_len]);
+                    write_len
+                })
+            });
+        }
+
+        self.current_user
+            .set(NonvolatileUser::App { app_id: appid });
+        let res = self.userspace_call_driver(app.command, region, app.offset, app.length);
+        if res.is_err() {
+            self.current_user.clear();
+        }
+        res
+    }
+
     fn userspace_call_driver(
         &self,
         command: NonvolatileCommand,
+        region: usize,
         offset: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
         // Calculate where we want to actually read from in the physical
         // storage.
-        let physical_address = offset + self.userspace_start_address;
+        let physical_address = self.region_address(region) + REGION_HEADER_LEN + offset;
 
         self.buffer
             .take()
@@ -334,7 +492,6 @@ impl<'a> NonvolatileStorage<'a> {
                 // allowed are long enough.
                 let active_len = cmp::min(length, buffer.len());
 
-                // self.current_app.set(Some(appid));
                 match command {
                     NonvolatileCommand::UserspaceRead => {
                         self.driver.read(buffer, physical_address, active_len)
@@ -347,7 +504,33 @@ impl<'a> NonvolatileStorage<'a> {
             })
     }
 
+    /// Tell the app whose pending command could not be started that it
+    /// failed with `error`.
+    fn command_failed(app: &mut App, error: ErrorCode) {
+        let callback = match app.command {
+            NonvolatileCommand::UserspaceWrite => &mut app.callback_write,
+            _ => &mut app.callback_read,
+        };
+        callback.schedule(0, kernel::into_statuscode(Err(error)), 0);
+    }
+
+    /// Fail the pending commands of all apps with `error`.
+    fn fail_pending_commands(&self, error: ErrorCode) {
+        self.apps.each(|_, app| {
+            if app.pending_command {
+                app.pending_command = false;
+                Self::command_failed(app, error);
+            }
+        });
+    }
+
     fn check_queue(&self) {
+        // Nothing can start while the storage is busy, for example while the
+        // region headers are read.
+        if self.current_user.is_some() {
+            return;
+        }
+
         // Check if there are any pending events.
         if self.kernel_pending_command.get() {
             self.kernel_buffer.take().map(|kernel_buffer| {
@@ -375,14 +558,12 @@ impl<'a> NonvolatileStorage<'a> {
                 let started_command = cntr.enter(|app| {
                     if app.pending_command {
                         app.pending_command = false;
-                        self.current_user
-                            .set(NonvolatileUser::App { app_id: appid });
-                        if let Ok(()) =
-                            self.userspace_call_driver(app.command, app.offset, app.length)
-                        {
-                            true
-                        } else {
-                            false
+                        match self.start_userspace_command(appid, app) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                Self::command_failed(app, e);
+                                false
+                            }
                         }
                     } else {
                         false
@@ -426,6 +607,31 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for Nonvolatile
                         app.callback_read.schedule(length, 0, 0);
                     });
                 }
+                NonvolatileUser::RegionScan { region } => {
+                    let owner = if buffer[0..4] == REGION_MAGIC.to_le_bytes() {
+                        buffer[4..8]
+                            .try_into()
+                            .map_or(REGION_FREE, u32::from_le_bytes)
+                    } else {
+                        REGION_FREE
+                    };
+                    self.region_owners[region].set(owner);
+                    self.buffer.replace(buffer);
+
+                    if region + 1 < NUM_APP_REGIONS {
+                        // If the next header cannot be read, the commands
+                        // waiting for the scan fail, and the next command
+                        // starts the scan again.
+                        if let Err(e) = self.scan_region(region + 1) {
+                            self.fail_pending_commands(e);
+                        }
+                    } else {
+                        self.regions_loaded.set(true);
+                    }
+                }
+                NonvolatileUser::RegionClaim { .. } => {
+                    self.buffer.replace(buffer);
+                }
             }
         });
 
@@ -450,6 +656,13 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for Nonvolatile
                         app.callback_write.schedule(length, 0, 0);
                     });
                 }
+                NonvolatileUser::RegionClaim { region, owner } => {
+                    self.region_owners[region].set(owner);
+                    self.buffer.replace(buffer);
+                }
+                NonvolatileUser::RegionScan { .. } => {
+                    self.buffer.replace(buffer);
+                }
             }
         });
 
@@ -547,8 +760,12 @@ impl Driver for NonvolatileStorage<'_> {
     ///
     /// ### `subscribe_num`
     ///
-    /// - `0`: Setup a read done callback.
-    /// - `1`: Setup a write done callback.
+    /// - `0`: Setup a read done callback. Called with the number of bytes
+    ///        read and `0`, or with `0` and the error code if a queued read
+    ///        could not be started.
+    /// - `1`: Setup a write done callback. Called with the number of bytes
+    ///        written and `0`, or with `0` and the error code if a queued
+    ///        write could not be started.
     fn subscribe(
         &self,
         subscribe_num: usize,
@@ -583,9 +800,13 @@ impl Driver for NonvolatileStorage<'_> {
     /// ### `command_num`
     ///
     /// - `0`: Return Ok(()) if this driver is included on the platform.
-    /// - `1`: Return the number of bytes available to userspace.
-    /// - `2`: Start a read from the nonvolatile storage.
-    /// - `3`: Start a write to the nonvolatile_storage.
+    /// - `1`: Return the number of bytes available in each object.
+    /// - `2`: Start a read from the selected object.
+    /// - `3`: Start a write to the selected object. Writing to the app's own
+    ///        object creates it if it does not exist yet.
+    /// - `4`: Select the object to access by the storage ID of its owner. `0`
+    ///        selects the app's own object, or the shared object for apps
+    ///        without storage permissions.
     fn command(
         &self,
         command_num: usize,
@@ -598,9 +819,9 @@ impl Driver for NonvolatileStorage<'_> {
                 CommandReturn::success()
             }
 
-            1 /* How many bytes are accessible from userspace */ => {
+            1 /* How many bytes are accessible in each object */ => {
                 // TODO: Would break on 64-bit platforms
-                CommandReturn::success_u32(self.userspace_length as u32)
+                CommandReturn::success_u32(self.region_data_length() as u32)
             },
 
             2 /* Issue a read command */ => {
@@ -633,6 +854,17 @@ impl Driver for NonvolatileStorage<'_> {
                 }
             }
 
+            4 /* Select the object to access */ => {
+                let res = self.apps.enter(appid, |app| {
+                    app.storage_id = if offset == 0 { None } else { Some(offset as u32) };
+                });
+
+                match res {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => CommandReturn::failure(e.into()),
+                }
+            }
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
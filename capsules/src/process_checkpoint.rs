@@ -0,0 +1,288 @@
+//! Save processes to nonvolatile storage and restore them later.
+//!
+//! This capsule stores snapshots of processes taken with
+//! `Process::checkpoint()` in a nonvolatile storage device, such as the FRAM
+//! provided by `capsules::fm25cl`, and restores them with
+//! `Process::restore()`. A board can use this to keep long-running apps going
+//! across power cycles: stop the apps and checkpoint them before powering
+//! down, and restore and resume them after booting.
+//!
+//! Processes are identified by their package name, which stays the same when
+//! the board reboots. Each snapshot is stored at an address chosen by the
+//! caller, as a 32-bit little-endian length followed by the snapshot.
+//!
+//! The storage device only needs to support writes and reads of up to the
+//! length of the chunk buffer; larger transfers are split by this capsule.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! struct CheckpointCapability;
+//! unsafe impl capabilities::ProcessManagementCapability for CheckpointCapability {}
+//! unsafe impl capabilities::ProcessCheckpointCapability for CheckpointCapability {}
+//!
+//! pub static mut CHECKPOINT_IMAGE: [u8; 8192] = [0; 8192];
+//! pub static mut CHECKPOINT_CHUNK: [u8; 256] = [0; 256];
+//! let checkpoint = static_init!(
+//!     capsules::process_checkpoint::ProcessCheckpoint<'static, CheckpointCapability>,
+//!     capsules::process_checkpoint::ProcessCheckpoint::new(
+//!         board_kernel,
+//!         fm25cl,
+//!         &mut CHECKPOINT_IMAGE,
+//!         &mut CHECKPOINT_CHUNK,
+//!         CheckpointCapability,
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, checkpoint);
+//! checkpoint.set_client(power_manager);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::capabilities::{ProcessCheckpointCapability, ProcessManagementCapability};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::procs::Process;
+use kernel::{ErrorCode, Kernel};
+
+/// Length of the length field stored before each snapshot.
+const LENGTH_FIELD_LEN: usize = 4;
+
+/// Receives the results of checkpoint and restore operations.
+pub trait ProcessCheckpointClient {
+    /// Called when the snapshot of a process was written to storage.
+    /// `result` contains the number of bytes written.
+    fn checkpoint_done(&self, result: Result<usize, ErrorCode>);
+
+    /// Called when a process was restored from storage. The restored process
+    /// is stopped and must be resumed to continue running.
+    fn restore_done(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Writing a snapshot to storage.
+    Checkpoint,
+    /// Reading the length of a stored snapshot.
+    RestoreLength,
+    /// Reading a stored snapshot.
+    Restore,
+}
+
+pub struct ProcessCheckpoint<'a, C: ProcessManagementCapability + ProcessCheckpointCapability> {
+    kernel: &'static Kernel,
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    client: OptionalCell<&'a dyn ProcessCheckpointClient>,
+    /// Holds the length field and the snapshot of the process.
+    image: TakeCell<'static, [u8]>,
+    /// Used for transfers to and from storage.
+    chunk: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    process_name: OptionalCell<&'static str>,
+    /// Storage address of the start of the current transfer.
+    address: Cell<usize>,
+    /// Number of bytes of `image` to transfer.
+    length: Cell<usize>,
+    /// Number of bytes of `image` transferred so far.
+    offset: Cell<usize>,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability + ProcessCheckpointCapability> ProcessCheckpoint<'a, C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        image: &'static mut [u8],
+        chunk: &'static mut [u8],
+        capability: C,
+    ) -> ProcessCheckpoint<'a, C> {
+        ProcessCheckpoint {
+            kernel,
+            storage,
+            client: OptionalCell::empty(),
+            image: TakeCell::new(image),
+            chunk: TakeCell::new(chunk),
+            state: Cell::new(State::Idle),
+            process_name: OptionalCell::empty(),
+            address: Cell::new(0),
+            length: Cell::new(0),
+            offset: Cell::new(0),
+            capability,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ProcessCheckpointClient) {
+        self.client.set(client);
+    }
+
+    /// Save a snapshot of the process named `process_name` at `address` in
+    /// storage. The process must be stopped.
+    pub fn checkpoint(&self, process_name: &'static str, address: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let length = self.image.map_or(Err(ErrorCode::NOMEM), |image| {
+            let snapshot = image.get_mut(LENGTH_FIELD_LEN..).ok_or(ErrorCode::SIZE)?;
+            let snapshot_len = self
+                .with_process(process_name, |process| {
+                    process.checkpoint(snapshot, &self.capability)
+                })
+                .unwrap_or(Err(ErrorCode::INVAL))?;
+            image[..LENGTH_FIELD_LEN].copy_from_slice(&(snapshot_len as u32).to_le_bytes());
+            Ok(LENGTH_FIELD_LEN + snapshot_len)
+        })?;
+
+        self.state.set(State::Checkpoint);
+        self.start_transfer(address, length).map_err(|e| {
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Restore the process named `process_name` from the snapshot at `address`
+    /// in storage. The process must not be running.
+    pub fn restore(&self, process_name: &'static str, address: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.with_process(process_name, |_| ()).is_none() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.process_name.set(process_name);
+        self.state.set(State::RestoreLength);
+        self.start_transfer(address, LENGTH_FIELD_LEN).map_err(|e| {
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Run `closure` on the process named `process_name`, if there is one.
+    fn with_process<F, R>(&self, process_name: &str, closure: F) -> Option<R>
+    where
+        F: FnOnce(&dyn Process) -> R,
+    {
+        let closure = Cell::new(Some(closure));
+        let result = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.get_process_name() == process_name {
+                    if let Some(closure) = closure.take() {
+                        result.set(Some(closure(process)));
+                    }
+                }
+            });
+        result.into_inner()
+    }
+
+    fn start_transfer(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        self.address.set(address);
+        self.length.set(length);
+        self.offset.set(0);
+        self.next_chunk()
+    }
+
+    /// Write or read the next part of the image.
+    fn next_chunk(&self) -> Result<(), ErrorCode> {
+        let offset = self.offset.get();
+        let address = self.address.get() + offset;
+        self.chunk.take().map_or(Err(ErrorCode::BUSY), |chunk| {
+            let length = cmp::min(chunk.len(), self.length.get() - offset);
+            if self.state.get() == State::Checkpoint {
+                self.image.map(|image| {
+                    chunk[..length].copy_from_slice(&image[offset..offset + length]);
+                });
+                self.storage.write(chunk, address, length)
+            } else {
+                self.storage.read(chunk, address, length)
+            }
+        })
+    }
+
+    /// The current transfer finished, or failed with `result`.
+    fn transfer_done(&self, result: Result<(), ErrorCode>) {
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        match state {
+            State::Idle => {}
+            State::Checkpoint => {
+                let length = self.length.get();
+                self.client
+                    .map(|client| client.checkpoint_done(result.map(|()| length)));
+            }
+            State::RestoreLength => {
+                let result = result.and_then(|()| {
+                    let snapshot_len = self.image.map_or(Err(ErrorCode::NOMEM), |image| {
+                        let mut length = [0; LENGTH_FIELD_LEN];
+                        length.copy_from_slice(&image[..LENGTH_FIELD_LEN]);
+                        let snapshot_len = u32::from_le_bytes(length) as usize;
+                        if LENGTH_FIELD_LEN + snapshot_len > image.len() {
+                            Err(ErrorCode::SIZE)
+                        } else {
+                            Ok(snapshot_len)
+                        }
+                    })?;
+                    self.state.set(State::Restore);
+                    self.start_transfer(self.address.get(), LENGTH_FIELD_LEN + snapshot_len)
+                });
+                if let Err(e) = result {
+                    self.state.set(State::Idle);
+                    self.client.map(|client| client.restore_done(Err(e)));
+                }
+            }
+            State::Restore => {
+                let result = result.and_then(|()| {
+                    let process_name = self.process_name.take().ok_or(ErrorCode::FAIL)?;
+                    let length = self.length.get();
+                    self.image.map_or(Err(ErrorCode::NOMEM), |image| {
+                        self.with_process(process_name, |process| {
+                            process.restore(&image[LENGTH_FIELD_LEN..length], &self.capability)
+                        })
+                        .unwrap_or(Err(ErrorCode::INVAL))
+                    })
+                });
+                self.client.map(|client| client.restore_done(result));
+            }
+        }
+    }
+
+    /// Account for a finished chunk, and start the next one if there is more
+    /// to transfer.
+    fn chunk_done(&self, length: usize) {
+        let offset = self.offset.get() + length;
+        self.offset.set(offset);
+        if length == 0 {
+            self.transfer_done(Err(ErrorCode::FAIL));
+        } else if offset >= self.length.get() {
+            self.transfer_done(Ok(()));
+        } else if let Err(e) = self.next_chunk() {
+            self.transfer_done(Err(e));
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability + ProcessCheckpointCapability>
+    hil::nonvolatile_storage::NonvolatileStorageClient<'static> for ProcessCheckpoint<'_, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let offset = self.offset.get();
+        let length = cmp::min(length, self.length.get() - offset);
+        self.image.map(|image| {
+            image[offset..offset + length].copy_from_slice(&buffer[..length]);
+        });
+        self.chunk.replace(buffer);
+        self.chunk_done(length);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.chunk.replace(buffer);
+        let length = cmp::min(length, self.length.get() - self.offset.get());
+        self.chunk_done(length);
+    }
+}
@@ -13,6 +13,8 @@
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
 //!  - 'panic' causes the kernel to run the panic handler
+//!  - 'lastpanic' prints the record of the last kernel panic, if the board
+//!    keeps one (see `set_panic_record()`)
 //!
 //! ### `list` Command Fields:
 //!
@@ -26,6 +28,9 @@
 //!   because the queue was full.
 //! - `Restarts`: How many times this process has crashed and been restarted by
 //!   the kernel.
+//! - `Run ms`: How long the process has executed in userspace, in milliseconds.
+//! - `Kernel ms`: How long the kernel has spent on behalf of the process, such
+//!   as handling its system calls, in milliseconds.
 //! - `State`: The state the process is in.
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
@@ -91,9 +96,9 @@
 //! Initialization complete. Entering main loop
 //! Hello World!
 //! list
-//! PID    Name    Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants
-//! 00     blink        0       113                0         0  Yielded    1/12
-//! 01     c_hello      0         8                0         0  Yielded    3/12
+//! PID    Name    Quanta  Syscalls  Dropped Upcalls  Restarts  Run ms  Kernel ms    State  Grants
+//! 00     blink        0       113                0         0       2          1  Yielded    1/12
+//! 01     c_hello      0         8                0         0       0          0  Yielded    3/12
 //! ```
 //!
 //! To get a general view of the system, use the status command:
@@ -118,12 +123,13 @@ use core::fmt;
 use core::fmt::write;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::ProcessId;
 
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::panic_record::PanicRecord;
 use kernel::ErrorCode;
 use kernel::Kernel;
 
@@ -212,6 +218,9 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     /// Memory addresses of where the kernel is placed in memory on chip.
     kernel_addresses: KernelAddresses,
 
+    /// Record of the last kernel panic, if the board keeps one.
+    panic_record: OptionalCell<&'static PanicRecord>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -276,10 +285,16 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             execute: Cell::new(false),
             kernel: kernel,
             kernel_addresses: kernel_addresses,
+            panic_record: OptionalCell::empty(),
             capability: capability,
         }
     }
 
+    /// Let the `lastpanic` command print `record`.
+    pub fn set_panic_record(&self, record: &'static PanicRecord) {
+        self.panic_record.set(record);
+    }
+
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.running.get() == false {
             self.rx_buffer.take().map(|buffer| {
@@ -302,7 +317,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
 
             let _ = self.write_bytes(b"Welcome to the process console.\n");
             let _ = self.write_bytes(
-                b"Valid commands are: help status list stop start fault process kernel\n",
+                b"Valid commands are: help status list stop start fault process kernel lastpanic\n",
             );
         }
         Ok(())
@@ -799,7 +814,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                             let _ = self.write_bytes(b"Welcome to the process console.\n");
                             let _ = self.write_bytes(b"Valid commands are: ");
                             let _ = self
-                                .write_bytes(b"help status list stop start fault process kernel\n");
+                                .write_bytes(b"help status list stop start fault process kernel lastpanic\n");
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -863,7 +878,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                         } else if clean_str.starts_with("list") {
                             let _ = self.write_bytes(b" PID    Name                Quanta  ");
                             let _ = self.write_bytes(b"Syscalls  Dropped Callbacks  ");
-                            let _ = self.write_bytes(b"Restarts    State  Grants\n");
+                            let _ = self.write_bytes(b"Restarts  Run ms  Kernel ms    State  Grants\n");
                             self.kernel
                                 .process_each_capability(&self.capability, |proc| {
                                     let info: KernelInfo = KernelInfo::new(self.kernel);
@@ -872,17 +887,21 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                     let process_id = proc.processid();
                                     let (grants_used, grants_total) =
                                         info.number_app_grant_uses(process_id, &self.capability);
+                                    let (userspace_us, kernel_us) =
+                                        info.app_execution_time_us(process_id, &self.capability);
                                     let mut console_writer = ConsoleWriter::new();
                                     let _ = write(
                                         &mut console_writer,
                                         format_args!(
-                                            "  {:?}\t{:<20}{:6}{:10}{:19}{:10}  {:?}{:5}/{}\n",
+                                            "  {:?}\t{:<20}{:6}{:10}{:19}{:10}{:8}{:11}  {:?}{:5}/{}\n",
                                             process_id,
                                             pname,
                                             proc.debug_timeslice_expiration_count(),
                                             proc.debug_syscall_count(),
                                             proc.debug_dropped_upcall_count(),
                                             proc.get_restart_count(),
+                                            userspace_us / 1000,
+                                            kernel_us / 1000,
                                             proc.get_state(),
                                             grants_used,
                                             grants_total
@@ -951,10 +970,23 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                             // Prints kernel memory by moving the writer to the
                             // start state.
                             self.write_state(WriterState::KernelStart, None);
+                        } else if clean_str.starts_with("lastpanic") {
+                            let printed = self.panic_record.map_or(false, |record| {
+                                match (record.count(), record.message()) {
+                                    (Some(count), Some(message)) => {
+                                        self.write_panic_record(record, count, message);
+                                        true
+                                    }
+                                    _ => false,
+                                }
+                            });
+                            if !printed {
+                                let _ = self.write_bytes(b"No panic recorded\n");
+                            }
                         } else {
                             let _ = self.write_bytes(b"Valid commands are: ");
                             let _ = self
-                                .write_bytes(b"help status list stop start fault process kernel\n");
+                                .write_bytes(b"help status list stop start fault process kernel lastpanic\n");
                         }
                     }
                     Err(_e) => {
@@ -974,6 +1006,30 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         self.command_index.set(0);
     }
 
+    fn write_panic_record(&self, record: &PanicRecord, count: u32, message: &str) {
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!("Panic {}: {}\nStack trace:", count, message),
+        );
+        for address in record.stack_trace() {
+            let _ = write(&mut console_writer, format_args!(" {:#010x}", address));
+        }
+        let _ = write(&mut console_writer, format_args!("\n"));
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+        for index in 0..record.process_count() {
+            record.process(index).map(|(name, state)| {
+                console_writer.clear();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("  {:<20}{:?}\n", name, state),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            });
+        }
+    }
+
     fn write_state(&self, state: WriterState, process: Option<ProcessId>) {
         if self.writer_state.get() == WriterState::Empty {
             self.writer_state.replace(state);
@@ -0,0 +1,329 @@
+//! Let a supervisor app manage the other processes.
+//!
+//! This capsule gives one app, the supervisor, the same control over processes
+//! that `capsules::process_console` gives over UART: it can list the
+//! processes, see what state they are in, and start, stop, fault, and restart
+//! them. This allows an on-device app manager to be written as an app.
+//!
+//! The supervisor is identified by the package name in its TBF header, which
+//! the board passes to `ProcessManager::new()`. The driver behaves as if it
+//! did not exist for all other apps. The supervisor cannot use the driver on
+//! itself.
+//!
+//! The package name is not authenticated: any app whose TBF header has the
+//! same name gets the same control over processes. Boards that may run apps
+//! they do not trust should load processes with `load_and_check_processes()`
+//! and an `AppCredentialsChecker` that only accepts an app with the name of
+//! the supervisor if it is signed by a key they trust.
+//!
+//! Processes are listed by index, and managed by their identifier, which
+//! changes when a process restarts.
+//!
+//! If the board records faults with `capsules::fault_log::FaultLog`, the
+//! process manager can be its client, and tells the supervisor about each
+//! fault once it is recorded.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let process_manager = static_init!(
+//!     capsules::process_manager::ProcessManager<ProcessMgmtCap>,
+//!     capsules::process_manager::ProcessManager::new(
+//!         board_kernel,
+//!         "app_manager",
+//!         board_kernel.create_grant(&grant_cap),
+//!         ProcessMgmtCap,
+//!     )
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Read-write allow `0`: buffer command `2` copies the process name to.
+//! - Subscribe `0`: called with the identifier the process had when it
+//!   faulted, the action the kernel took (`0` panic, `1` restart, `2` stop),
+//!   and whether the fault was recorded (`0`) or not (`1`).
+//! - Command `0`: driver check.
+//! - Command `1`: returns the number of processes.
+//! - Command `2`: returns the identifier, state, and name length of the process
+//!   at index `arg1`, and copies as much of its name as fits to the allowed
+//!   buffer. The state is `0` unstarted, `1` running, `2` yielded, `3` stopped
+//!   while running, `4` stopped while yielded, `5` faulted, `6` terminated, `7`
+//!   waiting for its credentials to be checked, `8` credentials failed.
+//! - Command `3`: start (resume) the process with identifier `arg1`.
+//! - Command `4`: stop the process with identifier `arg1`.
+//! - Command `5`: fault the process with identifier `arg1`, which applies the
+//!   fault policy of the board to it.
+//! - Command `6`: restart the process with identifier `arg1`.
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::OptionalCell;
+use kernel::procs::{FaultAction, Process, State};
+use kernel::{CommandReturn, Driver, ErrorCode, Grant, Kernel, ProcessId, Upcall};
+use kernel::{ReadWrite, ReadWriteAppSlice};
+
+/// Syscall driver number.
+use crate::driver;
+use crate::fault_log::{action_code, FaultLogClient};
+pub const DRIVER_NUM: usize = driver::NUM::ProcessManager as usize;
+
+#[derive(Default)]
+pub struct App {
+    name_buffer: ReadWriteAppSlice,
+    fault_callback: Upcall,
+}
+
+pub struct ProcessManager<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    /// Package name of the app that may use this driver.
+    supervisor: &'static str,
+    /// The last process found to be the supervisor.
+    supervisor_id: OptionalCell<ProcessId>,
+    apps: Grant<App>,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessManager<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        supervisor: &'static str,
+        grant: Grant<App>,
+        capability: C,
+    ) -> ProcessManager<C> {
+        ProcessManager {
+            kernel,
+            supervisor,
+            supervisor_id: OptionalCell::empty(),
+            apps: grant,
+            capability,
+        }
+    }
+
+    /// Run `closure` on the first process `predicate` is true for, if there is
+    /// one.
+    fn with_process<P, F, R>(&self, predicate: P, closure: F) -> Option<R>
+    where
+        P: Fn(usize, &dyn Process) -> bool,
+        F: FnOnce(&dyn Process) -> R,
+    {
+        let closure = Cell::new(Some(closure));
+        let result = Cell::new(None);
+        let index = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if predicate(index.get(), process) {
+                    if let Some(closure) = closure.take() {
+                        result.set(Some(closure(process)));
+                    }
+                }
+                index.set(index.get() + 1);
+            });
+        result.into_inner()
+    }
+
+    fn is_supervisor(&self, appid: ProcessId) -> bool {
+        if self.supervisor_id.contains(&appid) {
+            return true;
+        }
+        // The supervisor has a new identifier each time it restarts.
+        let supervisor = appid.get_process_name() == Some(self.supervisor);
+        if supervisor {
+            self.supervisor_id.set(appid);
+        }
+        supervisor
+    }
+
+    /// Run `closure` on the process with identifier `identifier`, unless it is
+    /// the supervisor itself.
+    fn manage<F>(&self, appid: ProcessId, identifier: usize, closure: F) -> Result<(), ErrorCode>
+    where
+        F: FnOnce(&dyn Process),
+    {
+        if identifier == appid.id() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.with_process(|_, process| process.processid().id() == identifier, closure)
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    fn process_info(&self, appid: ProcessId, index: usize) -> CommandReturn {
+        let info = self.with_process(
+            |i, _| i == index,
+            |process| {
+                let name = process.get_process_name();
+                let _ = self.apps.enter(appid, |app| {
+                    app.name_buffer.mut_map_or((), |buffer| {
+                        let length = cmp::min(buffer.len(), name.len());
+                        buffer[..length].copy_from_slice(&name.as_bytes()[..length]);
+                    });
+                });
+                (
+                    process.processid().id() as u32,
+                    state_code(process.get_state()),
+                    name.len() as u32,
+                )
+            },
+        );
+        match info {
+            Some((identifier, state, name_len)) => {
+                CommandReturn::success_u32_u32_u32(identifier, state, name_len)
+            }
+            None => CommandReturn::failure(ErrorCode::INVAL),
+        }
+    }
+}
+
+/// The number userspace sees for each process state.
+pub fn state_code(state: State) -> u32 {
+    match state {
+        State::Unstarted => 0,
+        State::Running => 1,
+        State::Yielded => 2,
+        State::StoppedRunning => 3,
+        State::StoppedYielded => 4,
+        State::Faulted => 5,
+        State::Terminated => 6,
+        State::CredentialsUnchecked => 7,
+        State::CredentialsFailed => 8,
+    }
+}
+
+impl<C: ProcessManagementCapability> FaultLogClient for ProcessManager<C> {
+    fn fault_recorded(
+        &self,
+        process: ProcessId,
+        action: FaultAction,
+        result: Result<(), ErrorCode>,
+    ) {
+        // The supervisor cannot have subscribed before it used the driver.
+        self.supervisor_id.map(|supervisor| {
+            let _ = self.apps.enter(*supervisor, |app| {
+                app.fault_callback.schedule(
+                    process.id(),
+                    action_code(action) as usize,
+                    result.is_err() as usize,
+                );
+            });
+        });
+    }
+}
+
+impl<C: ProcessManagementCapability> Driver for ProcessManager<C> {
+    /// Setup buffer to copy process names to.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer for process names.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            _ if !self.is_supervisor(appid) => Err(ErrorCode::NODEVICE),
+            0 => self
+                .apps
+                .enter(appid, |app| {
+                    mem::swap(&mut app.name_buffer, &mut slice);
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup callback for process faults.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for process faults.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            _ if !self.is_supervisor(app_id) => Err(ErrorCode::NODEVICE),
+            0 => self
+                .apps
+                .enter(app_id, |app| {
+                    mem::swap(&mut app.fault_callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Process management.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the number of processes.
+    /// - `2`: Get the identifier, state, and name of the process at index
+    ///   `arg1`.
+    /// - `3`: Start the process with identifier `arg1`.
+    /// - `4`: Stop the process with identifier `arg1`.
+    /// - `5`: Fault the process with identifier `arg1`.
+    /// - `6`: Restart the process with identifier `arg1`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        if !self.is_supervisor(appid) {
+            return CommandReturn::failure(ErrorCode::NODEVICE);
+        }
+
+        let res = match command_num {
+            0 /* This driver exists. */ => Ok(()),
+
+            1 => {
+                let count = Cell::new(0);
+                self.kernel
+                    .process_each_capability(&self.capability, |_| count.set(count.get() + 1));
+                return CommandReturn::success_u32(count.get());
+            }
+
+            2 => return self.process_info(appid, arg1),
+
+            3 => self.manage(appid, arg1, |process| process.resume()),
+
+            4 => self.manage(appid, arg1, |process| process.stop()),
+
+            5 => self.manage(appid, arg1, |process| process.set_fault_state()),
+
+            6 => self.manage(appid, arg1, |process| process.try_restart(0)),
+
+            _ /* Unknown command num */ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
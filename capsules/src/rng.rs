@@ -7,6 +7,12 @@
 //! randomness. A single command starts the RNG, the callback is called when the
 //! requested amount of randomness is received, or the buffer is filled.
 //!
+//! The driver can be watched by a watchdog service
+//! (`kernel::watchdog::Watched`). If the RNG stops providing randomness, the
+//! watchdog resets the driver, which cancels the request and calls the
+//! callbacks of waiting apps with `FAIL` and the number of bytes they did
+//! receive.
+//!
 //! Usage
 //! -----
 //!
@@ -27,6 +33,7 @@ use kernel::hil::entropy;
 use kernel::hil::entropy::{Entropy32, Entropy8};
 use kernel::hil::rng;
 use kernel::hil::rng::{Client, Continue, Random, Rng};
+use kernel::watchdog::Watched;
 use kernel::{
     CommandReturn, Driver, ErrorCode, Grant, ProcessId, ReadWrite, ReadWriteAppSlice, Upcall,
 };
@@ -47,6 +54,8 @@ pub struct RngDriver<'a> {
     rng: &'a dyn Rng<'a>,
     apps: Grant<App>,
     getting_randomness: Cell<bool>,
+    /// Whether randomness arrived since the last watchdog check.
+    progress: Cell<bool>,
 }
 
 impl<'a> RngDriver<'a> {
@@ -55,6 +64,7 @@ impl<'a> RngDriver<'a> {
             rng: rng,
             apps: grant,
             getting_randomness: Cell::new(false),
+            progress: Cell::new(false),
         }
     }
 }
@@ -65,6 +75,7 @@ impl rng::Client for RngDriver<'_> {
         randomness: &mut dyn Iterator<Item = u32>,
         _error: Result<(), ErrorCode>,
     ) -> rng::Continue {
+        self.progress.set(true);
         let mut done = true;
         for cntr in self.apps.iter() {
             cntr.enter(|app| {
@@ -226,6 +237,7 @@ impl<'a> Driver for RngDriver<'a> {
                     // result arrives anyways
                     if !self.getting_randomness.get() {
                         self.getting_randomness.set(true);
+                        self.progress.set(true);
                         let _ = self.rng.get();
                     }
 
@@ -237,6 +249,30 @@ impl<'a> Driver for RngDriver<'a> {
     }
 }
 
+impl Watched for RngDriver<'_> {
+    fn made_progress(&self) -> bool {
+        !self.getting_randomness.get() || self.progress.replace(false)
+    }
+
+    fn reset(&self) {
+        let _ = self.rng.cancel();
+        self.getting_randomness.set(false);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app| {
+                if app.remaining > 0 {
+                    app.remaining = 0;
+                    let received = app.idx;
+                    app.callback.schedule(
+                        kernel::into_statuscode(Err(ErrorCode::FAIL)),
+                        received,
+                        0,
+                    );
+                }
+            });
+        }
+    }
+}
+
 pub struct Entropy32ToRandom<'a> {
     egen: &'a dyn Entropy32<'a>,
     client: OptionalCell<&'a dyn rng::Client>,
@@ -0,0 +1,159 @@
+//! Watchdog service that finds hung processes and capsules.
+//!
+//! The hardware watchdog (`kernel::watchdog::WatchDog`) resets the chip when
+//! the kernel loop stops running. It does not notice a process that never
+//! yields while processes are run cooperatively, because the kernel loop keeps
+//! running to handle interrupts, nor a capsule that waits forever for an
+//! operation that never completes. This capsule checks for both every check
+//! interval:
+//!
+//! - A process that has been running without yielding for longer than the
+//!   process limit is put in the fault state. What happens to it then is up to
+//!   the `ProcessFaultPolicy` of the board, instead of the whole chip being
+//!   reset.
+//! - A watched capsule that has not made progress since the previous check is
+//!   reset.
+//!
+//! Only processes the scheduler runs without a timeslice, like those of the
+//! cooperative scheduler, are checked. Schedulers that give processes a
+//! timeslice preempt them when it expires, so a compute-bound process that
+//! does not yield does not keep other processes from running.
+//!
+//! The alarm interrupt also makes sure the kernel gets to run these checks
+//! while a process is spinning.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let watched = static_init!([&'static dyn kernel::watchdog::Watched; 1], [rng]);
+//! let monitor_alarm = static_init!(
+//!     capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let monitor = static_init!(
+//!     capsules::watchdog_monitor::WatchdogMonitor<
+//!         'static,
+//!         capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules::watchdog_monitor::WatchdogMonitor::new(
+//!         monitor_alarm,
+//!         board_kernel,
+//!         watched,
+//!         500,  // check interval in ms
+//!         2000, // process limit in ms
+//!         ProcessMgmtCap,
+//!     )
+//! );
+//! monitor_alarm.set_alarm_client(monitor);
+//! board_kernel.set_process_monitor(monitor, &main_loop_capability);
+//! monitor.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::OptionalCell;
+use kernel::debug;
+use kernel::hil::time::{self, Ticks};
+use kernel::watchdog::{ProcessMonitor, Watched};
+use kernel::{Kernel, ProcessId};
+
+pub struct WatchdogMonitor<'a, A: time::Alarm<'a>, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    kernel: &'static Kernel,
+    watched: &'a [&'a dyn Watched],
+    check_interval_ms: u32,
+    process_limit_ms: u32,
+    /// The process that is running or was preempted before it yielded.
+    running: OptionalCell<ProcessId>,
+    /// When `running` started running after it last yielded.
+    running_since: Cell<A::Ticks>,
+    capability: C,
+}
+
+impl<'a, A: time::Alarm<'a>, C: ProcessManagementCapability> WatchdogMonitor<'a, A, C> {
+    pub fn new(
+        alarm: &'a A,
+        kernel: &'static Kernel,
+        watched: &'a [&'a dyn Watched],
+        check_interval_ms: u32,
+        process_limit_ms: u32,
+        capability: C,
+    ) -> WatchdogMonitor<'a, A, C> {
+        WatchdogMonitor {
+            alarm,
+            kernel,
+            watched,
+            check_interval_ms,
+            process_limit_ms,
+            running: OptionalCell::empty(),
+            running_since: Cell::new(A::Ticks::from(0)),
+            capability,
+        }
+    }
+
+    /// Start checking for hung processes and capsules.
+    pub fn start(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(self.check_interval_ms));
+    }
+
+    fn check_process(&self, now: A::Ticks) {
+        let running_for = now.wrapping_sub(self.running_since.get());
+        if running_for < A::ticks_from_ms(self.process_limit_ms) {
+            return;
+        }
+        self.running.take().map(|hung| {
+            self.kernel
+                .process_each_capability(&self.capability, |process| {
+                    if process.processid() == hung {
+                        debug!(
+                            "Process {} has not yielded for {}ms",
+                            process.get_process_name(),
+                            self.process_limit_ms
+                        );
+                        process.set_fault_state();
+                    }
+                });
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: ProcessManagementCapability> ProcessMonitor
+    for WatchdogMonitor<'a, A, C>
+{
+    fn process_running(&self, process: ProcessId) {
+        if !self.running.contains(&process) {
+            self.running.set(process);
+            self.running_since.set(self.alarm.now());
+        }
+    }
+
+    fn process_yielded(&self, process: ProcessId) {
+        if self.running.contains(&process) {
+            self.running.clear();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: ProcessManagementCapability> time::AlarmClient
+    for WatchdogMonitor<'a, A, C>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        if self.running.is_some() {
+            self.check_process(now);
+        }
+        for watched in self.watched.iter() {
+            if !watched.made_progress() {
+                watched.reset();
+            }
+        }
+        self.alarm
+            .set_alarm(now, A::ticks_from_ms(self.check_interval_ms));
+    }
+}
@@ -0,0 +1,83 @@
+//! Alarm that follows the host clock, with a 1 kHz tick.
+
+use std::cell::Cell;
+use std::time::Instant;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Freq1KHz, Ticks, Ticks32, Time};
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+pub struct SimAlarm<'a> {
+    start: Instant,
+    armed: Cell<bool>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl SimAlarm<'_> {
+    pub fn new() -> Self {
+        SimAlarm {
+            start: Instant::now(),
+            armed: Cell::new(false),
+            reference: Cell::new(0.into()),
+            dt: Cell::new(0.into()),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl Time for SimAlarm<'_> {
+    type Frequency = Freq1KHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        // The counter wraps around like a hardware one.
+        (self.start.elapsed().as_millis() as u32).into()
+    }
+}
+
+impl<'a> Alarm<'a> for SimAlarm<'a> {
+    fn set_alarm_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+impl SimPeripheral for SimAlarm<'_> {
+    fn has_pending(&self) -> bool {
+        self.armed.get()
+            && self.now().wrapping_sub(self.reference.get()).into_u32() >= self.dt.get().into_u32()
+    }
+
+    fn service(&self) {
+        if self.has_pending() {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! The simulated chip.
+
+use std::fmt::Write;
+use std::thread;
+use std::time::Duration;
+
+use host::syscall::SysCall;
+
+/// A simulated peripheral with work for the kernel loop.
+///
+/// This is how the simulated peripherals interrupt the kernel: the chip
+/// services the peripherals that have work pending whenever the kernel
+/// handles interrupts.
+pub trait SimPeripheral {
+    /// Whether the peripheral has an operation to complete.
+    fn has_pending(&self) -> bool;
+
+    /// Complete pending operations and call the clients.
+    fn service(&self);
+}
+
+pub struct HostChip {
+    userspace_kernel_boundary: SysCall,
+    peripherals: &'static [&'static dyn SimPeripheral],
+}
+
+impl HostChip {
+    pub fn new(peripherals: &'static [&'static dyn SimPeripheral]) -> HostChip {
+        HostChip {
+            userspace_kernel_boundary: SysCall::new(),
+            peripherals,
+        }
+    }
+}
+
+impl kernel::Chip for HostChip {
+    type MPU = ();
+    type UserspaceKernelBoundary = SysCall;
+    type SchedulerTimer = ();
+    type WatchDog = ();
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &()
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        for peripheral in self.peripherals.iter() {
+            if peripheral.has_pending() {
+                peripheral.service();
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.peripherals
+            .iter()
+            .any(|peripheral| peripheral.has_pending())
+    }
+
+    fn sleep(&self) {
+        // There is no interrupt to wake up on, poll the peripherals instead.
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Peripherals only act in `service_pending_interrupts`, so the kernel
+        // is never interrupted.
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_str("\r\n---| Host simulator |---\r\n");
+    }
+}
@@ -0,0 +1,191 @@
+//! Flash stored in a file on the host.
+//!
+//! The file keeps its content between runs of the simulator, so it can stand
+//! in for persistent storage, such as a log or key-value store. Erased flash
+//! reads as `0xFF`.
+
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Index, IndexMut};
+use std::path::Path;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+pub const PAGE_SIZE: usize = 512;
+
+pub struct SimPage(pub [u8; PAGE_SIZE]);
+
+impl Default for SimPage {
+    fn default() -> Self {
+        Self { 0: [0; PAGE_SIZE] }
+    }
+}
+
+impl Index<usize> for SimPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for SimPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for SimPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+pub struct SimFlash<'a> {
+    file: RefCell<File>,
+    pages: usize,
+    client: OptionalCell<&'a dyn hil::flash::Client<SimFlash<'a>>>,
+    buffer: TakeCell<'static, SimPage>,
+    operation: Cell<Option<Operation>>,
+}
+
+impl<'a> SimFlash<'a> {
+    /// Flash of `pages` pages stored in the file at `path`, which is created
+    /// if needed.
+    pub fn new<P: AsRef<Path>>(path: P, pages: usize) -> io::Result<SimFlash<'a>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let size = (pages * PAGE_SIZE) as u64;
+        let current_size = file.metadata()?.len();
+        if current_size < size {
+            file.seek(SeekFrom::Start(current_size))?;
+            file.write_all(&vec![0xFF; (size - current_size) as usize])?;
+            file.flush()?;
+        }
+        Ok(SimFlash {
+            file: RefCell::new(file),
+            pages,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(None),
+        })
+    }
+
+    fn start(&self, operation: Operation, page_number: usize) -> Result<(), ErrorCode> {
+        if self.operation.get().is_some() {
+            Err(ErrorCode::BUSY)
+        } else if page_number >= self.pages {
+            Err(ErrorCode::INVAL)
+        } else {
+            self.operation.set(Some(operation));
+            Ok(())
+        }
+    }
+
+    fn read(&self, page_number: usize, page: &mut SimPage) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((page_number * PAGE_SIZE) as u64))?;
+        file.read_exact(&mut page.0)
+    }
+
+    fn write(&self, page_number: usize, data: &[u8]) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((page_number * PAGE_SIZE) as u64))?;
+        file.write_all(data)?;
+        file.flush()
+    }
+}
+
+fn flash_error(result: io::Result<()>) -> hil::flash::Error {
+    match result {
+        Ok(()) => hil::flash::Error::CommandComplete,
+        Err(_) => hil::flash::Error::FlashError,
+    }
+}
+
+impl<'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C> for SimFlash<'a> {
+    fn set_client(&'a self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl hil::flash::Flash for SimFlash<'_> {
+    type Page = SimPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        match self.start(Operation::Read(page_number), page_number) {
+            Ok(()) => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        match self.start(Operation::Write(page_number), page_number) {
+            Ok(()) => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        self.start(Operation::Erase(page_number), page_number)
+    }
+}
+
+impl SimPeripheral for SimFlash<'_> {
+    fn has_pending(&self) -> bool {
+        self.operation.get().is_some()
+    }
+
+    fn service(&self) {
+        match self.operation.take() {
+            Some(Operation::Read(page_number)) => {
+                self.buffer.take().map(|buffer| {
+                    let error = flash_error(self.read(page_number, buffer));
+                    self.client
+                        .map(move |client| client.read_complete(buffer, error));
+                });
+            }
+            Some(Operation::Write(page_number)) => {
+                self.buffer.take().map(|buffer| {
+                    let error = flash_error(self.write(page_number, &buffer.0));
+                    self.client
+                        .map(move |client| client.write_complete(buffer, error));
+                });
+            }
+            Some(Operation::Erase(page_number)) => {
+                let error = flash_error(self.write(page_number, &[0xFF; PAGE_SIZE]));
+                self.client.map(|client| client.erase_complete(error));
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! GPIO pins driven by the host.
+//!
+//! An output pin changes its level when the kernel sets it. The host code,
+//! for example a test, can read the level of a pin with `level()` and drive an
+//! input pin with `set_level()`, which fires interrupts like a hardware pin.
+
+use std::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio;
+
+use crate::chip::SimPeripheral;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edge {
+    Rising,
+    Falling,
+    Either,
+}
+
+pub struct SimPin<'a> {
+    level: Cell<bool>,
+    input: Cell<bool>,
+    output: Cell<bool>,
+    floating_state: Cell<gpio::FloatingState>,
+    interrupt_edge: Cell<Option<Edge>>,
+    interrupt_pending: Cell<bool>,
+    client: OptionalCell<&'a dyn gpio::Client>,
+}
+
+impl SimPin<'_> {
+    pub fn new() -> Self {
+        SimPin {
+            level: Cell::new(false),
+            input: Cell::new(false),
+            output: Cell::new(false),
+            floating_state: Cell::new(gpio::FloatingState::PullNone),
+            interrupt_edge: Cell::new(None),
+            interrupt_pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// The level of the pin.
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Drive the pin to `level` from outside the chip. Has no effect if the
+    /// pin is an output.
+    pub fn set_level(&self, level: bool) {
+        if !self.output.get() {
+            self.change_level(level);
+        }
+    }
+
+    fn change_level(&self, level: bool) {
+        let previous = self.level.replace(level);
+        if previous == level || !self.input.get() {
+            return;
+        }
+        let fires = match self.interrupt_edge.get() {
+            Some(Edge::Rising) => level,
+            Some(Edge::Falling) => !level,
+            Some(Edge::Either) => true,
+            None => false,
+        };
+        if fires {
+            self.interrupt_pending.set(true);
+        }
+    }
+
+    fn configuration_of(&self) -> gpio::Configuration {
+        match (self.input.get(), self.output.get()) {
+            (true, true) => gpio::Configuration::InputOutput,
+            (true, false) => gpio::Configuration::Input,
+            (false, true) => gpio::Configuration::Output,
+            (false, false) => gpio::Configuration::LowPower,
+        }
+    }
+}
+
+impl gpio::Configure for SimPin<'_> {
+    fn configuration(&self) -> gpio::Configuration {
+        self.configuration_of()
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.output.set(true);
+        self.configuration_of()
+    }
+
+    fn disable_output(&self) -> gpio::Configuration {
+        self.output.set(false);
+        self.configuration_of()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.input.set(true);
+        self.configuration_of()
+    }
+
+    fn disable_input(&self) -> gpio::Configuration {
+        self.input.set(false);
+        self.configuration_of()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.input.set(false);
+        self.output.set(false);
+    }
+
+    fn set_floating_state(&self, state: gpio::FloatingState) {
+        self.floating_state.set(state);
+        if !self.output.get() {
+            match state {
+                gpio::FloatingState::PullUp => self.change_level(true),
+                gpio::FloatingState::PullDown => self.change_level(false),
+                gpio::FloatingState::PullNone => {}
+            }
+        }
+    }
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        self.floating_state.get()
+    }
+}
+
+impl gpio::Output for SimPin<'_> {
+    fn set(&self) {
+        if self.output.get() {
+            self.change_level(true);
+        }
+    }
+
+    fn clear(&self) {
+        if self.output.get() {
+            self.change_level(false);
+        }
+    }
+
+    fn toggle(&self) -> bool {
+        if self.output.get() {
+            self.change_level(!self.level.get());
+        }
+        self.level.get()
+    }
+}
+
+impl gpio::Input for SimPin<'_> {
+    fn read(&self) -> bool {
+        self.level.get()
+    }
+}
+
+impl<'a> gpio::Interrupt<'a> for SimPin<'a> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.interrupt_edge.set(Some(match mode {
+            gpio::InterruptEdge::RisingEdge => Edge::Rising,
+            gpio::InterruptEdge::FallingEdge => Edge::Falling,
+            gpio::InterruptEdge::EitherEdge => Edge::Either,
+        }));
+    }
+
+    fn disable_interrupts(&self) {
+        self.interrupt_edge.set(None);
+        self.interrupt_pending.set(false);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.interrupt_pending.get()
+    }
+}
+
+impl gpio::Pin for SimPin<'_> {}
+impl<'a> gpio::InterruptPin<'a> for SimPin<'a> {}
+
+impl SimPeripheral for SimPin<'_> {
+    fn has_pending(&self) -> bool {
+        self.interrupt_pending.get()
+    }
+
+    fn service(&self) {
+        if self.interrupt_pending.replace(false) {
+            self.client.map(|client| client.fired());
+        }
+    }
+}
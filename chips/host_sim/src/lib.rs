@@ -0,0 +1,21 @@
+//! Chip that runs Tock as a process on the host.
+//!
+//! The peripherals of this chip are simulated with the host operating system:
+//! the alarm follows the host clock, the UART reads from and writes to host
+//! streams (by default stdin and stdout), the flash is stored in a file,
+//! GPIO pins are driven by the host code, for example a test, and the RNG
+//! draws from the host. Apps run in host threads, see the `host` arch crate.
+//!
+//! Peripherals complete their operations when the kernel loop services
+//! interrupts, so that clients get callbacks asynchronously, like with
+//! hardware.
+
+#![crate_name = "host_sim"]
+#![crate_type = "rlib"]
+
+pub mod alarm;
+pub mod chip;
+pub mod flash;
+pub mod gpio;
+pub mod rng;
+pub mod uart;
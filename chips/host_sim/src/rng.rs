@@ -0,0 +1,72 @@
+//! Random number generator that draws from the randomly seeded hasher of the
+//! host standard library.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::rng::{self, Rng};
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+pub struct SimRng<'a> {
+    requested: Cell<bool>,
+    client: OptionalCell<&'a dyn rng::Client>,
+}
+
+impl SimRng<'_> {
+    pub fn new() -> Self {
+        SimRng {
+            requested: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+/// Endless random words. Each hasher the standard library builds has keys of
+/// its own.
+struct Randomness;
+
+impl Iterator for Randomness {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        Some(hasher.finish() as u32)
+    }
+}
+
+impl<'a> Rng<'a> for SimRng<'a> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.requested.set(true);
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl SimPeripheral for SimRng<'_> {
+    fn has_pending(&self) -> bool {
+        self.requested.get()
+    }
+
+    fn service(&self) {
+        if self.requested.replace(false) {
+            self.client.map(|client| {
+                if client.randomness_available(&mut Randomness, Ok(())) == rng::Continue::More {
+                    self.requested.set(true);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! UART that writes to and reads from host streams.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+pub struct SimUart<'a> {
+    output: RefCell<Box<dyn Write>>,
+    input: Receiver<u8>,
+    /// Bytes received from `input` but not yet read by a client.
+    received: RefCell<VecDeque<u8>>,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_word: OptionalCell<u32>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_filled: Cell<usize>,
+    rx_word: Cell<bool>,
+}
+
+impl<'a> SimUart<'a> {
+    /// A UART that writes to `output` and receives the bytes sent to `input`.
+    pub fn new(output: Box<dyn Write>, input: Receiver<u8>) -> SimUart<'a> {
+        SimUart {
+            output: RefCell::new(output),
+            input,
+            received: RefCell::new(VecDeque::new()),
+            tx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_filled: Cell::new(0),
+            rx_word: Cell::new(false),
+        }
+    }
+
+    /// A UART connected to the stdin and stdout of the simulator.
+    pub fn stdio() -> SimUart<'a> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        SimUart::new(Box::new(io::stdout()), receiver)
+    }
+
+    fn transmitting(&self) -> bool {
+        self.tx_buffer.is_some() || self.tx_word.is_some()
+    }
+
+    fn receiving(&self) -> bool {
+        self.rx_buffer.is_some() || self.rx_word.get()
+    }
+
+    /// Move the bytes that arrived on `input` to `received`.
+    fn poll_input(&self) {
+        let mut received = self.received.borrow_mut();
+        while let Ok(byte) = self.input.try_recv() {
+            received.push_back(byte);
+        }
+    }
+}
+
+impl uart::Configure for SimUart<'_> {
+    fn configure(&self, _params: uart::Parameters) -> Result<(), ErrorCode> {
+        // Host streams have no line settings.
+        Ok(())
+    }
+}
+
+impl<'a> uart::Transmit<'a> for SimUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.transmitting() {
+            Err((ErrorCode::BUSY, tx_buffer))
+        } else if tx_len == 0 || tx_len > tx_buffer.len() {
+            Err((ErrorCode::SIZE, tx_buffer))
+        } else {
+            self.tx_len.set(tx_len);
+            self.tx_buffer.replace(tx_buffer);
+            Ok(())
+        }
+    }
+
+    fn transmit_word(&self, word: u32) -> Result<(), ErrorCode> {
+        if self.transmitting() {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.tx_word.set(word);
+            Ok(())
+        }
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        // Transmissions complete at the next interrupt and cannot be
+        // cancelled.
+        if self.transmitting() {
+            Err(ErrorCode::FAIL)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for SimUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.receiving() {
+            Err((ErrorCode::BUSY, rx_buffer))
+        } else if rx_len == 0 || rx_len > rx_buffer.len() {
+            Err((ErrorCode::SIZE, rx_buffer))
+        } else {
+            self.rx_len.set(rx_len);
+            self.rx_filled.set(0);
+            self.rx_buffer.replace(rx_buffer);
+            Ok(())
+        }
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        if self.receiving() {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.rx_word.set(true);
+            Ok(())
+        }
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        self.rx_word.set(false);
+        match self.rx_buffer.take() {
+            None => Ok(()),
+            Some(buffer) => {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(
+                        buffer,
+                        self.rx_filled.get(),
+                        Err(ErrorCode::CANCEL),
+                        uart::Error::Aborted,
+                    )
+                });
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+}
+
+impl<'a> uart::Uart<'a> for SimUart<'a> {}
+impl<'a> uart::UartData<'a> for SimUart<'a> {}
+
+impl SimPeripheral for SimUart<'_> {
+    fn has_pending(&self) -> bool {
+        if self.transmitting() {
+            return true;
+        }
+        self.poll_input();
+        self.receiving() && !self.received.borrow().is_empty()
+    }
+
+    fn service(&self) {
+        if let Some(buffer) = self.tx_buffer.take() {
+            let len = self.tx_len.get();
+            let result = {
+                let mut output = self.output.borrow_mut();
+                output
+                    .write_all(&buffer[..len])
+                    .and_then(|()| output.flush())
+                    .map_err(|_| ErrorCode::FAIL)
+            };
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, result));
+        }
+        if let Some(word) = self.tx_word.take() {
+            let result = {
+                let mut output = self.output.borrow_mut();
+                output
+                    .write_all(&[word as u8])
+                    .and_then(|()| output.flush())
+                    .map_err(|_| ErrorCode::FAIL)
+            };
+            self.tx_client.map(|client| client.transmitted_word(result));
+        }
+
+        self.poll_input();
+        if self.rx_word.get() {
+            let byte = self.received.borrow_mut().pop_front();
+            if let Some(byte) = byte {
+                self.rx_word.set(false);
+                self.rx_client
+                    .map(|client| client.received_word(byte as u32, Ok(()), uart::Error::None));
+            }
+        }
+        if let Some(buffer) = self.rx_buffer.take() {
+            let mut filled = self.rx_filled.get();
+            {
+                let mut received = self.received.borrow_mut();
+                while filled < self.rx_len.get() {
+                    match received.pop_front() {
+                        Some(byte) => buffer[filled] = byte,
+                        None => break,
+                    }
+                    filled += 1;
+                }
+            }
+            self.rx_filled.set(filled);
+            if filled == self.rx_len.get() {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(buffer, filled, Ok(()), uart::Error::None)
+                });
+            } else {
+                self.rx_buffer.replace(buffer);
+            }
+        }
+    }
+}
@@ -0,0 +1,724 @@
+//! Send CoAP requests to a server played by the test, over UDP on IP senders
+//! that hand packets straight to the IP receiver of the other stack.
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use capsules::net::coap::coap_node::{CoapClient, CoapNode};
+use capsules::net::coap::COAP_PORT;
+use capsules::net::coap::{coap_code, coap_opt};
+use capsules::net::coap::{encode_option, encode_payload};
+use capsules::net::coap::{Block, CoapHeader, CoapOptions, MessageType, Token};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Header, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::{PortQuery, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::net::udp::UDPHeader;
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::SimPeripheral;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::Random;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Loopback};
+
+const NODE_ADDR: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+const SERVER_ADDR: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+fn net_cap() -> &'static NetworkCapability {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ))
+}
+
+/// Random numbers that are just different from each other.
+#[derive(Default)]
+struct Counter(Cell<u32>);
+
+impl<'a> Random<'a> for Counter {
+    fn initialize(&'a self) {}
+    fn reseed(&self, seed: u32) {
+        self.0.set(seed);
+    }
+    fn random(&self) -> u32 {
+        self.0
+            .set(self.0.get().wrapping_mul(1103515245).wrapping_add(12345));
+        self.0.get()
+    }
+}
+
+/// No port is bound by userspace.
+struct NoUserPorts;
+
+impl PortQuery for NoUserPorts {
+    fn is_bound(&self, _port: u16) -> bool {
+        false
+    }
+}
+
+struct DriverCap;
+unsafe impl capabilities::UdpDriverCapability for DriverCap {}
+
+/// Builds a UDP stack that sends over `link` and receives from `receiver`,
+/// and returns a sender and a receiver bound to `port`.
+fn udp_stack(
+    link: &'static Loopback,
+    receiver: &'static IP6RecvStruct<'static>,
+    port: u16,
+) -> (
+    &'static UDPSendStruct<'static, Loopback>,
+    &'static UDPReceiver<'static>,
+) {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let udp_vis = leak(UdpVisibilityCapability::new(&create_cap));
+    let udp_send_mux: &MuxUdpSender<Loopback> = leak(MuxUdpSender::new(link));
+    link.set_client(udp_send_mux);
+    let udp_recv_mux = leak(MuxUdpReceiver::new());
+    receiver.set_client(udp_recv_mux);
+    let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+    let port_table = leak(UdpPortManager::new(
+        &create_table_cap,
+        leak([None; MAX_NUM_BOUND_PORTS]),
+        udp_vis,
+    ));
+    port_table.set_user_ports(&NoUserPorts, &DriverCap);
+    let udp_send = leak(UDPSendStruct::new(udp_send_mux, udp_vis));
+    let udp_recv = leak(UDPReceiver::new());
+    udp_recv_mux.add_client(udp_recv);
+    let socket = port_table.create_socket().unwrap();
+    let (send_binding, recv_binding) = port_table.bind(socket, port, net_cap()).unwrap();
+    udp_send.set_binding(send_binding);
+    udp_recv.set_binding(recv_binding);
+    (udp_send, udp_recv)
+}
+
+/// Records what the node hands to its client.
+#[derive(Default)]
+struct Recorder {
+    responses: RefCell<Vec<(u8, usize, Vec<u8>)>>,
+    done: RefCell<Vec<Result<u8, ErrorCode>>>,
+    notifications: RefCell<Vec<(u8, Vec<u8>, bool)>>,
+}
+
+impl CoapClient for Recorder {
+    fn response(&self, code: u8, offset: usize, payload: &[u8]) {
+        self.responses
+            .borrow_mut()
+            .push((code, offset, payload.to_vec()));
+    }
+
+    fn request_done(&self, result: Result<u8, ErrorCode>) {
+        self.done.borrow_mut().push(result);
+    }
+
+    fn notification(&self, code: u8, payload: &[u8], last: bool) {
+        self.notifications
+            .borrow_mut()
+            .push((code, payload.to_vec(), last));
+    }
+}
+
+/// A message the server received.
+struct Message {
+    header: CoapHeader,
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn values(&self, number: u16) -> Vec<&[u8]> {
+        self.options
+            .iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, value)| &value[..])
+            .collect()
+    }
+
+    fn block(&self, number: u16) -> Option<Block> {
+        self.values(number)
+            .first()
+            .map(|value| Block::from_uint(value).unwrap())
+    }
+}
+
+/// Returns the shortest encoding of an unsigned integer option value.
+fn uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+/// Plays the server: records the messages it receives, and sends what the
+/// test tells it to.
+struct Server {
+    link: &'static Loopback,
+    received: RefCell<Vec<Message>>,
+}
+
+impl Server {
+    fn send(
+        &self,
+        msg_type: MessageType,
+        code: u8,
+        message_id: u16,
+        token: Token,
+        options: &[(u16, &[u8])],
+        payload: &[u8],
+    ) {
+        let buf = leak([0; 512]);
+        let header = CoapHeader {
+            msg_type,
+            code,
+            message_id,
+            token,
+        };
+        let mut len = header.encode(buf).done().unwrap().0;
+        let mut prev = 0;
+        for (number, value) in options {
+            len += encode_option(&mut buf[len..], prev, *number, value)
+                .done()
+                .unwrap()
+                .0;
+            prev = *number;
+        }
+        len += encode_payload(&mut buf[len..], payload).done().unwrap().0;
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_src_port(COAP_PORT);
+        udp_header.set_dst_port(COAP_PORT);
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(0..len);
+        self.link
+            .send_to(
+                NODE_ADDR,
+                TransportHeader::UDP(udp_header),
+                &payload,
+                net_cap(),
+            )
+            .unwrap();
+    }
+
+    /// Answers `request` with a piggybacked response.
+    fn ack(&self, request: &Message, code: u8, options: &[(u16, &[u8])], payload: &[u8]) {
+        self.send(
+            MessageType::Acknowledgement,
+            code,
+            request.header.message_id,
+            request.header.token,
+            options,
+            payload,
+        );
+    }
+
+    fn last(&self) -> std::cell::Ref<Message> {
+        std::cell::Ref::map(self.received.borrow(), |received| received.last().unwrap())
+    }
+}
+
+impl IP6RecvClient for Server {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        assert_eq!(header.get_src_addr(), NODE_ADDR);
+        let (offset, udp_header) = UDPHeader::decode(payload).done().unwrap();
+        assert_eq!(udp_header.get_src_port(), COAP_PORT);
+        assert_eq!(udp_header.get_dst_port(), COAP_PORT);
+        let payload = &payload[offset..];
+        let (offset, header) = CoapHeader::decode(payload).done().unwrap();
+        let options = CoapOptions::new(&payload[offset..]);
+        self.received.borrow_mut().push(Message {
+            header: header,
+            options: options
+                .map(|option| (option.number, option.value.to_vec()))
+                .collect(),
+            payload: options.payload().unwrap().to_vec(),
+        });
+    }
+}
+
+struct Net {
+    node: &'static CoapNode<'static, SimAlarm<'static>>,
+    alarm: &'static SimAlarm<'static>,
+    client: &'static Recorder,
+    server: &'static Server,
+    node_link: &'static Loopback,
+    node_receiver: &'static IP6RecvStruct<'static>,
+    server_link: &'static Loopback,
+    server_receiver: &'static IP6RecvStruct<'static>,
+}
+
+impl Net {
+    fn new() -> Net {
+        let node_link = leak(Loopback::new(NODE_ADDR));
+        let node_receiver = leak(IP6RecvStruct::new());
+        let (udp_send, udp_recv) = udp_stack(node_link, node_receiver, COAP_PORT);
+        let alarm = leak(SimAlarm::new());
+        let node = leak(CoapNode::new(
+            udp_send,
+            alarm,
+            leak(Counter::default()),
+            leak([0; 256]),
+            leak([0; 64]),
+            leak([0; 1024]),
+            net_cap(),
+        ));
+        let client = leak(Recorder::default());
+        node.set_client(client);
+        alarm.set_alarm_client(node);
+        udp_send.set_client(node);
+        udp_recv.set_client(node);
+
+        let server_link = leak(Loopback::new(SERVER_ADDR));
+        let server_receiver = leak(IP6RecvStruct::new());
+        let server = leak(Server {
+            link: server_link,
+            received: RefCell::new(Vec::new()),
+        });
+        server_receiver.set_client(server);
+        Net {
+            node,
+            alarm,
+            client,
+            server,
+            node_link,
+            node_receiver,
+            server_link,
+            server_receiver,
+        }
+    }
+
+    fn run(&self, done: &dyn Fn() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            let delivered = self.node_link.deliver(self.server_receiver)
+                | self.server_link.deliver(self.node_receiver);
+            self.alarm.service();
+            if !delivered {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// Runs until the server received `count` messages in all.
+    fn run_until_received(&self, count: usize) {
+        self.run(&|| self.server.received.borrow().len() >= count);
+        assert_eq!(self.server.received.borrow().len(), count);
+    }
+
+    fn run_until_done(&self, count: usize) {
+        self.run(&|| self.client.done.borrow().len() >= count);
+    }
+}
+
+#[test]
+fn coap_request_and_response() {
+    let net = Net::new();
+    let node = net.node;
+    let server = net.server;
+
+    // The first message is lost, and retransmitted with the same message
+    // ID.
+    net.node_link.drop.set(1);
+    assert_eq!(
+        node.request(
+            coap_code::GET,
+            SERVER_ADDR,
+            COAP_PORT,
+            b"/sensors/temp?unit=C&avg",
+            &[],
+            false
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        node.request(coap_code::GET, SERVER_ADDR, COAP_PORT, b"x", &[], false),
+        Err(ErrorCode::BUSY)
+    );
+    net.run_until_received(1);
+    {
+        let request = server.last();
+        assert_eq!(request.header.msg_type, MessageType::Confirmable);
+        assert_eq!(request.header.code, coap_code::GET);
+        assert_eq!(
+            request.values(coap_opt::URI_PATH),
+            vec![&b"sensors"[..], b"temp"]
+        );
+        assert_eq!(
+            request.values(coap_opt::URI_QUERY),
+            vec![&b"unit=C"[..], b"avg"]
+        );
+        assert!(request.payload.is_empty());
+        server.ack(&request, coap_code::CONTENT, &[], b"21.5");
+    }
+    net.run_until_done(1);
+    assert_eq!(
+        *net.client.responses.borrow(),
+        vec![(coap_code::CONTENT, 0, b"21.5".to_vec())]
+    );
+    assert_eq!(*net.client.done.borrow(), vec![Ok(coap_code::CONTENT)]);
+
+    // The server acknowledges the request first, and sends the response
+    // later in a Confirmable message of its own.
+    assert_eq!(
+        node.request(
+            coap_code::POST,
+            SERVER_ADDR,
+            COAP_PORT,
+            b"actuators/led",
+            b"on",
+            false
+        ),
+        Ok(())
+    );
+    net.run_until_received(2);
+    let token = {
+        let request = server.last();
+        assert_eq!(request.header.code, coap_code::POST);
+        assert_eq!(request.payload, b"on");
+        assert_ne!(
+            request.header.message_id,
+            server.received.borrow()[0].header.message_id
+        );
+        server.ack(&request, coap_code::EMPTY, &[], &[]);
+        request.header.token
+    };
+    let separate = |server: &Server| {
+        server.send(
+            MessageType::Confirmable,
+            coap_code::CHANGED,
+            0x7000,
+            token,
+            &[],
+            &[],
+        )
+    };
+    separate(server);
+    net.run_until_done(2);
+    assert_eq!(net.client.done.borrow()[1], Ok(coap_code::CHANGED));
+    net.run_until_received(3);
+    assert_eq!(server.last().header.msg_type, MessageType::Acknowledgement);
+    assert_eq!(server.last().header.message_id, 0x7000);
+
+    // A retransmission of the response is acknowledged again, but not
+    // handed to the client again.
+    separate(server);
+    net.run_until_received(4);
+    assert_eq!(server.last().header.msg_type, MessageType::Acknowledgement);
+    assert_eq!(server.last().header.message_id, 0x7000);
+    assert_eq!(net.client.done.borrow().len(), 2);
+    assert_eq!(net.client.responses.borrow().len(), 2);
+}
+
+#[test]
+fn coap_rejects_unexpected_messages() {
+    let net = Net::new();
+    let node = net.node;
+    let server = net.server;
+
+    // The node is not a server.
+    let token = Token::new(&[9, 9]).unwrap();
+    server.send(
+        MessageType::Confirmable,
+        coap_code::GET,
+        0x100,
+        token,
+        &[(coap_opt::URI_PATH, b"x")],
+        &[],
+    );
+    net.run_until_received(1);
+    {
+        let reply = server.last();
+        assert_eq!(reply.header.msg_type, MessageType::Acknowledgement);
+        assert_eq!(reply.header.code, coap_code::NOT_FOUND);
+        assert_eq!(reply.header.message_id, 0x100);
+        assert_eq!(reply.header.token, token);
+    }
+
+    // Pings and responses nobody waits for are reset.
+    server.send(
+        MessageType::Confirmable,
+        coap_code::EMPTY,
+        0x101,
+        Token::default(),
+        &[],
+        &[],
+    );
+    net.run_until_received(2);
+    assert_eq!(server.last().header.msg_type, MessageType::Reset);
+    assert_eq!(server.last().header.message_id, 0x101);
+    server.send(
+        MessageType::Confirmable,
+        coap_code::CONTENT,
+        0x102,
+        token,
+        &[],
+        b"stale",
+    );
+    net.run_until_received(3);
+    assert_eq!(server.last().header.msg_type, MessageType::Reset);
+    assert_eq!(server.last().header.message_id, 0x102);
+
+    // Requests for the wrong methods, and paths with too long segments, are
+    // refused.
+    assert_eq!(
+        node.request(coap_code::CONTENT, SERVER_ADDR, COAP_PORT, b"x", &[], false),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        node.request(coap_code::PUT, SERVER_ADDR, COAP_PORT, b"x", &[], true),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        node.request(
+            coap_code::GET,
+            SERVER_ADDR,
+            COAP_PORT,
+            &[b'a'; 300],
+            &[],
+            false
+        ),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        node.request(
+            coap_code::PUT,
+            SERVER_ADDR,
+            COAP_PORT,
+            b"x",
+            &[0; 1025],
+            false
+        ),
+        Err(ErrorCode::SIZE)
+    );
+
+    // A server that resets the request fails it.
+    assert_eq!(
+        node.request(coap_code::DELETE, SERVER_ADDR, COAP_PORT, b"x", &[], false),
+        Ok(())
+    );
+    net.run_until_received(4);
+    let message_id = server.last().header.message_id;
+    server.send(
+        MessageType::Reset,
+        coap_code::EMPTY,
+        message_id,
+        Token::default(),
+        &[],
+        &[],
+    );
+    net.run_until_done(1);
+    assert_eq!(*net.client.done.borrow(), vec![Err(ErrorCode::FAIL)]);
+    assert!(net.client.responses.borrow().is_empty());
+}
+
+#[test]
+fn coap_block_transfers() {
+    let net = Net::new();
+    let node = net.node;
+    let server = net.server;
+
+    // The payload goes in blocks of 128 bytes, until the server asks for
+    // blocks of 64 bytes.
+    let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    assert_eq!(
+        node.request(
+            coap_code::PUT,
+            SERVER_ADDR,
+            COAP_PORT,
+            b"firmware",
+            &payload,
+            false
+        ),
+        Ok(())
+    );
+    let expected = [(0, 3, true), (2, 2, true), (3, 2, true), (4, 2, false)];
+    let mut received = Vec::new();
+    for (i, &(num, szx, more)) in expected.iter().enumerate() {
+        net.run_until_received(i + 1);
+        let request = server.last();
+        assert_eq!(request.values(coap_opt::URI_PATH), vec![&b"firmware"[..]]);
+        assert_eq!(
+            request.block(coap_opt::BLOCK1),
+            Some(Block { num, more, szx })
+        );
+        received.extend_from_slice(&request.payload);
+        let acked = Block { num, more, szx: 2 };
+        let code = if more {
+            coap_code::CONTINUE
+        } else {
+            coap_code::CHANGED
+        };
+        server.ack(
+            &request,
+            code,
+            &[(coap_opt::BLOCK1, &uint(acked.to_uint()))],
+            &[],
+        );
+    }
+    net.run_until_done(1);
+    assert_eq!(received, payload);
+    assert_eq!(*net.client.done.borrow(), vec![Ok(coap_code::CHANGED)]);
+
+    // The response comes in blocks of 64 bytes, which the node asks for
+    // one after the other.
+    let resource: Vec<u8> = (0..150).map(|i| (i * 3) as u8).collect();
+    assert_eq!(
+        node.request(coap_code::GET, SERVER_ADDR, COAP_PORT, b"log", &[], false),
+        Ok(())
+    );
+    for num in 0..3 {
+        net.run_until_received(expected.len() + num + 1);
+        let request = server.last();
+        assert_eq!(request.values(coap_opt::URI_PATH), vec![&b"log"[..]]);
+        let asked = request.block(coap_opt::BLOCK2);
+        if num == 0 {
+            assert_eq!(asked, None);
+        } else {
+            assert_eq!(
+                asked,
+                Some(Block {
+                    num: num as u32,
+                    more: false,
+                    szx: 2
+                })
+            );
+        }
+        let start = num * 64;
+        let end = std::cmp::min(start + 64, resource.len());
+        let block = Block {
+            num: num as u32,
+            more: end < resource.len(),
+            szx: 2,
+        };
+        server.ack(
+            &request,
+            coap_code::CONTENT,
+            &[(coap_opt::BLOCK2, &uint(block.to_uint()))],
+            &resource[start..end],
+        );
+    }
+    net.run_until_done(2);
+    assert_eq!(net.client.done.borrow()[1], Ok(coap_code::CONTENT));
+    let mut assembled = Vec::new();
+    for (code, offset, block) in net.client.responses.borrow()[1..].iter() {
+        assert_eq!(*code, coap_code::CONTENT);
+        assert_eq!(*offset, assembled.len());
+        assembled.extend_from_slice(block);
+    }
+    assert_eq!(assembled, resource);
+}
+
+#[test]
+fn coap_observe() {
+    let net = Net::new();
+    let node = net.node;
+    let server = net.server;
+
+    assert_eq!(node.cancel_observe(), Err(ErrorCode::ALREADY));
+    assert_eq!(
+        node.request(coap_code::GET, SERVER_ADDR, COAP_PORT, b"temp", &[], true),
+        Ok(())
+    );
+    net.run_until_received(1);
+    let token = {
+        let request = server.last();
+        assert_eq!(request.values(coap_opt::OBSERVE), vec![&[][..]]);
+        server.ack(
+            &request,
+            coap_code::CONTENT,
+            &[(coap_opt::OBSERVE, &uint(5))],
+            b"20",
+        );
+        request.header.token
+    };
+    net.run_until_done(1);
+    assert!(node.observing());
+    assert_eq!(
+        *net.client.responses.borrow(),
+        vec![(coap_code::CONTENT, 0, b"20".to_vec())]
+    );
+
+    let notify = |msg_type, message_id, sequence: Option<u32>, payload: &[u8]| {
+        let observe = sequence.map(uint);
+        let options: Vec<(u16, &[u8])> = observe
+            .iter()
+            .map(|value| (coap_opt::OBSERVE, &value[..]))
+            .collect();
+        server.send(
+            msg_type,
+            coap_code::CONTENT,
+            message_id,
+            token,
+            &options,
+            payload,
+        );
+    };
+
+    // Notifications older than the freshest one are dropped, and
+    // Confirmable ones are acknowledged.
+    notify(MessageType::NonConfirmable, 0x200, Some(6), b"21");
+    notify(MessageType::NonConfirmable, 0x201, Some(4), b"19");
+    notify(MessageType::Confirmable, 0x202, Some(7), b"22");
+    net.run_until_received(2);
+    assert_eq!(server.last().header.msg_type, MessageType::Acknowledgement);
+    assert_eq!(server.last().header.message_id, 0x202);
+    assert_eq!(
+        *net.client.notifications.borrow(),
+        vec![
+            (coap_code::CONTENT, b"21".to_vec(), false),
+            (coap_code::CONTENT, b"22".to_vec(), false),
+        ]
+    );
+
+    // Once cancelled, the next notification is reset.
+    assert_eq!(node.cancel_observe(), Ok(()));
+    assert!(!node.observing());
+    notify(MessageType::NonConfirmable, 0x203, Some(8), b"23");
+    net.run_until_received(3);
+    assert_eq!(server.last().header.msg_type, MessageType::Reset);
+    assert_eq!(server.last().header.message_id, 0x203);
+    assert_eq!(net.client.notifications.borrow().len(), 2);
+
+    // A notification without the Observe option ends the observation.
+    assert_eq!(
+        node.request(coap_code::GET, SERVER_ADDR, COAP_PORT, b"temp", &[], true),
+        Ok(())
+    );
+    net.run_until_received(4);
+    let token = {
+        let request = server.last();
+        server.ack(
+            &request,
+            coap_code::CONTENT,
+            &[(coap_opt::OBSERVE, &uint(9))],
+            b"24",
+        );
+        request.header.token
+    };
+    net.run_until_done(2);
+    assert!(node.observing());
+    server.send(
+        MessageType::NonConfirmable,
+        coap_code::NOT_FOUND,
+        0x204,
+        token,
+        &[],
+        &[],
+    );
+    net.run(&|| net.client.notifications.borrow().len() == 3);
+    assert_eq!(
+        net.client.notifications.borrow()[2],
+        (coap_code::NOT_FOUND, Vec::new(), true)
+    );
+    assert!(!node.observing());
+}
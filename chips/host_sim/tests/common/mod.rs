@@ -0,0 +1,253 @@
+//! Helpers shared by the tests on the simulated chip: a board to run apps
+//! on, and the links of the tests of the network stack.
+
+// Each test crate uses a different part of the helpers.
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::IP6RecvStruct;
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::NetworkCapability;
+use capsules::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use capsules::net::tcp::TCPHeader;
+use host::app::HostApp;
+use host_sim::chip::{HostChip, SimPeripheral};
+use kernel::common::cells::OptionalCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::ipc::IPC;
+use kernel::procs::{Process, ProcessFaultPolicy, State, StopFaultPolicy};
+use kernel::{capabilities, create_capability};
+use kernel::{Chip, Driver, ErrorCode, Kernel, Platform, Scheduler};
+use kernel::{RoundRobinProcessNode, RoundRobinSched};
+
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+/// How long tests run the kernel loop for at most.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The kernel and the chip of a simulated board with room for `N` processes.
+pub struct Sim<const N: usize> {
+    pub kernel: &'static Kernel,
+    pub chip: &'static HostChip,
+    pub processes: &'static [Option<&'static dyn Process>; N],
+    /// IPC, for boards created with `with_ipc()`.
+    pub ipc: Option<&'static IPC<N>>,
+    slots: *mut [Option<&'static dyn Process>; N],
+}
+
+impl<const N: usize> Sim<N> {
+    /// Create a kernel, and a chip with `peripherals`.
+    pub fn new(peripherals: &'static [&'static dyn SimPeripheral]) -> Sim<N> {
+        let slots: *mut [Option<&'static dyn Process>; N] = leak([None; N]);
+        // Safety: the kernel and the loader share the process array like
+        // boards share their static array of processes.
+        let processes = unsafe { &*slots };
+        Sim {
+            kernel: leak(Kernel::new(processes)),
+            chip: leak(HostChip::new(peripherals)),
+            processes,
+            ipc: None,
+            slots,
+        }
+    }
+
+    /// Create a kernel with IPC, and a chip without peripherals.
+    pub fn with_ipc() -> Sim<N> {
+        let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let mut sim = Sim::new(&[]);
+        sim.ipc = Some(leak(IPC::new(sim.kernel, &memory_allocation_cap)));
+        sim
+    }
+
+    /// The process array, for the loader to put processes in.
+    ///
+    /// Safety: the array must only be written to by a process loader.
+    pub unsafe fn slots(&self) -> &'static mut [Option<&'static dyn Process>; N] {
+        &mut *self.slots
+    }
+
+    /// Load `apps` with their TLVs in `memory_len` bytes of app memory, and
+    /// stop apps that fault.
+    pub fn load(&self, apps: &[(&HostApp, &[u8])], memory_len: usize) {
+        self.load_with_policy(apps, memory_len, &StopFaultPolicy {});
+    }
+
+    /// Load `apps` like `load()`, with `fault_policy`.
+    pub fn load_with_policy(
+        &self,
+        apps: &[(&HostApp, &[u8])],
+        memory_len: usize,
+        fault_policy: &'static dyn ProcessFaultPolicy,
+    ) {
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+        let boundary = self.chip.userspace_kernel_boundary();
+        unsafe {
+            kernel::procs::load_processes(
+                self.kernel,
+                self.chip,
+                boundary.load_apps_with_tlvs(apps),
+                boundary.app_memory(memory_len),
+                self.slots(),
+                fault_policy,
+                &process_management_cap,
+            )
+            .unwrap();
+        }
+    }
+
+    /// The process loaded into `slot`.
+    pub fn process(&self, slot: usize) -> &'static dyn Process {
+        self.processes[slot].unwrap()
+    }
+
+    /// A round robin scheduler for all processes.
+    pub fn round_robin(&self) -> &'static RoundRobinSched<'static> {
+        let scheduler = leak(RoundRobinSched::new());
+        for slot in self.processes.iter() {
+            scheduler
+                .processes
+                .push_tail(leak(RoundRobinProcessNode::new(slot)));
+        }
+        scheduler
+    }
+
+    /// Run the kernel loop with `board` and `scheduler` until `done` returns
+    /// true, or for `timeout`.
+    pub fn run_for<P: Platform, S: Scheduler<HostChip>>(
+        &self,
+        board: &P,
+        scheduler: &S,
+        timeout: Duration,
+        done: impl Fn() -> bool,
+    ) {
+        let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+        let start = Instant::now();
+        while !done() && start.elapsed() < timeout {
+            self.kernel.kernel_loop_operation(
+                board,
+                self.chip,
+                self.ipc,
+                scheduler,
+                false,
+                &main_loop_cap,
+            );
+        }
+    }
+
+    /// Run the kernel loop like `run_for()`, for at most `TIMEOUT`.
+    pub fn run_until<P: Platform, S: Scheduler<HostChip>>(
+        &self,
+        board: &P,
+        scheduler: &S,
+        done: impl Fn() -> bool,
+    ) {
+        self.run_for(board, scheduler, TIMEOUT, done);
+    }
+
+    /// Whether the processes in `slots` exited or faulted.
+    pub fn exited(&self, slots: &[usize]) -> bool {
+        slots.iter().all(|slot| {
+            let state = self.process(*slot).get_state();
+            state == State::Terminated || state == State::Faulted
+        })
+    }
+}
+
+/// A board that gives apps the drivers in the list, by driver number.
+pub struct SimBoard<const D: usize>(pub [(usize, &'static dyn Driver); D]);
+
+impl<const D: usize> Platform for SimBoard<D> {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn Driver>) -> R,
+    {
+        let driver = self.0.iter().find(|(num, _)| *num == driver_num);
+        f(driver.map(|(_, driver)| *driver))
+    }
+}
+
+/// IP sender that encodes packets and queues them for the receiver of the
+/// other stack.
+pub struct Loopback {
+    addr: Cell<IPAddr>,
+    packet: RefCell<IP6Packet<'static>>,
+    queue: RefCell<VecDeque<Vec<u8>>>,
+    /// Number of packets to drop, as if they were lost on the way.
+    pub drop: Cell<usize>,
+    client: OptionalCell<&'static dyn IP6SendClient>,
+}
+
+impl Loopback {
+    pub fn new(addr: IPAddr) -> Loopback {
+        let payload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: leak([0; 1220]),
+        };
+        Loopback {
+            addr: Cell::new(addr),
+            packet: RefCell::new(IP6Packet::new(payload)),
+            queue: RefCell::new(VecDeque::new()),
+            drop: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Hands the oldest queued packet to `receiver`, and tells the client it
+    /// was sent. Returns whether there was one.
+    pub fn deliver(&self, receiver: &IP6RecvStruct) -> bool {
+        let packet = self.queue.borrow_mut().pop_front();
+        match packet {
+            Some(packet) => {
+                if self.drop.get() > 0 {
+                    self.drop.set(self.drop.get() - 1);
+                } else {
+                    receiver.receive(&packet, packet.len(), Ok(()));
+                }
+                self.client.map(|client| client.send_done(Ok(())));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl IP6Sender<'static> for Loopback {
+    fn set_client(&self, client: &'static dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        let mut packet = self.packet.borrow_mut();
+        packet.header = IP6Header::default();
+        packet.header.src_addr = self.addr.get();
+        packet.header.dst_addr = dst;
+        packet.set_payload(transport_header, payload);
+        packet.set_transport_checksum();
+        let mut buf = vec![0; packet.get_total_len() as usize];
+        packet.encode(&mut buf).done().ok_or(ErrorCode::SIZE)?;
+        self.queue.borrow_mut().push_back(buf);
+        Ok(())
+    }
+}
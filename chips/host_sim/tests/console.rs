@@ -0,0 +1,139 @@
+//! Boot the kernel on the simulated chip and run an app that prints to the
+//! console.
+
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use capsules::console::{self, Console};
+use host::app::{AppContext, HostApp};
+use host_sim::chip::{HostChip, SimPeripheral};
+use host_sim::uart::SimUart;
+use kernel::capabilities;
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::procs::{Process, State, StopFaultPolicy};
+use kernel::{create_capability, static_init};
+use kernel::{Chip, Kernel, Platform, RoundRobinProcessNode, RoundRobinSched};
+
+const MESSAGE: &[u8] = b"hello from a host app\n";
+
+fn hello(ctx: &AppContext) {
+    let buffer = ctx.allocate(MESSAGE.len());
+    buffer.copy_from_slice(MESSAGE);
+    ctx.subscribe(console::DRIVER_NUM, 1, Some(written), 0);
+    ctx.allow_readonly(console::DRIVER_NUM, 1, buffer.as_ptr(), buffer.len());
+    ctx.command(console::DRIVER_NUM, 1, buffer.len(), 0);
+    ctx.yield_wait();
+}
+
+fn written(_ctx: &AppContext, _length: usize, _: usize, _: usize, _appdata: usize) {}
+
+const APPS: &[HostApp] = &[HostApp {
+    name: "hello",
+    main: hello,
+    minimum_ram_size: 8192,
+}];
+
+static mut PROCESSES: [Option<&'static dyn Process>; 1] = [None];
+
+/// Collects what the UART writes.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct SimBoard {
+    console: &'static Console<'static>,
+}
+
+impl Platform for SimBoard {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            console::DRIVER_NUM => f(Some(self.console)),
+            _ => f(None),
+        }
+    }
+}
+
+#[test]
+fn app_prints_to_console() {
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+    let output = Output::default();
+    let (_input, receiver) = mpsc::channel();
+
+    unsafe {
+        let board_kernel = static_init!(Kernel, Kernel::new(&PROCESSES));
+
+        let uart = static_init!(
+            SimUart<'static>,
+            SimUart::new(Box::new(output.clone()), receiver)
+        );
+        let peripherals = static_init!([&'static dyn SimPeripheral; 1], [uart]);
+        let chip = static_init!(HostChip, HostChip::new(peripherals));
+
+        let console = static_init!(
+            Console<'static>,
+            Console::new(
+                uart,
+                static_init!([u8; 64], [0; 64]),
+                static_init!([u8; 64], [0; 64]),
+                board_kernel.create_grant(&memory_allocation_cap),
+            )
+        );
+        uart.set_transmit_client(console);
+        uart.set_receive_client(console);
+
+        let scheduler = static_init!(RoundRobinSched<'static>, RoundRobinSched::new());
+        let node = static_init!(
+            RoundRobinProcessNode<'static>,
+            RoundRobinProcessNode::new(&PROCESSES[0])
+        );
+        scheduler.processes.push_head(node);
+
+        let apps = chip.userspace_kernel_boundary();
+        kernel::procs::load_processes(
+            board_kernel,
+            chip,
+            apps.load_apps(APPS),
+            apps.app_memory(16384),
+            &mut PROCESSES,
+            &StopFaultPolicy {},
+            &process_management_cap,
+        )
+        .unwrap();
+
+        let board = SimBoard { console };
+        let start = Instant::now();
+        while PROCESSES[0].unwrap().get_state() != State::Terminated
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            board_kernel.kernel_loop_operation(
+                &board,
+                chip,
+                None::<&kernel::ipc::IPC<0>>,
+                scheduler,
+                false,
+                &main_loop_cap,
+            );
+        }
+
+        assert_eq!(PROCESSES[0].unwrap().get_state(), State::Terminated);
+        assert_eq!(&output.0.lock().unwrap()[..], MESSAGE);
+    }
+}
@@ -0,0 +1,146 @@
+//! Allocate, resize, and free custom grants, and compact the grant region,
+//! from a capsule serving an app on the simulated chip.
+
+use host::app::{AppContext, HostApp};
+use kernel::procs::State;
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability};
+use kernel::{CommandReturn, CustomGrantSlice, Driver, ErrorCode, Grant, ProcessId};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = 0x9_0000;
+const SLOTS: usize = 3;
+
+#[derive(Default)]
+struct App {
+    buffers: [Option<CustomGrantSlice<u8>>; SLOTS],
+}
+
+/// Keeps buffers of any length for each app, each filled with its slot
+/// number plus one.
+struct Buffers {
+    apps: Grant<App>,
+}
+
+impl Driver for Buffers {
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let slot = arg1 % SLOTS;
+        let fill = slot as u8 + 1;
+        self.apps
+            .enter_with_allocator(appid, |app, allocator| match command_num {
+                // Allocate `arg2` bytes in `slot`.
+                1 => match allocator.alloc_slice_with(arg2, |_| fill) {
+                    Ok(buffer) => {
+                        app.buffers[slot] = Some(buffer);
+                        CommandReturn::success()
+                    }
+                    Err(_) => CommandReturn::failure(ErrorCode::NOMEM),
+                },
+                // Free `slot`.
+                2 => match app.buffers[slot].take().map(|buffer| buffer.free()) {
+                    Some(Ok(())) => CommandReturn::success(),
+                    _ => CommandReturn::failure(ErrorCode::FAIL),
+                },
+                // Compact the grant region.
+                3 => match allocator.compact() {
+                    Ok(reclaimed) => CommandReturn::success_u32(reclaimed as u32),
+                    Err(_) => CommandReturn::failure(ErrorCode::FAIL),
+                },
+                // Resize `slot` to `arg2` bytes.
+                4 => match app.buffers[slot]
+                    .as_mut()
+                    .map(|buffer| buffer.resize_with(arg2, |_| fill))
+                {
+                    Some(Ok(())) => CommandReturn::success(),
+                    _ => CommandReturn::failure(ErrorCode::NOMEM),
+                },
+                // Check the content of `slot` and return its length.
+                5 => match app.buffers[slot].as_mut().map(|buffer| {
+                    buffer
+                        .enter(|bytes| bytes.iter().all(|byte| *byte == fill).then(|| bytes.len()))
+                }) {
+                    Some(Ok(Some(len))) => CommandReturn::success_u32(len as u32),
+                    _ => CommandReturn::failure(ErrorCode::FAIL),
+                },
+                _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+}
+
+/// Call `command` of the driver. Returns the value it returns, or zero.
+fn command(ctx: &AppContext, command: usize, slot: usize, len: usize) -> Result<u32, ErrorCode> {
+    match ctx.command(DRIVER_NUM, command, slot, len) {
+        SyscallReturn::Success => Ok(0),
+        SyscallReturn::SuccessU32(value) => Ok(value),
+        SyscallReturn::Failure(err) => Err(err),
+        _ => panic!("unexpected return value"),
+    }
+}
+
+fn kernel_memory_break(ctx: &AppContext) -> u64 {
+    match ctx.memop(6, 0) {
+        SyscallReturn::SuccessU64(address) => address,
+        _ => panic!("no kernel memory break"),
+    }
+}
+
+fn resize_buffers(ctx: &AppContext) {
+    assert_eq!(command(ctx, 1, 0, 100), Ok(0));
+    assert_eq!(command(ctx, 1, 1, 200), Ok(0));
+    assert_eq!(command(ctx, 1, 2, 50), Ok(0));
+
+    // The freed buffer is reclaimed, and the buffers below it move up.
+    let before = kernel_memory_break(ctx);
+    assert_eq!(command(ctx, 2, 1, 0), Ok(0));
+    assert!(command(ctx, 3, 0, 0).unwrap() >= 200);
+    assert!(kernel_memory_break(ctx) >= before + 200);
+    assert_eq!(command(ctx, 5, 0, 0), Ok(100));
+    assert_eq!(command(ctx, 5, 2, 0), Ok(50));
+
+    // Growing a buffer moves it and keeps its content.
+    assert_eq!(command(ctx, 4, 0, 300), Ok(0));
+    assert_eq!(command(ctx, 5, 0, 0), Ok(300));
+    assert_eq!(command(ctx, 5, 2, 0), Ok(50));
+
+    // Shrinking keeps the buffer in place.
+    assert_eq!(command(ctx, 4, 2, 10), Ok(0));
+    assert_eq!(command(ctx, 5, 2, 0), Ok(10));
+
+    // A freed buffer can no longer be accessed.
+    assert_eq!(command(ctx, 2, 2, 0), Ok(0));
+    assert_eq!(command(ctx, 2, 2, 0), Err(ErrorCode::FAIL));
+    assert_eq!(command(ctx, 5, 0, 0), Ok(300));
+}
+
+#[test]
+fn custom_grants_resize_and_compact() {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let sim: Sim<1> = Sim::new(&[]);
+    let buffers = leak(Buffers {
+        apps: sim.kernel.create_grant(&memory_allocation_cap),
+    });
+
+    let app = HostApp {
+        name: "buffers",
+        main: resize_buffers,
+        minimum_ram_size: 8192,
+    };
+    sim.load(&[(&app, &[])], 16384);
+
+    sim.run_until(
+        &SimBoard([(DRIVER_NUM, buffers)]),
+        sim.round_robin(),
+        || sim.exited(&[0]),
+    );
+    assert_eq!(sim.process(0).get_state(), State::Terminated);
+}
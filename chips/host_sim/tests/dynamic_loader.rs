@@ -0,0 +1,277 @@
+//! Install and remove apps at runtime with the dynamic process loader and the
+//! app loader capsule on the simulated chip.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use capsules::app_loader::{self, AppLoader};
+use host::app::{AppContext, HostApp};
+use host_sim::chip::{HostChip, SimPeripheral};
+use kernel::common::cells::OptionalCell;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::procs::StopFaultPolicy;
+use kernel::procs::{DynamicProcessLoader, DynamicProcessLoading, State};
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability, Chip, ErrorCode};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = app_loader::DRIVER_NUM;
+
+/// App flash that accepts every write, and finishes it the next time the
+/// chip services its peripherals.
+struct SimAppFlash {
+    pending: Cell<Option<(&'static mut [u8], usize)>>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient<'static>>,
+}
+
+impl NonvolatileStorage<'static> for SimAppFlash {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient<'static>) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        _buffer: &'static mut [u8],
+        _address: usize,
+        _length: usize,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        _address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.pending.set(Some((buffer, length)));
+        Ok(())
+    }
+}
+
+impl SimPeripheral for SimAppFlash {
+    fn has_pending(&self) -> bool {
+        let pending = self.pending.take();
+        let has_pending = pending.is_some();
+        self.pending.set(pending);
+        has_pending
+    }
+
+    fn service(&self) {
+        if let Some((buffer, length)) = self.pending.take() {
+            self.client
+                .map(move |client| client.write_done(buffer, length));
+        }
+    }
+}
+
+fn command(ctx: &AppContext, command: usize, arg1: usize) -> Result<(), ErrorCode> {
+    match ctx.command(DRIVER_NUM, command, arg1, 0) {
+        SyscallReturn::Success => Ok(()),
+        SyscallReturn::Failure(err) => Err(err),
+        _ => panic!("unexpected return value"),
+    }
+}
+
+/// An app whose TBF header does not allow it to use the app loader.
+fn unprivileged(ctx: &AppContext) {
+    assert_eq!(command(ctx, 0, 0), Err(ErrorCode::NODEVICE));
+    assert_eq!(command(ctx, 1, 1024), Err(ErrorCode::NODEVICE));
+    assert_eq!(command(ctx, 5, 0), Err(ErrorCode::NODEVICE));
+}
+
+fn setup_done(_ctx: &AppContext, command: usize, status: usize, _: usize, _appdata: usize) {
+    assert_eq!((command, status), (1, 0));
+}
+
+/// An app that starts installing an app and stops before it finishes.
+fn installer(ctx: &AppContext) {
+    assert_eq!(command(ctx, 0, 0), Ok(()));
+    ctx.subscribe(DRIVER_NUM, 0, Some(setup_done), 0);
+    assert_eq!(command(ctx, 1, 1024), Ok(()));
+    ctx.yield_wait();
+    assert_eq!(command(ctx, 1, 1024), Err(ErrorCode::BUSY));
+}
+
+/// The process identifier of the reinstaller.
+static REINSTALLER: AtomicUsize = AtomicUsize::new(0);
+
+/// An app that installs an app after the installer stopped.
+fn reinstaller(ctx: &AppContext) {
+    ctx.subscribe(DRIVER_NUM, 0, Some(setup_done), 0);
+    assert_eq!(command(ctx, 1, 1024), Ok(()));
+    ctx.yield_wait();
+
+    // Writes that do not fit the buffer of the kernel are refused rather
+    // than cut short.
+    let data = ctx.allocate(600);
+    ctx.allow_readonly(DRIVER_NUM, 0, data.as_ptr(), data.len());
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 2, 16, 600),
+        SyscallReturn::Failure(ErrorCode::SIZE)
+    ));
+
+    // A process cannot remove itself.
+    let identifier = REINSTALLER.load(Ordering::SeqCst);
+    assert_eq!(command(ctx, 5, identifier), Err(ErrorCode::INVAL));
+}
+
+/// An app that waits forever.
+fn waiter(ctx: &AppContext) {
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+/// The syscall permissions TLV that allows every command of the app loader.
+fn app_loader_permission() -> Vec<u8> {
+    let mut tlv = Vec::new();
+    tlv.extend_from_slice(&0x8001u16.to_le_bytes());
+    tlv.extend_from_slice(&18u16.to_le_bytes());
+    tlv.extend_from_slice(&1u16.to_le_bytes());
+    tlv.extend_from_slice(&(DRIVER_NUM as u32).to_le_bytes());
+    tlv.extend_from_slice(&0u32.to_le_bytes());
+    tlv.extend_from_slice(&u64::MAX.to_le_bytes());
+    // Pad the TLV to a multiple of four bytes.
+    tlv.resize(24, 0);
+    tlv
+}
+
+/// Create a kernel with room for three processes and a loader for `apps`,
+/// which gives processes memory from `memory_len` bytes.
+fn boot(
+    apps: &[(&HostApp, &[u8])],
+    memory_len: usize,
+    peripherals: &'static [&'static dyn SimPeripheral],
+) -> (Sim<3>, &'static DynamicProcessLoader<HostChip>) {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+    let sim = Sim::new(peripherals);
+    let boundary = sim.chip.userspace_kernel_boundary();
+    let loader = leak(DynamicProcessLoader::new(
+        sim.kernel,
+        sim.chip,
+        boundary.load_apps_with_tlvs(apps),
+        boundary.app_memory(memory_len),
+        unsafe { sim.slots() },
+        &StopFaultPolicy {},
+        None,
+        &process_management_cap,
+    ));
+
+    (sim, loader)
+}
+
+#[test]
+fn uninstalled_app_memory_is_reused() {
+    let first = HostApp {
+        name: "first",
+        main: waiter,
+        minimum_ram_size: 4096,
+    };
+    let second = HostApp {
+        name: "second",
+        main: waiter,
+        minimum_ram_size: 4096,
+    };
+    let (sim, loader) = boot(&[(&first, &[]), (&second, &[])], 16384, &[]);
+    let processes = sim.processes;
+    loader.load_processes().unwrap();
+
+    // There is only room for two processes.
+    let process = processes[1].unwrap();
+    let address = process.flash_start() as usize;
+    let memory_start = process.mem_start();
+    assert!(processes[2].is_none());
+    assert_eq!(loader.load_process(address), Err(ErrorCode::NOMEM));
+
+    // Removing the second process frees its slot and its memory.
+    let identifier = process.processid().id();
+    let (flash_start, flash_length) = loader.uninstall_process(identifier).unwrap();
+    assert_eq!(flash_start, address);
+    assert!(flash_length > 0);
+    assert!(processes[1].is_none());
+    assert_eq!(loader.uninstall_process(identifier), Err(ErrorCode::INVAL));
+
+    let processid = loader.load_process(address).unwrap();
+    let process = processes[1].unwrap();
+    assert_eq!(process.processid(), processid);
+    assert_eq!(process.mem_start(), memory_start);
+    assert_eq!(process.get_state(), State::Unstarted);
+
+    // Something that is not an app is not loaded.
+    assert_eq!(loader.load_process(address + 4), Err(ErrorCode::INVAL));
+
+    // The memory can be reused again and again.
+    loader.uninstall_process(processid.id()).unwrap();
+    assert!(loader.load_process(address).is_ok());
+}
+
+#[test]
+fn app_loader_requires_permission_and_drops_abandoned_installs() {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let unprivileged_app = HostApp {
+        name: "unprivileged",
+        main: unprivileged,
+        minimum_ram_size: 8192,
+    };
+    let installer_app = HostApp {
+        name: "installer",
+        main: installer,
+        minimum_ram_size: 8192,
+    };
+    let reinstaller_app = HostApp {
+        name: "reinstaller",
+        main: reinstaller,
+        minimum_ram_size: 8192,
+    };
+
+    let flash: &'static SimAppFlash = leak(SimAppFlash {
+        pending: Cell::new(None),
+        client: OptionalCell::empty(),
+    });
+    let permission = app_loader_permission();
+    let (sim, loader) = boot(
+        &[
+            (&unprivileged_app, &[]),
+            (&installer_app, &permission),
+            (&reinstaller_app, &permission),
+        ],
+        65536,
+        leak([flash as &dyn SimPeripheral]),
+    );
+
+    let app_loader: &'static AppLoader = leak(AppLoader::new(
+        loader,
+        flash,
+        sim.kernel.create_grant(&memory_allocation_cap),
+        leak([0; 512]),
+    ));
+    flash.set_client(app_loader);
+    loader.load_processes().unwrap();
+
+    let scheduler = sim.round_robin();
+    let board = SimBoard([(DRIVER_NUM, app_loader)]);
+    let run_until_exited = |slots: &[usize]| {
+        sim.run_until(&board, scheduler, || sim.exited(slots));
+        for slot in slots {
+            assert_eq!(sim.process(*slot).get_state(), State::Terminated);
+        }
+    };
+
+    // The reinstaller only starts once the installer is gone.
+    let reinstaller_address = sim.process(2).flash_start() as usize;
+    loader
+        .uninstall_process(sim.process(2).processid().id())
+        .unwrap();
+    run_until_exited(&[0, 1]);
+
+    // The install of the installer can never finish, so it does not keep
+    // the reinstaller from installing.
+    let processid = loader.load_process(reinstaller_address).unwrap();
+    REINSTALLER.store(processid.id(), Ordering::SeqCst);
+    run_until_exited(&[2]);
+}
@@ -0,0 +1,136 @@
+//! Record the faults of a process in a log with the fault log on the
+//! simulated chip.
+
+use std::cell::{Cell, RefCell};
+
+use capsules::fault_log::{FaultLog, FAULT_RECORD_VERSION};
+use host::app::{AppContext, HostApp};
+use host_sim::chip::SimPeripheral;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::log::{LogWrite, LogWriteClient};
+use kernel::procs::{State, ThresholdRestartFaultPolicy};
+use kernel::ErrorCode;
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+/// Log that keeps the records appended to it, and finishes each append the
+/// next time the chip services its peripherals.
+struct SimLog {
+    pending: Cell<Option<(&'static mut [u8], usize)>>,
+    records: RefCell<Vec<Vec<u8>>>,
+    client: OptionalCell<&'static dyn LogWriteClient>,
+}
+
+impl LogWrite<'static> for SimLog {
+    fn set_append_client(&'static self, append_client: &'static dyn LogWriteClient) {
+        self.client.set(append_client);
+    }
+
+    fn append(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.pending.set(Some((buffer, length)));
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn erase(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl SimPeripheral for SimLog {
+    fn has_pending(&self) -> bool {
+        let pending = self.pending.take();
+        let has_pending = pending.is_some();
+        self.pending.set(pending);
+        has_pending
+    }
+
+    fn service(&self) {
+        if let Some((buffer, length)) = self.pending.take() {
+            self.records.borrow_mut().push(buffer[..length].to_vec());
+            self.client
+                .map(move |client| client.append_done(buffer, length, false, Ok(())));
+        }
+    }
+}
+
+/// An app that faults right away.
+fn crasher(ctx: &AppContext) {
+    ctx.fault();
+}
+
+/// Split `record` into its fixed part and its four sections.
+fn parse(record: &[u8]) -> (&[u8], Vec<&[u8]>) {
+    let (header, mut rest) = record.split_at(12);
+    let mut sections = Vec::new();
+    while !rest.is_empty() {
+        let length = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        sections.push(&rest[2..2 + length]);
+        rest = &rest[2 + length..];
+    }
+    (header, sections)
+}
+
+#[test]
+fn faults_are_recorded() {
+    let log: &'static SimLog = leak(SimLog {
+        pending: Cell::new(None),
+        records: RefCell::new(Vec::new()),
+        client: OptionalCell::empty(),
+    });
+    let sim: Sim<1> = Sim::new(leak([log as &dyn SimPeripheral]));
+
+    // Restart the process after its first fault, and stop it after the
+    // second.
+    let fault_log = leak(FaultLog::new(
+        log,
+        leak(ThresholdRestartFaultPolicy::new(0)),
+        leak([0; 256]),
+    ));
+    log.set_append_client(fault_log);
+
+    let app = HostApp {
+        name: "crasher",
+        main: crasher,
+        minimum_ram_size: 8192,
+    };
+    sim.load_with_policy(&[(&app, &[])], 16384, fault_log);
+
+    let process = sim.process(0);
+    let first_identifier = process.processid().id() as u32;
+    sim.run_until(&SimBoard([]), sim.round_robin(), || {
+        log.records.borrow().len() >= 2
+    });
+    assert_eq!(process.get_state(), State::Faulted);
+
+    let records = log.records.borrow();
+    assert_eq!(records.len(), 2);
+    for (restart_count, record) in records.iter().enumerate() {
+        let (header, sections) = parse(record);
+        assert_eq!(header[0], FAULT_RECORD_VERSION);
+        // The process is restarted once, then stopped.
+        assert_eq!(header[1], [1, 2][restart_count]);
+        assert_eq!(&header[2..4], &0u16.to_le_bytes());
+        let identifier = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if restart_count == 0 {
+            assert_eq!(identifier, first_identifier);
+        } else {
+            // A restarted process has a new identifier.
+            assert_ne!(identifier, first_identifier);
+        }
+        assert_eq!(&header[8..12], &(restart_count as u32).to_le_bytes());
+
+        // The host records no fault status, context, or stack pointer.
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[0], b"crasher");
+        assert!(sections[1..].iter().all(|section| section.is_empty()));
+    }
+}
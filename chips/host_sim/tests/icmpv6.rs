@@ -0,0 +1,256 @@
+//! Ping between two ICMPv6 nodes, and register one of them with a router
+//! played by the test, over IP senders that hand packets straight to the IP
+//! receiver of the other stack.
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use capsules::net::icmpv6::icmpv6_node::{ICMP6Node, PingClient, RegistrationState};
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, ICMP6RecvClient, MuxICMP6Receiver};
+use capsules::net::icmpv6::nd::{aro_status, prefix_flags, NDOption, NDOptions};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Header, TransportHeader};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::SimPeripheral;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Loopback};
+
+type Node = ICMP6Node<'static, SimAlarm<'static>>;
+
+const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0];
+
+/// Records the results of pings.
+#[derive(Default)]
+struct Pings {
+    done: RefCell<Vec<(u16, Result<u32, ErrorCode>)>>,
+}
+
+impl PingClient for Pings {
+    fn ping_done(&self, seqno: u16, result: Result<u32, ErrorCode>) {
+        self.done.borrow_mut().push((seqno, result));
+    }
+}
+
+struct Stack {
+    link: &'static Loopback,
+    receiver: &'static IP6RecvStruct<'static>,
+    mux: &'static MuxICMP6Receiver<'static>,
+    alarm: &'static SimAlarm<'static>,
+    node: &'static Node,
+    pings: &'static Pings,
+}
+
+fn net_cap() -> &'static NetworkCapability {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ))
+}
+
+fn stack(link_addr: MacAddress) -> Stack {
+    let addr = IPAddr::generate_from_mac(link_addr);
+    let link = leak(Loopback::new(addr));
+    let alarm = leak(SimAlarm::new());
+    let node = leak(ICMP6Node::new(
+        link,
+        alarm,
+        addr,
+        link_addr,
+        leak([0; 128]),
+        net_cap(),
+    ));
+    alarm.set_alarm_client(node);
+    link.set_client(node);
+    let mux = leak(MuxICMP6Receiver::new());
+    let node_receiver = leak(ICMP6Receiver::new());
+    node_receiver.set_client(node);
+    mux.add_client(node_receiver);
+    let receiver = leak(IP6RecvStruct::new());
+    receiver.set_client(mux);
+    let pings = leak(Pings::default());
+    node.set_ping_client(pings);
+    Stack {
+        link,
+        receiver,
+        mux,
+        alarm,
+        node,
+        pings,
+    }
+}
+
+/// Passes packets between the stacks and runs their timers until `done`
+/// holds. Panics if it does not within five seconds.
+fn run(a: &Stack, b: &Stack, done: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        let delivered = a.link.deliver(b.receiver) | b.link.deliver(a.receiver);
+        a.alarm.service();
+        b.alarm.service();
+        if !delivered {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[test]
+fn icmpv6_ping() {
+    let a = stack(MacAddress::Short(1));
+    let b = stack(MacAddress::Long([2; 8]));
+    let b_addr = IPAddr::generate_from_mac(MacAddress::Long([2; 8]));
+
+    assert_eq!(a.node.ping(b_addr, 32), Ok(0));
+    assert_eq!(a.node.ping(b_addr, 32), Err(ErrorCode::BUSY));
+    run(&a, &b, || !a.pings.done.borrow().is_empty());
+    assert!(matches!(a.pings.done.borrow()[0], (0, Ok(_))));
+
+    // Sequence numbers go up with each ping.
+    assert_eq!(a.node.ping(b_addr, 0), Ok(1));
+    run(&a, &b, || a.pings.done.borrow().len() == 2);
+    assert!(matches!(a.pings.done.borrow()[1], (1, Ok(_))));
+    assert_eq!(a.node.ping(b_addr, 1000), Err(ErrorCode::SIZE));
+}
+
+/// Plays a 6LoWPAN router: answers Router Solicitations with a Router
+/// Advertisement holding `PREFIX`, and accepts registrations.
+struct Router {
+    link: &'static Loopback,
+    addr: IPAddr,
+    link_addr: MacAddress,
+    registrations: Cell<usize>,
+    advertisements: RefCell<Vec<IPAddr>>,
+}
+
+impl Router {
+    fn send(&self, dst: IPAddr, header: ICMP6Header, options: &[NDOption]) {
+        let buf = leak([0; 128]);
+        let mut len = 0;
+        for option in options {
+            len += option.encode(&mut buf[len..]).done().unwrap().0;
+        }
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(0..len);
+        self.link
+            .send_to(dst, TransportHeader::ICMP(header), &payload, net_cap())
+            .unwrap();
+    }
+}
+
+impl ICMP6RecvClient for Router {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let src = ip_header.get_src_addr();
+        match icmp_header.get_type() {
+            ICMP6Type::Type133 => {
+                let mut header = ICMP6Header::new(ICMP6Type::Type134);
+                header.set_options(ICMP6HeaderOptions::Type134 {
+                    cur_hop_limit: 64,
+                    flags: 0,
+                    router_lifetime: 1800,
+                    reachable_time: 0,
+                    retrans_timer: 0,
+                });
+                let mut prefix = IPAddr::new();
+                prefix.0[..8].copy_from_slice(&PREFIX);
+                let options = [
+                    NDOption::SourceLinkLayerAddress(self.link_addr),
+                    NDOption::PrefixInformation {
+                        prefix_len: 64,
+                        flags: prefix_flags::ON_LINK | prefix_flags::AUTONOMOUS,
+                        valid_lifetime: 3600,
+                        preferred_lifetime: 3600,
+                        prefix,
+                    },
+                ];
+                self.send(src, header, &options);
+            }
+            ICMP6Type::Type135 => {
+                let registration = NDOptions::new(payload).find_map(|option| match option {
+                    NDOption::AddressRegistration {
+                        lifetime, eui64, ..
+                    } => Some((lifetime, eui64)),
+                    _ => None,
+                });
+                if let (Some((lifetime, eui64)), ICMP6HeaderOptions::Type135 { target, .. }) =
+                    (registration, icmp_header.get_options())
+                {
+                    self.registrations.set(self.registrations.get() + 1);
+                    let mut header = ICMP6Header::new(ICMP6Type::Type136);
+                    header.set_options(ICMP6HeaderOptions::Type136 { flags: 0, target });
+                    let option = NDOption::AddressRegistration {
+                        status: aro_status::SUCCESS,
+                        lifetime,
+                        eui64,
+                    };
+                    self.send(src, header, &[option]);
+                }
+            }
+            ICMP6Type::Type136 => {
+                if let ICMP6HeaderOptions::Type136 { target, .. } = icmp_header.get_options() {
+                    self.advertisements.borrow_mut().push(target);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn icmpv6_router_registration() {
+    let host = stack(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]));
+    let router_stack = stack(MacAddress::Short(0x100));
+    let router = leak(Router {
+        link: router_stack.link,
+        addr: IPAddr::generate_from_mac(MacAddress::Short(0x100)),
+        link_addr: MacAddress::Short(0x100),
+        registrations: Cell::new(0),
+        advertisements: RefCell::new(Vec::new()),
+    });
+    let router_receiver = leak(ICMP6Receiver::new());
+    router_receiver.set_client(router);
+    router_stack.mux.add_client(router_receiver);
+
+    assert_eq!(host.node.registration_state(), RegistrationState::Idle);
+    assert_eq!(host.node.start(), Ok(()));
+    assert_eq!(host.node.start(), Err(ErrorCode::ALREADY));
+    run(&host, &router_stack, || {
+        host.node.registration_state() == RegistrationState::Registered
+    });
+    assert_eq!(router.registrations.get(), 1);
+    assert_eq!(host.node.router(), Some(router.addr));
+
+    // The host registered the address formed from the prefix, and answers
+    // solicitations for it.
+    let registered = host.node.registered_addr();
+    assert_eq!(&registered.0[..8], &PREFIX);
+    assert_eq!(
+        &registered.0[8..],
+        &IPAddr::generate_from_mac(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8])).0[8..]
+    );
+    let mut header = ICMP6Header::new(ICMP6Type::Type135);
+    header.set_options(ICMP6HeaderOptions::Type135 {
+        reserved: 0,
+        target: registered,
+    });
+    router.send(
+        IPAddr::generate_from_mac(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8])),
+        header,
+        &[NDOption::SourceLinkLayerAddress(router.link_addr)],
+    );
+    run(&host, &router_stack, || {
+        !router.advertisements.borrow().is_empty()
+    });
+    assert_eq!(*router.advertisements.borrow(), vec![registered]);
+}
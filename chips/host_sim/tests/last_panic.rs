@@ -0,0 +1,157 @@
+//! Record a kernel panic, and read the record from a supervisor app with the
+//! last panic capsule on the simulated chip.
+
+use std::panic;
+use std::ptr;
+
+use capsules::last_panic::{self, LastPanic};
+use host::app::{AppContext, HostApp};
+use kernel::panic_record::PanicRecord;
+use kernel::procs::{Process, State};
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = last_panic::DRIVER_NUM;
+
+/// Where the test pretends the kernel code is. The test puts an address in
+/// this range on the stack, for the record to find it.
+const KERNEL_TEXT: (usize, usize) = (0x7e57_0000, 0x7e58_0000);
+const RETURN_ADDRESS: usize = 0x7e57_1234;
+
+/// An app that is not the supervisor, and sees no last panic driver.
+fn other(ctx: &AppContext) {
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 0, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    let buffer = ctx.allocate(8);
+    assert!(matches!(
+        ctx.allow_readwrite(DRIVER_NUM, 0, buffer.as_mut_ptr(), buffer.len()),
+        SyscallReturn::AllowReadWriteFailure(ErrorCode::NODEVICE, ..)
+    ));
+}
+
+/// The supervisor, which reads the record.
+fn supervisor(ctx: &AppContext) {
+    let buffer = ctx.allocate(128);
+    ctx.allow_readwrite(DRIVER_NUM, 0, buffer.as_mut_ptr(), buffer.len());
+
+    let (message_len, stack_trace_len) = match ctx.command(DRIVER_NUM, 1, 0, 0) {
+        SyscallReturn::SuccessU32U32U32(1, message_len, stack_trace_len) => {
+            (message_len as usize, stack_trace_len as usize)
+        }
+        _ => panic!("no panic recorded"),
+    };
+    let message = std::str::from_utf8(&buffer[..message_len]).unwrap();
+    assert!(message.contains("out of cheese"));
+    // Other words on the stack may look like return addresses too.
+    let found = (0..stack_trace_len).any(|index| {
+        matches!(
+            ctx.command(DRIVER_NUM, 2, index, 0),
+            SyscallReturn::SuccessU32(address) if address as usize == RETURN_ADDRESS
+        )
+    });
+    assert!(found);
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 2, stack_trace_len, 0),
+        SyscallReturn::Failure(ErrorCode::INVAL)
+    ));
+
+    // The process states when the kernel panicked, before any app ran.
+    for (index, name) in ["other", "supervisor"].iter().enumerate() {
+        match ctx.command(DRIVER_NUM, 3, index, 0) {
+            SyscallReturn::SuccessU32U32(0, name_len) => {
+                assert_eq!(&buffer[..name_len as usize], name.as_bytes());
+            }
+            _ => panic!("process not recorded"),
+        }
+    }
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 3, 2, 0),
+        SyscallReturn::Failure(ErrorCode::INVAL)
+    ));
+}
+
+/// Panic, and record the panic in `record` like a panic handler would.
+fn panic_into(
+    record: &'static mut PanicRecord,
+    processes: &'static [Option<&'static dyn Process>; 2],
+) {
+    // The start of the kernel stack, as far as the record is concerned.
+    let stack_start = 0usize;
+    let stack_end = &stack_start as *const usize as usize;
+    // The hook must be `Send`, which references to processes are not.
+    let record = record as *mut PanicRecord as usize;
+    let processes = processes as *const [Option<&'static dyn Process>; 2] as usize;
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |panic_info| unsafe {
+        (*(record as *mut PanicRecord)).record(
+            panic_info,
+            &*(processes as *const [Option<&'static dyn Process>; 2]),
+            (KERNEL_TEXT.0 as *const u8, KERNEL_TEXT.1 as *const u8),
+            stack_end as *const u8,
+        );
+    }));
+    let result = panic::catch_unwind(|| {
+        // A return address of the kernel on the stack.
+        let return_address = RETURN_ADDRESS;
+        unsafe { ptr::read_volatile(&return_address) };
+        panic!("out of cheese");
+    });
+    panic::set_hook(default_hook);
+    assert!(result.is_err());
+}
+
+#[test]
+fn supervisor_reads_the_last_panic() {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let sim: Sim<2> = Sim::new(&[]);
+
+    // The record the panic handler writes, like a static in `.noinit`.
+    let record_ptr: *mut PanicRecord = leak(PanicRecord::new());
+    // Safety: the panic handler only writes the record before apps run.
+    let record: &'static PanicRecord = unsafe { &*record_ptr };
+    assert!(!record.is_valid());
+    assert_eq!(record.count(), None);
+
+    let last_panic = leak(LastPanic::new(
+        record,
+        "supervisor",
+        sim.kernel.create_grant(&memory_allocation_cap),
+    ));
+
+    let other_app = HostApp {
+        name: "other",
+        main: other,
+        minimum_ram_size: 8192,
+    };
+    let supervisor_app = HostApp {
+        name: "supervisor",
+        main: supervisor,
+        minimum_ram_size: 8192,
+    };
+    sim.load(&[(&other_app, &[]), (&supervisor_app, &[])], 32768);
+
+    panic_into(unsafe { &mut *record_ptr }, sim.processes);
+    assert_eq!(record.count(), Some(1));
+    assert!(record.stack_trace().contains(&(RETURN_ADDRESS as u32)));
+    assert_eq!(record.process_count(), 2);
+    assert_eq!(record.process(1), Some(("supervisor", State::Unstarted)));
+
+    sim.run_until(
+        &SimBoard([(DRIVER_NUM, last_panic)]),
+        sim.round_robin(),
+        || sim.exited(&[0, 1]),
+    );
+    assert_eq!(sim.process(0).get_state(), State::Terminated);
+    assert_eq!(sim.process(1).get_state(), State::Terminated);
+}
@@ -0,0 +1,154 @@
+//! Send requests to a service app and receive its replies with message
+//! passing IPC on the simulated chip.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use capsules::message_ipc::{self, MessageIpc, ServiceAccess};
+use host::app::{AppContext, HostApp};
+use kernel::procs::State;
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = message_ipc::DRIVER_NUM;
+
+struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
+/// The length of the request the service got last, plus one.
+static REQUEST: AtomicUsize = AtomicUsize::new(0);
+/// The length of the reply the client got last, plus one.
+static REPLY: AtomicUsize = AtomicUsize::new(0);
+
+fn request(_ctx: &AppContext, _client: usize, length: usize, _: usize, _appdata: usize) {
+    REQUEST.store(length + 1, Ordering::SeqCst);
+}
+
+fn reply(_ctx: &AppContext, _service: usize, length: usize, _: usize, _appdata: usize) {
+    REPLY.store(length + 1, Ordering::SeqCst);
+}
+
+fn command(ctx: &AppContext, command: usize, arg1: usize, arg2: usize) -> SyscallReturn {
+    ctx.command(DRIVER_NUM, command, arg1, arg2)
+}
+
+/// A service that replies to each request with the request in upper case.
+fn service(ctx: &AppContext) {
+    let requests = ctx.allocate(16);
+    let replies = ctx.allocate(16);
+    ctx.allow_readwrite(DRIVER_NUM, 0, requests.as_mut_ptr(), requests.len());
+    ctx.allow_readonly(DRIVER_NUM, 1, replies.as_ptr(), replies.len());
+    ctx.subscribe(DRIVER_NUM, 0, Some(request), 0);
+    assert!(matches!(command(ctx, 3, 0, 0), SyscallReturn::Success));
+
+    loop {
+        ctx.yield_wait();
+        let length = match REQUEST.swap(0, Ordering::SeqCst) {
+            0 => continue,
+            length => length - 1,
+        };
+        for (reply, request) in replies.iter_mut().zip(requests[..length].iter()) {
+            *reply = request.to_ascii_uppercase();
+        }
+        assert!(matches!(command(ctx, 4, length, 0), SyscallReturn::Success));
+    }
+}
+
+/// Send `message` to `service`, and wait for the reply.
+fn round_trip(ctx: &AppContext, service: usize, message: &[u8]) -> &'static [u8] {
+    let request = ctx.allocate(message.len());
+    request.copy_from_slice(message);
+    let replies = ctx.allocate(16);
+    ctx.allow_readonly(DRIVER_NUM, 0, request.as_ptr(), request.len());
+    ctx.allow_readwrite(DRIVER_NUM, 1, replies.as_mut_ptr(), replies.len());
+    assert!(matches!(
+        command(ctx, 2, service, message.len()),
+        SyscallReturn::Success
+    ));
+    loop {
+        ctx.yield_wait();
+        match REPLY.swap(0, Ordering::SeqCst) {
+            0 => continue,
+            length => return &replies[..length - 1],
+        }
+    }
+}
+
+/// A client of the service, that sends it two requests.
+fn client(ctx: &AppContext) {
+    let name = ctx.allocate(5);
+    name.copy_from_slice(b"upper");
+    ctx.allow_readonly(DRIVER_NUM, 2, name.as_ptr(), name.len());
+    ctx.subscribe(DRIVER_NUM, 1, Some(reply), 0);
+    let service = match command(ctx, 1, 0, 0) {
+        SyscallReturn::SuccessU32(service) => service as usize,
+        _ => panic!("service not found"),
+    };
+
+    assert_eq!(round_trip(ctx, service, b"hello"), b"HELLO");
+    assert_eq!(round_trip(ctx, service, b"again"), b"AGAIN");
+}
+
+/// A client the board does not let use the service.
+fn stranger(ctx: &AppContext) {
+    let name = ctx.allocate(5);
+    name.copy_from_slice(b"upper");
+    ctx.allow_readonly(DRIVER_NUM, 2, name.as_ptr(), name.len());
+    assert!(matches!(
+        command(ctx, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+}
+
+static SERVICE_ACCESS: [ServiceAccess; 1] = [ServiceAccess {
+    service: "upper",
+    clients: &["client"],
+}];
+
+#[test]
+fn client_gets_replies_from_idle_service() {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let sim: Sim<3> = Sim::new(&[]);
+    let message_ipc = leak(MessageIpc::new(
+        sim.kernel,
+        sim.kernel.create_grant(&memory_allocation_cap),
+        &SERVICE_ACCESS,
+        ProcessMgmtCap,
+    ));
+
+    let service_app = HostApp {
+        name: "upper",
+        main: service,
+        minimum_ram_size: 8192,
+    };
+    let client_app = HostApp {
+        name: "client",
+        main: client,
+        minimum_ram_size: 8192,
+    };
+    let stranger_app = HostApp {
+        name: "stranger",
+        main: stranger,
+        minimum_ram_size: 8192,
+    };
+    sim.load(
+        &[
+            (&service_app, &[]),
+            (&client_app, &[]),
+            (&stranger_app, &[]),
+        ],
+        49152,
+    );
+
+    sim.run_until(
+        &SimBoard([(DRIVER_NUM, message_ipc)]),
+        sim.round_robin(),
+        || sim.exited(&[1, 2]),
+    );
+    assert_eq!(sim.process(0).get_state(), State::Yielded);
+    assert_eq!(sim.process(1).get_state(), State::Terminated);
+    assert_eq!(sim.process(2).get_state(), State::Terminated);
+}
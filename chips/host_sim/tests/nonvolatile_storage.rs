@@ -0,0 +1,231 @@
+//! Store objects in the userspace nonvolatile storage from apps with and
+//! without storage permissions, across reboots of the simulated chip.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use capsules::nonvolatile_storage_driver::{self, NonvolatileStorage, SHARED_STORAGE_ID};
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use host::app::{AppContext, HostApp};
+use host_sim::chip::SimPeripheral;
+use host_sim::flash::{SimFlash, SimPage, PAGE_SIZE};
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+use kernel::procs::State;
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = nonvolatile_storage_driver::DRIVER_NUM;
+
+static DONE: AtomicBool = AtomicBool::new(false);
+static LENGTH: AtomicUsize = AtomicUsize::new(0);
+static STATUS: AtomicUsize = AtomicUsize::new(0);
+
+fn done(_ctx: &AppContext, length: usize, status: usize, _: usize, _appdata: usize) {
+    LENGTH.store(length, Ordering::SeqCst);
+    STATUS.store(status, Ordering::SeqCst);
+    DONE.store(true, Ordering::SeqCst);
+}
+
+/// Wait for the read or write of the app to finish. Returns the length and
+/// the status the capsule passed.
+fn wait(ctx: &AppContext) -> (usize, usize) {
+    while !DONE.swap(false, Ordering::SeqCst) {
+        ctx.yield_wait();
+    }
+    (LENGTH.load(Ordering::SeqCst), STATUS.load(Ordering::SeqCst))
+}
+
+fn command(ctx: &AppContext, command: usize, arg1: usize, arg2: usize) -> Result<(), ErrorCode> {
+    match ctx.command(DRIVER_NUM, command, arg1, arg2) {
+        SyscallReturn::Success => Ok(()),
+        SyscallReturn::Failure(err) => Err(err),
+        _ => panic!("unexpected return value"),
+    }
+}
+
+/// Subscribe to both callbacks and share buffers of `len` bytes. Returns the
+/// buffer reads go to and the buffer writes come from.
+fn setup(ctx: &AppContext, len: usize) -> (&'static mut [u8], &'static mut [u8]) {
+    ctx.subscribe(DRIVER_NUM, 0, Some(done), 0);
+    ctx.subscribe(DRIVER_NUM, 1, Some(done), 0);
+    let read = ctx.allocate(len);
+    let write = ctx.allocate(len);
+    ctx.allow_readwrite(DRIVER_NUM, 0, read.as_mut_ptr(), len);
+    ctx.allow_readonly(DRIVER_NUM, 0, write.as_ptr(), len);
+    (read, write)
+}
+
+fn write(ctx: &AppContext, buffer: &mut [u8], data: &[u8]) {
+    buffer[..data.len()].copy_from_slice(data);
+    assert_eq!(command(ctx, 3, 0, data.len()), Ok(()));
+    assert_eq!(wait(ctx), (data.len(), 0));
+}
+
+fn read(ctx: &AppContext, buffer: &[u8], data: &[u8]) {
+    assert_eq!(command(ctx, 2, 0, data.len()), Ok(()));
+    assert_eq!(wait(ctx), (data.len(), 0));
+    assert_eq!(&buffer[..data.len()], data);
+}
+
+/// An app without storage permissions, from before storage IDs.
+fn legacy(ctx: &AppContext) {
+    let (read_buffer, write_buffer) = setup(ctx, 8);
+
+    // The first command waits for the capsule to find the owners of the
+    // objects, so the permission check fails through the callback.
+    assert_eq!(command(ctx, 4, 1, 0), Ok(()));
+    assert_eq!(command(ctx, 2, 0, 4), Ok(()));
+    assert_eq!(wait(ctx), (0, ErrorCode::NOSUPPORT as usize));
+    assert_eq!(command(ctx, 2, 0, 4), Err(ErrorCode::NOSUPPORT));
+
+    // The app can use the shared object.
+    assert_eq!(command(ctx, 4, 0, 0), Ok(()));
+    write(ctx, write_buffer, b"old");
+    read(ctx, read_buffer, b"old");
+}
+
+/// An app with storage ID 1.
+fn owner(ctx: &AppContext) {
+    let (read_buffer, write_buffer) = setup(ctx, 8);
+
+    // The object does not exist until the app writes to it.
+    assert_eq!(command(ctx, 2, 0, 5), Ok(()));
+    assert_eq!(wait(ctx), (0, ErrorCode::INVAL as usize));
+    write(ctx, write_buffer, b"hello");
+    read(ctx, read_buffer, b"hello");
+
+    // The app did not list the shared object.
+    assert_eq!(command(ctx, 4, SHARED_STORAGE_ID as usize, 0), Ok(()));
+    assert_eq!(command(ctx, 2, 0, 3), Err(ErrorCode::NOSUPPORT));
+}
+
+/// An app with storage ID 2, that may read the objects of storage ID 1 and
+/// of apps without storage permissions.
+fn reader(ctx: &AppContext) {
+    let (read_buffer, write_buffer) = setup(ctx, 8);
+
+    assert_eq!(command(ctx, 4, 1, 0), Ok(()));
+    assert_eq!(command(ctx, 2, 0, 5), Ok(()));
+    assert_eq!(wait(ctx), (5, 0));
+    assert_eq!(&read_buffer[..5], b"hello");
+
+    // The app may not modify the object.
+    write_buffer[..3].copy_from_slice(b"bye");
+    assert_eq!(command(ctx, 3, 0, 3), Err(ErrorCode::NOSUPPORT));
+    read(ctx, read_buffer, b"hello");
+
+    assert_eq!(command(ctx, 4, SHARED_STORAGE_ID as usize, 0), Ok(()));
+    read(ctx, read_buffer, b"old");
+
+    // Storage ID 2 has no object yet.
+    assert_eq!(command(ctx, 4, 0, 0), Ok(()));
+    assert_eq!(command(ctx, 2, 0, 5), Err(ErrorCode::INVAL));
+}
+
+/// An app whose storage is larger than the flash behind it.
+fn misconfigured(ctx: &AppContext) {
+    setup(ctx, 8);
+
+    // Reading the owner of an object past the end of the flash fails, and so
+    // does the command waiting for it.
+    assert_eq!(command(ctx, 2, 0, 4), Ok(()));
+    assert_eq!(wait(ctx), (0, ErrorCode::INVAL as usize));
+}
+
+/// The storage permissions TLV with `write_id`, `read_ids` and no modify IDs.
+fn storage_permissions(write_id: u32, read_ids: &[u32]) -> Vec<u8> {
+    let mut tlv = Vec::new();
+    tlv.extend_from_slice(&7u16.to_le_bytes());
+    tlv.extend_from_slice(&((8 + 4 * read_ids.len()) as u16).to_le_bytes());
+    tlv.extend_from_slice(&write_id.to_le_bytes());
+    tlv.extend_from_slice(&(read_ids.len() as u16).to_le_bytes());
+    for id in read_ids {
+        tlv.extend_from_slice(&id.to_le_bytes());
+    }
+    tlv.extend_from_slice(&0u16.to_le_bytes());
+    tlv
+}
+
+/// Boot the kernel with `app`, with `userspace_length` bytes of storage in
+/// the flash stored at `path`, and run it until the app exits.
+fn boot(path: &std::path::Path, pages: usize, userspace_length: usize, app: (&HostApp, &[u8])) {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let flash: &'static SimFlash = leak(SimFlash::new(path, pages).unwrap());
+    let sim: Sim<1> = Sim::new(leak([flash as &dyn SimPeripheral]));
+    let pages_driver: &'static NonvolatileToPages<SimFlash> =
+        leak(NonvolatileToPages::new(flash, leak(SimPage::default())));
+    flash.set_client(pages_driver);
+    let storage: &'static NonvolatileStorage = leak(NonvolatileStorage::new(
+        pages_driver,
+        sim.kernel.create_grant(&memory_allocation_cap),
+        0,
+        userspace_length,
+        0,
+        0,
+        leak([0; 512]),
+    ));
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(pages_driver, storage);
+
+    sim.load(&[app], 16384);
+    sim.run_until(
+        &SimBoard([(DRIVER_NUM, storage)]),
+        sim.round_robin(),
+        || sim.exited(&[0]),
+    );
+    assert_eq!(sim.process(0).get_state(), State::Terminated);
+}
+
+#[test]
+fn nonvolatile_storage_objects_persist_and_are_isolated() {
+    let path = std::env::temp_dir().join(format!("tock-nvm-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let legacy_app = HostApp {
+        name: "legacy",
+        main: legacy,
+        minimum_ram_size: 8192,
+    };
+    let owner_app = HostApp {
+        name: "owner",
+        main: owner,
+        minimum_ram_size: 8192,
+    };
+    let reader_app = HostApp {
+        name: "reader",
+        main: reader,
+        minimum_ram_size: 8192,
+    };
+
+    // Each boot runs one app, so each app sees what the one before stored.
+    boot(&path, 8, 8 * PAGE_SIZE, (&legacy_app, &[]));
+    boot(
+        &path,
+        8,
+        8 * PAGE_SIZE,
+        (&owner_app, &storage_permissions(1, &[])),
+    );
+    boot(
+        &path,
+        8,
+        8 * PAGE_SIZE,
+        (
+            &reader_app,
+            &storage_permissions(2, &[1, SHARED_STORAGE_ID]),
+        ),
+    );
+    // Only half of the regions of the storage are in this flash.
+    let small_path =
+        std::env::temp_dir().join(format!("tock-nvm-small-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&small_path);
+    let misconfigured_app = HostApp {
+        name: "misconfigured",
+        main: misconfigured,
+        minimum_ram_size: 8192,
+    };
+    boot(&small_path, 4, 8 * PAGE_SIZE, (&misconfigured_app, &[]));
+    let _ = std::fs::remove_file(&small_path);
+}
@@ -0,0 +1,177 @@
+//! Let a low priority service serve a high priority client over IPC despite a
+//! busy process of intermediate priority, with the priority scheduler on the
+//! simulated chip.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use host::app::{AppContext, HostApp};
+use kernel::ipc;
+use kernel::procs::State;
+use kernel::syscall::SyscallReturn;
+use kernel::PrioritySched;
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = ipc::DRIVER_NUM;
+
+/// The number of times the spinner yielded when the service got the request,
+/// plus one.
+static SERVED_AFTER: AtomicUsize = AtomicUsize::new(0);
+/// The number of times the spinner yielded.
+static SPINS: AtomicUsize = AtomicUsize::new(0);
+/// Whether the spinner was told to start.
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+/// Whether the service is ready for requests.
+static READY: AtomicUsize = AtomicUsize::new(0);
+
+fn request(_ctx: &AppContext, _client: usize, _: usize, _: usize, _appdata: usize) {
+    SERVED_AFTER.store(SPINS.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+}
+
+fn start(_ctx: &AppContext, _: usize, _: usize, _: usize, _appdata: usize) {
+    STARTED.store(1, Ordering::SeqCst);
+}
+
+fn ready(_ctx: &AppContext, _: usize, _: usize, _: usize, _appdata: usize) {
+    READY.store(1, Ordering::SeqCst);
+}
+
+/// Look up the IPC descriptor of the process called `name`.
+fn discover(ctx: &AppContext, name: &[u8]) -> usize {
+    let buffer = ctx.allocate(name.len());
+    buffer.copy_from_slice(name);
+    ctx.allow_readonly(DRIVER_NUM, 0, buffer.as_ptr(), buffer.len());
+    match ctx.command(DRIVER_NUM, 1, 0, 0) {
+        SyscallReturn::SuccessU32(descriptor) => descriptor as usize,
+        _ => panic!("process not found"),
+    }
+}
+
+/// The client, which starts the spinner and sends a request to the service
+/// once the service is ready.
+fn client(ctx: &AppContext) {
+    let service = discover(ctx, b"service");
+    let spinner = discover(ctx, b"spinner");
+    ctx.subscribe(DRIVER_NUM, service, Some(ready), 0);
+    while READY.load(Ordering::SeqCst) == 0 {
+        ctx.yield_wait();
+    }
+
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 3, spinner, 0),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 2, service, 0),
+        SyscallReturn::Success
+    ));
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+/// A compute-bound app that yields without waiting for a while once started.
+fn spinner(ctx: &AppContext) {
+    let client = discover(ctx, b"client");
+    ctx.subscribe(DRIVER_NUM, client, Some(start), 0);
+    while STARTED.load(Ordering::SeqCst) == 0 {
+        ctx.yield_wait();
+    }
+    for _ in 0..100 {
+        SPINS.fetch_add(1, Ordering::SeqCst);
+        ctx.yield_no_wait();
+    }
+}
+
+/// A service that tells the client it is ready, and never answers requests.
+fn service(ctx: &AppContext) {
+    ctx.subscribe(DRIVER_NUM, 0, Some(request), 0);
+    let client = discover(ctx, b"client");
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 3, client, 0),
+        SyscallReturn::Success
+    ));
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+#[test]
+fn service_runs_at_client_priority_until_it_yields() {
+    let sim: Sim<3> = Sim::with_ipc();
+    let scheduler = leak(PrioritySched::new(sim.kernel));
+
+    // In order of priority: the client, the spinner, and the service.
+    let client_app = HostApp {
+        name: "client",
+        main: client,
+        minimum_ram_size: 8192,
+    };
+    let spinner_app = HostApp {
+        name: "spinner",
+        main: spinner,
+        minimum_ram_size: 8192,
+    };
+    let service_app = HostApp {
+        name: "service",
+        main: service,
+        minimum_ram_size: 8192,
+    };
+    sim.load(
+        &[(&client_app, &[]), (&spinner_app, &[]), (&service_app, &[])],
+        49152,
+    );
+
+    let board = SimBoard([(DRIVER_NUM, sim.ipc.unwrap())]);
+    let spinner = sim.process(1);
+    sim.run_until(&board, scheduler, || {
+        spinner.get_state() == State::Terminated
+    });
+    assert_eq!(spinner.get_state(), State::Terminated);
+
+    // With the priority of the client, the service runs before the spinner.
+    assert_eq!(SERVED_AFTER.load(Ordering::SeqCst), 1);
+    // The service yields without notifying the client, and gives up the
+    // priority of the client.
+    let service = sim.process(2);
+    assert_eq!(service.get_state(), State::Yielded);
+    assert_eq!(service.priority(), 2);
+    assert_eq!(sim.process(0).priority(), 0);
+}
+
+fn idle(ctx: &AppContext) {
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+#[test]
+fn priority_is_released_in_a_cycle_of_services() {
+    let sim: Sim<3> = Sim::new(&[]);
+    let app = HostApp {
+        name: "idle",
+        main: idle,
+        minimum_ram_size: 8192,
+    };
+    sim.load(&[(&app, &[]), (&app, &[]), (&app, &[])], 49152);
+    let client = sim.process(0);
+    let a = sim.process(1);
+    let b = sim.process(2);
+
+    // `a` and `b` serve each other, and `b` serves the client.
+    a.inherit_priority(b.processid());
+    b.inherit_priority(a.processid());
+    assert_eq!((a.priority(), b.priority()), (1, 1));
+    b.inherit_priority(client.processid());
+    assert_eq!((a.priority(), b.priority()), (0, 0));
+
+    // Neither keeps the priority of the client through the other once `b`
+    // served it.
+    b.release_priority(client.processid());
+    assert_eq!((a.priority(), b.priority()), (1, 1));
+    a.release_priority(b.processid());
+    assert_eq!((a.priority(), b.priority()), (1, 1));
+    b.release_priority(a.processid());
+    assert_eq!((a.priority(), b.priority()), (1, 2));
+}
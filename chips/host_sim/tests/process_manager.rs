@@ -0,0 +1,94 @@
+//! Manage processes from a supervisor app with the process manager on the
+//! simulated chip.
+
+use capsules::process_manager::{self, ProcessManager};
+use host::app::{AppContext, HostApp};
+use kernel::procs::State;
+use kernel::syscall::SyscallReturn;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+const DRIVER_NUM: usize = process_manager::DRIVER_NUM;
+
+struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
+/// An app that is not the supervisor, and sees no process manager.
+fn other(ctx: &AppContext) {
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 0, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 4, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    assert!(matches!(
+        ctx.subscribe(DRIVER_NUM, 0, None, 0),
+        SyscallReturn::SubscribeFailure(ErrorCode::NODEVICE, ..)
+    ));
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+/// The supervisor, which stops the other app.
+fn supervisor(ctx: &AppContext) {
+    // Let the other app run first.
+    ctx.yield_no_wait();
+
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 0, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32(2)
+    ));
+    let identifier = match ctx.command(DRIVER_NUM, 2, 0, 0) {
+        SyscallReturn::SuccessU32U32U32(identifier, state, name_len) => {
+            assert_eq!((state, name_len), (2, 5));
+            identifier as usize
+        }
+        _ => panic!("unexpected return value"),
+    };
+    assert!(matches!(
+        ctx.command(DRIVER_NUM, 4, identifier, 0),
+        SyscallReturn::Success
+    ));
+}
+
+#[test]
+fn only_the_supervisor_manages_processes() {
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let sim: Sim<2> = Sim::new(&[]);
+    let process_manager = leak(ProcessManager::new(
+        sim.kernel,
+        "supervisor",
+        sim.kernel.create_grant(&memory_allocation_cap),
+        ProcessMgmtCap,
+    ));
+
+    let other_app = HostApp {
+        name: "other",
+        main: other,
+        minimum_ram_size: 8192,
+    };
+    let supervisor_app = HostApp {
+        name: "supervisor",
+        main: supervisor,
+        minimum_ram_size: 8192,
+    };
+    sim.load(&[(&other_app, &[]), (&supervisor_app, &[])], 32768);
+
+    sim.run_until(
+        &SimBoard([(DRIVER_NUM, process_manager)]),
+        sim.round_robin(),
+        || sim.exited(&[1]),
+    );
+    assert_eq!(sim.process(1).get_state(), State::Terminated);
+    assert_eq!(sim.process(0).get_state(), State::StoppedYielded);
+}
@@ -0,0 +1,250 @@
+//! Join a RPL DODAG whose root is played by the test, over IP senders that
+//! hand packets straight to the IP receiver of the other stack.
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, ICMP6RecvClient, MuxICMP6Receiver};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::routing::{RouteLookup, RoutingTable};
+use capsules::net::ipv6::{IP6Header, TransportHeader};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::rpl::rpl_node::{RplNode, RplState, ALL_RPL_NODES, INFINITE_RANK};
+use capsules::net::rpl::{rpl_code, rpl_mop, Dao, DaoAck, Dio, RplOption, RplOptions};
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::SimPeripheral;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Loopback};
+
+const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0];
+const MIN_HOP_RANK_INCREASE: u16 = 256;
+
+fn net_cap() -> &'static NetworkCapability {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ))
+}
+
+fn global(link_addr: MacAddress) -> IPAddr {
+    let mut addr = IPAddr::generate_from_mac(link_addr);
+    addr.set_prefix(&PREFIX, 64);
+    addr
+}
+
+/// Plays the root of a non-storing mode DODAG: answers DISs with DIOs, and
+/// acknowledges DAOs.
+struct Root {
+    link: &'static Loopback,
+    dodag_id: IPAddr,
+    version: Cell<u8>,
+    rank: Cell<u16>,
+    /// Sources and Target and Transit Information options of the DAOs
+    /// received.
+    daos: RefCell<Vec<(IPAddr, Vec<IPAddr>, Option<IPAddr>)>>,
+}
+
+impl Root {
+    fn send(&self, dst: IPAddr, code: u8, len: usize, buf: &'static mut [u8]) {
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        header.set_code(code);
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(0..len);
+        self.link
+            .send_to(dst, TransportHeader::ICMP(header), &payload, net_cap())
+            .unwrap();
+    }
+
+    fn send_dio(&self) {
+        let buf = leak([0; 128]);
+        let dio = Dio {
+            instance_id: 1,
+            version: self.version.get(),
+            rank: self.rank.get(),
+            grounded: true,
+            mop: rpl_mop::NON_STORING,
+            preference: 0,
+            dtsn: 0,
+            dodag_id: self.dodag_id,
+        };
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&PREFIX);
+        let options = [
+            RplOption::DodagConfiguration {
+                flags: 0,
+                dio_interval_doublings: 20,
+                dio_interval_min: 3,
+                dio_redundancy: 10,
+                max_rank_increase: 0,
+                min_hop_rank_increase: MIN_HOP_RANK_INCREASE,
+                objective_code_point: 0,
+                default_lifetime: 30,
+                lifetime_unit: 60,
+            },
+            RplOption::PrefixInformation {
+                prefix_len: 64,
+                flags: 0x40,
+                valid_lifetime: 3600,
+                preferred_lifetime: 3600,
+                prefix,
+            },
+        ];
+        let mut len = dio.encode(buf).done().unwrap().0;
+        for option in options.iter() {
+            len += option.encode(&mut buf[len..]).done().unwrap().0;
+        }
+        self.send(ALL_RPL_NODES, rpl_code::DIO, len, buf);
+    }
+}
+
+impl ICMP6RecvClient for Root {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        if icmp_header.get_type() != ICMP6Type::Type155 {
+            return;
+        }
+        match icmp_header.get_code() {
+            rpl_code::DIS => self.send_dio(),
+            rpl_code::DAO => {
+                let (off, dao) = Dao::decode(payload).done().unwrap();
+                let mut targets = Vec::new();
+                let mut parent = None;
+                for option in RplOptions::new(&payload[off..]) {
+                    match option {
+                        RplOption::Target { prefix, .. } => targets.push(prefix),
+                        RplOption::TransitInformation { parent: p, .. } => parent = p,
+                        _ => {}
+                    }
+                }
+                let src = ip_header.get_src_addr();
+                self.daos.borrow_mut().push((src, targets, parent));
+                assert!(dao.ack_requested);
+                let ack = DaoAck {
+                    instance_id: dao.instance_id,
+                    sequence: dao.sequence,
+                    status: 0,
+                    dodag_id: None,
+                };
+                let buf = leak([0; 128]);
+                let len = ack.encode(buf).done().unwrap().0;
+                self.send(src, rpl_code::DAO_ACK, len, buf);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Stack {
+    link: &'static Loopback,
+    receiver: &'static IP6RecvStruct<'static>,
+    mux: &'static MuxICMP6Receiver<'static>,
+}
+
+fn stack(link_addr: MacAddress) -> Stack {
+    let link = leak(Loopback::new(IPAddr::generate_from_mac(link_addr)));
+    let mux = leak(MuxICMP6Receiver::new());
+    let receiver = leak(IP6RecvStruct::new());
+    receiver.set_client(mux);
+    Stack {
+        link,
+        receiver,
+        mux,
+    }
+}
+
+#[test]
+fn rpl_join_dodag() {
+    let node_mac = MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]);
+    let root_mac = MacAddress::Short(0x100);
+    let node_stack = stack(node_mac);
+    let root_stack = stack(root_mac);
+
+    let routes = leak(RoutingTable::new(leak([Cell::new(None), Cell::new(None)])));
+    let alarm = leak(SimAlarm::new());
+    let node: &RplNode<SimAlarm> = leak(RplNode::new(
+        node_stack.link,
+        alarm,
+        routes,
+        IPAddr::generate_from_mac(node_mac),
+        leak([0; 128]),
+        net_cap(),
+    ));
+    alarm.set_alarm_client(node);
+    node_stack.link.set_client(node);
+    let node_receiver = leak(ICMP6Receiver::new());
+    node_receiver.set_client(node);
+    node_stack.mux.add_client(node_receiver);
+
+    let root = leak(Root {
+        link: root_stack.link,
+        dodag_id: global(root_mac),
+        version: Cell::new(0),
+        rank: Cell::new(MIN_HOP_RANK_INCREASE),
+        daos: RefCell::new(Vec::new()),
+    });
+    let root_receiver = leak(ICMP6Receiver::new());
+    root_receiver.set_client(root);
+    root_stack.mux.add_client(root_receiver);
+
+    let run = |done: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            let delivered = node_stack.link.deliver(root_stack.receiver)
+                | root_stack.link.deliver(node_stack.receiver);
+            alarm.service();
+            if !delivered {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    };
+
+    assert_eq!(node.state(), RplState::Idle);
+    assert_eq!(node.start(), Ok(()));
+    assert_eq!(node.start(), Err(ErrorCode::ALREADY));
+    run(&|| node.state() == RplState::Reachable);
+
+    // The root is the parent and default route, one step of rank below.
+    let root_link_local = IPAddr::generate_from_mac(root_mac);
+    assert_eq!(node.parent(), Some(root_link_local));
+    assert_eq!(node.dodag_id(), Some(root.dodag_id));
+    assert_eq!(node.rank(), MIN_HOP_RANK_INCREASE * 4);
+    assert_eq!(routes.default_route(), Some(root_mac));
+    assert_eq!(routes.next_hop(root.dodag_id), Some(root_mac));
+
+    // The DAO came from the global address of the node, announcing that
+    // address through the root.
+    assert_eq!(node.global_addr(), Some(global(node_mac)));
+    assert_eq!(
+        *root.daos.borrow(),
+        vec![(
+            global(node_mac),
+            vec![global(node_mac)],
+            Some(root.dodag_id)
+        )]
+    );
+
+    // A new version of the DODAG is advertised to the root with a new DAO.
+    root.version.set(1);
+    root.send_dio();
+    run(&|| root.daos.borrow().len() == 2);
+    run(&|| node.state() == RplState::Reachable);
+
+    // The node leaves the DODAG once its parent loses its route.
+    root.rank.set(INFINITE_RANK);
+    root.send_dio();
+    run(&|| node.state() == RplState::Detached);
+    assert_eq!(node.parent(), None);
+    assert_eq!(node.rank(), INFINITE_RANK);
+    assert_eq!(routes.default_route(), None);
+}
@@ -0,0 +1,58 @@
+//! Load apps from TBF objects that name the app to run, like the host board
+//! loads TBF files.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use host::app::{AppContext, HostApp};
+use kernel::procs::{State, StopFaultPolicy};
+use kernel::{capabilities, create_capability, Chip};
+
+mod common;
+use common::{Sim, SimBoard};
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn counter(_ctx: &AppContext) {
+    RUNS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn tbf_objects_run_the_app_they_name() {
+    let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+    let app = HostApp {
+        name: "counter",
+        main: counter,
+        minimum_ram_size: 8192,
+    };
+    let unknown = HostApp {
+        name: "unknown",
+        main: counter,
+        minimum_ram_size: 8192,
+    };
+    let tbf = app.tbf(&[]);
+    let unknown_tbf = unknown.tbf(&[]);
+
+    let sim: Sim<3> = Sim::new(&[]);
+    let boundary = sim.chip.userspace_kernel_boundary();
+    unsafe {
+        kernel::procs::load_processes(
+            sim.kernel,
+            sim.chip,
+            boundary.load_tbfs(&[&tbf, &unknown_tbf, &tbf], &[app]),
+            boundary.app_memory(49152),
+            sim.slots(),
+            &StopFaultPolicy {},
+            &process_management_cap,
+        )
+        .unwrap();
+    }
+
+    sim.run_until(&SimBoard([]), sim.round_robin(), || sim.exited(&[0, 1, 2]));
+
+    // Both copies of the app ran, and the process with no app to run faulted.
+    assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+    assert_eq!(sim.process(0).get_state(), State::Terminated);
+    assert_eq!(sim.process(1).get_state(), State::Faulted);
+    assert_eq!(sim.process(2).get_state(), State::Terminated);
+}
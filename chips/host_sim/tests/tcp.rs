@@ -0,0 +1,346 @@
+//! Open, use, and close TCP connections between two TCP stacks, linked by IP
+//! senders that hand packets straight to the IP receiver of the other stack.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use capsules::net::tcp::tcp_mux::MuxTcp;
+use capsules::net::tcp::tcp_socket::{TcpClient, TcpSocket, TcpState};
+use capsules::net::tcp::TCPHeader;
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::SimPeripheral;
+use host_sim::rng::SimRng;
+use kernel::common::cells::OptionalCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+type Socket = TcpSocket<'static, SimAlarm<'static>>;
+
+fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+/// IP sender that encodes packets and queues them for the receiver of the
+/// other stack.
+struct Loopback {
+    addr: IPAddr,
+    packet: RefCell<IP6Packet<'static>>,
+    queue: RefCell<VecDeque<Vec<u8>>>,
+    /// Number of packets to drop, as if they were lost on the way.
+    drop: Cell<usize>,
+    client: OptionalCell<&'static dyn IP6SendClient>,
+}
+
+impl Loopback {
+    fn new(addr: IPAddr) -> Loopback {
+        let payload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: leak([0; 1220]),
+        };
+        Loopback {
+            addr,
+            packet: RefCell::new(IP6Packet::new(payload)),
+            queue: RefCell::new(VecDeque::new()),
+            drop: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Hands the oldest queued packet to `receiver`, and tells the client it
+    /// was sent. Returns whether there was one.
+    fn deliver(&self, receiver: &IP6RecvStruct) -> bool {
+        let packet = self.queue.borrow_mut().pop_front();
+        match packet {
+            Some(packet) => {
+                if self.drop.get() > 0 {
+                    self.drop.set(self.drop.get() - 1);
+                } else {
+                    receiver.receive(&packet, packet.len(), Ok(()));
+                }
+                self.client.map(|client| client.send_done(Ok(())));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl IP6Sender<'static> for Loopback {
+    fn set_client(&self, client: &'static dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        let mut packet = self.packet.borrow_mut();
+        packet.header = IP6Header::default();
+        packet.header.src_addr = self.addr;
+        packet.header.dst_addr = dst;
+        packet.set_payload(transport_header, payload);
+        packet.set_transport_checksum();
+        let mut buf = vec![0; packet.get_total_len() as usize];
+        packet.encode(&mut buf).done().ok_or(ErrorCode::SIZE)?;
+        self.queue.borrow_mut().push_back(buf);
+        Ok(())
+    }
+}
+
+/// Records what a socket tells its client.
+#[derive(Default)]
+struct Events {
+    connected: Cell<bool>,
+    peer_closed: Cell<bool>,
+    closed: Cell<Option<Result<(), ErrorCode>>>,
+}
+
+impl TcpClient for Events {
+    fn connected(&self, _socket: usize) {
+        self.connected.set(true);
+    }
+
+    fn received(&self, _socket: usize, _available: usize) {}
+
+    fn sent(&self, _socket: usize, _space: usize) {}
+
+    fn peer_closed(&self, _socket: usize) {
+        self.peer_closed.set(true);
+    }
+
+    fn closed(&self, _socket: usize, result: Result<(), ErrorCode>) {
+        self.closed.set(Some(result));
+    }
+}
+
+struct Stack {
+    link: &'static Loopback,
+    receiver: &'static IP6RecvStruct<'static>,
+    alarm: &'static SimAlarm<'static>,
+    rng: &'static SimRng<'static>,
+    socket: &'static Socket,
+    events: &'static Events,
+}
+
+fn stack(addr: IPAddr, net_cap: &'static NetworkCapability) -> Stack {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let ip_vis = leak(IpVisibilityCapability::new(&create_cap));
+    let link = leak(Loopback::new(addr));
+    let alarm = leak(SimAlarm::new());
+    let rng = leak(SimRng::new());
+    let mux = leak(MuxTcp::new(
+        link,
+        alarm,
+        rng,
+        leak([0; 100]),
+        net_cap,
+        ip_vis,
+    ));
+    alarm.set_alarm_client(mux);
+    rng.set_client(mux);
+    assert_eq!(mux.init_secret(), Ok(()));
+    link.set_client(mux);
+    let receiver = leak(IP6RecvStruct::new());
+    receiver.set_client(mux);
+    let socket = leak(TcpSocket::new(mux, 0, leak([0; 256]), leak([0; 256])));
+    mux.add_socket(socket);
+    let events = leak(Events::default());
+    socket.set_client(events);
+    Stack {
+        link,
+        receiver,
+        alarm,
+        rng,
+        socket,
+        events,
+    }
+}
+
+fn stacks() -> (Stack, Stack, &'static NetworkCapability) {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let net_cap: &'static NetworkCapability = leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ));
+    let client = stack(IPAddr([1; 16]), net_cap);
+    let server = stack(IPAddr([2; 16]), net_cap);
+    client.rng.service();
+    server.rng.service();
+    (client, server, net_cap)
+}
+
+/// Passes packets between the stacks and runs their timers until `done`
+/// holds. Panics if it does not within five seconds.
+fn run(a: &Stack, b: &Stack, done: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        let delivered = a.link.deliver(b.receiver) | b.link.deliver(a.receiver);
+        a.alarm.service();
+        b.alarm.service();
+        if !delivered {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// Reads everything `socket` received into `data`.
+fn read_into(socket: &Socket, data: &RefCell<Vec<u8>>) {
+    let mut buf = [0; 64];
+    loop {
+        let count = socket.recv(&mut buf);
+        if count == 0 {
+            break;
+        }
+        data.borrow_mut().extend_from_slice(&buf[..count]);
+    }
+}
+
+#[test]
+fn tcp_connect_transfer_close() {
+    let (client, server, net_cap) = stacks();
+    assert_eq!(server.socket.listen(80, net_cap), Ok(()));
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 80, 0, net_cap),
+        Ok(())
+    );
+    run(&client, &server, || {
+        client.events.connected.get() && server.events.connected.get()
+    });
+    assert_eq!(client.socket.state(), TcpState::Established);
+    assert_eq!(
+        server.socket.remote_endpoint(),
+        (IPAddr([1; 16]), client.socket.local_port())
+    );
+
+    // More data than fits the buffers and the window is sent in several
+    // segments, as the receiver reads it.
+    let message: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let received = RefCell::new(Vec::new());
+    let sent = Cell::new(0);
+    run(&client, &server, || {
+        if sent.get() < message.len() {
+            sent.set(sent.get() + client.socket.send(&message[sent.get()..]).unwrap());
+        }
+        read_into(server.socket, &received);
+        received.borrow().len() == message.len()
+    });
+    assert_eq!(*received.borrow(), message);
+
+    // The server answers, then both close their side.
+    assert_eq!(server.socket.send(b"pong"), Ok(4));
+    assert_eq!(client.socket.close(), Ok(()));
+    let reply = RefCell::new(Vec::new());
+    run(&client, &server, || {
+        read_into(client.socket, &reply);
+        server.events.peer_closed.get() && reply.borrow().len() == 4
+    });
+    assert_eq!(*reply.borrow(), b"pong");
+    assert_eq!(server.socket.send(b"more"), Ok(4));
+    assert_eq!(server.socket.close(), Ok(()));
+    run(&client, &server, || server.events.closed.get().is_some());
+    assert_eq!(server.events.closed.get(), Some(Ok(())));
+    assert_eq!(server.socket.state(), TcpState::Closed);
+    // The client waits for retransmissions of the peer before it is closed.
+    assert_eq!(client.socket.state(), TcpState::TimeWait);
+    read_into(client.socket, &reply);
+    assert_eq!(*reply.borrow(), b"pongmore");
+}
+
+#[test]
+fn tcp_retransmits_lost_segments() {
+    let (client, server, net_cap) = stacks();
+    assert_eq!(server.socket.listen(80, net_cap), Ok(()));
+    // The first SYN is lost.
+    client.link.drop.set(1);
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 80, 0, net_cap),
+        Ok(())
+    );
+    run(&client, &server, || server.events.connected.get());
+
+    // So is the first data segment.
+    client.link.drop.set(1);
+    assert_eq!(client.socket.send(b"hello"), Ok(5));
+    run(&client, &server, || server.socket.available() == 5);
+    let mut buf = [0; 5];
+    assert_eq!(server.socket.recv(&mut buf), 5);
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn tcp_waits_for_the_secret() {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let net_cap: &'static NetworkCapability = leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ));
+    let client = stack(IPAddr([1; 16]), net_cap);
+    let server = stack(IPAddr([2; 16]), net_cap);
+    server.rng.service();
+    // Initial sequence numbers need the secret from the RNG.
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 80, 0, net_cap),
+        Err(ErrorCode::BUSY)
+    );
+    assert_eq!(client.socket.state(), TcpState::Closed);
+
+    client.rng.service();
+    assert_eq!(server.socket.listen(80, net_cap), Ok(()));
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 80, 0, net_cap),
+        Ok(())
+    );
+    run(&client, &server, || server.events.connected.get());
+}
+
+#[test]
+fn tcp_refused_and_reset() {
+    let (client, server, net_cap) = stacks();
+    // No socket listens on the port, so the server stack resets the
+    // connection.
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 81, 0, net_cap),
+        Ok(())
+    );
+    run(&client, &server, || client.events.closed.get().is_some());
+    assert_eq!(client.events.closed.get(), Some(Err(ErrorCode::CANCEL)));
+    assert_eq!(client.socket.state(), TcpState::Closed);
+
+    // An aborted connection is reset at the peer.
+    client.events.closed.set(None);
+    assert_eq!(server.socket.listen(80, net_cap), Ok(()));
+    assert_eq!(
+        client.socket.connect(IPAddr([2; 16]), 80, 0, net_cap),
+        Ok(())
+    );
+    run(&client, &server, || server.events.connected.get());
+    server.socket.abort();
+    run(&client, &server, || client.events.closed.get().is_some());
+    assert_eq!(client.events.closed.get(), Some(Err(ErrorCode::CANCEL)));
+}
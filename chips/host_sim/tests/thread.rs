@@ -0,0 +1,380 @@
+//! Attach to a Thread network whose routers are played by the test, over
+//! UDP on IP senders that hand packets straight to the IP receiver of the
+//! other stack.
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use capsules::ieee802154::device::{MacDevice, RxClient, TxClient};
+use capsules::ieee802154::framer::{DeviceProcedure, Frame, KeyProcedure};
+use capsules::net::ieee802154::{KeyId, MacAddress, PanID, SecurityLevel};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::routing::{RouteLookup, RoutingTable};
+use capsules::net::ipv6::{IP6Header, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::thread::mle::{self, Command, MLE_PORT, SECURITY_LEVEL};
+use capsules::net::thread::mle_node::{MleNode, MleState};
+use capsules::net::thread::tlv::{MulticastResponder, Tlv, TlvType, Tlvs};
+use capsules::net::udp::udp_port_table::{PortQuery, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::net::udp::UDPHeader;
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::SimPeripheral;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::Random;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability, ErrorCode};
+
+mod common;
+use common::{leak, Loopback};
+
+const KEY: [u8; 16] = [7; 16];
+const KEY_SEQUENCE: u32 = 2;
+const PARENT_RLOC16: u16 = 0x0400;
+const CHILD_RLOC16: u16 = 0x0401;
+
+fn net_cap() -> &'static NetworkCapability {
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &create_cap,
+    ))
+}
+
+/// MAC that records the short address the node takes.
+#[derive(Default)]
+struct FakeMac {
+    address: Cell<u16>,
+    committed: Cell<bool>,
+}
+
+impl MacDevice<'static> for FakeMac {
+    fn set_transmit_client(&self, _client: &'static dyn TxClient) {}
+    fn set_receive_client(&self, _client: &'static dyn RxClient) {}
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+    fn get_address_long(&self) -> [u8; 8] {
+        [0; 8]
+    }
+    fn get_pan(&self) -> u16 {
+        0xface
+    }
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+        self.committed.set(false);
+    }
+    fn set_address_long(&self, _addr: [u8; 8]) {}
+    fn set_pan(&self, _id: u16) {}
+    fn config_commit(&self) {
+        self.committed.set(true);
+    }
+    fn is_on(&self) -> bool {
+        true
+    }
+    fn prepare_data_frame(
+        &self,
+        buf: &'static mut [u8],
+        _dst_pan: PanID,
+        _dst_addr: MacAddress,
+        _src_pan: PanID,
+        _src_addr: MacAddress,
+        _security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        Err(buf)
+    }
+    fn transmit(&self, _frame: Frame) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        unreachable!()
+    }
+}
+
+/// Random numbers that are just different from each other.
+#[derive(Default)]
+struct Counter(Cell<u32>);
+
+impl<'a> Random<'a> for Counter {
+    fn initialize(&'a self) {}
+    fn reseed(&self, seed: u32) {
+        self.0.set(seed);
+    }
+    fn random(&self) -> u32 {
+        self.0
+            .set(self.0.get().wrapping_mul(1103515245).wrapping_add(12345));
+        self.0.get()
+    }
+}
+
+/// No port is bound by userspace.
+struct NoUserPorts;
+
+impl PortQuery for NoUserPorts {
+    fn is_bound(&self, _port: u16) -> bool {
+        false
+    }
+}
+
+struct DriverCap;
+unsafe impl capabilities::UdpDriverCapability for DriverCap {}
+
+/// Plays the routers around the node: answers Parent Requests that reach
+/// router-eligible end devices from a few neighbors, and accepts the node
+/// as a child of the one it picks.
+struct Routers {
+    link: &'static Loopback,
+    /// Commands received, with the source and destination they came with.
+    received: RefCell<Vec<(Command, IPAddr, IPAddr)>>,
+    /// Challenges the neighbors sent in their Parent Responses.
+    challenges: RefCell<Vec<(IPAddr, [u8; 8])>>,
+}
+
+impl Routers {
+    fn send(&self, src: MacAddress, dst: IPAddr, command: Command, tlvs: &[Tlv]) {
+        let buf = leak([0; 256]);
+        let mut len = mle::encode_header(buf, command).done().unwrap().0;
+        for tlv in tlvs {
+            len += tlv.encode(&mut buf[len..]).done().unwrap().0;
+        }
+        let mut header = UDPHeader::new();
+        header.set_src_port(MLE_PORT);
+        header.set_dst_port(MLE_PORT);
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(0..len);
+        self.link.set_addr(IPAddr::generate_from_mac(src));
+        self.link
+            .send_to(dst, TransportHeader::UDP(header), &payload, net_cap())
+            .unwrap();
+    }
+
+    fn leader_data() -> Tlv<'static> {
+        Tlv::LeaderData {
+            partition_id: 0x12345678,
+            weighting: 64,
+            data_version: 1,
+            stable_data_version: 1,
+            leader_router_id: 1,
+        }
+    }
+
+    fn parent_response(&self, src: MacAddress, dst: IPAddr, response: [u8; 8], margin: u8) {
+        let challenge = [margin; 8];
+        self.challenges
+            .borrow_mut()
+            .push((IPAddr::generate_from_mac(src), challenge));
+        self.send(
+            src,
+            dst,
+            Command::ParentResponse,
+            &[
+                Tlv::SourceAddress(PARENT_RLOC16),
+                Routers::leader_data(),
+                Tlv::LinkLayerFrameCounter(0),
+                Tlv::Response(response),
+                Tlv::Challenge(challenge),
+                Tlv::LinkMargin(margin),
+                Tlv::Connectivity {
+                    parent_priority: 0,
+                    link_quality_3: 2,
+                    link_quality_2: 0,
+                    link_quality_1: 0,
+                    leader_cost: 1,
+                    id_sequence: 1,
+                    active_routers: 2,
+                    sed_buffer_size: None,
+                    sed_datagram_count: None,
+                },
+                Tlv::Version(2),
+            ],
+        );
+    }
+}
+
+impl IP6RecvClient for Routers {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        let (offset, udp_header) = UDPHeader::decode(payload).done().unwrap();
+        assert_eq!(udp_header.get_src_port(), MLE_PORT);
+        assert_eq!(udp_header.get_dst_port(), MLE_PORT);
+        let payload = &payload[offset..];
+        let (offset, command) = mle::decode_header(payload).done().unwrap();
+        let src = header.get_src_addr();
+        let dst = header.get_dst_addr();
+        self.received.borrow_mut().push((command, src, dst));
+        let tlvs: Vec<Tlv> = Tlvs::new(&payload[offset..]).collect();
+        assert!(tlvs.iter().any(|tlv| matches!(tlv, Tlv::Mode(0x0c))));
+        match command {
+            Command::ParentRequest => {
+                let challenge = tlvs
+                    .iter()
+                    .find_map(|tlv| match tlv {
+                        Tlv::Challenge(challenge) => Some(*challenge),
+                        _ => None,
+                    })
+                    .unwrap();
+                let both = MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8;
+                if !tlvs
+                    .iter()
+                    .any(|tlv| matches!(tlv, Tlv::ScanMask(mask) if *mask == both))
+                {
+                    // Only router-eligible end devices are around.
+                    let router = MulticastResponder::Router as u8;
+                    assert!(tlvs
+                        .iter()
+                        .any(|tlv| matches!(tlv, Tlv::ScanMask(mask) if *mask == router)));
+                    return;
+                }
+                // The neighbor with the best link is picked, unless it does
+                // not answer the challenge.
+                self.parent_response(MacAddress::Long([1; 8]), src, challenge, 15);
+                self.parent_response(MacAddress::Long([2; 8]), src, challenge, 30);
+                self.parent_response(MacAddress::Long([3; 8]), src, [0; 8], 40);
+            }
+            Command::ChildIdRequest => {
+                let (_, challenge) = self.challenges.borrow()[1];
+                assert!(tlvs
+                    .iter()
+                    .any(|tlv| matches!(tlv, Tlv::Response(response) if *response == challenge)));
+                assert!(tlvs.iter().any(|tlv| matches!(tlv, Tlv::Timeout(240))));
+                let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+                assert!(tlvs
+                    .iter()
+                    .any(|tlv| matches!(tlv, Tlv::TlvRequest(types) if *types == requested)));
+                self.send(
+                    dst.mac_from_iid(),
+                    src,
+                    Command::ChildIdResponse,
+                    &[
+                        Tlv::SourceAddress(PARENT_RLOC16),
+                        Routers::leader_data(),
+                        Tlv::Address16(CHILD_RLOC16),
+                        Tlv::NetworkData(&[]),
+                    ],
+                );
+            }
+            _ => panic!("unexpected command {:?}", command),
+        }
+    }
+}
+
+#[test]
+fn thread_attach_as_child() {
+    let node_mac = MacAddress::Long([9, 8, 7, 6, 5, 4, 3, 2]);
+    let node_addr = IPAddr::generate_from_mac(node_mac);
+    let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+    let udp_vis = leak(UdpVisibilityCapability::new(&create_cap));
+
+    // The UDP stack of the node, over a link to the routers.
+    let node_link = leak(Loopback::new(node_addr));
+    let node_receiver = leak(IP6RecvStruct::new());
+    let udp_send_mux: &MuxUdpSender<Loopback> = leak(MuxUdpSender::new(node_link));
+    node_link.set_client(udp_send_mux);
+    let udp_recv_mux = leak(MuxUdpReceiver::new());
+    node_receiver.set_client(udp_recv_mux);
+    let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+    let port_table = leak(UdpPortManager::new(
+        &create_table_cap,
+        leak([None; MAX_NUM_BOUND_PORTS]),
+        udp_vis,
+    ));
+    port_table.set_user_ports(&NoUserPorts, &DriverCap);
+    let udp_send = leak(UDPSendStruct::new(udp_send_mux, udp_vis));
+    let udp_recv = leak(UDPReceiver::new());
+    udp_recv_mux.add_client(udp_recv);
+    let socket = port_table.create_socket().unwrap();
+    let (send_binding, recv_binding) = port_table.bind(socket, MLE_PORT, net_cap()).unwrap();
+    udp_send.set_binding(send_binding);
+    udp_recv.set_binding(recv_binding);
+
+    let mac = leak(FakeMac::default());
+    let routes = leak(RoutingTable::new(leak([])));
+    let alarm = leak(SimAlarm::new());
+    let node: &MleNode<SimAlarm> = leak(MleNode::new(
+        udp_send,
+        alarm,
+        leak(Counter::default()),
+        mac,
+        routes,
+        KEY,
+        KEY_SEQUENCE,
+        leak([0; 128]),
+        net_cap(),
+    ));
+    alarm.set_alarm_client(node);
+    udp_send.set_client(node);
+    udp_recv.set_client(node);
+
+    let routers_link = leak(Loopback::new(IPAddr::new()));
+    let routers_receiver = leak(IP6RecvStruct::new());
+    let routers = leak(Routers {
+        link: routers_link,
+        received: RefCell::new(Vec::new()),
+        challenges: RefCell::new(Vec::new()),
+    });
+    routers_receiver.set_client(routers);
+
+    let run = |done: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            let delivered =
+                node_link.deliver(routers_receiver) | routers_link.deliver(node_receiver);
+            alarm.service();
+            if !delivered {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    };
+
+    assert_eq!(node.state(), MleState::Disabled);
+    assert_eq!(node.start(), Ok(()));
+    assert_eq!(node.start(), Err(ErrorCode::ALREADY));
+    run(&|| node.state() == MleState::Child);
+
+    // Routers were asked first, then router-eligible end devices too, and
+    // the neighbor with the best link answering the challenge was picked.
+    let all_routers = IPAddr([0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    let parent_mac = MacAddress::Long([2; 8]);
+    let parent_addr = IPAddr::generate_from_mac(parent_mac);
+    assert_eq!(
+        *routers.received.borrow(),
+        vec![
+            (Command::ParentRequest, node_addr, all_routers),
+            (Command::ParentRequest, node_addr, all_routers),
+            (Command::ChildIdRequest, node_addr, parent_addr),
+        ]
+    );
+
+    // The node took the RLOC16 its parent assigned, and routes through it.
+    assert_eq!(node.rloc16(), Some(CHILD_RLOC16));
+    assert_eq!(node.parent(), Some(parent_addr));
+    assert_eq!(mac.address.get(), CHILD_RLOC16);
+    assert!(mac.committed.get());
+    assert_eq!(routes.default_route(), Some(parent_mac));
+    let mut global = IPAddr::generate_from_mac(MacAddress::Short(7));
+    global.set_prefix(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0], 64);
+    assert_eq!(routes.next_hop(global), Some(parent_mac));
+
+    // Frames are secured with the key of the key sequence, and the parent
+    // is known by its RLOC16 too.
+    assert_eq!(node.key_id(), KeyId::Index(3));
+    assert_eq!(node.lookup_key(SECURITY_LEVEL, KeyId::Index(3)), Some(KEY));
+    assert_eq!(node.lookup_key(SECURITY_LEVEL, KeyId::Index(2)), None);
+    assert_eq!(
+        node.lookup_key(SecurityLevel::EncMic64, KeyId::Index(3)),
+        None
+    );
+    assert_eq!(
+        node.lookup_addr_long(MacAddress::Short(PARENT_RLOC16)),
+        Some([2; 8])
+    );
+    assert_eq!(node.lookup_addr_long(MacAddress::Short(0x0800)), None);
+    assert_eq!(
+        node.lookup_addr_long(node_mac),
+        Some([9, 8, 7, 6, 5, 4, 3, 2])
+    );
+}
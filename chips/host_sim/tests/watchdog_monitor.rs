@@ -0,0 +1,199 @@
+//! Find processes that keep the others from running and capsules that stop
+//! making progress with the watchdog monitor, on the simulated chip.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Once};
+use std::time::Duration;
+
+use capsules::rng::{self, RngDriver};
+use capsules::watchdog_monitor::WatchdogMonitor;
+use host::app::{AppContext, HostApp};
+use host_sim::alarm::SimAlarm;
+use host_sim::chip::{HostChip, SimPeripheral};
+use host_sim::uart::SimUart;
+use kernel::common::RingBuffer;
+use kernel::debug::{self, DebugWriter, DebugWriterWrapper};
+use kernel::hil::rng::{self as hil_rng, Rng};
+use kernel::hil::time::Alarm;
+use kernel::hil::uart::Transmit;
+use kernel::procs::{Process, State};
+use kernel::watchdog::Watched;
+use kernel::{capabilities, create_capability, ErrorCode, Scheduler};
+use kernel::{CoopProcessNode, CooperativeSched, RoundRobinProcessNode, RoundRobinSched};
+
+mod common;
+use common::{leak, Sim, SimBoard};
+
+/// Give `debug!()`, which the monitor reports hung processes with, a UART
+/// that drops what it writes.
+fn set_debug_writer() {
+    static DEBUG_WRITER: Once = Once::new();
+    DEBUG_WRITER.call_once(|| {
+        let (_, input) = mpsc::channel();
+        let uart: &'static SimUart = leak(SimUart::new(Box::new(std::io::sink()), input));
+        let writer: &'static DebugWriter = leak(DebugWriter::new(
+            uart,
+            leak([0; 64]),
+            leak(RingBuffer::new(leak([0; 1024]))),
+        ));
+        uart.set_transmit_client(writer);
+        unsafe { debug::set_debug_writer_wrapper(leak(DebugWriterWrapper::new(writer))) };
+    });
+}
+
+struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
+/// A compute-bound app that never waits.
+fn spinner(ctx: &AppContext) {
+    loop {
+        ctx.yield_no_wait();
+    }
+}
+
+/// An app that waits for upcalls that never come.
+fn sleeper(ctx: &AppContext) {
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+/// What the RNG upcall of `waiter` reported, or `usize::MAX` until it came.
+static RNG_STATUS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn rng_done(_ctx: &AppContext, status: usize, _received: usize, _: usize, _appdata: usize) {
+    RNG_STATUS.store(status, Ordering::SeqCst);
+}
+
+/// An app that asks for randomness and waits for it.
+fn waiter(ctx: &AppContext) {
+    let buffer = ctx.allocate(8);
+    ctx.allow_readwrite(rng::DRIVER_NUM, 0, buffer.as_mut_ptr(), buffer.len());
+    ctx.subscribe(rng::DRIVER_NUM, 0, Some(rng_done), 0);
+    ctx.command(rng::DRIVER_NUM, 1, buffer.len(), 0);
+    loop {
+        ctx.yield_wait();
+    }
+}
+
+/// An RNG whose hardware never answers.
+struct StuckRng;
+
+impl<'a> Rng<'a> for StuckRng {
+    fn get(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn set_client(&'a self, _client: &'a dyn hil_rng::Client) {}
+}
+
+/// Boot the kernel with `app`, an RNG driver on an RNG that never answers, and
+/// a monitor that watches the driver and checks every 10ms for processes that
+/// have not yielded for 50ms, and run it with the scheduler
+/// `create_scheduler` returns for the slot of the process for 300ms. Returns
+/// the state of the process.
+fn run<S: Scheduler<HostChip> + 'static>(
+    app: &HostApp,
+    create_scheduler: impl FnOnce(&'static Option<&'static dyn Process>) -> &'static S,
+) -> State {
+    set_debug_writer();
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let alarm: &'static SimAlarm = leak(SimAlarm::new());
+    let sim: Sim<1> = Sim::new(leak([alarm as &dyn SimPeripheral]));
+
+    let rng: &'static RngDriver = leak(RngDriver::new(
+        leak(StuckRng),
+        sim.kernel.create_grant(&memory_allocation_cap),
+    ));
+    let watched: &'static [&'static dyn Watched] = leak([rng as &dyn Watched]);
+
+    let monitor = leak(WatchdogMonitor::new(
+        alarm,
+        sim.kernel,
+        watched,
+        10,
+        50,
+        ProcessMgmtCap,
+    ));
+    alarm.set_alarm_client(monitor);
+    sim.kernel.set_process_monitor(monitor, &main_loop_cap);
+    monitor.start();
+
+    let scheduler = create_scheduler(&sim.processes[0]);
+    sim.load(&[(app, &[])], 16384);
+
+    let process = sim.process(0);
+    sim.run_for(
+        &SimBoard([(rng::DRIVER_NUM, rng)]),
+        scheduler,
+        Duration::from_millis(300),
+        || process.get_state() == State::Faulted,
+    );
+    process.get_state()
+}
+
+fn cooperative(slot: &'static Option<&'static dyn Process>) -> &'static CooperativeSched<'static> {
+    let scheduler = leak(CooperativeSched::new());
+    scheduler
+        .processes
+        .push_head(leak(CoopProcessNode::new(slot)));
+    scheduler
+}
+
+fn round_robin(slot: &'static Option<&'static dyn Process>) -> &'static RoundRobinSched<'static> {
+    let scheduler = leak(RoundRobinSched::new());
+    scheduler
+        .processes
+        .push_head(leak(RoundRobinProcessNode::new(slot)));
+    scheduler
+}
+
+#[test]
+fn process_that_does_not_yield_is_faulted() {
+    let app = HostApp {
+        name: "spinner",
+        main: spinner,
+        minimum_ram_size: 8192,
+    };
+    assert_eq!(run(&app, cooperative), State::Faulted);
+}
+
+#[test]
+fn process_that_yields_is_not_faulted() {
+    let app = HostApp {
+        name: "sleeper",
+        main: sleeper,
+        minimum_ram_size: 8192,
+    };
+    assert_eq!(run(&app, cooperative), State::Yielded);
+}
+
+#[test]
+fn process_with_a_timeslice_is_not_faulted() {
+    let app = HostApp {
+        name: "spinner",
+        main: spinner,
+        minimum_ram_size: 8192,
+    };
+    assert_eq!(run(&app, round_robin), State::Running);
+}
+
+#[test]
+fn capsule_that_makes_no_progress_is_reset() {
+    let app = HostApp {
+        name: "waiter",
+        main: waiter,
+        minimum_ram_size: 8192,
+    };
+    assert_eq!(run(&app, cooperative), State::Yielded);
+    assert_eq!(
+        RNG_STATUS.load(Ordering::SeqCst),
+        kernel::into_statuscode(Err(ErrorCode::FAIL))
+    );
+}
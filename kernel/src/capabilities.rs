@@ -51,6 +51,10 @@
 /// otherwise managing processes.
 pub unsafe trait ProcessManagementCapability {}
 
+/// The `ProcessCheckpointCapability` allows the holder to read the complete
+/// state of a process, including its memory, and to replace it.
+pub unsafe trait ProcessCheckpointCapability {}
+
 /// The `MainLoopCapability` capability allows the holder to start executing as
 /// well as manage the main scheduler loop in Tock. This is needed in a board's
 /// main.rs file to start the kernel. It also allows an external implementation
@@ -24,6 +24,35 @@ impl<'a, T: Copy> RingBuffer<'a, T> {
         self.ring.len().saturating_sub(1 + queue::Queue::len(self))
     }
 
+    /// Enqueues `val` before the first element for which `f` returns true, or
+    /// at the tail if there is none. Returns false if the ring buffer is full.
+    pub fn insert_before<F>(&mut self, val: T, mut f: F) -> bool
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if queue::Queue::is_full(self) {
+            return false;
+        }
+
+        let len = self.ring.len();
+        let mut position = self.head;
+        while position != self.tail && !f(&self.ring[position]) {
+            position = (position + 1) % len;
+        }
+
+        // Move the elements from `position` to the tail back by one.
+        let mut index = self.tail;
+        while index != position {
+            let previous = (index + len - 1) % len;
+            self.ring[index] = self.ring[previous];
+            index = previous;
+        }
+
+        self.ring[position] = val;
+        self.tail = (self.tail + 1) % len;
+        true
+    }
+
     /// Returns up to 2 slices that together form the contents of the ring buffer.
     ///
     /// Returns:
@@ -280,4 +309,26 @@ mod test {
         assert_eq!(buf.dequeue(), Some(9));
         assert_eq!(buf.dequeue(), None);
     }
+
+    #[test]
+    fn test_insert_before() {
+        const LEN: usize = 6;
+        let mut ring = [0; LEN];
+        let mut buf = RingBuffer::new(&mut ring);
+
+        move_head(&mut buf, LEN - 2);
+        assert!(buf.insert_before(20, |x| *x > 20));
+        assert!(buf.insert_before(40, |x| *x > 40));
+        assert!(buf.insert_before(10, |x| *x > 10));
+        assert!(buf.insert_before(30, |x| *x > 30));
+        assert!(buf.insert_before(21, |x| *x > 21));
+        assert!(!buf.insert_before(0, |x| *x > 0));
+
+        assert_eq!(buf.dequeue(), Some(10));
+        assert_eq!(buf.dequeue(), Some(20));
+        assert_eq!(buf.dequeue(), Some(21));
+        assert_eq!(buf.dequeue(), Some(30));
+        assert_eq!(buf.dequeue(), Some(40));
+        assert_eq!(buf.dequeue(), None);
+    }
 }
@@ -40,6 +40,15 @@ pub(crate) struct Config {
     /// into which SRAM addresses. This can be useful to debug whether the kernel could
     /// successfully load processes, and whether the allocated SRAM is as expected.
     pub(crate) debug_load_processes: bool,
+
+    /// Whether the kernel should paint process memory when a process starts.
+    ///
+    /// If enabled, the kernel fills the memory of a process with a known pattern before the process
+    /// starts, and finds how deep the stack of the process has grown by looking for the deepest
+    /// stack word that was overwritten. This is useful on architectures where the kernel cannot
+    /// observe the stack pointer of a process whenever it switches to the kernel, at the cost of
+    /// painting the memory on every process start and scanning it on every query.
+    pub(crate) paint_process_stack: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are defined. These
@@ -47,4 +56,5 @@ pub(crate) struct Config {
 pub(crate) const CONFIG: Config = Config {
     trace_syscalls: false,
     debug_load_processes: false,
+    paint_process_stack: false,
 };
@@ -0,0 +1,444 @@
+//! Loading and removing processes while the kernel is running.
+//!
+//! Normally processes are only discovered once at boot by `load_processes()`.
+//! A board that wants to install and remove apps at runtime instead creates a
+//! `DynamicProcessLoader` with the process array, the app flash region and the
+//! app memory region, and uses it to load the apps already in flash at boot.
+//! The loader keeps the process memory that was not used at boot so that it
+//! can create more processes later.
+//!
+//! The loader does not write flash itself. A capsule (for example
+//! `capsules::app_loader`) asks the loader where a new app can go, writes the
+//! TBF object there, and then asks the loader to create a process for it. To
+//! remove an app the capsule asks the loader to stop the process and free its
+//! slot, and then marks the app's flash as padding so it can be reused.
+//!
+//! Apps in flash form a linked list, so the loader only places new apps in
+//! padding entries or after the end of the list, aligned to their size rounded
+//! up to a power of two (which is what MPUs generally require). Space used to
+//! align an app must be covered by a padding entry to keep the list intact;
+//! `AppFlashLocation` tells the capsule where those padding entries go.
+//!
+//! If the board checks the credentials of its apps, the loader checks the
+//! credentials of new apps the same way, and new processes only run once the
+//! `ProcessCheckerMachine` approves them.
+//!
+//! The memory of removed processes is kept in a small table of free blocks,
+//! next to what is left of the app memory region, and new processes get their
+//! memory from the first block they fit in. Blocks that are next to each other
+//! are merged. If the table is full, the memory of a removed process is lost
+//! until the board restarts.
+
+use core::cmp;
+use core::convert::TryInto;
+use core::slice;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::common::cells::{MapCell, TakeCell};
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::platform::Chip;
+use crate::process::{Process, ProcessId};
+use crate::process_checker::ProcessCheckerMachine;
+use crate::process_policies::ProcessFaultPolicy;
+use crate::process_standard::ProcessStandard;
+use crate::process_utilities::{load_processes_advanced, ProcessLoadError};
+use crate::sched::Kernel;
+
+/// The size of the header of a padding entry in the app linked list.
+const PADDING_HEADER_LEN: usize = 16;
+
+/// The number of separate blocks of free app memory the loader keeps track of,
+/// including what is left of the app memory region.
+const MEMORY_BLOCKS: usize = 4;
+
+/// Where in app flash a new app of a given length can be installed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppFlashLocation {
+    /// The address the TBF object of the app must be written to.
+    pub address: usize,
+    /// A padding entry `(address, length)` that must be written before the app
+    /// to cover the space used to align it.
+    pub padding_before: Option<(usize, usize)>,
+    /// A padding entry `(address, length)` that must be written after the app
+    /// to cover the rest of the free space the app is installed in.
+    pub padding_after: Option<(usize, usize)>,
+}
+
+/// Interface for capsules that install and remove apps at runtime.
+pub trait DynamicProcessLoading {
+    /// Find a place in app flash for a TBF object of `length` bytes.
+    ///
+    /// Returns `Err(ErrorCode::NOMEM)` if there is no space for the app.
+    fn find_flash_for_app(&self, length: usize) -> Result<AppFlashLocation, ErrorCode>;
+
+    /// Create a process for the TBF object that was written to app flash at
+    /// `address`, and start it.
+    ///
+    /// Returns `Err(ErrorCode::NOMEM)` if there is no empty process slot or not
+    /// enough memory for the process, and `Err(ErrorCode::INVAL)` if there is
+    /// no valid, enabled app at `address`.
+    fn load_process(&self, address: usize) -> Result<ProcessId, ErrorCode>;
+
+    /// Stop the process with the identifier `identifier` (see
+    /// `ProcessId::id()`) and free its process slot.
+    ///
+    /// Returns the `(address, length)` of the TBF object of the process in
+    /// flash, which the caller should overwrite with a padding entry to
+    /// reclaim the space.
+    fn uninstall_process(&self, identifier: usize) -> Result<(usize, usize), ErrorCode>;
+}
+
+/// Loads processes at boot and keeps what is needed to load more processes
+/// later.
+pub struct DynamicProcessLoader<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    /// App memory that has not been given to a process, or that was freed
+    /// when a process was removed.
+    app_memory: [TakeCell<'static, [u8]>; MEMORY_BLOCKS],
+    procs: MapCell<&'static mut [Option<&'static dyn Process>]>,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    checker: Option<&'static ProcessCheckerMachine>,
+}
+
+impl<C: 'static + Chip> DynamicProcessLoader<C> {
+    /// Create a loader for the apps in `app_flash`, which gives processes
+    /// memory from `app_memory` and stores them in `procs`.
+    ///
+    /// If `checker` is `Some`, processes are loaded like with
+    /// `load_and_check_processes()`: they only run once the checker approves
+    /// their credentials. The checker must be set up as the client of its
+    /// `AppCredentialsChecker`.
+    ///
+    /// The loader can create processes, so we require the
+    /// `ProcessManagementCapability` to create it.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        procs: &'static mut [Option<&'static dyn Process>],
+        fault_policy: &'static dyn ProcessFaultPolicy,
+        checker: Option<&'static ProcessCheckerMachine>,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> DynamicProcessLoader<C> {
+        DynamicProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            app_memory: [
+                TakeCell::new(app_memory),
+                TakeCell::empty(),
+                TakeCell::empty(),
+                TakeCell::empty(),
+            ],
+            procs: MapCell::new(procs),
+            fault_policy,
+            checker,
+        }
+    }
+
+    /// Load the apps that are in flash at boot. This is the equivalent of
+    /// `load_processes()`, but keeps the unused app memory so that more
+    /// processes can be loaded later.
+    pub fn load_processes(&self) -> Result<(), ProcessLoadError> {
+        let app_memory = self.app_memory[0]
+            .take()
+            .ok_or(ProcessLoadError::InternalError)?;
+        let result = self
+            .procs
+            .map_or(Err(ProcessLoadError::InternalError), move |procs| {
+                load_processes_advanced(
+                    self.kernel,
+                    self.chip,
+                    self.app_flash,
+                    app_memory,
+                    procs,
+                    self.fault_policy,
+                    self.checker.map(|checker| checker.checker),
+                )
+            })
+            .and_then(|(remaining_memory, skipped)| {
+                self.app_memory[0].replace(remaining_memory);
+                skipped.map_or(Ok(()), Err)
+            });
+
+        // Check any processes that were created, even if a later app failed
+        // to load.
+        self.checker.map(|checker| checker.start());
+        result
+    }
+
+    /// Add `block` to the free app memory, merging it with the free blocks
+    /// right before and after it.
+    fn free_memory(&self, mut block: &'static mut [u8]) {
+        let mut merged = true;
+        while merged {
+            merged = false;
+            for free in self.app_memory.iter() {
+                let start = block.as_ptr() as usize;
+                let end = start + block.len();
+                let adjacent = free.map_or(false, |free| {
+                    let free_start = free.as_ptr() as usize;
+                    free_start + free.len() == start || end == free_start
+                });
+                if adjacent {
+                    if let Some(free) = free.take() {
+                        let merged_start = cmp::min(free.as_mut_ptr(), block.as_mut_ptr());
+                        // Safety: the two blocks are next to each other, and
+                        // the loader owns both of them and gives up both
+                        // slices here.
+                        block = unsafe {
+                            slice::from_raw_parts_mut(merged_start, free.len() + block.len())
+                        };
+                        merged = true;
+                    }
+                }
+            }
+        }
+
+        match self.app_memory.iter().find(|free| free.is_none()) {
+            Some(free) => {
+                free.replace(block);
+            }
+            None => {
+                if config::CONFIG.debug_load_processes {
+                    debug!(
+                        "No room to track free app memory {:#010X}-{:#010X}",
+                        block.as_ptr() as usize,
+                        block.as_ptr() as usize + block.len() - 1
+                    );
+                }
+            }
+        }
+    }
+
+    /// Try to place an app of `length` bytes at the start of the free space
+    /// `free_start..free_end` (absolute addresses), aligned to `alignment`.
+    /// `free_end` is `None` if the free space is the unused flash at the end
+    /// of the app linked list.
+    fn place_app(
+        &self,
+        free_start: usize,
+        free_end: Option<usize>,
+        length: usize,
+        alignment: usize,
+    ) -> Option<AppFlashLocation> {
+        let flash_end = self.app_flash.as_ptr() as usize + self.app_flash.len();
+
+        let mut address = align_up(free_start, alignment);
+        // Alignment space must be big enough to hold a padding entry.
+        if address != free_start && address - free_start < PADDING_HEADER_LEN {
+            address = align_up(free_start + PADDING_HEADER_LEN, alignment);
+        }
+        let app_end = address.checked_add(length)?;
+        if app_end > free_end.unwrap_or(flash_end) {
+            return None;
+        }
+
+        let padding_before = if address > free_start {
+            Some((free_start, address - free_start))
+        } else {
+            None
+        };
+        let padding_after = match free_end {
+            Some(end) if end > app_end => {
+                if end - app_end < PADDING_HEADER_LEN {
+                    return None;
+                }
+                Some((app_end, end - app_end))
+            }
+            _ => None,
+        };
+
+        Some(AppFlashLocation {
+            address,
+            padding_before,
+            padding_after,
+        })
+    }
+}
+
+impl<C: 'static + Chip> DynamicProcessLoading for DynamicProcessLoader<C> {
+    fn find_flash_for_app(&self, length: usize) -> Result<AppFlashLocation, ErrorCode> {
+        if length < PADDING_HEADER_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+        let alignment = length.next_power_of_two();
+        let flash_start = self.app_flash.as_ptr() as usize;
+
+        // Walk the app linked list looking for padding entries the app fits
+        // in. If there are none, the app goes after the end of the list.
+        let mut offset = 0;
+        while let Some(test_header_slice) = self.app_flash.get(offset..offset + 8) {
+            let header: &'static [u8; 8] = match test_header_slice.try_into() {
+                Ok(header) => header,
+                Err(_) => break,
+            };
+            let entry_length = match tock_tbf::parse::parse_tbf_header_lengths(header) {
+                Ok((version, header_length, entry_length)) => {
+                    let is_padding = self
+                        .app_flash
+                        .get(offset..offset + header_length as usize)
+                        .map_or(false, |header_flash| {
+                            tock_tbf::parse::parse_tbf_header(header_flash, version)
+                                .map_or(false, |header| !header.is_app())
+                        });
+                    if is_padding {
+                        let entry_start = flash_start + offset;
+                        let entry_end = entry_start + entry_length as usize;
+                        if let Some(location) =
+                            self.place_app(entry_start, Some(entry_end), length, alignment)
+                        {
+                            return Ok(location);
+                        }
+                    }
+                    entry_length
+                }
+                Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
+                    entry_length
+                }
+                Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => break,
+            };
+
+            if entry_length == 0 {
+                // A zero length entry would make us loop forever, and means
+                // the list is corrupt.
+                return Err(ErrorCode::FAIL);
+            }
+            offset += entry_length as usize;
+        }
+
+        self.place_app(flash_start + offset, None, length, alignment)
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    fn load_process(&self, address: usize) -> Result<ProcessId, ErrorCode> {
+        let flash_start = self.app_flash.as_ptr() as usize;
+        let entry_flash = address
+            .checked_sub(flash_start)
+            .and_then(|offset| self.app_flash.get(offset..))
+            .ok_or(ErrorCode::INVAL)?;
+
+        let header: &'static [u8; 8] = entry_flash
+            .get(0..8)
+            .ok_or(ErrorCode::INVAL)?
+            .try_into()
+            .or(Err(ErrorCode::INVAL))?;
+        let (version, header_length, entry_length) =
+            tock_tbf::parse::parse_tbf_header_lengths(header).or(Err(ErrorCode::INVAL))?;
+        let entry_flash = entry_flash
+            .get(0..entry_length as usize)
+            .ok_or(ErrorCode::INVAL)?;
+
+        self.procs.map_or(Err(ErrorCode::FAIL), |procs| {
+            let index = procs
+                .iter()
+                .position(|slot| slot.is_none())
+                .ok_or(ErrorCode::NOMEM)?;
+            // Try the free blocks of app memory in turn until one is big
+            // enough for the process.
+            let mut error = ProcessLoadError::NotEnoughMemory;
+            for free in self.app_memory.iter() {
+                let app_memory = match free.take() {
+                    Some(app_memory) => app_memory,
+                    None => continue,
+                };
+
+                // Safety: the memory we pass has not been given to any other
+                // process.
+                let result = unsafe {
+                    ProcessStandard::create(
+                        self.kernel,
+                        self.chip,
+                        entry_flash,
+                        header_length as usize,
+                        version,
+                        app_memory,
+                        self.fault_policy,
+                        self.checker.map(|checker| checker.checker),
+                        index,
+                    )
+                };
+
+                match result {
+                    Ok((process, remaining_memory)) => {
+                        free.replace(remaining_memory);
+
+                        // `None` means this is padding or a disabled app.
+                        let process = process.ok_or(ErrorCode::INVAL)?;
+                        if config::CONFIG.debug_load_processes {
+                            debug!(
+                                "Loaded process[{}] from flash={:#010X}-{:#010X} = {:?}",
+                                index,
+                                entry_flash.as_ptr() as usize,
+                                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                                process.get_process_name()
+                            );
+                        }
+                        procs[index] = Some(process);
+                        self.checker.map(|checker| checker.start());
+                        return Ok(process.processid());
+                    }
+                    Err((load_error, remaining_memory)) => {
+                        free.replace(remaining_memory);
+                        error = load_error;
+                        match error {
+                            // Another block may be big enough, or start at
+                            // the address the process needs.
+                            ProcessLoadError::NotEnoughMemory
+                            | ProcessLoadError::MemoryAddressMismatch { .. } => continue,
+                            _ => break,
+                        }
+                    }
+                }
+            }
+
+            if config::CONFIG.debug_load_processes {
+                debug!("Error loading process at {:#010X}: {:?}", address, error);
+            }
+            Err(match error {
+                ProcessLoadError::NotEnoughMemory
+                | ProcessLoadError::MemoryAddressMismatch { .. } => ErrorCode::NOMEM,
+                ProcessLoadError::InternalError => ErrorCode::FAIL,
+                _ => ErrorCode::INVAL,
+            })
+        })
+    }
+
+    fn uninstall_process(&self, identifier: usize) -> Result<(usize, usize), ErrorCode> {
+        self.procs.map_or(Err(ErrorCode::FAIL), |procs| {
+            let slot = procs
+                .iter_mut()
+                .find(|slot| slot.map_or(false, |p| p.processid().id() == identifier))
+                .ok_or(ErrorCode::INVAL)?;
+            let process = slot.take().ok_or(ErrorCode::INVAL)?;
+
+            // Stop the process before it leaves the processes array so that
+            // its pending work is removed from the kernel.
+            process.terminate(0);
+
+            let start = process.flash_start() as usize;
+            let end = process.flash_end() as usize;
+
+            let memory_start = process.mem_start() as *mut u8;
+            let memory_len = process.mem_end() as usize - memory_start as usize;
+            // Safety: the memory was given to the process by this loader, and
+            // the process is no longer in the processes array, so the kernel
+            // does not look up the process (whose struct and grants are in
+            // this memory) anymore. `ProcessId`s of the process held by
+            // capsules no longer match any process.
+            self.free_memory(unsafe { slice::from_raw_parts_mut(memory_start, memory_len) });
+
+            Ok((start, end - start))
+        })
+    }
+}
+
+/// Round `value` up to a multiple of `alignment`, which must be a power of two.
+fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
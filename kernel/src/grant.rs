@@ -111,6 +111,7 @@ use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
 use core::ptr::{write, NonNull};
+use core::slice;
 
 use crate::process::{Error, Process, ProcessCustomGrantIdentifer, ProcessId};
 use crate::sched::Kernel;
@@ -671,7 +672,128 @@ impl<T> CustomGrant<T> {
                 // once (because of the `&mut self` requirement).
                 let custom_grant = unsafe { &mut *(grant_ptr as *mut T) };
                 let borrowed = GrantMemory::new(custom_grant);
-                Ok(fun(borrowed))
+                let res = fun(borrowed);
+
+                // The custom grant may be moved again.
+                process.leave_custom_grant(self.identifier);
+                Ok(res)
+            })
+    }
+
+    /// Free the memory of the custom grant.
+    ///
+    /// The value in the custom grant is not dropped. Its memory is returned to
+    /// the process when the grant region is compacted, which happens when an
+    /// allocation in the grant region would otherwise fail, or with
+    /// `GrantRegionAllocator::compact()`.
+    pub fn free(self) -> Result<(), Error> {
+        self.processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
+                process.free_custom_grant(self.identifier)
+            })
+    }
+}
+
+/// A custom grant holding a number of items that can change, allocated with
+/// `GrantRegionAllocator::alloc_slice_with()`.
+///
+/// This lets a capsule size per-process buffers to what each process needs,
+/// rather than for the worst case.
+pub struct CustomGrantSlice<T> {
+    /// An identifier for this custom grant within a process's grant region.
+    identifier: ProcessCustomGrantIdentifer,
+
+    /// Identifier for the process where this custom grant is allocated.
+    processid: ProcessId,
+
+    /// Number of items in the slice.
+    len: usize,
+
+    /// Used to keep the Rust type of the items.
+    _phantom: PhantomData<T>,
+}
+
+impl<T> CustomGrantSlice<T> {
+    /// Helper function to get the ProcessId from the custom grant.
+    pub fn processid(&self) -> ProcessId {
+        self.processid
+    }
+
+    /// The number of items in the slice.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Gives access to the items within the given closure.
+    ///
+    /// If the process has since been restarted or crashed, or the memory is
+    /// otherwise no longer present, then this function will not call the given
+    /// closure, and will instead directly return `Err(Error::NoSuchApp)`.
+    pub fn enter<F, R>(&mut self, fun: F) -> Result<R, Error>
+    where
+        F: FnOnce(GrantMemory<'_, [T]>) -> R,
+    {
+        let len = self.len;
+        self.processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
+                let grant_ptr = process.enter_custom_grant(self.identifier)?;
+
+                // # Safety
+                //
+                // The memory holds `len` initialized items, and there are no
+                // other references to it for the same reasons as for
+                // `CustomGrant::enter()`.
+                let items = unsafe { slice::from_raw_parts_mut(grant_ptr as *mut T, len) };
+                let res = fun(GrantMemory::new(items));
+
+                process.leave_custom_grant(self.identifier);
+                Ok(res)
+            })
+    }
+
+    /// Change the number of items in the slice to `len`, keeping the first
+    /// items. If the slice grows, `init` is called with the index of each new
+    /// item to initialize it. Items removed when the slice shrinks are not
+    /// dropped.
+    ///
+    /// If there is not enough memory, this returns `Err(Error::OutOfMemory)`
+    /// and the slice is unchanged.
+    pub fn resize_with<F>(&mut self, len: usize, mut init: F) -> Result<(), Error>
+    where
+        F: FnMut(usize) -> T,
+    {
+        let alloc_size = size_of::<T>().checked_mul(len).ok_or(Error::OutOfMemory)?;
+        let typed_ptr = self.processid.kernel.process_map_or(
+            Err(Error::NoSuchApp),
+            self.processid,
+            |process| {
+                process
+                    .reallocate_custom_grant(self.identifier, alloc_size, align_of::<T>())
+                    .map(NonNull::cast::<T>)
+            },
+        )?;
+
+        for i in self.len..len {
+            // # Safety
+            //
+            // The memory was reallocated to hold `len` items, and the items
+            // from `self.len` on are not initialized.
+            unsafe {
+                write(typed_ptr.as_ptr().add(i), init(i));
+            }
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Free the memory of the slice, like `CustomGrant::free()`.
+    pub fn free(self) -> Result<(), Error> {
+        self.processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
+                process.free_custom_grant(self.identifier)
             })
     }
 }
@@ -749,6 +871,57 @@ impl GrantRegionAllocator {
         Ok(CustomGrant::new(custom_grant_identifier, self.processid))
     }
 
+    /// Allocates a slice of `len` instances of a given type, which can later
+    /// be resized. Each instance is initialized using the provided function,
+    /// like with `alloc_n_with()`.
+    ///
+    /// # Panic Safety
+    ///
+    /// If `init` panics, the freshly allocated memory and any values already
+    /// written will be leaked.
+    pub fn alloc_slice_with<T, F>(
+        &mut self,
+        len: usize,
+        mut init: F,
+    ) -> Result<CustomGrantSlice<T>, Error>
+    where
+        F: FnMut(usize) -> T,
+    {
+        let (custom_grant_identifier, typed_ptr) = self.alloc_n_raw::<T>(len)?;
+
+        for i in 0..len {
+            // # Safety
+            //
+            // The allocate function guarantees that `ptr` points to memory
+            // large enough to allocate `len` copies of the object.
+            unsafe {
+                write(typed_ptr.as_ptr().add(i), init(i));
+            }
+        }
+
+        Ok(CustomGrantSlice {
+            identifier: custom_grant_identifier,
+            processid: self.processid,
+            len,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Reclaims the memory of freed custom grants in the process's grant
+    /// region, so that it can be used by the process or for new allocations.
+    ///
+    /// Grants are never moved, and neither are the custom grants above them,
+    /// which were allocated before them, or custom grants that are entered.
+    /// Memory freed above any of those is not reclaimed. Returns the number of
+    /// bytes reclaimed.
+    pub fn compact(&mut self) -> Result<usize, Error> {
+        self.processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
+                Ok(process.compact_grant_region())
+            })
+    }
+
     /// Allocates uninitialized grant memory appropriate to store a `T`.
     ///
     /// The caller must initialize the memory.
@@ -123,6 +123,46 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the largest number of bytes of stack this app has used, if
+    /// known. The kernel only knows this if the app has told it where its
+    /// stack starts.
+    pub fn app_stack_high_water_mark(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<usize> {
+        self.kernel
+            .process_map_or(None, app, |process| process.debug_stack_high_water_mark())
+    }
+
+    /// Returns the largest amount of memory in bytes this app has had
+    /// accessible to it, from the start of its memory to the highest its app
+    /// break has been moved with `brk` or `sbrk`. Together with the memory
+    /// the kernel uses on behalf of the app, this is the RAM the app needs, so
+    /// it can be used to choose the `minimum_ram_size` in the TBF header.
+    pub fn app_memory_high_water_mark(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app, |process| {
+            process.debug_app_break_high_water_mark() as usize - process.mem_start() as usize
+        })
+    }
+
+    /// Returns a tuple of (the time this app has spent executing in
+    /// userspace, the time the kernel has spent on its behalf), both in
+    /// microseconds. Time spent while the app runs cooperatively, without a
+    /// timeslice, is not counted.
+    pub fn app_execution_time_us(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (u64, u64) {
+        self.kernel
+            .process_map_or((0, 0), app, |process| process.debug_execution_time_us())
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -153,3 +193,15 @@ impl KernelInfo {
         count.get()
     }
 }
+
+/// Implemented by schedulers that give processes deadlines, to report how well
+/// the processes meet them.
+pub trait DeadlineInfo {
+    /// Returns how many deadlines the process has missed, or `None` if the
+    /// process does not have deadlines.
+    fn number_app_deadline_misses(
+        &self,
+        app: ProcessId,
+        capability: &dyn ProcessManagementCapability,
+    ) -> Option<usize>;
+}
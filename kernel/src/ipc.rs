@@ -2,10 +2,25 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! A client shares a buffer with a service by allowing it to this driver with
+//! the service's identifier: read-write with `allow_readwrite` or read-only
+//! with `allow_readonly`. When the client next notifies the service, the
+//! kernel adds an MPU region covering exactly that buffer to the service, with
+//! the same permissions, and passes the buffer to the service's upcall. The
+//! service then accesses the client's memory directly, without copies. Since
+//! the region must cover exactly the buffer, the buffer has to meet the
+//! alignment and size requirements of the MPU (for the Cortex-M MPU, e.g., a
+//! power of two of at least 32 bytes that is aligned to its size).
+//!
+//! Sharing a different buffer, or an empty one, removes the service's access
+//! to the previous buffer. The access is also removed when the client
+//! restarts.
 
 use crate::capabilities::MemoryAllocationCapability;
 use crate::grant::Grant;
 use crate::mem::Read;
+use crate::platform::mpu;
 use crate::process;
 use crate::process::ProcessId;
 use crate::sched::Kernel;
@@ -31,6 +46,12 @@ struct IPCData<const NUM_PROCS: usize> {
     /// An array of app slices that this application has shared with other
     /// applications.
     shared_memory: [ReadWriteAppSlice; NUM_PROCS],
+    /// An array of app slices that this application has shared read-only with
+    /// other applications. A buffer in `shared_memory` takes precedence.
+    shared_memory_readonly: [ReadOnlyAppSlice; NUM_PROCS],
+    /// The MPU region of each other application that gives it access to the
+    /// buffer this application shared with it, and that application.
+    shared_regions: [Option<(ProcessId, mpu::Region)>; NUM_PROCS],
     search_slice: ReadOnlyAppSlice,
     /// An array of upcalls this process has registered to receive upcalls
     /// from other services.
@@ -42,8 +63,11 @@ struct IPCData<const NUM_PROCS: usize> {
 impl<const NUM_PROCS: usize> Default for IPCData<NUM_PROCS> {
     fn default() -> IPCData<NUM_PROCS> {
         const DEFAULT_RW_APP_SLICE: ReadWriteAppSlice = ReadWriteAppSlice::const_default();
+        const DEFAULT_RO_APP_SLICE: ReadOnlyAppSlice = ReadOnlyAppSlice::const_default();
         IPCData {
             shared_memory: [DEFAULT_RW_APP_SLICE; NUM_PROCS],
+            shared_memory_readonly: [DEFAULT_RO_APP_SLICE; NUM_PROCS],
+            shared_regions: [None; NUM_PROCS],
             search_slice: ReadOnlyAppSlice::default(),
             client_upcalls: [Upcall::default(); NUM_PROCS],
             upcall: Upcall::default(),
@@ -86,40 +110,72 @@ impl<const NUM_PROCS: usize> IPC<NUM_PROCS> {
                     // sure we have access to that slice and then call
                     // the upcall. If no slice was shared then just
                     // call the upcall.
-                    match schedule_on.index() {
-                        Some(i) => {
-                            if i >= called_from_data.shared_memory.len() {
-                                return;
-                            }
+                    let i = match schedule_on.index() {
+                        Some(i) if i < NUM_PROCS => i,
+                        _ => return,
+                    };
 
-                            match called_from_data.shared_memory.get(i) {
-                                Some(slice) => {
-                                    self.data
-                                        .kernel
-                                        .process_map_or(None, schedule_on, |process| {
-                                            process.add_mpu_region(
-                                                slice.ptr(),
-                                                slice.len(),
-                                                slice.len(),
-                                            )
-                                        });
-                                    upcall.schedule(
-                                        called_from.id() + 1,
-                                        crate::mem::Read::len(slice),
-                                        crate::mem::Read::ptr(slice) as usize,
-                                    );
-                                }
-                                None => {
-                                    upcall.schedule(called_from.id() + 1, 0, 0);
-                                }
-                            }
-                        }
-                        None => {}
+                    let (ptr, len, permissions) = if called_from_data.shared_memory[i].len() > 0 {
+                        let slice = &called_from_data.shared_memory[i];
+                        (slice.ptr(), slice.len(), mpu::Permissions::ReadWriteOnly)
+                    } else {
+                        let slice = &called_from_data.shared_memory_readonly[i];
+                        (slice.ptr(), slice.len(), mpu::Permissions::ReadOnly)
+                    };
+                    if len == 0 {
+                        upcall.schedule(called_from.id() + 1, 0, 0);
+                        return;
+                    }
+
+                    // Map the buffer into the other process, unless it already
+                    // has access to it.
+                    let shared_region = &mut called_from_data.shared_regions[i];
+                    let mapped = match shared_region {
+                        Some((process, _)) if *process == schedule_on => true,
+                        _ => self
+                            .data
+                            .kernel
+                            .process_map_or(false, schedule_on, |process| {
+                                process
+                                    .add_shared_mpu_region(called_from, ptr, len, permissions)
+                                    .map(|region| *shared_region = Some((schedule_on, region)))
+                                    .is_ok()
+                            }),
+                    };
+                    if mapped {
+                        upcall.schedule(called_from.id() + 1, len, ptr as usize);
+                    } else {
+                        upcall.schedule(called_from.id() + 1, 0, 0);
                     }
                 })
             })
             .and_then(|x| x)
     }
+
+    /// Remove the access of another process to the buffer `owner` shared with
+    /// it, if it has been given access.
+    fn unshare(&self, owner: ProcessId, shared_region: &mut Option<(ProcessId, mpu::Region)>) {
+        if let Some((process, region)) = shared_region.take() {
+            self.data.kernel.process_map_or((), process, |process| {
+                let _ = process.remove_shared_mpu_region(owner, region);
+            });
+        }
+    }
+
+    /// Find the index of the process with the IPC identifier `target_id`.
+    fn target_index(&self, target_id: usize) -> Result<usize, ErrorCode> {
+        // Lookup the index of the app based on the passed in identifier. This
+        // also let's us check that the other app is actually valid.
+        let app_identifier = target_id - 1;
+        let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
+        match otherapp {
+            Some(oa) => match oa.index() {
+                Some(i) if i < NUM_PROCS => Ok(i),
+                _ => Err(ErrorCode::INVAL),
+            },
+            None => Err(ErrorCode::BUSY),
+        }
+    }
 }
 
 impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
@@ -262,7 +318,12 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
                             |target| {
                                 let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
                                 match ret {
-                                    true => CommandReturn::success(),
+                                    true => {
+                                        // The service runs at the priority of
+                                        // this client until it notifies it.
+                                        target.inherit_priority(appid);
+                                        CommandReturn::success()
+                                    }
                                     false => CommandReturn::failure(ErrorCode::FAIL),
                                 }
                             },
@@ -285,7 +346,13 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
                             |target| {
                                 let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
                                 match ret {
-                                    true => CommandReturn::success(),
+                                    true => {
+                                        // The request of the client is served.
+                                        self.data.kernel.process_map_or((), appid, |service| {
+                                            service.release_priority(otherapp)
+                                        });
+                                        CommandReturn::success()
+                                    }
                                     false => CommandReturn::failure(ErrorCode::FAIL),
                                 }
                             },
@@ -298,23 +365,37 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
 
     /// allow_readonly with subdriver number `0` stores the provided buffer for service discovery.
     /// The buffer should contain the package name of a process that exports an IPC service.
+    ///
+    /// If allow_readonly is called with subdriver >= 1, it is a share command where the
+    /// application shares a slice read-only with an IPC service (as specified by the
+    /// subdriver). The service gets access to the slice when the application next notifies
+    /// it.
     fn allow_readonly(
         &self,
         appid: ProcessId,
         subdriver: usize,
         mut slice: ReadOnlyAppSlice,
     ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
-        if subdriver == 0 {
+        let res = if subdriver == 0 {
             // Package name for discovery
-            let res = self.data.enter(appid, |data| {
-                core::mem::swap(&mut data.search_slice, &mut slice);
-            });
-            match res {
-                Ok(_) => Ok(slice),
-                Err(e) => Err((slice, e.into())),
-            }
+            self.data
+                .enter(appid, |data| {
+                    core::mem::swap(&mut data.search_slice, &mut slice);
+                })
+                .map_err(|e| e.into())
         } else {
-            Err((slice, ErrorCode::NOSUPPORT))
+            self.target_index(subdriver).and_then(|i| {
+                self.data
+                    .enter(appid, |data| {
+                        core::mem::swap(&mut data.shared_memory_readonly[i], &mut slice);
+                        self.unshare(appid, &mut data.shared_regions[i]);
+                    })
+                    .map_err(|e| e.into())
+            })
+        };
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
         }
     }
 
@@ -324,7 +405,9 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
     /// If allow is called with target_id >= 1, it is a share command where the
     /// application is explicitly sharing a slice with an IPC service (as
     /// specified by the target_id). allow() simply allows both processes to
-    /// access the buffer, it does not signal the service.
+    /// access the buffer, it does not signal the service. The service gets
+    /// access to the slice when the application next notifies it, and loses
+    /// access to any slice the application shared with it before.
     ///
     /// target_id == 0 is currently unsupported and reserved for future use.
     fn allow_readwrite(
@@ -336,29 +419,17 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
         if target_id == 0 {
             Err((slice, ErrorCode::NOSUPPORT))
         } else {
-            match self.data.enter(appid, |data| {
-                // Lookup the index of the app based on the passed in
-                // identifier. This also let's us check that the other app is
-                // actually valid.
-                let app_identifier = target_id - 1;
-                let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
-                if let Some(oa) = otherapp {
-                    if let Some(i) = oa.index() {
-                        if let Some(smem) = data.shared_memory.get_mut(i) {
-                            core::mem::swap(smem, &mut slice);
-                            Ok(())
-                        } else {
-                            Err(ErrorCode::INVAL)
-                        }
-                    } else {
-                        Err(ErrorCode::INVAL)
-                    }
-                } else {
-                    Err(ErrorCode::BUSY)
-                }
-            }) {
-                Ok(_) => Ok(slice),
-                Err(e) => Err((slice, e.into())),
+            let res = self.target_index(target_id).and_then(|i| {
+                self.data
+                    .enter(appid, |data| {
+                        core::mem::swap(&mut data.shared_memory[i], &mut slice);
+                        self.unshare(appid, &mut data.shared_regions[i]);
+                    })
+                    .map_err(|e| e.into())
+            });
+            match res {
+                Ok(()) => Ok(slice),
+                Err(e) => Err((slice, e)),
             }
         }
     }
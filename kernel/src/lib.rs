@@ -96,10 +96,14 @@ pub mod debug;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod panic_record;
+pub mod process_checker;
+pub mod storage_permissions;
 pub mod syscall;
 
 mod config;
 mod driver;
+mod dynamic_loader;
 mod errorcode;
 mod grant;
 mod mem;
@@ -115,7 +119,7 @@ mod upcall;
 pub use crate::driver::{CommandReturn, Driver};
 pub use crate::errorcode::into_statuscode;
 pub use crate::errorcode::ErrorCode;
-pub use crate::grant::{Grant, ProcessGrant};
+pub use crate::grant::{CustomGrant, CustomGrantSlice, Grant, GrantRegionAllocator, ProcessGrant};
 pub use crate::mem::{Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice};
 pub use crate::platform::scheduler_timer::{SchedulerTimer, VirtualSchedulerTimer};
 pub use crate::platform::watchdog;
@@ -123,9 +127,11 @@ pub use crate::platform::{mpu, Chip, InterruptService, Platform};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use crate::process::ProcessId;
 pub use crate::sched::cooperative::{CoopProcessNode, CooperativeSched};
+pub use crate::sched::edf::{EDFProcessNode, EDFSched};
 pub use crate::sched::mlfq::{MLFQProcessNode, MLFQSched};
 pub use crate::sched::priority::PrioritySched;
 pub use crate::sched::round_robin::{RoundRobinProcessNode, RoundRobinSched};
+pub use crate::sched::stride::{StrideProcessNode, StrideSched};
 pub use crate::sched::{Kernel, Scheduler};
 pub use crate::upcall::Upcall;
 
@@ -135,6 +141,9 @@ pub use crate::upcall::Upcall;
 // processes.
 /// Publicly available process-related objects.
 pub mod procs {
+    pub use crate::dynamic_loader::{
+        AppFlashLocation, DynamicProcessLoader, DynamicProcessLoading,
+    };
     pub use crate::process::{
         Error, FaultAction, FunctionCall, FunctionCallSource, Process, State, Task,
     };
@@ -144,5 +153,8 @@ pub mod procs {
         ThresholdRestartThenPanicFaultPolicy,
     };
     pub use crate::process_standard::ProcessStandard;
-    pub use crate::process_utilities::{load_processes, ProcessLoadError};
+    pub use crate::process_utilities::{
+        load_and_check_processes, load_processes, ProcessLoadError,
+    };
+    pub use tock_tbf::types::{create_padding_header, CommandPermissions};
 }
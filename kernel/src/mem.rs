@@ -387,11 +387,19 @@ impl ReadOnlyAppSlice {
     pub(crate) fn consume(self) -> (*const u8, usize) {
         (self.ptr, self.len)
     }
-}
 
-impl Default for ReadOnlyAppSlice {
-    fn default() -> Self {
-        ReadOnlyAppSlice {
+    /// This is a `const` version of `Default::default` with the same semantics.
+    ///
+    /// Having a const initializer allows initializing a fixed-size array with default values
+    /// without the struct being marked `Copy` as such:
+    ///
+    /// ```
+    /// use kernel::ReadOnlyAppSlice;
+    /// const DEFAULT_ROAPPSLICE_VAL: ReadOnlyAppSlice = ReadOnlyAppSlice::const_default();
+    /// let my_array = [DEFAULT_ROAPPSLICE_VAL; 12];
+    /// ```
+    pub const fn const_default() -> Self {
+        Self {
             ptr: 0x0 as *mut u8,
             len: 0,
             process_id: None,
@@ -399,6 +407,12 @@ impl Default for ReadOnlyAppSlice {
     }
 }
 
+impl Default for ReadOnlyAppSlice {
+    fn default() -> Self {
+        Self::const_default()
+    }
+}
+
 impl Read for ReadOnlyAppSlice {
     fn len(&self) -> usize {
         self.process_id
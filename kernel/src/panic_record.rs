@@ -0,0 +1,292 @@
+//! Record of the last kernel panic that survives a reset.
+//!
+//! `debug::panic()` prints what it knows about a panic to a synchronous writer,
+//! which on deployed devices is usually not connected to anything. A board can
+//! additionally write a compact `PanicRecord` in its panic handler. If the
+//! record is placed in the `.noinit` section, which the kernel does not zero on
+//! boot, it is still there after the device resets, and capsules such as
+//! `capsules::last_panic` can show it.
+//!
+//! The record holds the panic message, a kernel stack trace, and the state of
+//! each process. The stack trace is not unwound: it lists the words on the
+//! kernel stack that point into kernel code, newest first. Most of them are
+//! return addresses of the functions that led to the panic, the first one
+//! being closest to where the panic happened, but some may be stale values.
+//!
+//! A record in RAM that was never written, for example after the device was
+//! powered off, is detected with a checksum and ignored.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! #[link_section = ".noinit"]
+//! pub static mut PANIC_RECORD: PanicRecord = PanicRecord::new();
+//!
+//! #[panic_handler]
+//! pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
+//!     PANIC_RECORD.record(
+//!         pi,
+//!         &PROCESSES,
+//!         (&_stext as *const u8, &_etext as *const u8),
+//!         &_estack as *const u8,
+//!     );
+//!     debug::panic(...)
+//! }
+//! ```
+
+use core::cmp;
+use core::fmt::{self, Write};
+use core::mem;
+use core::panic::PanicInfo;
+use core::ptr;
+use core::slice;
+use core::str;
+
+use crate::process::{Process, State};
+
+/// Length of the panic message kept in the record.
+pub const MESSAGE_LEN: usize = 96;
+/// Number of stack trace entries kept in the record.
+pub const STACK_TRACE_LEN: usize = 8;
+/// Number of processes whose state is kept in the record.
+pub const PROCESSES_LEN: usize = 8;
+/// Length of the process names kept in the record.
+pub const PROCESS_NAME_LEN: usize = 16;
+
+const PANIC_RECORD_MAGIC: u32 = 0x5041_4E43;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ProcessRecord {
+    name: [u8; PROCESS_NAME_LEN],
+    name_len: u8,
+    state: u8,
+}
+
+impl ProcessRecord {
+    const fn new() -> ProcessRecord {
+        ProcessRecord {
+            name: [0; PROCESS_NAME_LEN],
+            name_len: 0,
+            state: 0,
+        }
+    }
+}
+
+/// The last kernel panic.
+#[repr(C)]
+pub struct PanicRecord {
+    magic: u32,
+    checksum: u32,
+    /// Number of panics recorded since the record was last invalid.
+    count: u32,
+    message_len: u32,
+    message: [u8; MESSAGE_LEN],
+    stack_trace_len: u32,
+    stack_trace: [u32; STACK_TRACE_LEN],
+    processes_len: u32,
+    processes: [ProcessRecord; PROCESSES_LEN],
+}
+
+impl PanicRecord {
+    /// An empty record, to initialize the static that holds the record.
+    pub const fn new() -> PanicRecord {
+        PanicRecord {
+            magic: 0,
+            checksum: 0,
+            count: 0,
+            message_len: 0,
+            message: [0; MESSAGE_LEN],
+            stack_trace_len: 0,
+            stack_trace: [0; STACK_TRACE_LEN],
+            processes_len: 0,
+            processes: [ProcessRecord::new(); PROCESSES_LEN],
+        }
+    }
+
+    /// Record the panic described by `panic_info`.
+    ///
+    /// `kernel_text` is the range of addresses of the kernel code, and
+    /// `kernel_stack_end` the address just after the kernel stack, which is
+    /// where it starts since it grows down.
+    ///
+    /// ### Safety
+    ///
+    /// Must only be called from the panic handler, as it reads the kernel
+    /// stack between the current stack pointer and `kernel_stack_end`.
+    pub unsafe fn record(
+        &mut self,
+        panic_info: &PanicInfo,
+        processes: &'static [Option<&'static dyn Process>],
+        kernel_text: (*const u8, *const u8),
+        kernel_stack_end: *const u8,
+    ) {
+        let count = if self.is_valid() { self.count } else { 0 };
+        *self = PanicRecord::new();
+        self.count = count.wrapping_add(1);
+
+        let mut writer = MessageWriter {
+            buffer: &mut self.message,
+            length: 0,
+        };
+        let _ = write!(writer, "{}", panic_info);
+        self.message_len = writer.length as u32;
+
+        // The address of a local variable is close enough to the stack
+        // pointer, and works on every architecture.
+        let stack_marker = 0usize;
+        let stack_pointer = &stack_marker as *const usize as usize;
+        let (text_start, text_end) = (kernel_text.0 as usize, kernel_text.1 as usize);
+        let mut address = stack_pointer & !(mem::size_of::<usize>() - 1);
+        while address < kernel_stack_end as usize
+            && (self.stack_trace_len as usize) < STACK_TRACE_LEN
+        {
+            let word = ptr::read_volatile(address as *const usize);
+            if word >= text_start && word < text_end {
+                self.stack_trace[self.stack_trace_len as usize] = word as u32;
+                self.stack_trace_len += 1;
+            }
+            address += mem::size_of::<usize>();
+        }
+
+        for process in processes.iter().filter_map(|process| *process) {
+            let index = self.processes_len as usize;
+            if index == PROCESSES_LEN {
+                break;
+            }
+            let name = process.get_process_name().as_bytes();
+            let name_len = cmp::min(name.len(), PROCESS_NAME_LEN);
+            let record = &mut self.processes[index];
+            record.name[..name_len].copy_from_slice(&name[..name_len]);
+            record.name_len = name_len as u8;
+            record.state = state_to_code(process.get_state());
+            self.processes_len += 1;
+        }
+
+        self.magic = PANIC_RECORD_MAGIC;
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Whether the record holds a panic.
+    pub fn is_valid(&self) -> bool {
+        self.magic == PANIC_RECORD_MAGIC
+            && self.checksum == self.compute_checksum()
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.stack_trace_len as usize <= STACK_TRACE_LEN
+            && self.processes_len as usize <= PROCESSES_LEN
+    }
+
+    /// The number of panics recorded, which changes with each panic. Returns
+    /// `None` if the record does not hold a panic.
+    pub fn count(&self) -> Option<u32> {
+        self.valid().map(|record| record.count)
+    }
+
+    /// The panic message, including where in the kernel the panic happened.
+    pub fn message(&self) -> Option<&str> {
+        self.valid().map(|record| {
+            str::from_utf8(&record.message[..record.message_len as usize]).unwrap_or("")
+        })
+    }
+
+    /// The kernel stack trace, newest entry first.
+    pub fn stack_trace(&self) -> &[u32] {
+        self.valid().map_or(&[][..], |record| {
+            &record.stack_trace[..record.stack_trace_len as usize]
+        })
+    }
+
+    /// The number of processes whose state was recorded.
+    pub fn process_count(&self) -> usize {
+        self.valid()
+            .map_or(0, |record| record.processes_len as usize)
+    }
+
+    /// The name and state of the process at `index`, at the time of the panic.
+    pub fn process(&self, index: usize) -> Option<(&str, State)> {
+        self.valid()
+            .filter(|record| index < record.processes_len as usize)
+            .and_then(|record| {
+                let process = &record.processes[index];
+                let name_len = cmp::min(process.name_len as usize, PROCESS_NAME_LEN);
+                let name = str::from_utf8(&process.name[..name_len]).unwrap_or("");
+                state_from_code(process.state).map(|state| (name, state))
+            })
+    }
+
+    fn valid(&self) -> Option<&PanicRecord> {
+        if self.is_valid() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Checksum of all fields but `checksum` itself.
+    fn compute_checksum(&self) -> u32 {
+        // Safety: `PanicRecord` is `repr(C)` and only contains integers.
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                self as *const PanicRecord as *const u8,
+                mem::size_of::<PanicRecord>(),
+            )
+        };
+        let checksum_offset = mem::size_of::<u32>();
+        bytes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i < checksum_offset || *i >= checksum_offset + mem::size_of::<u32>())
+            .fold(0u32, |checksum, (_, byte)| {
+                checksum.rotate_left(5) ^ (*byte as u32)
+            })
+    }
+}
+
+/// Writes as much of a message as fits in a buffer.
+struct MessageWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut copied = cmp::min(s.len(), self.buffer.len() - self.length);
+        // Do not cut a character in half, so the message stays valid UTF-8.
+        while !s.is_char_boundary(copied) {
+            copied -= 1;
+        }
+        self.buffer[self.length..self.length + copied].copy_from_slice(&s.as_bytes()[..copied]);
+        self.length += copied;
+        Ok(())
+    }
+}
+
+fn state_to_code(state: State) -> u8 {
+    match state {
+        State::Unstarted => 0,
+        State::Running => 1,
+        State::Yielded => 2,
+        State::StoppedRunning => 3,
+        State::StoppedYielded => 4,
+        State::Faulted => 5,
+        State::Terminated => 6,
+        State::CredentialsUnchecked => 7,
+        State::CredentialsFailed => 8,
+    }
+}
+
+fn state_from_code(code: u8) -> Option<State> {
+    match code {
+        0 => Some(State::Unstarted),
+        1 => Some(State::Running),
+        2 => Some(State::Yielded),
+        3 => Some(State::StoppedRunning),
+        4 => Some(State::StoppedYielded),
+        5 => Some(State::Faulted),
+        6 => Some(State::Terminated),
+        7 => Some(State::CredentialsUnchecked),
+        8 => Some(State::CredentialsFailed),
+        _ => None,
+    }
+}
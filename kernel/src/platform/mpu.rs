@@ -142,6 +142,11 @@ pub trait MPU {
     ///
     /// Returns the start and size of the allocated MPU region. If it is
     /// infeasible to allocate the MPU region, returns None.
+    ///
+    /// If all hardware regions are in use, an implementation may store the
+    /// region in `config` without placing it in the hardware. The region is
+    /// then loaded by `swap_in_region()` once the process accesses it, so a
+    /// process can have more regions than the MPU supports.
     #[allow(unused_variables)]
     fn allocate_region(
         &self,
@@ -158,6 +163,55 @@ pub trait MPU {
         }
     }
 
+    /// Removes an MPU region that was allocated with `allocate_region()`.
+    ///
+    /// An implementation must remove the region from `config`, so that the
+    /// memory it covered is no longer accessible with its permissions once
+    /// `config` is next applied.
+    ///
+    /// # Arguments
+    ///
+    /// - `region`: the region returned by `allocate_region()`
+    /// - `config`: MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns an error if `region` is not stored in `config`, or if the
+    /// implementation cannot remove regions.
+    #[allow(unused_variables)]
+    fn remove_memory_region(&self, region: Region, config: &mut Self::MpuConfig) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Loads the region of `config` that covers `fault_address` into the
+    /// hardware, in place of another region of `config`.
+    ///
+    /// The kernel calls this when a process faults accessing `fault_address`.
+    /// If the address belongs to a region that `allocate_region()` could not
+    /// place in the hardware, or that was replaced by another region since, an
+    /// implementation must swap it in, so that the process can retry the
+    /// access once `config` is applied again. The replaced region is swapped
+    /// in again the same way when the process next accesses it. The region
+    /// covering the app-owned memory must always stay in the hardware.
+    ///
+    /// # Arguments
+    ///
+    /// - `fault_address`: address the process accessed
+    /// - `config`:        MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns an error if no region of `config` that is out of the hardware
+    /// covers `fault_address`, in which case the fault is a real one.
+    #[allow(unused_variables)]
+    fn swap_in_region(
+        &self,
+        fault_address: *const u8,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        Err(())
+    }
+
     /// Chooses the location for a process's memory, and allocates an MPU region
     /// covering the app-owned part.
     ///
@@ -268,6 +322,15 @@ pub trait MPU {
 /// Implement default MPU trait for unit.
 impl MPU for () {
     type MpuConfig = MpuConfigDefault;
+
+    /// Without an MPU, no region gives access to anything.
+    fn remove_memory_region(
+        &self,
+        _region: Region,
+        _config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
 }
 
 /// The generic trait that particular kernel level memory protection unit
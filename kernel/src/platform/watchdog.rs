@@ -1,5 +1,7 @@
 //! Interface for configuring a watchdog
 
+use crate::process::ProcessId;
+
 /// A trait for implementing a watchdog in the kernel.
 /// This trait is called from the `kernel_loop()` code to setup
 /// and maintain the watchdog timer.
@@ -33,3 +35,38 @@ pub trait WatchDog {
 
 /// Implement default WatchDog trait for unit.
 impl WatchDog for () {}
+
+/// A trait for watchdog services that need to know which process is running.
+///
+/// The hardware watchdog only notices if the kernel loop stops. A process that
+/// never yields while it is run without a timeslice (for example by the
+/// cooperative scheduler) keeps other processes from running, but the kernel
+/// loop still runs to handle interrupts. The kernel reports to this trait when
+/// it runs a process without a timeslice and when such a process runs out of
+/// work, so that a process that does not yield can be detected. Processes run
+/// with a timeslice are preempted by the scheduler, and are not reported.
+pub trait ProcessMonitor {
+    /// The kernel is about to run `process` without a timeslice.
+    fn process_running(&self, process: ProcessId);
+
+    /// `process` stopped running because it has no work left to do.
+    fn process_yielded(&self, process: ProcessId);
+}
+
+/// A trait for capsules that can be checked for progress by a watchdog
+/// service.
+///
+/// A capsule can get stuck without the kernel hanging, for example if it waits
+/// for a callback from hardware that never comes. Processes using the capsule
+/// then wait forever as well.
+pub trait Watched {
+    /// Returns `false` if the capsule has had an operation outstanding since
+    /// the previous call and has not made progress on it. Returns `true` if it
+    /// made progress or has nothing to do.
+    fn made_progress(&self) -> bool;
+
+    /// The capsule has not made progress for too long. It should abandon the
+    /// outstanding operation, report an error to its client, and return to a
+    /// state where new operations can be started.
+    fn reset(&self);
+}
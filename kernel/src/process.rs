@@ -12,8 +12,10 @@ use crate::ipc;
 use crate::mem::{ReadOnlyAppSlice, ReadWriteAppSlice};
 use crate::platform::mpu::{self};
 use crate::sched::Kernel;
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn};
 use crate::upcall::UpcallId;
+use tock_tbf::types::CommandPermissions;
 
 /// Userspace process identifier.
 ///
@@ -158,6 +160,32 @@ impl ProcessId {
             (start, end)
         })
     }
+
+    /// Which commands in the block of 64 commands starting at `offset * 64`
+    /// this app may call on driver `driver_num`, as declared in its TBF
+    /// header. Returns `CommandPermissions::NoPermsThisDriver` if the app does
+    /// not exist.
+    pub fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        self.kernel
+            .process_map_or(CommandPermissions::NoPermsThisDriver, *self, |process| {
+                process.get_command_permissions(driver_num, offset)
+            })
+    }
+
+    /// Get the name of the app, from its TBF header. Returns `None` if the app
+    /// does not exist.
+    pub fn get_process_name(&self) -> Option<&'static str> {
+        self.kernel
+            .process_map_or(None, *self, |process| Some(process.get_process_name()))
+    }
+
+    /// Get the permissions this app has to access objects in persistent
+    /// storage. Returns `None` if the app does not exist or its TBF header
+    /// does not give it any storage permissions.
+    pub fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_storage_permissions())
+    }
 }
 
 /// This trait represents a generic process that the Tock scheduler can
@@ -176,11 +204,47 @@ pub trait Process {
     ///
     /// This will fail if the process is no longer active, and therefore cannot
     /// execute any new tasks.
+    ///
+    /// Tasks are executed in order of priority, and in the order they were
+    /// enqueued for the same priority. An IPC task has the priority of the
+    /// process that caused it, and other tasks have the priority of the index
+    /// of this process.
     fn enqueue_task(&self, task: Task) -> bool;
 
     /// Returns whether this process is ready to execute.
     fn ready(&self) -> bool;
 
+    /// Returns the priority of this process, as used by priority schedulers.
+    /// The lower the value, the higher the priority.
+    ///
+    /// This is the index of the process in the processes array, unless the
+    /// process inherited a higher priority from a client it serves, see
+    /// `inherit_priority()`.
+    fn priority(&self) -> usize;
+
+    /// Let this process run at the priority of `client`, if it is higher,
+    /// because this process serves a request of `client`. Otherwise a process
+    /// of intermediate priority could keep this process, and so `client`, from
+    /// running.
+    ///
+    /// The priority is inherited transitively, if `client` itself inherited a
+    /// priority, until `release_priority()` is called for `client` or this
+    /// process yields with no request of `client` queued. Processes with an
+    /// index greater than or equal to the number of bits in a `usize` are not
+    /// tracked and do not pass on their priority.
+    fn inherit_priority(&self, client: ProcessId);
+
+    /// Stop running at the priority of `client`, once its request is served.
+    fn release_priority(&self, client: ProcessId);
+
+    /// Update the priority of this process after the priority of `client`
+    /// changed, if this process inherited the priority of `client`.
+    fn client_priority_changed(&self, client: ProcessId);
+
+    /// Returns the set of the indices of the processes whose priority this
+    /// process inherited directly, one bit for each index.
+    fn priority_clients(&self) -> usize;
+
     /// Return if there are any Tasks (upcalls/IPC requests) enqueued
     /// for the process.
     fn has_tasks(&self) -> bool;
@@ -222,6 +286,22 @@ pub trait Process {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Move this process out of the `CredentialsUnchecked` state because its
+    /// credentials were approved. The process is queued to run its init
+    /// function as if it was just created.
+    ///
+    /// This will fail (i.e. not do anything) if the process is not waiting on
+    /// a credentials check.
+    fn mark_credentials_pass(&self);
+
+    /// Move this process from the `CredentialsUnchecked` state to the
+    /// `CredentialsFailed` state because its credentials were not approved.
+    /// The process will never run.
+    ///
+    /// This will fail (i.e. not do anything) if the process is not waiting on
+    /// a credentials check.
+    fn mark_credentials_fail(&self);
+
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
@@ -294,6 +374,15 @@ pub trait Process {
     /// The lowest address of the grant region for the process.
     fn kernel_memory_break(&self) -> *const u8;
 
+    /// The part of the process's TBF object in flash covered by its
+    /// credentials: from the start of the TBF header to the end of the
+    /// application binary.
+    fn get_tbf_binary(&self) -> &'static [u8];
+
+    /// The footer region of the process's TBF object in flash, which holds
+    /// its credentials. This is empty if the process has no footers.
+    fn get_tbf_footers(&self) -> &'static [u8];
+
     /// How many writeable flash regions defined in the TBF header for this
     /// process.
     fn number_writeable_flash_regions(&self) -> usize;
@@ -302,6 +391,16 @@ pub trait Process {
     /// writeable flash region.
     fn get_writeable_flash_region(&self, region_index: usize) -> (u32, u32);
 
+    /// Which commands in the block of 64 commands starting at `offset * 64`
+    /// this process may call on driver `driver_num`, as declared in its TBF
+    /// header. Processes without a syscall permissions section may call every
+    /// command on every driver.
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions;
+
+    /// The permissions this process has to access objects in persistent
+    /// storage, as declared in its TBF header.
+    fn get_storage_permissions(&self) -> Option<StoragePermissions>;
+
     /// Debug function to update the kernel on where the stack starts for this
     /// process. Processes are not required to call this through the memop
     /// system call, but it aids in debugging the process.
@@ -401,6 +500,41 @@ pub trait Process {
         min_region_size: usize,
     ) -> Option<mpu::Region>;
 
+    /// Give this process access to `size` bytes of memory at `start` that
+    /// belong to the process `owner`, with the user mode permissions
+    /// `permissions`.
+    ///
+    /// The MPU must be able to cover exactly this memory, so that no other
+    /// memory of `owner` becomes accessible. Returns `INVAL` if it cannot, and
+    /// `NOMEM` if this process has no MPU regions left. The access is removed
+    /// with `remove_shared_mpu_region()`, or when `owner` restarts or is
+    /// removed.
+    fn add_shared_mpu_region(
+        &self,
+        owner: ProcessId,
+        start: *const u8,
+        size: usize,
+        permissions: mpu::Permissions,
+    ) -> Result<mpu::Region, ErrorCode>;
+
+    /// Remove the access to the memory of `owner` given by
+    /// `add_shared_mpu_region()`. Returns `INVAL` if `region` was not shared
+    /// with this process by `owner`, and `FAIL` if the MPU cannot remove it.
+    fn remove_shared_mpu_region(
+        &self,
+        owner: ProcessId,
+        region: mpu::Region,
+    ) -> Result<(), ErrorCode>;
+
+    /// Handle a fault of the process that may only be an access to one of its
+    /// MPU regions that is not in the MPU hardware, because the process has
+    /// more regions than the hardware supports.
+    ///
+    /// If so, the region is swapped in and this returns `true`: the process
+    /// retries the access when it next runs, and must not be treated as
+    /// faulted. Otherwise this returns `false`.
+    fn swap_in_mpu_region(&self) -> bool;
+
     // grants
 
     /// Allocate memory from the grant region and store the reference in the
@@ -432,7 +566,9 @@ pub trait Process {
     ///
     /// If successful, return a Some() with an identifier that can be used with
     /// `enter_custom_grant()` to get access to the memory and the pointer to
-    /// the memory which must be used to initialize the memory.
+    /// the memory which must be used to initialize the memory. A process can
+    /// only have a few custom grants at the same time, so this returns `None`
+    /// if it has as many as it can have.
     fn allocate_custom_grant(
         &self,
         size: usize,
@@ -460,6 +596,41 @@ pub trait Process {
     fn enter_custom_grant(&self, identifier: ProcessCustomGrantIdentifer)
         -> Result<*mut u8, Error>;
 
+    /// Opposite of `enter_custom_grant()`. Used to signal that the custom
+    /// grant is no longer entered.
+    fn leave_custom_grant(&self, identifier: ProcessCustomGrantIdentifer);
+
+    /// Free a custom grant. Its memory is returned to the process once the
+    /// grant region is compacted, see `compact_grant_region()`.
+    ///
+    /// This returns an error if the process is inactive, if the custom grant
+    /// no longer exists, or if it is entered.
+    fn free_custom_grant(&self, identifier: ProcessCustomGrantIdentifer) -> Result<(), Error>;
+
+    /// Resize a custom grant to `size` bytes aligned to `align` bytes, keeping
+    /// its identifier.
+    ///
+    /// The custom grant may be moved to new memory, in which case its first
+    /// `size` bytes, or all of them if it shrinks, are copied there. Any memory
+    /// beyond the previous size is uninitialized. Returns the pointer to the
+    /// custom grant, or an error if the process is inactive, if the custom
+    /// grant no longer exists or is entered, or if there is not enough memory.
+    fn reallocate_custom_grant(
+        &self,
+        identifier: ProcessCustomGrantIdentifer,
+        size: usize,
+        align: usize,
+    ) -> Result<NonNull<u8>, Error>;
+
+    /// Move custom grants together, so that the memory of freed custom grants
+    /// is returned to the unallocated memory between the app break and the
+    /// kernel memory break.
+    ///
+    /// Only the custom grants below all grants are moved, and not while they
+    /// are entered, so memory freed above a grant or an entered custom grant
+    /// is not reclaimed. Returns the number of bytes reclaimed.
+    fn compact_grant_region(&self) -> usize;
+
     /// Opposite of `enter_grant()`. Used to signal that the grant is no longer
     /// entered.
     ///
@@ -516,6 +687,41 @@ pub trait Process {
     /// context, and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
 
+    // checkpoint
+
+    /// Write a snapshot of a stopped process to `buffer`: its memory
+    /// (including its grant regions) and the architecture-specific state the
+    /// kernel stores for it. The snapshot can be given to `restore()` later,
+    /// for example after the chip was powered off, to continue the process
+    /// where it stopped.
+    ///
+    /// Upcalls that are queued for the process are not part of the snapshot.
+    ///
+    /// Returns the number of bytes written. Returns `Err(ErrorCode::BUSY)` if
+    /// the process is not stopped and `Err(ErrorCode::SIZE)` if `buffer` is
+    /// too small.
+    fn checkpoint(
+        &self,
+        buffer: &mut [u8],
+        capability: &dyn capabilities::ProcessCheckpointCapability,
+    ) -> Result<usize, ErrorCode>;
+
+    /// Replace the state of the process with a snapshot from `checkpoint()`.
+    ///
+    /// The snapshot must be of the same app, loaded at the same flash and RAM
+    /// addresses. After a successful restore the process is stopped in the
+    /// state it was in when the snapshot was taken, and can be resumed with
+    /// `resume()`.
+    ///
+    /// Returns `Err(ErrorCode::INVAL)` if the snapshot is not valid for this
+    /// process, and `Err(ErrorCode::BUSY)` if the process is running or one of
+    /// its grants is in use.
+    fn restore(
+        &self,
+        buffer: &[u8],
+        capability: &dyn capabilities::ProcessCheckpointCapability,
+    ) -> Result<(), ErrorCode>;
+
     // debug
 
     /// Returns how many syscalls this app has called.
@@ -530,6 +736,15 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Add to the time this process has spent executing in userspace and the
+    /// time the kernel has spent on its behalf, both in microseconds.
+    fn debug_execution_time_add(&self, userspace_us: u32, kernel_us: u32);
+
+    /// Returns the total time in microseconds this process has spent executing
+    /// in userspace and the total time the kernel has spent on its behalf, as
+    /// `(userspace, kernel)`.
+    fn debug_execution_time_us(&self) -> (u64, u64);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -542,6 +757,40 @@ pub trait Process {
 
     /// Return the lowest recorded address of the process stack, if known.
     fn debug_stack_end(&self) -> Option<*const u8>;
+
+    /// Return the largest number of bytes the process stack has used, if
+    /// known. This requires the process to have told the kernel where its
+    /// stack starts.
+    fn debug_stack_high_water_mark(&self) -> Option<usize>;
+
+    /// Return the highest address the app break has been moved to since the
+    /// process started.
+    fn debug_app_break_high_water_mark(&self) -> *const u8;
+
+    /// Write the architecture-specific registers that describe why a process
+    /// last faulted to `out`, for example the fault status registers on
+    /// Cortex-M.
+    ///
+    /// Returns the number of bytes written, which is zero if the architecture
+    /// does not record any or the process did not fault the last time it ran
+    /// (the kernel forced the fault), or `Err(ErrorCode::SIZE)` if `out` is
+    /// too small.
+    fn debug_fault_status(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Write the architecture-specific state the kernel stores for the process,
+    /// such as its registers, to `out`. The format is the one `checkpoint()`
+    /// uses.
+    ///
+    /// Returns the number of bytes written, or `Err(ErrorCode::SIZE)` if `out`
+    /// is too small.
+    fn debug_stored_context(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Copy the top of the stack of the process, starting at the stack pointer
+    /// it had when it last stopped running, to `out`.
+    ///
+    /// Returns the number of bytes copied, which is zero if the stack pointer
+    /// is unknown or outside of the process's memory.
+    fn debug_stack_excerpt(&self, out: &mut [u8]) -> usize;
 }
 
 /// Opaque identifier for custom grants allocated dynamically from a process's
@@ -553,13 +802,14 @@ pub trait Process {
 ///
 /// We use this type rather than a direct pointer so that any attempt to access
 /// can ensure the process still exists and is valid, and that the custom grant
-/// has not been freed.
+/// has not been freed. It also lets the process move the custom grant when it
+/// compacts its grant region.
 ///
 /// The fields of this struct are private so only Process can create this
 /// identifier.
 #[derive(Copy, Clone)]
 pub struct ProcessCustomGrantIdentifer {
-    pub(crate) offset: usize,
+    pub(crate) id: usize,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -643,6 +893,15 @@ pub enum State {
     /// processes yet. It can also happen if an process is terminated and all
     /// of its state is reset as if it has not been executed yet.
     Unstarted,
+
+    /// The process was loaded by a kernel that checks credentials and the
+    /// check has not finished yet. The process cannot run until its
+    /// credentials are approved.
+    CredentialsUnchecked,
+
+    /// The credentials of the process were not approved. The process cannot
+    /// run.
+    CredentialsFailed,
 }
 
 /// A wrapper around `Cell<State>` is used by `Process` to prevent bugs arising from
@@ -0,0 +1,227 @@
+//! Checking the credentials of processes before they are allowed to run.
+//!
+//! A board that wants to require that apps be signed (or otherwise carry some
+//! credential) provides an implementation of `AppCredentialsChecker` and loads
+//! processes with `load_and_check_processes()` instead of `load_processes()`.
+//! Processes loaded this way start in the `CredentialsUnchecked` state and are
+//! not scheduled. Once the kernel loop starts, the `ProcessCheckerMachine`
+//! walks the credentials footers of each process and asks the checker whether
+//! to accept the process. Processes that are accepted are moved to the
+//! `Unstarted` state and run normally, processes that are rejected are left in
+//! the `CredentialsFailed` state and never run.
+//!
+//! Checking credentials is generally asynchronous (for example it uses a
+//! hardware hash engine through the `hil::digest` traits), which is why it
+//! happens after the processes are created rather than inside of the loader.
+
+use core::cell::Cell;
+
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::process::{Process, State};
+use crate::sched::Kernel;
+
+pub use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+/// What a checker decided about one credential of a process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The credential is valid and the process may run.
+    Accept,
+    /// The checker has no opinion about this credential. The next credential
+    /// in the footer (if any) is checked.
+    Pass,
+    /// The credential is not valid and the process must not run.
+    Reject,
+}
+
+/// Receives the result of a credentials check.
+pub trait Client<'a> {
+    /// Called when the check started with `check_credentials()` finishes.
+    /// `credentials` and `binary` are the values passed to
+    /// `check_credentials()`.
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        credentials: TbfFooterV2Credentials<'a>,
+        binary: &'a [u8],
+    );
+}
+
+/// Implemented by a board-provided policy which decides whether a process is
+/// allowed to run based on the credentials in its TBF footer.
+pub trait AppCredentialsChecker<'a> {
+    fn set_client(&self, client: &'a dyn Client<'a>);
+
+    /// Whether a process must carry a credential that this checker accepts
+    /// to run. If this is `false`, processes without credentials (or with
+    /// credentials this checker passes on) are allowed to run.
+    fn require_credentials(&self) -> bool;
+
+    /// Start checking `credentials` for a process whose TBF object (from the
+    /// start of the header to the end of the application binary) is
+    /// `binary`. The result is returned through `Client::check_done()`.
+    ///
+    /// Returns `Err(ErrorCode::NOSUPPORT)` if the checker does not handle this
+    /// credentials format, in which case the credential is treated as if the
+    /// checker returned `CheckResult::Pass`. Any other error rejects the
+    /// process.
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials<'a>,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials<'a>, &'a [u8])>;
+}
+
+/// Check whether the footers of a TBF object contain any credential that is
+/// not just reserved space.
+pub(crate) fn footers_contain_credentials(footers: &[u8]) -> bool {
+    tock_tbf::parse::parse_tbf_footers(footers).any(|footer| match footer {
+        Ok(credentials) => credentials.format() != TbfFooterV2CredentialsType::Reserved,
+        Err(_) => false,
+    })
+}
+
+/// Walks every process waiting on a credentials check and checks its
+/// credentials one at a time with the board's `AppCredentialsChecker`.
+pub struct ProcessCheckerMachine {
+    kernel: &'static Kernel,
+    pub(crate) checker: &'static dyn AppCredentialsChecker<'static>,
+    /// Index in the processes array of the process being checked.
+    process: Cell<usize>,
+    /// Index of the next footer of the process being checked.
+    footer: Cell<usize>,
+    /// Whether the machine is walking the processes array.
+    checking: Cell<bool>,
+    /// Whether a process was added while walking the processes array, in
+    /// which case the walk starts over once it reaches the end.
+    rescan: Cell<bool>,
+}
+
+impl ProcessCheckerMachine {
+    pub fn new(
+        kernel: &'static Kernel,
+        checker: &'static dyn AppCredentialsChecker<'static>,
+    ) -> ProcessCheckerMachine {
+        ProcessCheckerMachine {
+            kernel,
+            checker,
+            process: Cell::new(0),
+            footer: Cell::new(0),
+            checking: Cell::new(false),
+            rescan: Cell::new(false),
+        }
+    }
+
+    /// Start checking the credentials of all processes that are waiting on
+    /// a check. If a check is in progress, the processes are walked again
+    /// once it finishes, so that processes created in the meantime (for
+    /// example by the dynamic loader) are checked too.
+    pub(crate) fn start(&self) {
+        if self.checking.get() {
+            self.rescan.set(true);
+            return;
+        }
+        self.checking.set(true);
+        self.process.set(0);
+        self.footer.set(0);
+        self.next();
+    }
+
+    /// Move on to the next process in the processes array.
+    fn advance(&self) {
+        self.process.set(self.process.get() + 1);
+        self.footer.set(0);
+    }
+
+    /// Finish the check of the current process.
+    fn finish(&self, process: &dyn Process, approved: bool) {
+        if config::CONFIG.debug_load_processes {
+            debug!(
+                "Process {} credentials {}",
+                process.get_process_name(),
+                if approved { "approved" } else { "failed" }
+            );
+        }
+        if approved {
+            process.mark_credentials_pass();
+        } else {
+            process.mark_credentials_fail();
+        }
+        self.advance();
+    }
+
+    /// Start the next credentials check. This returns once a check is
+    /// in progress or every process has been checked.
+    fn next(&self) {
+        loop {
+            if self.process.get() >= self.kernel.number_of_process_slots() {
+                if !self.rescan.take() {
+                    self.checking.set(false);
+                    return;
+                }
+                self.process.set(0);
+                self.footer.set(0);
+            }
+
+            let process = match self.kernel.get_process_at_index(self.process.get()) {
+                Some(p) if p.get_state() == State::CredentialsUnchecked => p,
+                _ => {
+                    self.advance();
+                    continue;
+                }
+            };
+
+            let footer = tock_tbf::parse::parse_tbf_footers(process.get_tbf_footers())
+                .nth(self.footer.get());
+            self.footer.set(self.footer.get() + 1);
+            match footer {
+                Some(Ok(credentials)) => {
+                    if credentials.format() == TbfFooterV2CredentialsType::Reserved {
+                        continue;
+                    }
+                    match self
+                        .checker
+                        .check_credentials(credentials, process.get_tbf_binary())
+                    {
+                        Ok(()) => return,
+                        Err((ErrorCode::NOSUPPORT, _, _)) => continue,
+                        Err(_) => self.finish(process, false),
+                    }
+                }
+                Some(Err(_)) => {
+                    // A malformed footer means the credentials cannot be
+                    // trusted.
+                    self.finish(process, false);
+                }
+                None => {
+                    // No more credentials to check. Whether the process may
+                    // run depends on if the checker requires credentials.
+                    self.finish(process, !self.checker.require_credentials());
+                }
+            }
+        }
+    }
+}
+
+impl Client<'static> for ProcessCheckerMachine {
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        _credentials: TbfFooterV2Credentials<'static>,
+        _binary: &'static [u8],
+    ) {
+        if let Some(process) = self.kernel.get_process_at_index(self.process.get()) {
+            match result {
+                Ok(CheckResult::Accept) => self.finish(process, true),
+                Ok(CheckResult::Pass) => {}
+                Ok(CheckResult::Reject) | Err(_) => self.finish(process, false),
+            }
+        } else {
+            // The process went away while it was being checked.
+            self.advance();
+        }
+        self.next();
+    }
+}
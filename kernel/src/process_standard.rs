@@ -6,9 +6,11 @@
 use core::cell::Cell;
 use core::cmp;
 use core::fmt::Write;
+use core::ops::Range;
 use core::ptr::{write_volatile, NonNull};
 use core::{mem, ptr, slice, str};
 
+use crate::capabilities;
 use crate::common::cells::{MapCell, NumericCellExt};
 use crate::common::{Queue, RingBuffer};
 use crate::config;
@@ -19,15 +21,65 @@ use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
 use crate::process::{FaultAction, ProcessCustomGrantIdentifer, ProcessId, ProcessStateCell};
+use crate::process_checker::{self, AppCredentialsChecker};
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_utilities::ProcessLoadError;
 use crate::sched::Kernel;
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
 use crate::upcall::UpcallId;
+use tock_tbf::types::CommandPermissions;
 
 // The completion code for a process if it faulted.
 const COMPLETION_FAULT: u32 = 0xffffffff;
 
+// Process snapshots written by `checkpoint()` start with a header of 32-bit
+// little-endian words: magic, version, flash start, memory start, memory
+// length, app break, kernel memory break, allow high water mark (as offsets
+// from the memory start), stopped state, the length of the stored context, and
+// the number of grants, the identifier of the next custom grant, and the number
+// of custom grants. The stored context follows, then the memory below the app
+// break, then the grant allocations between the kernel memory break and the
+// process struct, then the grant pointers as offsets from the memory start
+// (zero for grants that are not allocated), then the identifier, offset from
+// the memory start, length, and alignment of each custom grant.
+//
+// The process struct and its upcall queue are not part of the snapshot, as
+// they hold pointers into the kernel.
+const CHECKPOINT_MAGIC: u32 = 0x5043_4b54; // "TKCP"
+const CHECKPOINT_VERSION: u32 = 1;
+const CHECKPOINT_HEADER_WORDS: usize = 13;
+const CHECKPOINT_CUSTOM_GRANT_WORDS: usize = 4;
+const CHECKPOINT_STOPPED_RUNNING: u32 = 0;
+const CHECKPOINT_STOPPED_YIELDED: u32 = 1;
+
+// Pattern process memory is painted with when `paint_process_stack` is
+// enabled, and the number of consecutive painted words that mark the end of
+// the used part of the stack.
+const STACK_PAINT: u32 = 0xA5A5_A5A5;
+const STACK_PAINT_END_WORDS: usize = 4;
+
+/// Number of custom grants a process can have at the same time.
+const NUM_CUSTOM_GRANTS: usize = 8;
+
+/// A custom grant in the table of custom grants of a process.
+///
+/// Custom grants are kept in a table rather than found through their address,
+/// so that they can be freed, and moved when the grant region is compacted.
+#[derive(Copy, Clone)]
+struct CustomGrantEntry {
+    /// Identifier the capsule uses to find the custom grant.
+    id: usize,
+    /// Address of the custom grant in the grant region.
+    address: usize,
+    /// Number of bytes allocated.
+    len: usize,
+    /// Alignment of the allocation.
+    align: usize,
+    /// Whether the custom grant is entered, in which case it cannot be moved.
+    entered: bool,
+}
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -54,6 +106,13 @@ struct ProcessStandardDebug {
     /// How low have we ever seen the stack pointer.
     app_stack_min_pointer: Option<*const u8>,
 
+    /// The stack pointer when the process last stopped running.
+    app_stack_pointer: Option<*const u8>,
+
+    /// Whether the process faulted the last time it ran, in which case the
+    /// fault status the architecture recorded is its own.
+    faulted_running: bool,
+
     /// How many syscalls have occurred since the process started.
     syscall_count: usize,
 
@@ -67,6 +126,16 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// Total time this process has executed in userspace, in microseconds.
+    userspace_time_us: u64,
+
+    /// Total time the kernel has spent on behalf of this process, such as
+    /// handling its syscalls and switching to it, in microseconds.
+    kernel_time_us: u64,
+
+    /// The highest address the app break has been moved to.
+    app_break_max_pointer: *const u8,
 }
 
 /// A type for userspace processes in Tock.
@@ -131,6 +200,16 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
+    /// Pointer to the start of the process control block, above which the
+    /// grant region starts. Grants are allocated down from here.
+    grant_region_top: Cell<*const u8>,
+
+    /// Identifier given to the next custom grant.
+    next_custom_grant_id: Cell<usize>,
+
+    /// Custom grants allocated in the grant region.
+    custom_grants: [Cell<Option<CustomGrantEntry>>; NUM_CUSTOM_GRANTS],
+
     /// Pointer to the end of process RAM that has been sbrk'd to the process.
     app_break: Cell<*const u8>,
 
@@ -169,10 +248,22 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// MPU regions are saved as a pointer-size pair.
     mpu_regions: [Cell<Option<mpu::Region>>; 6],
 
+    /// MPU regions that cover memory of other processes, together with the
+    /// process that owns the memory.
+    shared_mpu_regions: [Cell<Option<(ProcessId, mpu::Region)>>; 6],
+
     /// Essentially a list of upcalls that want to call functions in the
     /// process.
     tasks: MapCell<RingBuffer<'a, Task>>,
 
+    /// Set of the indices of the processes whose priority this process
+    /// inherited, one bit for each index.
+    priority_clients: Cell<usize>,
+
+    /// Priority of this process, including the priorities it inherited from
+    /// its clients. Kept up to date by `update_priority()`.
+    effective_priority: Cell<usize>,
+
     /// Count of how many times this process has entered the fault condition and
     /// been restarted. This is used by some `ProcessRestartPolicy`s to
     /// determine if the process should be restarted or not.
@@ -197,7 +288,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return false;
         }
 
-        let ret = self.tasks.map_or(false, |tasks| tasks.enqueue(task));
+        let priority = self.task_priority(&task);
+        let ret = self.tasks.map_or(false, |tasks| {
+            // Keep tasks sorted by priority, and in order for equal
+            // priorities.
+            tasks.insert_before(task, |queued| self.task_priority(queued) > priority)
+        });
 
         // Make a note that we lost this upcall if the enqueue function
         // fails.
@@ -217,6 +313,32 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             || self.state.get() == State::Running
     }
 
+    fn priority(&self) -> usize {
+        self.effective_priority.get()
+    }
+
+    fn inherit_priority(&self, client: ProcessId) {
+        self.priority_clients
+            .set(self.priority_clients.get() | Self::priority_bit(client));
+        self.update_priority();
+    }
+
+    fn release_priority(&self, client: ProcessId) {
+        self.priority_clients
+            .set(self.priority_clients.get() & !Self::priority_bit(client));
+        self.update_priority();
+    }
+
+    fn client_priority_changed(&self, client: ProcessId) {
+        if self.priority_clients.get() & Self::priority_bit(client) != 0 {
+            self.update_priority();
+        }
+    }
+
+    fn priority_clients(&self) -> usize {
+        self.priority_clients.get()
+    }
+
     fn remove_pending_upcalls(&self, upcall_id: UpcallId) {
         self.tasks.map(|tasks| {
             let count_before = tasks.len();
@@ -256,6 +378,23 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     fn set_yielded_state(&self) {
         if self.state.get() == State::Running {
             self.state.update(State::Yielded);
+
+            // A process that waits is done with the requests it received, so
+            // it only keeps the priority of the clients whose requests are
+            // still queued. This bounds how long a process can run at the
+            // priority of a client that it never notifies.
+            let mut queued = 0;
+            self.tasks.map(|tasks| {
+                let (left, right) = tasks.as_slices();
+                for task in left.into_iter().chain(right).flatten() {
+                    if let Task::IPC((from, _)) = task {
+                        queued |= Self::priority_bit(*from);
+                    }
+                }
+            });
+            self.priority_clients
+                .set(self.priority_clients.get() & queued);
+            self.update_priority();
         }
     }
 
@@ -276,6 +415,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn set_fault_state(&self) {
+        // A process whose credentials were not approved never ran, and must
+        // not be restarted by the fault policy.
+        if !self.credentials_approved() {
+            return;
+        }
+
         // Use the per-process fault policy to determine what action the kernel
         // should take since the process faulted.
         let action = self.fault_policy.action(self);
@@ -302,6 +447,11 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn try_restart(&self, completion_code: u32) {
+        // Only processes with approved credentials may be (re)started.
+        if !self.credentials_approved() {
+            return;
+        }
+
         // Terminate the process, freeing its state and removing any
         // pending tasks from the scheduler's queue.
         self.terminate(completion_code);
@@ -315,6 +465,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn terminate(&self, _completion_code: u32) {
+        // A process whose credentials were not approved has nothing to clean
+        // up, and must stay in its state so it is never restarted.
+        if !self.credentials_approved() {
+            return;
+        }
+
         // Remove the tasks that were scheduled for the app from the
         // amount of work queue.
         let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
@@ -327,6 +483,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             tasks.empty();
         });
 
+        // The requests of clients are dropped with the tasks.
+        self.priority_clients.set(0);
+        self.update_priority();
+
         // Clear any grant regions this app has setup with any capsules.
         unsafe {
             self.grant_ptrs_reset();
@@ -336,6 +496,19 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.state.update(State::Terminated);
     }
 
+    fn mark_credentials_pass(&self) {
+        if self.state.get() == State::CredentialsUnchecked {
+            self.enqueue_init_task();
+            self.state.update(State::Unstarted);
+        }
+    }
+
+    fn mark_credentials_fail(&self) {
+        if self.state.get() == State::CredentialsUnchecked {
+            self.state.update(State::CredentialsFailed);
+        }
+    }
+
     fn get_restart_count(&self) -> usize {
         self.restart_count.get()
     }
@@ -377,6 +550,18 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.kernel_memory_break.get()
     }
 
+    fn get_tbf_binary(&self) -> &'static [u8] {
+        self.flash
+            .get(0..self.header.get_binary_end() as usize)
+            .unwrap_or(self.flash)
+    }
+
+    fn get_tbf_footers(&self) -> &'static [u8] {
+        self.flash
+            .get(self.header.get_binary_end() as usize..)
+            .unwrap_or(&[])
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -385,6 +570,16 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.header.get_writeable_flash_region(region_index)
     }
 
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        self.header.get_command_permissions(driver_num, offset)
+    }
+
+    fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        self.header
+            .get_storage_permissions()
+            .map(StoragePermissions::new)
+    }
+
     fn update_stack_start_pointer(&self, stack_pointer: *const u8) {
         if stack_pointer >= self.mem_start() && stack_pointer < self.mem_end() {
             self.debug.map(|debug| {
@@ -410,11 +605,42 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn setup_mpu(&self) {
+        // Memory of a process that restarted or was removed is no longer
+        // shared with this process.
+        for shared in self.shared_mpu_regions.iter() {
+            if let Some((owner, region)) = shared.get() {
+                if !self.kernel.processid_is_valid(&owner) {
+                    self.mpu_config.map(|config| {
+                        let _ = self.chip.mpu().remove_memory_region(region, config);
+                    });
+                    shared.set(None);
+                }
+            }
+        }
+
         self.mpu_config.map(|config| {
             self.chip.mpu().configure_mpu(&config, &self.processid());
         });
     }
 
+    fn swap_in_mpu_region(&self) -> bool {
+        let fault_address = self.stored_state.map_or(None, |stored_state| unsafe {
+            // Safety: the process memory between `mem_start()` and the app
+            // break is valid.
+            self.chip.userspace_kernel_boundary().memory_fault_address(
+                self.mem_start(),
+                self.app_break.get(),
+                stored_state,
+            )
+        });
+
+        fault_address.map_or(false, |address| {
+            self.mpu_config.map_or(false, |config| {
+                self.chip.mpu().swap_in_region(address, config).is_ok()
+            })
+        })
+    }
+
     fn add_mpu_region(
         &self,
         unallocated_memory_start: *const u8,
@@ -446,6 +672,66 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         })
     }
 
+    fn add_shared_mpu_region(
+        &self,
+        owner: ProcessId,
+        start: *const u8,
+        size: usize,
+        permissions: mpu::Permissions,
+    ) -> Result<mpu::Region, ErrorCode> {
+        if !self.is_active() {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let slot = self
+            .shared_mpu_regions
+            .iter()
+            .find(|shared| shared.get().is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+
+        self.mpu_config.map_or(Err(ErrorCode::FAIL), |config| {
+            let region = self
+                .chip
+                .mpu()
+                .allocate_region(start, size, size, permissions, config)
+                .ok_or(ErrorCode::NOMEM)?;
+            if region.start_address() != start || region.size() != size {
+                // The MPU would have to give access to more memory of the
+                // owner than was shared.
+                let _ = self.chip.mpu().remove_memory_region(region, config);
+                return Err(ErrorCode::INVAL);
+            }
+            slot.set(Some((owner, region)));
+            Ok(region)
+        })
+    }
+
+    fn remove_shared_mpu_region(
+        &self,
+        owner: ProcessId,
+        region: mpu::Region,
+    ) -> Result<(), ErrorCode> {
+        let slot = self
+            .shared_mpu_regions
+            .iter()
+            .find(|shared| {
+                shared.get().map_or(false, |(shared_owner, shared_region)| {
+                    shared_owner == owner
+                        && shared_region.start_address() == region.start_address()
+                        && shared_region.size() == region.size()
+                })
+            })
+            .ok_or(ErrorCode::INVAL)?;
+
+        self.mpu_config
+            .map_or(Err(()), |config| {
+                self.chip.mpu().remove_memory_region(region, config)
+            })
+            .map_err(|()| ErrorCode::FAIL)?;
+        slot.set(None);
+        Ok(())
+    }
+
     fn sbrk(&self, increment: isize) -> Result<*const u8, Error> {
         // Do not modify an inactive process.
         if !self.is_active() {
@@ -478,6 +764,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 } else {
                     let old_break = self.app_break.get();
                     self.app_break.set(new_break);
+                    self.update_app_break_high_water_mark();
                     self.chip.mpu().configure_mpu(&config, &self.processid());
                     Ok(old_break)
                 }
@@ -707,13 +994,25 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return None;
         }
 
+        // The custom grant needs an entry in the table of custom grants.
+        let slot = self
+            .custom_grants
+            .iter()
+            .find(|custom_grant| custom_grant.get().is_none())?;
+
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
         if let Some(ptr) = self.allocate_in_grant_region_internal(size, align) {
             // Create the identifier that the caller will use to get access to
             // this custom grant in the future.
-            let identifier = self.create_custom_grant_identifier(ptr);
-
+            let identifier = self.create_custom_grant_identifier();
+            slot.set(Some(CustomGrantEntry {
+                id: identifier.id,
+                address: ptr.as_ptr() as usize,
+                len: size,
+                align: cmp::max(align, 2),
+                entered: false,
+            }));
             Some((identifier, ptr))
         } else {
             // Could not allocate memory for the custom grant.
@@ -770,12 +1069,163 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return Err(Error::InactiveApp);
         }
 
-        // Get the address of the custom grant based on the identifier.
-        let custom_grant_address = self.get_custom_grant_address(identifier);
+        // Find the custom grant based on the identifier. It is not found if
+        // it was freed.
+        let slot = self
+            .find_custom_grant(identifier)
+            .ok_or(Error::AddressOutOfBounds)?;
+        let mut custom_grant = slot.get().ok_or(Error::AddressOutOfBounds)?;
+        if custom_grant.entered {
+            return Err(Error::AlreadyInUse);
+        }
+
+        // Mark the custom grant entered so that it is not moved while the
+        // capsule accesses it.
+        custom_grant.entered = true;
+        slot.set(Some(custom_grant));
+        Ok(custom_grant.address as *mut u8)
+    }
+
+    fn leave_custom_grant(&self, identifier: ProcessCustomGrantIdentifer) {
+        if let Some(slot) = self.find_custom_grant(identifier) {
+            slot.set(slot.get().map(|custom_grant| CustomGrantEntry {
+                entered: false,
+                ..custom_grant
+            }));
+        }
+    }
+
+    fn free_custom_grant(&self, identifier: ProcessCustomGrantIdentifer) -> Result<(), Error> {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return Err(Error::InactiveApp);
+        }
+
+        let slot = self
+            .find_custom_grant(identifier)
+            .ok_or(Error::AddressOutOfBounds)?;
+        if slot
+            .get()
+            .map_or(false, |custom_grant| custom_grant.entered)
+        {
+            return Err(Error::AlreadyInUse);
+        }
+        slot.set(None);
+        Ok(())
+    }
+
+    fn reallocate_custom_grant(
+        &self,
+        identifier: ProcessCustomGrantIdentifer,
+        size: usize,
+        align: usize,
+    ) -> Result<NonNull<u8>, Error> {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return Err(Error::InactiveApp);
+        }
+
+        let slot = self
+            .find_custom_grant(identifier)
+            .ok_or(Error::AddressOutOfBounds)?;
+        let custom_grant = slot.get().ok_or(Error::AddressOutOfBounds)?;
+        if custom_grant.entered {
+            return Err(Error::AlreadyInUse);
+        }
 
-        // We never deallocate custom grants and only we can change the
-        // `identifier` so we know this is a valid address for the custom grant.
-        Ok(custom_grant_address as *mut u8)
+        // A custom grant that shrinks stays where it is.
+        if size <= custom_grant.len && align <= custom_grant.align {
+            slot.set(Some(CustomGrantEntry {
+                len: size,
+                ..custom_grant
+            }));
+            // ### Safety
+            //
+            // The custom grant is in process memory, so it is not null.
+            return Ok(unsafe { NonNull::new_unchecked(custom_grant.address as *mut u8) });
+        }
+
+        let new_ptr = self
+            .allocate_in_grant_region_internal(size, align)
+            .ok_or(Error::OutOfMemory)?;
+
+        // Allocating may have compacted the grant region and moved the custom
+        // grant, so its entry is read again.
+        let custom_grant = slot.get().ok_or(Error::KernelError)?;
+
+        // ### Safety
+        //
+        // Both allocations are in the grant region, and the new one is below
+        // the kernel memory break the current one was above.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                custom_grant.address as *const u8,
+                new_ptr.as_ptr(),
+                cmp::min(custom_grant.len, size),
+            );
+        }
+        slot.set(Some(CustomGrantEntry {
+            address: new_ptr.as_ptr() as usize,
+            len: size,
+            align: cmp::max(align, 2),
+            ..custom_grant
+        }));
+        Ok(new_ptr)
+    }
+
+    fn compact_grant_region(&self) -> usize {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return 0;
+        }
+
+        // Grants are not moved, as their size is not recorded. Only the custom
+        // grants below all grants can be moved up, into the memory of freed
+        // custom grants.
+        let bottom = self.kernel_memory_break.get() as usize;
+        let lowest_grant = self.grant_pointers.map_or(bottom, |grant_pointers| {
+            grant_pointers
+                .iter()
+                // The lowest bit marks entered grants.
+                .map(|grant_ptr| *grant_ptr as usize & !0x1)
+                .filter(|address| *address != 0)
+                .fold(self.grant_region_top.get() as usize, cmp::min)
+        });
+
+        let mut table = [None; NUM_CUSTOM_GRANTS];
+        for (entry, slot) in table.iter_mut().zip(self.custom_grants.iter()) {
+            *entry = slot.get();
+        }
+        let dest = compact_custom_grants(&mut table, lowest_grant, |custom_grant, address| {
+            // ### Safety
+            //
+            // Both locations are in the grant region, below all grants and
+            // the custom grants already moved.
+            unsafe {
+                ptr::copy(
+                    custom_grant.address as *const u8,
+                    address as *mut u8,
+                    custom_grant.len,
+                );
+            }
+        });
+        for (slot, entry) in self.custom_grants.iter().zip(table.iter()) {
+            slot.set(*entry);
+        }
+
+        if dest > bottom {
+            self.kernel_memory_break.set(dest as *const u8);
+            // Giving memory back to the app memory region cannot fail.
+            self.mpu_config.map(|config| {
+                let _ = self.chip.mpu().update_app_memory_region(
+                    self.app_break.get(),
+                    dest as *const u8,
+                    mpu::Permissions::ReadWriteOnly,
+                    config,
+                );
+            });
+        }
+        dest - bottom
     }
 
     fn leave_grant(&self, grant_num: usize) {
@@ -926,10 +1376,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 }
             });
 
+        self.debug.map(|debug| {
+            debug.faulted_running = switch_reason == Some(syscall::ContextSwitchReason::Fault);
+        });
+
         // If the UKB implementation passed us a stack pointer, update our
         // debugging state. This is completely optional.
         stack_pointer.map(|sp| {
             self.debug.map(|debug| {
+                debug.app_stack_pointer = Some(sp);
                 match debug.app_stack_min_pointer {
                     None => debug.app_stack_min_pointer = Some(sp),
                     Some(asmp) => {
@@ -963,6 +1418,19 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_execution_time_add(&self, userspace_us: u32, kernel_us: u32) {
+        self.debug.map(|debug| {
+            debug.userspace_time_us += userspace_us as u64;
+            debug.kernel_time_us += kernel_us as u64;
+        });
+    }
+
+    fn debug_execution_time_us(&self) -> (u64, u64) {
+        self.debug.map_or((0, 0), |debug| {
+            (debug.userspace_time_us, debug.kernel_time_us)
+        })
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -981,8 +1449,69 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn debug_stack_end(&self) -> Option<*const u8> {
+        let observed = self
+            .debug
+            .map_or(None, |debug| debug.app_stack_min_pointer.map(|p| p));
+        let painted = if config::CONFIG.paint_process_stack {
+            self.debug_stack_start()
+                .map(|stack_start| self.lowest_unpainted_address(stack_start))
+        } else {
+            None
+        };
+        match (observed, painted) {
+            (Some(observed), Some(painted)) => Some(cmp::min(observed, painted)),
+            (observed, painted) => observed.or(painted),
+        }
+    }
+
+    fn debug_stack_high_water_mark(&self) -> Option<usize> {
+        self.debug_stack_start()
+            .and_then(|start| self.debug_stack_end().map(|end| (start, end)))
+            .map(|(start, end)| (start as usize).saturating_sub(end as usize))
+    }
+
+    fn debug_app_break_high_water_mark(&self) -> *const u8 {
         self.debug
-            .map_or(None, |debug| debug.app_stack_min_pointer.map(|p| p))
+            .map_or(self.app_break.get(), |debug| debug.app_break_max_pointer)
+    }
+
+    fn debug_fault_status(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        // A fault the kernel forced, e.g. because of an invalid syscall, has
+        // no fault status. The one the architecture recorded last belongs to
+        // an earlier fault.
+        if !self.debug.map_or(false, |debug| debug.faulted_running) {
+            return Ok(0);
+        }
+        self.chip
+            .userspace_kernel_boundary()
+            .store_fault_status(out)
+    }
+
+    fn debug_stored_context(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.stored_state
+            .map_or(Err(ErrorCode::FAIL), |stored_state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .store_context(stored_state, out)
+            })
+    }
+
+    fn debug_stack_excerpt(&self, out: &mut [u8]) -> usize {
+        let stack_pointer = match self.debug.map_or(None, |debug| debug.app_stack_pointer) {
+            Some(stack_pointer) => stack_pointer as usize,
+            None => return 0,
+        };
+        let memory_start = self.mem_start() as usize;
+        let app_break = self.app_break.get() as usize;
+        if stack_pointer < memory_start || stack_pointer >= app_break {
+            return 0;
+        }
+        let length = cmp::min(out.len(), app_break - stack_pointer);
+        // Safety: the range is within the memory of the process, below its
+        // app break, and the process is not running.
+        let stack = unsafe { slice::from_raw_parts(stack_pointer as *const u8, length) };
+        out[..length].copy_from_slice(stack);
+        length
     }
 
     fn print_memory_map(&self, writer: &mut dyn Write) {
@@ -1003,9 +1532,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         let sram_stack_start: Option<usize> = self.debug.map_or(None, |debug| {
             debug.app_stack_start_pointer.map(|p| p as usize)
         });
-        let sram_stack_bottom: Option<usize> = self.debug.map_or(None, |debug| {
-            debug.app_stack_min_pointer.map(|p| p as usize)
-        });
+        let sram_stack_bottom: Option<usize> = self.debug_stack_end().map(|p| p as usize);
         let sram_start = self.mem_start() as usize;
 
         // SRAM sizes
@@ -1233,6 +1760,433 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             }
         });
     }
+
+    fn checkpoint(
+        &self,
+        buffer: &mut [u8],
+        _capability: &dyn capabilities::ProcessCheckpointCapability,
+    ) -> Result<usize, ErrorCode> {
+        let stopped_state = match self.state.get() {
+            State::StoppedRunning => CHECKPOINT_STOPPED_RUNNING,
+            State::StoppedYielded => CHECKPOINT_STOPPED_YIELDED,
+            _ => return Err(ErrorCode::BUSY),
+        };
+
+        let memory_start = self.mem_start() as usize;
+        let app_break = self.app_break.get() as usize - memory_start;
+        let kernel_memory_break = self.kernel_memory_break.get() as usize - memory_start;
+        let grant_region_top = self.grant_region_top.get() as usize - memory_start;
+        let allow_high_water_mark = self.allow_high_water_mark.get() as usize - memory_start;
+        let grant_count = self
+            .grant_pointers
+            .map_or(0, |grant_pointers| grant_pointers.len());
+        let custom_grant_count = self
+            .custom_grants
+            .iter()
+            .filter(|custom_grant| custom_grant.get().is_some())
+            .count();
+        let tables_len = grant_count * 4 + custom_grant_count * CHECKPOINT_CUSTOM_GRANT_WORDS * 4;
+
+        let header_len = CHECKPOINT_HEADER_WORDS * 4;
+        if buffer.len() < header_len {
+            return Err(ErrorCode::SIZE);
+        }
+        let (header, body) = buffer.split_at_mut(header_len);
+
+        let context_len = self
+            .stored_state
+            .map_or(Err(ErrorCode::FAIL), |stored_state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .store_context(stored_state, body)
+            })?;
+
+        // Only the memory the process and its grants are using is saved: the
+        // memory below the app break, and the grant allocations.
+        let grant_len = grant_region_top - kernel_memory_break;
+        let memory_len = app_break + grant_len;
+        let memory_image = body
+            .get_mut(context_len..context_len + memory_len + tables_len)
+            .ok_or(ErrorCode::SIZE)?;
+        let (memory_image, grant_offsets) = memory_image.split_at_mut(memory_len);
+        let (grant_offsets, custom_grants) = grant_offsets.split_at_mut(grant_count * 4);
+        // Safety: the process is stopped so its memory is not changing, and
+        // we only read the memory below the process struct, which belongs to
+        // the process and its grants.
+        let memory = unsafe { slice::from_raw_parts(self.memory_start, grant_region_top) };
+        memory_image[..app_break].copy_from_slice(&memory[..app_break]);
+        memory_image[app_break..].copy_from_slice(&memory[kernel_memory_break..]);
+
+        self.grant_pointers.map(|grant_pointers| {
+            for (chunk, grant_ptr) in grant_offsets.chunks_exact_mut(4).zip(grant_pointers.iter()) {
+                // The lowest bit marks entered grants.
+                let address = *grant_ptr as usize & !0x1;
+                let offset = if address == 0 {
+                    0
+                } else {
+                    address - memory_start
+                };
+                chunk.copy_from_slice(&(offset as u32).to_le_bytes());
+            }
+        });
+
+        let entries = self
+            .custom_grants
+            .iter()
+            .filter_map(|custom_grant| custom_grant.get());
+        for (entry, custom_grant) in custom_grants
+            .chunks_exact_mut(CHECKPOINT_CUSTOM_GRANT_WORDS * 4)
+            .zip(entries)
+        {
+            let words = [
+                custom_grant.id as u32,
+                (custom_grant.address - memory_start) as u32,
+                custom_grant.len as u32,
+                custom_grant.align as u32,
+            ];
+            for (chunk, word) in entry.chunks_exact_mut(4).zip(words.iter()) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        let words = [
+            CHECKPOINT_MAGIC,
+            CHECKPOINT_VERSION,
+            self.flash_start() as u32,
+            memory_start as u32,
+            self.memory_len as u32,
+            app_break as u32,
+            kernel_memory_break as u32,
+            allow_high_water_mark as u32,
+            stopped_state,
+            context_len as u32,
+            grant_count as u32,
+            self.next_custom_grant_id.get() as u32,
+            custom_grant_count as u32,
+        ];
+        for (chunk, word) in header.chunks_exact_mut(4).zip(words.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(header_len + context_len + memory_len + tables_len)
+    }
+
+    fn restore(
+        &self,
+        buffer: &[u8],
+        _capability: &dyn capabilities::ProcessCheckpointCapability,
+    ) -> Result<(), ErrorCode> {
+        // A process that is scheduled to run cannot have its state replaced,
+        // and a snapshot must not let a process skip its credentials check.
+        match self.state.get() {
+            State::Running | State::Yielded => return Err(ErrorCode::BUSY),
+            State::CredentialsUnchecked | State::CredentialsFailed => return Err(ErrorCode::INVAL),
+            _ => {}
+        }
+
+        let header_len = CHECKPOINT_HEADER_WORDS * 4;
+        let mut words = [0u32; CHECKPOINT_HEADER_WORDS];
+        for (word, chunk) in words.iter_mut().zip(
+            buffer
+                .get(..header_len)
+                .ok_or(ErrorCode::INVAL)?
+                .chunks_exact(4),
+        ) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        let (magic, version, flash_start, memory_start, memory_len) =
+            (words[0], words[1], words[2], words[3], words[4]);
+        let app_break = words[5] as usize;
+        let kernel_memory_break = words[6] as usize;
+        let allow_high_water_mark = words[7] as usize;
+        let stopped_state = words[8];
+        let context_len = words[9] as usize;
+        let grant_count = words[10] as usize;
+        let next_custom_grant_id = words[11] as usize;
+        let custom_grant_count = words[12] as usize;
+        let grant_region_top = self.grant_region_top.get() as usize - self.mem_start() as usize;
+
+        // The snapshot must be of this app at the same addresses, as the
+        // process memory contains absolute pointers.
+        if magic != CHECKPOINT_MAGIC
+            || version != CHECKPOINT_VERSION
+            || flash_start as usize != self.flash_start() as usize
+            || memory_start as usize != self.mem_start() as usize
+            || memory_len as usize != self.memory_len
+            || allow_high_water_mark > app_break
+            || app_break > kernel_memory_break
+            || kernel_memory_break > grant_region_top
+            || Some(grant_count)
+                != self
+                    .grant_pointers
+                    .map(|grant_pointers| grant_pointers.len())
+            || custom_grant_count > NUM_CUSTOM_GRANTS
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        let new_state = match stopped_state {
+            CHECKPOINT_STOPPED_RUNNING => State::StoppedRunning,
+            CHECKPOINT_STOPPED_YIELDED => State::StoppedYielded,
+            _ => return Err(ErrorCode::INVAL),
+        };
+
+        let grant_len = grant_region_top - kernel_memory_break;
+        let memory_len = app_break + grant_len;
+        let context = buffer
+            .get(header_len..header_len + context_len)
+            .ok_or(ErrorCode::INVAL)?;
+        let memory_image = buffer
+            .get(header_len + context_len..header_len + context_len + memory_len)
+            .ok_or(ErrorCode::INVAL)?;
+        let grant_offsets = buffer
+            .get(header_len + context_len + memory_len..)
+            .and_then(|offsets| offsets.get(..grant_count * 4))
+            .ok_or(ErrorCode::INVAL)?;
+        let custom_grant_words = buffer
+            .get(header_len + context_len + memory_len + grant_count * 4..)
+            .and_then(|words| words.get(..custom_grant_count * CHECKPOINT_CUSTOM_GRANT_WORDS * 4))
+            .ok_or(ErrorCode::INVAL)?;
+
+        // Grants must be allocated in the grant region of the snapshot.
+        let grant_offset = |chunk: &[u8]| -> usize {
+            u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize
+        };
+        let grants_valid = grant_offsets
+            .chunks_exact(4)
+            .map(grant_offset)
+            .all(|offset| {
+                offset == 0 || (offset >= kernel_memory_break && offset < grant_region_top)
+            });
+        if !grants_valid {
+            return Err(ErrorCode::INVAL);
+        }
+
+        // Custom grants must also be in the grant region, aligned to a power of
+        // two, and not overlap each other. Their identifiers must be unique and
+        // below the identifier of the next custom grant, so that a new custom
+        // grant never gets the identifier of a restored one.
+        let custom_grant = |chunk: &[u8]| -> CustomGrantEntry {
+            let word = |i: usize| {
+                u32::from_le_bytes([chunk[i], chunk[i + 1], chunk[i + 2], chunk[i + 3]]) as usize
+            };
+            CustomGrantEntry {
+                id: word(0),
+                address: (self.memory_start as usize).wrapping_add(word(4)),
+                len: word(8),
+                align: word(12),
+                entered: false,
+            }
+        };
+        let mut custom_grants = [None; NUM_CUSTOM_GRANTS];
+        for (slot, chunk) in custom_grants
+            .iter_mut()
+            .zip(custom_grant_words.chunks_exact(CHECKPOINT_CUSTOM_GRANT_WORDS * 4))
+        {
+            *slot = Some(custom_grant(chunk));
+        }
+        if !custom_grant_table_valid(
+            &custom_grants,
+            self.memory_start as usize,
+            next_custom_grant_id,
+            kernel_memory_break..grant_region_top,
+        ) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        // Capsules may hold a reference into an entered grant or custom grant,
+        // which we are about to overwrite.
+        let grant_entered = self.grant_pointers.map_or(true, |grant_pointers| {
+            grant_pointers
+                .iter()
+                .any(|grant_ptr| (*grant_ptr as usize) & 0x1 == 0x1)
+        }) || self.custom_grants.iter().any(|custom_grant| {
+            custom_grant
+                .get()
+                .map_or(false, |custom_grant| custom_grant.entered)
+        });
+        if grant_entered {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let new_app_break = self.memory_start.wrapping_add(app_break);
+        let new_kernel_memory_break = self.memory_start.wrapping_add(kernel_memory_break);
+        self.mpu_config.map_or(Err(ErrorCode::FAIL), |config| {
+            self.chip
+                .mpu()
+                .update_app_memory_region(
+                    new_app_break,
+                    new_kernel_memory_break,
+                    mpu::Permissions::ReadWriteOnly,
+                    config,
+                )
+                .or(Err(ErrorCode::INVAL))
+        })?;
+
+        let restored = self
+            .stored_state
+            .map_or(Err(ErrorCode::FAIL), |stored_state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .restore_context(stored_state, context)
+            });
+        if let Err(e) = restored {
+            // Put the MPU configuration back to match the unchanged process.
+            self.mpu_config.map(|config| {
+                let _ = self.chip.mpu().update_app_memory_region(
+                    self.app_break.get(),
+                    self.kernel_memory_break.get(),
+                    mpu::Permissions::ReadWriteOnly,
+                    config,
+                );
+            });
+            return Err(e);
+        }
+
+        // Safety: we checked that both parts of the image fit in the memory
+        // of this process below the process struct, the process is not
+        // running, and no grant is entered.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                memory_image.as_ptr(),
+                self.memory_start as *mut u8,
+                app_break,
+            );
+            ptr::copy_nonoverlapping(
+                memory_image[app_break..].as_ptr(),
+                new_kernel_memory_break as *mut u8,
+                grant_len,
+            );
+        }
+        // Point the grants at their allocations in the restored grant region.
+        self.grant_pointers.map(|grant_pointers| {
+            for (grant_ptr, chunk) in grant_pointers.iter_mut().zip(grant_offsets.chunks_exact(4)) {
+                *grant_ptr = match grant_offset(chunk) {
+                    0 => ptr::null_mut(),
+                    offset => self.memory_start.wrapping_add(offset) as *mut u8,
+                };
+            }
+        });
+        // Custom grants are found through the restored table.
+        for (slot, custom_grant) in self.custom_grants.iter().zip(custom_grants.iter()) {
+            slot.set(*custom_grant);
+        }
+        self.next_custom_grant_id.set(next_custom_grant_id);
+        self.app_break.set(new_app_break);
+        self.update_app_break_high_water_mark();
+        self.kernel_memory_break.set(new_kernel_memory_break);
+        self.allow_high_water_mark
+            .set(self.memory_start.wrapping_add(allow_high_water_mark));
+
+        // Queued upcalls (including the init function of a process that has
+        // not started yet) belong to the state we just replaced.
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+
+        self.state.update(new_state);
+        Ok(())
+    }
+}
+
+/// Bit of the process with `index` in a set of processes, or zero if the index
+/// does not fit.
+fn priority_bit(index: usize) -> usize {
+    1usize.checked_shl(index as u32).unwrap_or(0)
+}
+
+/// Priority of the process with `index` that serves the processes in the set
+/// `clients`: the lowest index of the process, its clients, their clients
+/// (given by `clients_of`), and so on.
+fn inherited_priority(index: usize, clients: usize, clients_of: impl Fn(usize) -> usize) -> usize {
+    let own_bit = priority_bit(index);
+    let mut visited = own_bit;
+    let mut pending = clients & !visited;
+    while pending != 0 {
+        let client = pending.trailing_zeros() as usize;
+        visited |= 1 << client;
+        pending = (pending | clients_of(client)) & !visited;
+    }
+    // The lowest bit is that of the process with the lowest index.
+    if visited & !own_bit == 0 {
+        index
+    } else {
+        cmp::min(index, visited.trailing_zeros() as usize)
+    }
+}
+
+/// Move the custom grants in `table` that are below `top` up, as far as their
+/// alignment allows, and return the lowest address they use afterwards.
+///
+/// The custom grants are moved from the highest one down, so that each one
+/// only moves up into free memory or memory it already occupies. `move_to` is
+/// called in that order to copy a custom grant to its new address, before its
+/// entry is updated. Entered custom grants do not move.
+fn compact_custom_grants(
+    table: &mut [Option<CustomGrantEntry>],
+    top: usize,
+    mut move_to: impl FnMut(&CustomGrantEntry, usize),
+) -> usize {
+    // The custom grants at or above `cursor` are compacted, and `dest` is the
+    // lowest address they use.
+    let mut cursor = top;
+    let mut dest = top;
+    while let Some(custom_grant) = table
+        .iter_mut()
+        .flatten()
+        .filter(|custom_grant| custom_grant.address < cursor)
+        .max_by_key(|custom_grant| custom_grant.address)
+    {
+        cursor = custom_grant.address;
+
+        // The alignment must be a power of two, 2^a. The expression
+        // `!(align - 1)` then returns a mask with leading ones, followed by
+        // `a` trailing zeros.
+        if !custom_grant.entered {
+            let address = (dest - custom_grant.len) & !(custom_grant.align - 1);
+            if address != custom_grant.address {
+                move_to(custom_grant, address);
+                custom_grant.address = address;
+            }
+        }
+        dest = custom_grant.address;
+    }
+    dest
+}
+
+/// Whether the custom grants in `table` are aligned to a power of two, lie
+/// within `region` (as offsets from `memory_start`), do not overlap each
+/// other, and have unique identifiers below `next_id`.
+fn custom_grant_table_valid(
+    table: &[Option<CustomGrantEntry>],
+    memory_start: usize,
+    next_id: usize,
+    region: Range<usize>,
+) -> bool {
+    let range = |custom_grant: &CustomGrantEntry| {
+        let start = custom_grant.address.wrapping_sub(memory_start);
+        start.checked_add(custom_grant.len).map(|end| start..end)
+    };
+    let entries = || table.iter().flatten();
+    entries().enumerate().all(|(i, a)| {
+        let a_range = match range(a) {
+            Some(a_range) => a_range,
+            None => return false,
+        };
+        a.align.is_power_of_two()
+            && a.id < next_id
+            && a_range.start >= region.start
+            && a_range.end <= region.end
+            && entries().skip(i + 1).all(|b| {
+                a.id != b.id
+                    && range(b).map_or(false, |b_range| {
+                        a_range.end <= b_range.start || b_range.end <= a_range.start
+                    })
+            })
+    })
 }
 
 fn exceeded_check(size: usize, allocated: usize) -> &'static str {
@@ -1251,6 +2205,57 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     // Memory offset to make room for this process's metadata.
     const PROCESS_STRUCT_OFFSET: usize = mem::size_of::<ProcessStandard<C>>();
 
+    /// Raise the recorded app break high water mark to the current app break.
+    fn update_app_break_high_water_mark(&self) {
+        let app_break = self.app_break.get();
+        self.debug.map(|debug| {
+            if app_break > debug.app_break_max_pointer {
+                debug.app_break_max_pointer = app_break;
+            }
+        });
+    }
+
+    /// Fill the memory below the app break with `STACK_PAINT`, so that
+    /// `lowest_unpainted_address()` can later find how far the stack grew.
+    fn paint_stack(&self) {
+        let words =
+            (self.app_break.get() as usize - self.memory_start as usize) / mem::size_of::<u32>();
+        for i in 0..words {
+            // The process is not running, and this memory belongs to it.
+            unsafe {
+                write_volatile((self.memory_start as *mut u32).add(i), STACK_PAINT);
+            }
+        }
+    }
+
+    /// Find the lowest address below `stack_start` that the stack has
+    /// overwritten, by scanning down until `STACK_PAINT_END_WORDS` painted
+    /// words in a row are found.
+    fn lowest_unpainted_address(&self, stack_start: *const u8) -> *const u8 {
+        let memory_start = self.memory_start as usize;
+        let top = cmp::min(stack_start as usize, self.app_break.get() as usize);
+        let mut lowest = top;
+        let word = mem::size_of::<u32>();
+        let mut address = memory_start + (top - memory_start) / word * word;
+        let mut painted_words = 0;
+        while address > memory_start && painted_words < STACK_PAINT_END_WORDS {
+            address -= word;
+            // The address is below the app break, so it is process memory.
+            if unsafe { ptr::read_volatile(address as *const u32) } == STACK_PAINT {
+                painted_words += 1;
+            } else {
+                painted_words = 0;
+                lowest = address;
+            }
+        }
+        lowest as *const u8
+    }
+
+    /// Create a process for the app in `app_flash` out of
+    /// `remaining_memory`. Returns the process, if the app is an enabled app,
+    /// and the memory after the process. On error, returns the memory the
+    /// next process can use, which is all of `remaining_memory` unless the
+    /// error happened after the memory was given to the process.
     pub(crate) unsafe fn create<'a>(
         kernel: &'static Kernel,
         chip: &'static C,
@@ -1259,16 +2264,22 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         app_version: u16,
         remaining_memory: &'a mut [u8],
         fault_policy: &'static dyn ProcessFaultPolicy,
+        credentials_checker: Option<&'static dyn AppCredentialsChecker<'static>>,
         index: usize,
-    ) -> Result<(Option<&'static dyn Process>, &'a mut [u8]), ProcessLoadError> {
+    ) -> Result<(Option<&'static dyn Process>, &'a mut [u8]), (ProcessLoadError, &'a mut [u8])>
+    {
         // Get a slice for just the app header.
-        let header_flash = app_flash
-            .get(0..header_length as usize)
-            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let header_flash = match app_flash.get(0..header_length as usize) {
+            Some(header_flash) => header_flash,
+            None => return Err((ProcessLoadError::NotEnoughFlash, remaining_memory)),
+        };
 
         // Parse the full TBF header to see if this is a valid app. If the
         // header can't parse, we will error right here.
-        let tbf_header = tock_tbf::parse::parse_tbf_header(header_flash, app_version)?;
+        let tbf_header = match tock_tbf::parse::parse_tbf_header(header_flash, app_version) {
+            Ok(tbf_header) => tbf_header,
+            Err(error) => return Err((error.into(), remaining_memory)),
+        };
 
         // First thing: check that the process is at the correct location in
         // flash if the TBF header specified a fixed address. If there is a
@@ -1279,10 +2290,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             let actual_address = app_flash.as_ptr() as u32 + tbf_header.get_protected_size();
             let expected_address = fixed_flash_start;
             if actual_address != expected_address {
-                return Err(ProcessLoadError::IncorrectFlashAddress {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::IncorrectFlashAddress {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         }
 
@@ -1313,11 +2327,30 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             return Ok((None, remaining_memory));
         }
 
+        // If the kernel requires credentials, reject apps that do not carry
+        // any before giving them memory. Apps that do carry credentials are
+        // checked once they are created.
+        if let Some(checker) = credentials_checker {
+            let footers = app_flash
+                .get(tbf_header.get_binary_end() as usize..)
+                .unwrap_or(&[]);
+            if checker.require_credentials()
+                && !process_checker::footers_contain_credentials(footers)
+            {
+                if config::CONFIG.debug_load_processes {
+                    debug!(
+                        "[!] flash={:#010X}-{:#010X} process={:?} - no credentials",
+                        app_flash.as_ptr() as usize,
+                        app_flash.as_ptr() as usize + app_flash.len() - 1,
+                        process_name
+                    );
+                }
+                return Err((ProcessLoadError::MissingCredentials, remaining_memory));
+            }
+        }
+
         // Otherwise, actually load the app.
         let process_ram_requested_size = tbf_header.get_minimum_app_ram_size() as usize;
-        let init_fn = app_flash
-            .as_ptr()
-            .offset(tbf_header.get_init_function_offset() as isize) as usize;
 
         // Initialize MPU region configuration.
         let mut mpu_config: <<C as Chip>::MPU as MPU>::MpuConfig = Default::default();
@@ -1342,7 +2375,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                     process_name
                 );
             }
-            return Err(ProcessLoadError::MpuInvalidFlashLength);
+            return Err((ProcessLoadError::MpuInvalidFlashLength, remaining_memory));
         }
 
         // Determine how much space we need in the application's
@@ -1393,12 +2426,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // Right now, we only support skipping some RAM and leaving a chunk
         // unused so that the memory region starts where the process needs it
         // to.
-        let remaining_memory = if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram()
-        {
+        let memory_offset = if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram() {
             // The process does have a fixed address.
             if fixed_memory_start == remaining_memory.as_ptr() as u32 {
                 // Address already matches.
-                remaining_memory
+                0
             } else if fixed_memory_start > remaining_memory.as_ptr() as u32 {
                 // Process wants a memory address farther in memory. Try to
                 // advance the memory region to make the address match.
@@ -1408,35 +2440,39 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                     let actual_address =
                         remaining_memory.as_ptr() as u32 + remaining_memory.len() as u32 - 1;
                     let expected_address = fixed_memory_start;
-                    return Err(ProcessLoadError::MemoryAddressMismatch {
-                        actual_address,
-                        expected_address,
-                    });
+                    return Err((
+                        ProcessLoadError::MemoryAddressMismatch {
+                            actual_address,
+                            expected_address,
+                        },
+                        remaining_memory,
+                    ));
                 } else {
                     // Change the memory range to start where the process
                     // requested it.
-                    remaining_memory
-                        .get_mut(diff..)
-                        .ok_or(ProcessLoadError::InternalError)?
+                    diff
                 }
             } else {
                 // Address is earlier in memory, nothing we can do.
                 let actual_address = remaining_memory.as_ptr() as u32;
                 let expected_address = fixed_memory_start;
-                return Err(ProcessLoadError::MemoryAddressMismatch {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::MemoryAddressMismatch {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         } else {
-            remaining_memory
+            0
         };
 
         // Determine where process memory will go and allocate MPU region for
         // app-owned memory.
         let (app_memory_start, app_memory_size) = match chip.mpu().allocate_app_memory_region(
-            remaining_memory.as_ptr() as *const u8,
-            remaining_memory.len(),
+            remaining_memory.as_ptr().add(memory_offset) as *const u8,
+            remaining_memory.len() - memory_offset,
             min_total_memory_size,
             min_process_memory_size,
             initial_kernel_memory_size,
@@ -1455,41 +2491,41 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                         min_total_memory_size
                     );
                 }
-                return Err(ProcessLoadError::NotEnoughMemory);
+                return Err((ProcessLoadError::NotEnoughMemory, remaining_memory));
             }
         };
 
-        // Get a slice for the memory dedicated to the process. This can fail if
-        // the MPU returns a region of memory that is not inside of the
-        // `remaining_memory` slice passed to `create()` to allocate the
-        // process's memory out of.
-        let memory_start_offset = app_memory_start as usize - remaining_memory.as_ptr() as usize;
-        // First split the remaining memory into a slice that contains the
-        // process memory and a slice that will not be used by this process.
-        let (app_memory_oversize, unused_memory) =
-            remaining_memory.split_at_mut(memory_start_offset + app_memory_size);
-        // Then since the process's memory need not start at the beginning of
-        // the remaining slice given to create(), get a smaller slice as needed.
-        let app_memory = app_memory_oversize
-            .get_mut(memory_start_offset..)
-            .ok_or(ProcessLoadError::InternalError)?;
-
         // Check if the memory region is valid for the process. If a process
         // included a fixed address for the start of RAM in its TBF header (this
         // field is optional, processes that are position independent do not
         // need a fixed address) then we check that we used the same address
         // when we allocated it in RAM.
         if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram() {
-            let actual_address = app_memory.as_ptr() as u32;
+            let actual_address = app_memory_start as u32;
             let expected_address = fixed_memory_start;
             if actual_address != expected_address {
-                return Err(ProcessLoadError::MemoryAddressMismatch {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::MemoryAddressMismatch {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         }
 
+        // Get a slice for the memory dedicated to the process. The MPU
+        // returns a region of memory inside of the `remaining_memory` slice
+        // passed to `create()` to allocate the process's memory out of.
+        let memory_start_offset = app_memory_start as usize - remaining_memory.as_ptr() as usize;
+        // First split the remaining memory into a slice that contains the
+        // process memory and a slice that will not be used by this process.
+        let (app_memory_oversize, unused_memory) =
+            remaining_memory.split_at_mut(memory_start_offset + app_memory_size);
+        // Then since the process's memory need not start at the beginning of
+        // the remaining slice given to create(), get a smaller slice as needed.
+        let app_memory = &mut app_memory_oversize[memory_start_offset..];
+
         // Set the initial process-accessible memory to the amount specified by
         // the context switch implementation.
         let initial_app_brk = app_memory.as_ptr().add(min_process_memory_size);
@@ -1565,6 +2601,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.memory_len = app_memory.len();
         process.header = tbf_header;
         process.kernel_memory_break = Cell::new(kernel_memory_break);
+        process.grant_region_top = Cell::new(kernel_memory_break);
+        process.next_custom_grant_id = Cell::new(0);
+        process.custom_grants = [
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+        ];
         process.app_break = Cell::new(initial_app_brk);
         process.grant_pointers = MapCell::new(opts);
 
@@ -1575,6 +2623,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.state = ProcessStateCell::new(process.kernel);
         process.fault_policy = fault_policy;
         process.restart_count = Cell::new(0);
+        process.priority_clients = Cell::new(0);
+        process.effective_priority = Cell::new(index);
 
         process.mpu_config = MapCell::new(mpu_config);
         process.mpu_regions = [
@@ -1585,6 +2635,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             Cell::new(None),
             Cell::new(None),
         ];
+        process.shared_mpu_regions = [
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+            Cell::new(None),
+        ];
         process.tasks = MapCell::new(tasks);
         process.process_name = process_name.unwrap_or("");
 
@@ -1594,25 +2652,20 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             app_heap_start_pointer: None,
             app_stack_start_pointer: None,
             app_stack_min_pointer: None,
+            app_stack_pointer: None,
+            faulted_running: false,
             syscall_count: 0,
             last_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            userspace_time_us: 0,
+            kernel_time_us: 0,
+            app_break_max_pointer: initial_app_brk,
         });
 
-        let flash_protected_size = process.header.get_protected_size() as usize;
-        let flash_app_start_addr = app_flash.as_ptr() as usize + flash_protected_size;
-
-        process.tasks.map(|tasks| {
-            tasks.enqueue(Task::FunctionCall(FunctionCall {
-                source: FunctionCallSource::Kernel,
-                pc: init_fn,
-                argument0: flash_app_start_addr,
-                argument1: process.memory_start as usize,
-                argument2: process.memory_len,
-                argument3: process.app_break.get() as usize,
-            }));
-        });
+        if config::CONFIG.paint_process_stack {
+            process.paint_stack();
+        }
 
         // Handle any architecture-specific requirements for a new process.
         //
@@ -1639,11 +2692,17 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                         process_name
                     );
                 }
-                return Err(ProcessLoadError::InternalError);
+                return Err((ProcessLoadError::InternalError, unused_memory));
             }
         };
 
-        kernel.increment_work();
+        // If credentials have to be checked the process waits for the check
+        // before it is queued to run. Otherwise it can run right away.
+        if credentials_checker.is_some() {
+            process.state.update(State::CredentialsUnchecked);
+        } else {
+            process.enqueue_init_task();
+        }
 
         // Return the process object and a remaining memory for processes slice.
         Ok((Some(process), unused_memory))
@@ -1670,17 +2729,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.app_stack_pointer = None;
+            debug.faulted_running = false;
+            debug.userspace_time_us = 0;
+            debug.kernel_time_us = 0;
         });
 
         // FLASH
 
-        // We are going to start this process over again, so need the init_fn
-        // location.
-        let app_flash_address = self.flash_start();
-        let init_fn = unsafe {
-            app_flash_address.offset(self.header.get_init_function_offset() as isize) as usize
-        };
-
         // Reset MPU region configuration.
         // TODO: ideally, this would be moved into a helper function used by both
         // create() and reset(), but process load debugging complicates this.
@@ -1746,18 +2802,33 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // memory.
         let app_brk = app_mpu_mem_start.wrapping_add(min_process_memory_size);
         self.app_break.set(app_brk);
+        self.debug
+            .map(|debug| debug.app_break_max_pointer = app_brk);
         // kernel_brk is calculated backwards from the end of memory the size of
         // the initial kernel data structures.
         let kernel_brk = app_mpu_mem_start
             .wrapping_add(app_mpu_mem_len)
             .wrapping_sub(initial_kernel_memory_size);
         self.kernel_memory_break.set(kernel_brk);
+        self.grant_region_top.set(kernel_brk);
+        // The custom grants were in the old grant region.
+        for custom_grant in self.custom_grants.iter() {
+            custom_grant.set(None);
+        }
         // High water mark for `allow`ed memory is reset to the start of the
         // process's memory region.
         self.allow_high_water_mark.set(app_mpu_mem_start);
 
         // Drop the old config and use the clean one
         self.mpu_config.replace(mpu_config);
+        // The new configuration does not share memory of other processes.
+        for shared in self.shared_mpu_regions.iter() {
+            shared.set(None);
+        }
+
+        if config::CONFIG.paint_process_stack {
+            self.paint_stack();
+        }
 
         // Handle any architecture-specific requirements for a process when it
         // first starts (as it would when it is new).
@@ -1781,17 +2852,25 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
-        // And queue up this app to be restarted.
-        let flash_protected_size = self.header.get_protected_size() as usize;
-        let flash_app_start = app_flash_address as usize + flash_protected_size;
-
         // Mark the state as `Unstarted` for the scheduler.
         self.state.update(State::Unstarted);
 
         // Mark that we restarted this process.
         self.restart_count.increment();
 
-        // Enqueue the initial function.
+        // And queue up this app to be restarted.
+        self.enqueue_init_task();
+
+        Ok(())
+    }
+
+    /// Queue the process's init function (`_start`) so that the process starts
+    /// executing from the beginning the next time it is scheduled.
+    fn enqueue_init_task(&self) {
+        let flash_start = self.flash_start() as usize;
+        let init_fn = flash_start + self.header.get_init_function_offset() as usize;
+        let flash_app_start = flash_start + self.header.get_protected_size() as usize;
+
         self.tasks.map(|tasks| {
             tasks.enqueue(Task::FunctionCall(FunctionCall {
                 source: FunctionCallSource::Kernel,
@@ -1805,8 +2884,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
 
         // Mark that the process is ready to run.
         self.kernel.increment_work();
+    }
 
-        Ok(())
+    /// Check if the credentials of this process have been approved (or did
+    /// not need to be checked). Processes that are still waiting on a check
+    /// or that failed the check must never be started.
+    fn credentials_approved(&self) -> bool {
+        let current_state = self.state.get();
+        current_state != State::CredentialsUnchecked && current_state != State::CredentialsFailed
     }
 
     /// Checks if the buffer represented by the passed in base pointer and size
@@ -1858,7 +2943,22 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     /// If there is not enough memory, or the MPU cannot isolate the process
     /// accessible region from the new kernel memory break after doing the
     /// allocation, then this will return `None`.
+    ///
+    /// If there is not enough memory at first, the grant region is compacted
+    /// to reclaim the memory of freed custom grants.
     fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        self.allocate_below_kernel_memory_break(size, align)
+            .or_else(|| {
+                if self.compact_grant_region() > 0 {
+                    self.allocate_below_kernel_memory_break(size, align)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Allocate memory by lowering the kernel memory break.
+    fn allocate_below_kernel_memory_break(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
         self.mpu_config.and_then(|mut config| {
             // First, compute the candidate new pointer. Note that at this
             // point we have not yet checked whether there is space for
@@ -1915,27 +3015,70 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     /// Create the identifier for a custom grant that grant.rs uses to access
     /// the custom grant.
     ///
-    /// We create this identifier by calculating the number of bytes between
-    /// where the custom grant starts and the end of the process memory.
-    fn create_custom_grant_identifier(&self, ptr: NonNull<u8>) -> ProcessCustomGrantIdentifer {
-        let custom_grant_address = ptr.as_ptr() as usize;
-        let process_memory_end = self.mem_end() as usize;
+    /// The identifier is a number kept in the table of custom grants, rather
+    /// than the address of the custom grant, because the custom grant moves
+    /// when the grant region is compacted.
+    fn create_custom_grant_identifier(&self) -> ProcessCustomGrantIdentifer {
+        let id = self.next_custom_grant_id.get();
+        self.next_custom_grant_id.set(id.wrapping_add(1));
+        ProcessCustomGrantIdentifer { id }
+    }
 
-        ProcessCustomGrantIdentifer {
-            offset: process_memory_end - custom_grant_address,
-        }
+    /// Find the entry of a custom grant in the table of custom grants. It is
+    /// not found if the custom grant was freed.
+    fn find_custom_grant(
+        &self,
+        identifier: ProcessCustomGrantIdentifer,
+    ) -> Option<&Cell<Option<CustomGrantEntry>>> {
+        self.custom_grants.iter().find(|slot| {
+            slot.get()
+                .map_or(false, |custom_grant| custom_grant.id == identifier.id)
+        })
     }
 
-    /// Use a ProcessCustomGrantIdentifer to find the address of the custom
-    /// grant.
+    /// Compute the priority of this process from its index and the indices of
+    /// its clients, their clients, and so on, and tell the processes this
+    /// process is a client of if it changed.
     ///
-    /// This reverses `create_custom_grant_identifier()`.
-    fn get_custom_grant_address(&self, identifier: ProcessCustomGrantIdentifer) -> usize {
-        let process_memory_end = self.mem_end() as usize;
+    /// Each client is visited once, so cycles of processes serving each other
+    /// end the walk. The priority is computed from the indices rather than from
+    /// the priorities the clients inherited, because in a cycle these include
+    /// what the clients inherited from this process, which would keep a
+    /// priority from being released. Only changes are passed on.
+    fn update_priority(&self) {
+        let priority = inherited_priority(
+            self.process_id.get().index,
+            self.priority_clients.get(),
+            |client| {
+                self.kernel
+                    .get_process_iter()
+                    .find(|process| process.processid().index == client)
+                    .map_or(0, |process| process.priority_clients())
+            },
+        );
+        if priority != self.effective_priority.replace(priority) {
+            let processid = self.processid();
+            self.kernel
+                .get_process_iter()
+                .for_each(|process| process.client_priority_changed(processid));
+        }
+    }
 
-        // Subtract the offset in the identifier from the end of the process
-        // memory to get the address of the custom grant.
-        process_memory_end - identifier.offset
+    /// Bit of `process` in the set of processes whose priority is inherited.
+    fn priority_bit(process: ProcessId) -> usize {
+        priority_bit(process.index)
+    }
+
+    /// Priority of `task`: that of the process that sent an IPC task, or the
+    /// priority of this process given by its index.
+    fn task_priority(&self, task: &Task) -> usize {
+        let own_priority = self.process_id.get().index;
+        match task {
+            Task::IPC((from, _)) => self
+                .kernel
+                .process_map_or(own_priority, *from, |process| process.priority()),
+            Task::FunctionCall(_) => own_priority,
+        }
     }
 
     /// Check if the process is active.
@@ -1946,10 +3089,166 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     /// resetting its state.
     ///
     /// A process is inactive if the kernel cannot resume its execution, such as
-    /// if the process faults and is in an invalid state, or if the process
-    /// explicitly exits.
+    /// if the process faults and is in an invalid state, if the process
+    /// explicitly exits, or if its credentials have not been approved.
     fn is_active(&self) -> bool {
         let current_state = self.state.get();
-        current_state != State::Terminated && current_state != State::Faulted
+        current_state != State::Terminated
+            && current_state != State::Faulted
+            && self.credentials_approved()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compact_custom_grants, custom_grant_table_valid, CustomGrantEntry};
+    use super::{inherited_priority, priority_bit};
+
+    const MEMORY_START: usize = 0x2000_0000;
+
+    fn entry(id: usize, offset: usize, len: usize) -> Option<CustomGrantEntry> {
+        Some(CustomGrantEntry {
+            id,
+            address: MEMORY_START.wrapping_add(offset),
+            len,
+            align: 4,
+            entered: false,
+        })
+    }
+
+    fn valid(table: &[Option<CustomGrantEntry>]) -> bool {
+        custom_grant_table_valid(table, MEMORY_START, 3, 0x100..0x200)
+    }
+
+    #[test]
+    fn test_custom_grant_table_valid() {
+        assert!(valid(&[]));
+        assert!(valid(&[entry(0, 0x100, 0x10), None, entry(2, 0x110, 0xf0)]));
+    }
+
+    #[test]
+    fn test_custom_grant_table_rejects_bad_entries() {
+        // Outside of the grant region.
+        assert!(!valid(&[entry(0, 0xf0, 0x20)]));
+        assert!(!valid(&[entry(0, 0x1f0, 0x20)]));
+        assert!(!valid(&[entry(0, usize::MAX, 2)]));
+        // Identifiers the process has not handed out yet.
+        assert!(!valid(&[entry(3, 0x100, 0x10)]));
+        // Alignments that are not a power of two.
+        let mut misaligned = entry(0, 0x100, 0x10);
+        misaligned.as_mut().unwrap().align = 3;
+        assert!(!valid(&[misaligned]));
+    }
+
+    #[test]
+    fn test_custom_grant_table_rejects_duplicates_and_overlaps() {
+        assert!(!valid(&[entry(1, 0x100, 0x10), entry(1, 0x120, 0x10)]));
+        assert!(!valid(&[
+            entry(0, 0x100, 0x20),
+            None,
+            entry(1, 0x110, 0x10)
+        ]));
+        assert!(!valid(&[entry(0, 0x110, 0x10), entry(1, 0x100, 0x20)]));
+    }
+
+    /// Compact `table` below `top`. Returns the lowest address of the custom
+    /// grants and the moves, as offsets from the start of memory, in the order
+    /// they were made.
+    fn compact(
+        table: &mut [Option<CustomGrantEntry>],
+        top: usize,
+    ) -> (usize, [Option<(usize, usize)>; 3]) {
+        let mut moves = [None; 3];
+        let mut next = moves.iter_mut();
+        let dest = compact_custom_grants(table, MEMORY_START + top, |custom_grant, address| {
+            *next.next().unwrap() =
+                Some((custom_grant.address - MEMORY_START, address - MEMORY_START));
+        });
+        (dest - MEMORY_START, moves)
+    }
+
+    fn address(entry: &Option<CustomGrantEntry>) -> usize {
+        entry.unwrap().address.wrapping_sub(MEMORY_START)
+    }
+
+    #[test]
+    fn test_compact_custom_grants_closes_gaps() {
+        // Custom grants at 0x100..0x110 and 0x180..0x1a0, below grants at
+        // 0x200.
+        let mut table = [entry(0, 0x180, 0x20), None, entry(1, 0x100, 0x10)];
+        let (dest, moves) = compact(&mut table, 0x200);
+        assert_eq!(dest, 0x1d0);
+        assert_eq!(address(&table[0]), 0x1e0);
+        assert_eq!(address(&table[2]), 0x1d0);
+        // The highest custom grant moves first.
+        assert_eq!(moves, [Some((0x180, 0x1e0)), Some((0x100, 0x1d0)), None]);
+
+        // Compacting again does not move anything.
+        assert_eq!(compact(&mut table, 0x200), (dest, [None; 3]));
+    }
+
+    #[test]
+    fn test_compact_custom_grants_keeps_alignment_and_entered_grants() {
+        let mut table = [
+            entry(0, 0x100, 0x8),
+            entry(1, 0x140, 0x10),
+            entry(2, 0x180, 0x4),
+        ];
+        table[0].as_mut().unwrap().align = 0x40;
+        table[1].as_mut().unwrap().entered = true;
+        let (dest, moves) = compact(&mut table, 0x200);
+        assert_eq!(address(&table[2]), 0x1fc);
+        // The entered custom grant stays, and the one below it cannot move up
+        // to the next multiple of its alignment.
+        assert_eq!(address(&table[1]), 0x140);
+        assert_eq!(address(&table[0]), 0x100);
+        assert_eq!(dest, 0x100);
+        assert_eq!(moves, [Some((0x180, 0x1fc)), None, None]);
+
+        table[0].as_mut().unwrap().align = 0x4;
+        let (dest, moves) = compact(&mut table, 0x200);
+        assert_eq!(address(&table[0]), 0x138);
+        assert_eq!(dest, 0x138);
+        assert_eq!(moves, [Some((0x100, 0x138)), None, None]);
+    }
+
+    #[test]
+    fn test_priority_bit() {
+        assert_eq!(priority_bit(0), 1);
+        assert_eq!(priority_bit(5), 0b10_0000);
+        assert_eq!(
+            priority_bit(usize::MAX.count_ones() as usize - 1),
+            1 << (usize::MAX.count_ones() - 1)
+        );
+        // Processes with higher indices are not tracked.
+        assert_eq!(priority_bit(usize::MAX.count_ones() as usize), 0);
+        assert_eq!(priority_bit(1000), 0);
+    }
+
+    #[test]
+    fn test_inherited_priority_follows_chains() {
+        // Process 3 serves 2, which serves 1.
+        let clients_of = |index| [0, 0, 0b10, 0b100][index];
+        assert_eq!(inherited_priority(3, 0, clients_of), 3);
+        assert_eq!(inherited_priority(3, 0b100, clients_of), 1);
+        assert_eq!(inherited_priority(2, 0b10, clients_of), 1);
+        // A process keeps its own priority if its clients have a lower one.
+        assert_eq!(inherited_priority(0, 0b1000, |_| 0), 0);
+        // Processes that are not tracked keep their own priority, or inherit
+        // that of tracked clients.
+        assert_eq!(inherited_priority(1000, 0, |_| 0), 1000);
+        assert_eq!(inherited_priority(1000, 0b100, |_| 0), 2);
+    }
+
+    #[test]
+    fn test_inherited_priority_ends_at_cycles() {
+        // Processes 2 and 3 serve each other, and 3 may serve 1.
+        let with_client = |index| [0, 0, 0b1000, 0b110][index];
+        let without_client = |index| [0, 0, 0b1000, 0b100][index];
+        assert_eq!(inherited_priority(2, 0b1000, with_client), 1);
+        assert_eq!(inherited_priority(3, 0b110, with_client), 1);
+        // Once 3 served 1, neither keeps its priority through the other.
+        assert_eq!(inherited_priority(2, 0b1000, without_client), 2);
+        assert_eq!(inherited_priority(3, 0b100, without_client), 2);
     }
 }
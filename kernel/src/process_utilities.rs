@@ -8,6 +8,7 @@ use crate::config;
 use crate::debug;
 use crate::platform::Chip;
 use crate::process::Process;
+use crate::process_checker::{AppCredentialsChecker, ProcessCheckerMachine};
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandard;
 use crate::sched::Kernel;
@@ -45,6 +46,11 @@ pub enum ProcessLoadError {
         expected_address: u32,
     },
 
+    /// The kernel requires apps to carry credentials (e.g. a signature) in
+    /// their TBF footer, and an app has none. The app is skipped, and the
+    /// apps after it are still loaded.
+    MissingCredentials,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -98,6 +104,10 @@ impl fmt::Debug for ProcessLoadError {
                 actual_address, expected_address
             ),
 
+            ProcessLoadError::MissingCredentials => {
+                write!(f, "App has no credentials but the kernel requires them")
+            }
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -134,6 +144,67 @@ pub fn load_processes<C: Chip>(
     fault_policy: &'static dyn ProcessFaultPolicy,
     _capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
+    load_processes_advanced(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        None,
+    )
+    .and_then(|(_, skipped)| skipped.map_or(Ok(()), Err))
+}
+
+/// Load processes like `load_processes()`, but require that their credentials
+/// are approved by the board's `AppCredentialsChecker` before they run.
+///
+/// If the checker requires credentials, apps without credentials in their TBF
+/// footer are skipped, the apps after them are still loaded, and this returns
+/// `ProcessLoadError::MissingCredentials`. Processes that are created start in
+/// the `CredentialsUnchecked` state, and `checker` then checks each of them in
+/// turn. A process only runs once a credential in its footer is accepted (or,
+/// if the checker does not require credentials, no credential is rejected).
+/// The checker must be set up as the client of the `AppCredentialsChecker`
+/// before calling this function.
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    checker: &'static ProcessCheckerMachine,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    let result = load_processes_advanced(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        Some(checker.checker),
+    );
+
+    // Check any processes that were created, even if a later app failed to
+    // load.
+    checker.start();
+    result.and_then(|(_, skipped)| skipped.map_or(Ok(()), Err))
+}
+
+/// Load the processes in `app_flash` into `procs`. On success returns the part
+/// of `app_memory` that was not given to any process, and the error of the
+/// first app that was skipped without stopping the loading of the others.
+pub(crate) fn load_processes_advanced<'a, C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'a mut [u8],
+    procs: &mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    credentials_checker: Option<&'static dyn AppCredentialsChecker<'static>>,
+) -> Result<(&'a mut [u8], Option<ProcessLoadError>), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
             "Loading processes from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X}",
@@ -146,6 +217,7 @@ pub fn load_processes<C: Chip>(
 
     let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
+    let mut skipped = None;
 
     // Try to discover up to `procs.len()` processes in flash.
     for i in 0..procs.len() {
@@ -157,7 +229,7 @@ pub fn load_processes<C: Chip>(
                 // Not enough flash to test for another app. This just means
                 // we are at the end of flash, and there are no more apps to
                 // load.
-                return Ok(());
+                return Ok((remaining_memory, skipped));
             }
         };
 
@@ -180,7 +252,7 @@ pub fn load_processes<C: Chip>(
                 // header we started to parse is intentionally invalid to signal
                 // the end of apps. This is ok and just means we have finished
                 // loading apps.
-                return Ok(());
+                return Ok((remaining_memory, skipped));
             }
         };
 
@@ -209,7 +281,7 @@ pub fn load_processes<C: Chip>(
             // Try to create a process object from that app slice. If we don't
             // get a process and we didn't get a loading error (aka we got to
             // this point), then the app is a disabled process or just padding.
-            let (process_option, unused_memory) = unsafe {
+            let result = unsafe {
                 ProcessStandard::create(
                     kernel,
                     chip,
@@ -218,8 +290,19 @@ pub fn load_processes<C: Chip>(
                     version,
                     remaining_memory,
                     fault_policy,
+                    credentials_checker,
                     i,
-                )?
+                )
+            };
+            let (process_option, unused_memory) = match result {
+                Ok(created) => created,
+                // Apps without credentials are skipped like disabled apps,
+                // but the board still learns that some app did not load.
+                Err((ProcessLoadError::MissingCredentials, unused_memory)) => {
+                    skipped.get_or_insert(ProcessLoadError::MissingCredentials);
+                    (None, unused_memory)
+                }
+                Err((error, _)) => return Err(error),
             };
             process_option.map(|process| {
                 if config::CONFIG.debug_load_processes {
@@ -245,5 +328,5 @@ pub fn load_processes<C: Chip>(
         };
     }
 
-    Ok(())
+    Ok((remaining_memory, skipped))
 }
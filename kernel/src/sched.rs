@@ -6,15 +6,18 @@
 //! selected by a board.
 
 pub(crate) mod cooperative;
+pub(crate) mod edf;
 pub(crate) mod mlfq;
 pub(crate) mod priority;
 pub(crate) mod round_robin;
+pub(crate) mod stride;
 
 use core::cell::Cell;
+use core::cmp;
 use core::ptr::NonNull;
 
 use crate::capabilities;
-use crate::common::cells::NumericCellExt;
+use crate::common::cells::{NumericCellExt, OptionalCell};
 use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::config;
 use crate::debug;
@@ -25,13 +28,14 @@ use crate::ipc;
 use crate::memop;
 use crate::platform::mpu::MPU;
 use crate::platform::scheduler_timer::SchedulerTimer;
-use crate::platform::watchdog::WatchDog;
+use crate::platform::watchdog::{ProcessMonitor, WatchDog};
 use crate::platform::{Chip, Platform};
 use crate::process::ProcessId;
 use crate::process::{self, Task};
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
 use crate::syscall::{Syscall, YieldCall};
 use crate::upcall::{Upcall, UpcallId};
+use tock_tbf::types::CommandPermissions;
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -146,6 +150,9 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// Watchdog service that is told which process is running, if any.
+    process_monitor: OptionalCell<&'static dyn ProcessMonitor>,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -181,9 +188,21 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            process_monitor: OptionalCell::empty(),
         }
     }
 
+    /// Report to `monitor` which process the kernel runs without a timeslice
+    /// and when it runs out of work, so that `monitor` can detect processes
+    /// that do not yield.
+    pub fn set_process_monitor(
+        &self,
+        monitor: &'static dyn ProcessMonitor,
+        _capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        self.process_monitor.set(monitor);
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     ///
     /// This is only exposed in the core kernel crate.
@@ -345,6 +364,20 @@ impl Kernel {
         None
     }
 
+    /// Returns the number of slots in the processes array.
+    pub(crate) fn number_of_process_slots(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Returns the process stored at `index` in the processes array, if there
+    /// is one.
+    pub(crate) fn get_process_at_index(
+        &self,
+        index: usize,
+    ) -> Option<&'static dyn process::Process> {
+        self.processes.get(index).copied().flatten()
+    }
+
     /// Retrieve the `ProcessId` of the given app based on its identifier. This is
     /// useful if an app identifier is passed to the kernel from somewhere (such
     /// as from userspace) and needs to be expanded to a full `ProcessId` for use
@@ -497,6 +530,13 @@ impl Kernel {
                     match scheduler.next(self) {
                         SchedulingDecision::RunProcess((appid, timeslice_us)) => {
                             self.process_map_or((), appid, |process| {
+                                // Only a process that runs without a
+                                // timeslice can keep the others from running.
+                                let monitored = timeslice_us.is_none();
+                                if monitored {
+                                    self.process_monitor
+                                        .map(|monitor| monitor.process_running(appid));
+                                }
                                 let (reason, time_executed) = self.do_process(
                                     platform,
                                     chip,
@@ -505,6 +545,10 @@ impl Kernel {
                                     ipc,
                                     timeslice_us,
                                 );
+                                if monitored && reason == StoppedExecutingReason::NoWorkLeft {
+                                    self.process_monitor
+                                        .map(|monitor| monitor.process_yielded(appid));
+                                }
                                 scheduler.result(reason, time_executed);
                             });
                         }
@@ -619,6 +663,10 @@ impl Kernel {
         // inform the scheduler.
         let mut return_reason = StoppedExecutingReason::NoWorkLeft;
 
+        // Time the process spent executing in userspace, as opposed to time
+        // the kernel spent on its behalf.
+        let mut userspace_us: u32 = 0;
+
         // Since the timeslice counts both the process's execution time and the
         // time spent in the kernel on behalf of the process (setting it up and
         // handling its syscalls), we intend to keep running the process until
@@ -662,27 +710,42 @@ impl Kernel {
                     process.setup_mpu();
 
                     chip.mpu().enable_app_mpu();
+                    let remaining_before = scheduler_timer.get_remaining_us();
                     scheduler_timer.arm();
                     let context_switch_reason = process.switch_to();
                     scheduler_timer.disarm();
                     chip.mpu().disable_app_mpu();
 
+                    // Account for the time the process spent in userspace.
+                    // We cannot call `.get_remaining_us()` again if it returns
+                    // `None`, so we keep this value for the checks below.
+                    let remaining_after = scheduler_timer.get_remaining_us();
+                    if let Some(before) = remaining_before {
+                        userspace_us += before - cmp::min(remaining_after.unwrap_or(0), before);
+                    }
+
                     // Now the process has returned back to the kernel. Check
                     // why and handle the process as appropriate.
                     match context_switch_reason {
                         Some(ContextSwitchReason::Fault) => {
-                            // The app faulted, check if the chip wants to
-                            // handle the fault.
-                            if platform.process_fault_hook(process).is_err() {
-                                // Let process deal with it as appropriate.
-                                process.set_fault_state();
+                            // The app may only have accessed one of its MPU
+                            // regions that is not in the MPU hardware. Then
+                            // the region is swapped in and the app retries
+                            // the access when it runs next.
+                            if !process.swap_in_mpu_region() {
+                                // The app faulted, check if the chip wants to
+                                // handle the fault.
+                                if platform.process_fault_hook(process).is_err() {
+                                    // Let process deal with it as appropriate.
+                                    process.set_fault_state();
+                                }
                             }
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
                             self.handle_syscall(platform, process, syscall);
                         }
                         Some(ContextSwitchReason::Interrupted) => {
-                            if scheduler_timer.get_remaining_us().is_none() {
+                            if remaining_after.is_none() {
                                 // This interrupt was a timeslice expiration.
                                 process.debug_timeslice_expired();
                                 return_reason = StoppedExecutingReason::TimesliceExpired;
@@ -701,6 +764,13 @@ impl Kernel {
                             process.set_fault_state();
                         }
                     }
+
+                    if remaining_after.is_none() {
+                        // The timeslice expired while the process was running.
+                        process.debug_timeslice_expired();
+                        return_reason = StoppedExecutingReason::TimesliceExpired;
+                        break;
+                    }
                 }
                 process::State::Yielded | process::State::Unstarted => {
                     // If the process is yielded or hasn't been started it is
@@ -760,6 +830,12 @@ impl Kernel {
                     return_reason = StoppedExecutingReason::Stopped;
                     break;
                 }
+                process::State::CredentialsUnchecked | process::State::CredentialsFailed => {
+                    // Processes whose credentials have not been approved are
+                    // never ready, so there is nothing to do.
+                    return_reason = StoppedExecutingReason::NoWorkLeft;
+                    break;
+                }
             }
         }
 
@@ -779,6 +855,14 @@ impl Kernel {
             }
         });
 
+        // Whatever part of the timeslice the process did not spend in
+        // userspace the kernel spent on its behalf. Processes that run
+        // cooperatively are not accounted for, as there is no timer.
+        time_executed_us.map(|executed_us| {
+            let userspace_us = cmp::min(userspace_us, executed_us);
+            process.debug_execution_time_add(userspace_us, executed_us - userspace_us);
+        });
+
         // Reset the scheduler timer in case it unconditionally triggers
         // interrupts upon expiration. We do not want it to expire while the
         // chip is sleeping, for example.
@@ -788,7 +872,8 @@ impl Kernel {
     }
 
     /// Method to invoke a system call on a particular process.
-    /// Applies the kernel system call filtering policy (if any).
+    /// Applies the kernel system call filtering policy (if any), and the
+    /// permissions declared in the TBF header of the process (if any).
     /// Handles `Yield` and `Exit`, dispatches `Memop` to `memop::memop`,
     /// and dispatches peripheral driver system calls to peripheral
     /// driver capsules through the platforms `with_driver` method.
@@ -920,18 +1005,25 @@ impl Kernel {
                 let upcall = ptr.map_or(Upcall::default(), |ptr| {
                     Upcall::new(process.processid(), upcall_id, appdata, ptr.cast())
                 });
-                let rval = platform.with_driver(driver_number, |driver| match driver {
-                    Some(d) => {
-                        let res = d.subscribe(subdriver_number, upcall, process.processid());
-                        match res {
-                            // An Ok() returns the previous upcall, while
-                            // Err() returns the one that was just passed
-                            // (because the call was rejected).
-                            Ok(oldcb) => oldcb.into_subscribe_success(),
-                            Err((newcb, err)) => newcb.into_subscribe_failure(err),
+                let rval = platform.with_driver(driver_number, |driver| {
+                    match driver.filter(|_| {
+                        driver_permitted(
+                            |driver, offset| process.get_command_permissions(driver, offset),
+                            driver_number,
+                        )
+                    }) {
+                        Some(d) => {
+                            let res = d.subscribe(subdriver_number, upcall, process.processid());
+                            match res {
+                                // An Ok() returns the previous upcall, while
+                                // Err() returns the one that was just passed
+                                // (because the call was rejected).
+                                Ok(oldcb) => oldcb.into_subscribe_success(),
+                                Err((newcb, err)) => newcb.into_subscribe_failure(err),
+                            }
                         }
+                        None => upcall.into_subscribe_failure(ErrorCode::NODEVICE),
                     }
-                    None => upcall.into_subscribe_failure(ErrorCode::NODEVICE),
                 });
                 if config::CONFIG.trace_syscalls {
                     debug!(
@@ -953,9 +1045,17 @@ impl Kernel {
                 arg0,
                 arg1,
             } => {
-                let cres = platform.with_driver(driver_number, |driver| match driver {
-                    Some(d) => d.command(subdriver_number, arg0, arg1, process.processid()),
-                    None => CommandReturn::failure(ErrorCode::NODEVICE),
+                let cres = platform.with_driver(driver_number, |driver| {
+                    match driver.filter(|_| {
+                        command_permitted(
+                            |driver, offset| process.get_command_permissions(driver, offset),
+                            driver_number,
+                            subdriver_number,
+                        )
+                    }) {
+                        Some(d) => d.command(subdriver_number, arg0, arg1, process.processid()),
+                        None => CommandReturn::failure(ErrorCode::NODEVICE),
+                    }
                 });
 
                 let res = SyscallReturn::from_command_return(cres);
@@ -979,55 +1079,62 @@ impl Kernel {
                 allow_address,
                 allow_size,
             } => {
-                let res = platform.with_driver(driver_number, |driver| match driver {
-                    Some(d) => {
-                        // Try to create an appropriate [`ReadWriteAppSlice`].
-                        // This method will ensure that the memory in question
-                        // is located in the process-accessible memory space.
-                        //
-                        // TODO: Enforce anti buffer-aliasing guarantees to avoid
-                        // undefined behavior in Rust.
-                        match process.build_readwrite_appslice(allow_address, allow_size) {
-                            Ok(appslice) => {
-                                // Creating the [`ReadWriteAppSlice`] worked,
-                                // provide it to the capsule.
-                                match d.allow_readwrite(
-                                    process.processid(),
-                                    subdriver_number,
-                                    appslice,
-                                ) {
-                                    Ok(returned_appslice) => {
-                                        // The capsule has accepted the allow
-                                        // operation. Pass the previous buffer
-                                        // information back to the process.
-                                        //
-                                        // TODO: Prevent swapping of AppSlices by
-                                        // the capsule
-                                        let (ptr, len) = returned_appslice.consume();
-                                        SyscallReturn::AllowReadWriteSuccess(ptr, len)
-                                    }
-                                    Err((rejected_appslice, err)) => {
-                                        let (ptr, len) = rejected_appslice.consume();
-                                        SyscallReturn::AllowReadWriteFailure(err, ptr, len)
+                let res = platform.with_driver(driver_number, |driver| {
+                    match driver.filter(|_| {
+                        driver_permitted(
+                            |driver, offset| process.get_command_permissions(driver, offset),
+                            driver_number,
+                        )
+                    }) {
+                        Some(d) => {
+                            // Try to create an appropriate [`ReadWriteAppSlice`].
+                            // This method will ensure that the memory in question
+                            // is located in the process-accessible memory space.
+                            //
+                            // TODO: Enforce anti buffer-aliasing guarantees to avoid
+                            // undefined behavior in Rust.
+                            match process.build_readwrite_appslice(allow_address, allow_size) {
+                                Ok(appslice) => {
+                                    // Creating the [`ReadWriteAppSlice`] worked,
+                                    // provide it to the capsule.
+                                    match d.allow_readwrite(
+                                        process.processid(),
+                                        subdriver_number,
+                                        appslice,
+                                    ) {
+                                        Ok(returned_appslice) => {
+                                            // The capsule has accepted the allow
+                                            // operation. Pass the previous buffer
+                                            // information back to the process.
+                                            //
+                                            // TODO: Prevent swapping of AppSlices by
+                                            // the capsule
+                                            let (ptr, len) = returned_appslice.consume();
+                                            SyscallReturn::AllowReadWriteSuccess(ptr, len)
+                                        }
+                                        Err((rejected_appslice, err)) => {
+                                            let (ptr, len) = rejected_appslice.consume();
+                                            SyscallReturn::AllowReadWriteFailure(err, ptr, len)
+                                        }
                                     }
                                 }
-                            }
-                            Err(allow_error) => {
-                                // There was an error creating the [`ReadWriteAppSlice`].
-                                // Report back to the process.
-                                SyscallReturn::AllowReadWriteFailure(
-                                    allow_error,
-                                    allow_address,
-                                    allow_size,
-                                )
+                                Err(allow_error) => {
+                                    // There was an error creating the [`ReadWriteAppSlice`].
+                                    // Report back to the process.
+                                    SyscallReturn::AllowReadWriteFailure(
+                                        allow_error,
+                                        allow_address,
+                                        allow_size,
+                                    )
+                                }
                             }
                         }
+                        None => SyscallReturn::AllowReadWriteFailure(
+                            ErrorCode::NODEVICE,
+                            allow_address,
+                            allow_size,
+                        ),
                     }
-                    None => SyscallReturn::AllowReadWriteFailure(
-                        ErrorCode::NODEVICE,
-                        allow_address,
-                        allow_size,
-                    ),
                 });
 
                 if config::CONFIG.trace_syscalls {
@@ -1049,61 +1156,68 @@ impl Kernel {
                 allow_address,
                 allow_size,
             } => {
-                let res = platform.with_driver(driver_number, |driver| match driver {
-                    Some(d) => {
-                        // Try to create an appropriate [`ReadOnlyAppSlice`].
-                        // This method will ensure that the memory in question
-                        // is located in the process-accessible memory space.
-                        //
-                        // TODO: Enforce anti buffer-aliasing guarantees to avoid
-                        // undefined behavior in Rust.
-                        match process.build_readonly_appslice(allow_address, allow_size) {
-                            Ok(appslice) => {
-                                // Creating the [`ReadOnlyAppSlice`] worked,
-                                // provide it to the capsule.
-                                match d.allow_readonly(
-                                    process.processid(),
-                                    subdriver_number,
-                                    appslice,
-                                ) {
-                                    Ok(returned_appslice) => {
-                                        // The capsule has accepted the allow
-                                        // operation. Pass the previous buffer
-                                        // information back to the process.
-                                        //
-                                        // TODO: Prevent swapping of AppSlices by
-                                        // the capsule
-                                        let (ptr, len) = returned_appslice.consume();
-                                        SyscallReturn::AllowReadOnlySuccess(ptr, len)
-                                    }
-                                    Err((rejected_appslice, err)) => {
-                                        // The capsule has rejected the allow
-                                        // operation. Pass the new buffer information
-                                        // back to the process.
-                                        //
-                                        // TODO: Ensure that the capsule has passed
-                                        // the newly constructed AppSlice back
-                                        let (ptr, len) = rejected_appslice.consume();
-                                        SyscallReturn::AllowReadOnlyFailure(err, ptr, len)
+                let res = platform.with_driver(driver_number, |driver| {
+                    match driver.filter(|_| {
+                        driver_permitted(
+                            |driver, offset| process.get_command_permissions(driver, offset),
+                            driver_number,
+                        )
+                    }) {
+                        Some(d) => {
+                            // Try to create an appropriate [`ReadOnlyAppSlice`].
+                            // This method will ensure that the memory in question
+                            // is located in the process-accessible memory space.
+                            //
+                            // TODO: Enforce anti buffer-aliasing guarantees to avoid
+                            // undefined behavior in Rust.
+                            match process.build_readonly_appslice(allow_address, allow_size) {
+                                Ok(appslice) => {
+                                    // Creating the [`ReadOnlyAppSlice`] worked,
+                                    // provide it to the capsule.
+                                    match d.allow_readonly(
+                                        process.processid(),
+                                        subdriver_number,
+                                        appslice,
+                                    ) {
+                                        Ok(returned_appslice) => {
+                                            // The capsule has accepted the allow
+                                            // operation. Pass the previous buffer
+                                            // information back to the process.
+                                            //
+                                            // TODO: Prevent swapping of AppSlices by
+                                            // the capsule
+                                            let (ptr, len) = returned_appslice.consume();
+                                            SyscallReturn::AllowReadOnlySuccess(ptr, len)
+                                        }
+                                        Err((rejected_appslice, err)) => {
+                                            // The capsule has rejected the allow
+                                            // operation. Pass the new buffer information
+                                            // back to the process.
+                                            //
+                                            // TODO: Ensure that the capsule has passed
+                                            // the newly constructed AppSlice back
+                                            let (ptr, len) = rejected_appslice.consume();
+                                            SyscallReturn::AllowReadOnlyFailure(err, ptr, len)
+                                        }
                                     }
                                 }
-                            }
-                            Err(allow_error) => {
-                                // There was an error creating the [`ReadOnlyAppSlice`].
-                                // Report back to the process.
-                                SyscallReturn::AllowReadOnlyFailure(
-                                    allow_error,
-                                    allow_address,
-                                    allow_size,
-                                )
+                                Err(allow_error) => {
+                                    // There was an error creating the [`ReadOnlyAppSlice`].
+                                    // Report back to the process.
+                                    SyscallReturn::AllowReadOnlyFailure(
+                                        allow_error,
+                                        allow_address,
+                                        allow_size,
+                                    )
+                                }
                             }
                         }
+                        None => SyscallReturn::AllowReadOnlyFailure(
+                            ErrorCode::NODEVICE,
+                            allow_address,
+                            allow_size,
+                        ),
                     }
-                    None => SyscallReturn::AllowReadOnlyFailure(
-                        ErrorCode::NODEVICE,
-                        allow_address,
-                        allow_size,
-                    ),
                 });
 
                 if config::CONFIG.trace_syscalls {
@@ -1135,3 +1249,73 @@ impl Kernel {
         }
     }
 }
+
+/// Check whether a process whose TBF header grants `permissions` (see
+/// `Process::get_command_permissions()`) may use the driver `driver_number` at
+/// all. Drivers a process is not allowed to use look to the process as if they
+/// do not exist.
+fn driver_permitted<F: Fn(usize, usize) -> CommandPermissions>(
+    permissions: F,
+    driver_number: usize,
+) -> bool {
+    permissions(driver_number, 0) != CommandPermissions::NoPermsThisDriver
+}
+
+/// Check whether a process whose TBF header grants `permissions` may call
+/// command `command_number` on the driver `driver_number`.
+fn command_permitted<F: Fn(usize, usize) -> CommandPermissions>(
+    permissions: F,
+    driver_number: usize,
+    command_number: usize,
+) -> bool {
+    match permissions(driver_number, command_number / 64) {
+        CommandPermissions::NoPermsAtAll => true,
+        CommandPermissions::NoPermsThisDriver => false,
+        CommandPermissions::Mask(allowed) => (1 << (command_number % 64)) & allowed != 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_permitted, driver_permitted};
+    use tock_tbf::types::CommandPermissions;
+
+    /// Permissions of a process that may call commands 0 and 2, and command
+    /// 65, on driver 1, and no command on driver 2.
+    fn listed(driver: usize, offset: usize) -> CommandPermissions {
+        match (driver, offset) {
+            (1, 0) => CommandPermissions::Mask(0b101),
+            (1, 1) => CommandPermissions::Mask(0b10),
+            (1, _) | (2, _) => CommandPermissions::Mask(0),
+            _ => CommandPermissions::NoPermsThisDriver,
+        }
+    }
+
+    #[test]
+    fn test_no_permissions_allow_everything() {
+        let permissions = |_, _| CommandPermissions::NoPermsAtAll;
+        assert!(driver_permitted(permissions, 1));
+        assert!(command_permitted(permissions, 1, 0));
+        assert!(command_permitted(permissions, 0x40001, 200));
+    }
+
+    #[test]
+    fn test_driver_permitted() {
+        assert!(driver_permitted(listed, 1));
+        // A listed driver can be used even if it allows no commands.
+        assert!(driver_permitted(listed, 2));
+        assert!(!driver_permitted(listed, 3));
+    }
+
+    #[test]
+    fn test_command_permitted() {
+        assert!(command_permitted(listed, 1, 0));
+        assert!(!command_permitted(listed, 1, 1));
+        assert!(command_permitted(listed, 1, 2));
+        assert!(!command_permitted(listed, 1, 64));
+        assert!(command_permitted(listed, 1, 65));
+        assert!(!command_permitted(listed, 1, 130));
+        assert!(!command_permitted(listed, 2, 0));
+        assert!(!command_permitted(listed, 3, 0));
+    }
+}
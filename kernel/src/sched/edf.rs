@@ -0,0 +1,336 @@
+//! Earliest deadline first scheduler for Tock
+//!
+//! Real-time processes are configured with a period and a budget (their worst
+//! case execution time per period), both in microseconds. At the start of each
+//! period a process gets its full budget back, and its deadline is set to the
+//! end of the period. Of the real-time processes that are ready and have budget
+//! left, the one with the earliest deadline runs, with its remaining budget as
+//! its timeslice. Once a process has used its budget it does not run again
+//! until its next period starts, so a misbehaving process cannot take time away
+//! from the others.
+//!
+//! A real-time process misses its deadline if it was ready to run at some point
+//! during a period and had not finished its work (yielded with nothing left to
+//! do) by the end of the period. Deadline misses are counted per process and
+//! are available through `DeadlineInfo`.
+//!
+//! Processes without real-time parameters run in round-robin order when no
+//! real-time process can run.
+//!
+//! The total utilization (sum of budget divided by period) of the real-time
+//! processes must not exceed 1 for all deadlines to be met; the scheduler does
+//! not check this.
+//!
+//! Time is measured with an alarm. The alarm must be read at least once every
+//! time it wraps around, which the scheduler does whenever it makes a
+//! scheduling decision.
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::common::cells::OptionalCell;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
+use crate::common::list::{List, ListLink, ListNode};
+use crate::errorcode::ErrorCode;
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::introspection::DeadlineInfo;
+use crate::platform::Chip;
+use crate::process::{Process, ProcessId};
+use crate::sched::{
+    Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason, MIN_QUANTA_THRESHOLD_US,
+};
+use core::cell::Cell;
+
+/// Real-time parameters and per-period state of a process.
+#[derive(Default)]
+struct EdfProcState {
+    /// Length of a period in microseconds. Zero for processes that are not
+    /// real-time.
+    period_us: Cell<u32>,
+    /// Execution time the process gets each period.
+    budget_us: Cell<u32>,
+    /// End of the current period, in microseconds since the scheduler started.
+    deadline_us: Cell<u64>,
+    /// Execution time the process has left in the current period.
+    budget_left_us: Cell<u32>,
+    /// Whether the process had work to do in the current period that it has
+    /// not finished yet.
+    job_pending: Cell<bool>,
+    deadline_misses: Cell<usize>,
+}
+
+/// Nodes store per-process state
+pub struct EDFProcessNode<'a> {
+    proc: &'static Option<&'static dyn Process>,
+    state: EdfProcState,
+    next: ListLink<'a, EDFProcessNode<'a>>,
+}
+
+impl<'a> EDFProcessNode<'a> {
+    pub fn new(proc: &'static Option<&'static dyn Process>) -> EDFProcessNode<'a> {
+        EDFProcessNode {
+            proc,
+            state: EdfProcState::default(),
+            next: ListLink::empty(),
+        }
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.state.period_us.get() != 0
+    }
+
+    fn ready(&self) -> bool {
+        self.proc.map_or(false, |proc| proc.ready())
+    }
+
+    /// Whether this is a real-time process that can run now.
+    fn can_run_realtime(&self) -> bool {
+        self.is_realtime()
+            && self.state.budget_left_us.get() > MIN_QUANTA_THRESHOLD_US
+            && self.ready()
+    }
+}
+
+impl<'a> ListNode<'a, EDFProcessNode<'a>> for EDFProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, EDFProcessNode<'a>> {
+        &self.next
+    }
+}
+
+pub struct EDFSched<'a, A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    pub processes: List<'a, EDFProcessNode<'a>>,
+    /// Alarm value when the time was last updated.
+    last_ticks: Cell<A::Ticks>,
+    /// Ticks elapsed since the scheduler started.
+    elapsed_ticks: Cell<u64>,
+    /// The process that was last scheduled.
+    last_node: OptionalCell<&'a EDFProcessNode<'a>>,
+    /// Whether the last scheduled process ran as a real-time process.
+    last_realtime: Cell<bool>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EDFSched<'a, A> {
+    /// Timeslice of processes that are not real-time.
+    pub const DEFAULT_TIMESLICE_US: u32 = 10000;
+
+    pub fn new(alarm: &'static A) -> Self {
+        Self {
+            alarm,
+            processes: List::new(),
+            last_ticks: Cell::new(alarm.now()),
+            elapsed_ticks: Cell::new(0),
+            last_node: OptionalCell::empty(),
+            last_realtime: Cell::new(false),
+        }
+    }
+
+    /// Make the process named `process_name` a real-time process that needs
+    /// to run for `budget_us` every `period_us` microseconds. A period of zero
+    /// makes the process a background process again.
+    ///
+    /// Returns `Err(ErrorCode::INVAL)` if there is no process with that name
+    /// or the budget is longer than the period.
+    pub fn configure_process(
+        &self,
+        process_name: &str,
+        period_us: u32,
+        budget_us: u32,
+    ) -> Result<(), ErrorCode> {
+        if budget_us > period_us {
+            return Err(ErrorCode::INVAL);
+        }
+        let node = self
+            .processes
+            .iter()
+            .find(|node| {
+                node.proc
+                    .map_or(false, |proc| proc.get_process_name() == process_name)
+            })
+            .ok_or(ErrorCode::INVAL)?;
+
+        let now = self.now_us();
+        node.state.period_us.set(period_us);
+        node.state.budget_us.set(budget_us);
+        node.state.deadline_us.set(now + period_us as u64);
+        node.state.budget_left_us.set(budget_us);
+        node.state.job_pending.set(node.ready());
+        Ok(())
+    }
+
+    /// Microseconds since the scheduler started.
+    fn now_us(&self) -> u64 {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_ticks.get()).into_u32() as u64;
+        self.last_ticks.set(now);
+        self.elapsed_ticks.set(self.elapsed_ticks.get() + elapsed);
+        let ticks = self.elapsed_ticks.get();
+        let frequency = A::Frequency::frequency() as u64;
+        (ticks / frequency) * 1_000_000 + (ticks % frequency) * 1_000_000 / frequency
+    }
+
+    /// Start new periods for the real-time processes whose deadline passed,
+    /// and note which processes have work to do.
+    fn update_periods(&self, now: u64) {
+        for node in self.processes.iter().filter(|node| node.is_realtime()) {
+            let state = &node.state;
+            let deadline = state.deadline_us.get();
+            if now >= deadline {
+                if state.job_pending.get() {
+                    state.deadline_misses.set(state.deadline_misses.get() + 1);
+                }
+                // Skip any periods that passed while nothing was scheduled.
+                let period = state.period_us.get() as u64;
+                let periods = (now - deadline) / period + 1;
+                state.deadline_us.set(deadline + periods * period);
+                state.budget_left_us.set(state.budget_us.get());
+                state.job_pending.set(false);
+            }
+            if node.ready() {
+                state.job_pending.set(true);
+            }
+        }
+    }
+
+    /// The real-time process that should run now, if any.
+    fn next_realtime(&self) -> Option<&'a EDFProcessNode<'a>> {
+        self.processes
+            .iter()
+            .filter(|node| node.can_run_realtime())
+            .min_by_key(|node| node.state.deadline_us.get())
+    }
+
+    /// The start of the next period of any real-time process.
+    fn next_release(&self) -> Option<u64> {
+        self.processes
+            .iter()
+            .filter(|node| node.is_realtime())
+            .map(|node| node.state.deadline_us.get())
+            .min()
+    }
+
+    /// Move the first ready process that is not real-time to the head of the
+    /// list, and return it.
+    fn next_background(&self) -> Option<&'a EDFProcessNode<'a>> {
+        let count = self.processes.iter().count();
+        for _ in 0..count {
+            match self.processes.head() {
+                Some(node) if !node.is_realtime() && node.ready() => return Some(node),
+                Some(_) => self.processes.push_tail(self.processes.pop_head().unwrap()),
+                None => break,
+            }
+        }
+        None
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for EDFSched<'a, A> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        let now = self.now_us();
+        self.update_periods(now);
+
+        if let Some(node) = self.next_realtime() {
+            self.last_node.set(node);
+            self.last_realtime.set(true);
+            let next = node.proc.unwrap().processid(); // Only ready nodes are chosen.
+            return SchedulingDecision::RunProcess((next, Some(node.state.budget_left_us.get())));
+        }
+
+        if !kernel.processes_blocked() {
+            if let Some(node) = self.next_background() {
+                // Stop before the next period starts so that real-time
+                // processes are not delayed.
+                let timeslice = self
+                    .next_release()
+                    .map_or(Self::DEFAULT_TIMESLICE_US, |release| {
+                        let until_release = release.saturating_sub(now);
+                        if until_release < Self::DEFAULT_TIMESLICE_US as u64 {
+                            until_release as u32 + MIN_QUANTA_THRESHOLD_US
+                        } else {
+                            Self::DEFAULT_TIMESLICE_US
+                        }
+                    });
+                self.last_node.set(node);
+                self.last_realtime.set(false);
+                let next = node.proc.unwrap().processid(); // Only ready nodes are chosen.
+                return SchedulingDecision::RunProcess((next, Some(timeslice)));
+            }
+        }
+
+        // Processes that used their budget may be waiting for their next
+        // period. Make sure the chip wakes up when it starts; the alarm
+        // interrupt is enough for that, so the alarm needs no client.
+        let throttled = self
+            .processes
+            .iter()
+            .any(|node| node.is_realtime() && node.ready());
+        if throttled {
+            if let Some(release) = self.next_release() {
+                let until_release = release.saturating_sub(now);
+                let dt = (until_release * A::Frequency::frequency() as u64 / 1_000_000) as u32;
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::Ticks::from(dt.max(1)));
+            }
+        }
+        SchedulingDecision::TrySleep
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap_or(0); // Processes are never run cooperatively.
+        let node = match self.last_node.take() {
+            Some(node) => node,
+            None => return,
+        };
+
+        if self.last_realtime.get() {
+            let state = &node.state;
+            state
+                .budget_left_us
+                .set(state.budget_left_us.get().saturating_sub(execution_time_us));
+            if result == StoppedExecutingReason::NoWorkLeft && !node.ready() {
+                state.job_pending.set(false);
+            }
+        } else if result != StoppedExecutingReason::KernelPreemption {
+            // Give the other background processes a turn.
+            if let Some(head) = self.processes.head() {
+                if head as *const _ == node as *const _ {
+                    self.processes.push_tail(self.processes.pop_head().unwrap());
+                }
+            }
+        }
+    }
+
+    unsafe fn continue_process(&self, _: ProcessId, chip: &C) -> bool {
+        if chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+        {
+            return false;
+        }
+
+        // Preempt the running process if a real-time process with an earlier
+        // deadline can run.
+        self.update_periods(self.now_us());
+        let running_deadline = self.last_node.map_or(None, |node| {
+            if self.last_realtime.get() {
+                Some(node.state.deadline_us.get())
+            } else {
+                None
+            }
+        });
+        self.next_realtime().map_or(true, |next| {
+            running_deadline.map_or(false, |deadline| next.state.deadline_us.get() >= deadline)
+        })
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> DeadlineInfo for EDFSched<'a, A> {
+    fn number_app_deadline_misses(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<usize> {
+        self.processes
+            .iter()
+            .find(|node| node.proc.map_or(false, |proc| proc.processid() == app))
+            .filter(|node| node.is_realtime())
+            .map(|node| node.state.deadline_misses.get())
+    }
+}
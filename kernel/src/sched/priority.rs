@@ -5,6 +5,11 @@
 //! point in time. Kernel tasks (bottom half interrupt handling / deferred call
 //! handling) always take priority over userspace processes.
 //!
+//! A process serving a request of a higher priority process over IPC inherits
+//! the priority of its client until it notifies the client or yields, so
+//! processes of intermediate priority cannot delay the client by keeping the
+//! service from running. See `Process::priority()`.
+//!
 //! Notably, there is no need to enforce timeslices, as it is impossible for a
 //! process running to not be the highest priority process at any point while it
 //! is running. The only way for a process to longer be the highest priority is
@@ -37,13 +42,15 @@ impl<C: Chip> Scheduler<C> for PrioritySched {
             // No processes ready
             SchedulingDecision::TrySleep
         } else {
-            // Iterates in-order through the process array, always running the
-            // first process it finds that is ready to run. This enforces the
-            // priorities of all processes.
+            // Iterates in-order through the process array, running the ready
+            // process with the highest priority, and the first one among equal
+            // priorities. Without inherited priorities, this is the first
+            // process that is ready to run.
             let next = self
                 .kernel
                 .get_process_iter()
-                .find(|&proc| proc.ready())
+                .filter(|&proc| proc.ready())
+                .min_by_key(|&proc| proc.priority())
                 .map_or(None, |proc| Some(proc.processid()));
             self.running.insert(next);
 
@@ -58,14 +65,17 @@ impl<C: Chip> Scheduler<C> for PrioritySched {
         // this app is communicating via IPC with a higher priority app.
         !(chip.has_pending_interrupts()
             || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
-            || self
-                .kernel
-                .get_process_iter()
-                .position(|proc| proc.ready())
-                .map_or(false, |ready_idx| {
-                    self.running
-                        .map_or(false, |running| ready_idx < running.index)
-                }))
+            || self.running.map_or(false, |running| {
+                // The priority of the running process may have changed, if
+                // it received or answered an IPC request. Priorities are
+                // cached by the processes, so this is a single pass.
+                let running_priority =
+                    self.kernel
+                        .process_map_or(running.index, *running, |proc| proc.priority());
+                self.kernel
+                    .get_process_iter()
+                    .any(|proc| proc.ready() && proc.priority() < running_priority)
+            }))
     }
 
     fn result(&self, _: StoppedExecutingReason, _: Option<u32>) {
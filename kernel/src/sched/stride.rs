@@ -0,0 +1,139 @@
+//! Stride scheduler for Tock
+//!
+//! This scheduler gives each process a share of the CPU time in proportion to
+//! its number of tickets. It is based on "Stride Scheduling: Deterministic
+//! Proportional-Share Resource Management" by Carl A. Waldspurger and William
+//! E. Weihl.
+//!
+//! Each process has a pass value, which grows by the time the process runs
+//! divided by its tickets. The ready process with the lowest pass value runs
+//! next. Over time, every process that is always ready gets exactly its share
+//! of tickets of the CPU time, e.g. a process with 10 tickets gets 10% of the
+//! CPU time if the other processes have 90 tickets together.
+//!
+//! Time a process is not ready is not saved up: a process that becomes ready
+//! again continues from the pass value of the processes that were running, so
+//! it cannot take more than its share afterwards.
+//!
+//! Processes have `DEFAULT_TICKETS` tickets unless the board configures them
+//! with `configure_process()`.
+
+use crate::common::cells::OptionalCell;
+use crate::common::list::{List, ListLink, ListNode};
+use crate::errorcode::ErrorCode;
+use crate::platform::Chip;
+use crate::process::Process;
+use crate::sched::{Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason};
+use core::cell::Cell;
+
+/// Scales pass values so that the stride of a process is precise enough.
+const STRIDE1: u64 = 1 << 20;
+
+/// Nodes store per-process state
+pub struct StrideProcessNode<'a> {
+    proc: &'static Option<&'static dyn Process>,
+    tickets: Cell<u32>,
+    pass: Cell<u64>,
+    /// Whether the process was ready when the scheduler last looked at it.
+    was_ready: Cell<bool>,
+    next: ListLink<'a, StrideProcessNode<'a>>,
+}
+
+impl<'a> StrideProcessNode<'a> {
+    pub fn new(proc: &'static Option<&'static dyn Process>) -> StrideProcessNode<'a> {
+        StrideProcessNode {
+            proc,
+            tickets: Cell::new(StrideSched::DEFAULT_TICKETS),
+            pass: Cell::new(0),
+            was_ready: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, StrideProcessNode<'a>> for StrideProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, StrideProcessNode<'a>> {
+        &self.next
+    }
+}
+
+pub struct StrideSched<'a> {
+    pub processes: List<'a, StrideProcessNode<'a>>,
+    /// Pass value of the process that was last scheduled.
+    global_pass: Cell<u64>,
+    last_node: OptionalCell<&'a StrideProcessNode<'a>>,
+}
+
+impl<'a> StrideSched<'a> {
+    /// Number of tickets of processes that were not configured.
+    pub const DEFAULT_TICKETS: u32 = 100;
+    /// How long a process can run before being pre-empted
+    pub const TIMESLICE_US: u32 = 10000;
+
+    pub const fn new() -> StrideSched<'a> {
+        StrideSched {
+            processes: List::new(),
+            global_pass: Cell::new(0),
+            last_node: OptionalCell::empty(),
+        }
+    }
+
+    /// Give the process named `process_name` `tickets` tickets.
+    ///
+    /// Returns `Err(ErrorCode::INVAL)` if there is no process with that name
+    /// or `tickets` is zero.
+    pub fn configure_process(&self, process_name: &str, tickets: u32) -> Result<(), ErrorCode> {
+        if tickets == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.processes
+            .iter()
+            .find(|node| {
+                node.proc
+                    .map_or(false, |proc| proc.get_process_name() == process_name)
+            })
+            .map(|node| node.tickets.set(tickets))
+            .ok_or(ErrorCode::INVAL)
+    }
+}
+
+impl<'a, C: Chip> Scheduler<C> for StrideSched<'a> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        if kernel.processes_blocked() {
+            // No processes ready
+            return SchedulingDecision::TrySleep;
+        }
+
+        let mut next: Option<&'a StrideProcessNode<'a>> = None;
+        for node in self.processes.iter() {
+            let ready = node.proc.map_or(false, |proc| proc.ready());
+            if ready && !node.was_ready.get() {
+                // Do not let the process make up for the time it was not ready.
+                node.pass
+                    .set(core::cmp::max(node.pass.get(), self.global_pass.get()));
+            }
+            node.was_ready.set(ready);
+            if ready && next.map_or(true, |next| node.pass.get() < next.pass.get()) {
+                next = Some(node);
+            }
+        }
+
+        match next {
+            Some(node) => {
+                self.global_pass.set(node.pass.get());
+                self.last_node.set(node);
+                let next = node.proc.unwrap().processid(); // Only ready nodes are chosen.
+                SchedulingDecision::RunProcess((next, Some(Self::TIMESLICE_US)))
+            }
+            None => SchedulingDecision::TrySleep,
+        }
+    }
+
+    fn result(&self, _result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap_or(0) as u64; // should never fail
+        self.last_node.take().map(|node| {
+            node.pass
+                .set(node.pass.get() + execution_time_us * STRIDE1 / node.tickets.get() as u64);
+        });
+    }
+}
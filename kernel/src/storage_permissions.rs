@@ -0,0 +1,37 @@
+//! Permissions of processes to access objects in persistent storage.
+//!
+//! Capsules that store data for processes across reboots (for example in
+//! nonvolatile storage) tag each object with the storage ID of the process
+//! that created it. Storage IDs come from the TBF header of the app, so unlike
+//! `ProcessId`s they stay the same when the board reboots or the app is
+//! updated. A capsule gets the permissions of a process with
+//! `ProcessId::get_storage_permissions()` and uses them to decide whether the
+//! process may read or modify an object.
+
+use tock_tbf::types::TbfHeaderV2StoragePermissions;
+
+/// The persistent storage permissions of one process.
+#[derive(Clone, Copy, Debug)]
+pub struct StoragePermissions(TbfHeaderV2StoragePermissions);
+
+impl StoragePermissions {
+    pub(crate) fn new(permissions: TbfHeaderV2StoragePermissions) -> StoragePermissions {
+        StoragePermissions(permissions)
+    }
+
+    /// The storage ID new objects created by this process must be tagged
+    /// with. Returns `None` if the process may not create objects.
+    pub fn write_id(&self) -> Option<u32> {
+        self.0.write_id()
+    }
+
+    /// Whether the process may read an object tagged with `stored_id`.
+    pub fn check_read_permission(&self, stored_id: u32) -> bool {
+        self.write_id() == Some(stored_id) || self.0.read_ids().contains(&stored_id)
+    }
+
+    /// Whether the process may modify an object tagged with `stored_id`.
+    pub fn check_modify_permission(&self, stored_id: u32) -> bool {
+        self.write_id() == Some(stored_id) || self.0.modify_ids().contains(&stored_id)
+    }
+}
@@ -586,4 +586,52 @@ pub trait UserspaceKernelBoundary {
         state: &Self::StoredState,
         writer: &mut dyn Write,
     );
+
+    /// Write the stored state of a process to `out` so that it can later be
+    /// restored with `restore_context()`, for example after the chip was
+    /// powered off.
+    ///
+    /// Returns the number of bytes written, or `Err(ErrorCode::SIZE)` if `out`
+    /// is too small.
+    fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Replace the stored state of a process with state previously written by
+    /// `store_context()`.
+    ///
+    /// Returns `Err(ErrorCode::SIZE)` if `input` is too short to hold the
+    /// stored state.
+    fn restore_context(&self, state: &mut Self::StoredState, input: &[u8])
+        -> Result<(), ErrorCode>;
+
+    /// Write the registers describing the most recent process fault, as the
+    /// architecture recorded them when the process faulted, to `out`.
+    ///
+    /// Returns the number of bytes written, or `Err(ErrorCode::SIZE)` if `out`
+    /// is too small. Architectures that do not record any fault status write
+    /// nothing.
+    fn store_fault_status(&self, _out: &mut [u8]) -> Result<usize, ErrorCode> {
+        Ok(0)
+    }
+
+    /// Return the address whose access made the process fault, if the last
+    /// fault was a memory access that the MPU did not allow and the
+    /// architecture recorded the address.
+    ///
+    /// The kernel uses this address to load an MPU region of the process that
+    /// is not in the MPU hardware, see `mpu::MPU::swap_in_region()`. The
+    /// process can then retry the access when it is resumed. Architectures that
+    /// cannot resume a process after a fault return `None`.
+    ///
+    /// ### Safety
+    ///
+    /// This function may read process memory between `accessible_memory_start`
+    /// and `app_brk`, which must be valid.
+    unsafe fn memory_fault_address(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &Self::StoredState,
+    ) -> Option<*const u8> {
+        None
+    }
 }
@@ -126,10 +126,15 @@ pub fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<types::TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<types::TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<types::TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<types::TbfHeaderV2FixedAddresses> = None;
+                let mut syscall_permissions_pointer: Option<types::TbfHeaderV2SyscallPermissions> =
+                    None;
+                let mut storage_permissions_pointer: Option<types::TbfHeaderV2StoragePermissions> =
+                    None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -157,6 +162,31 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2Program>();
+
+                            if tlv_header.length as usize == entry_len {
+                                let program: types::TbfHeaderV2Program = remaining.try_into()?;
+
+                                // The binary must end inside of this TBF
+                                // object and after the header.
+                                let binary_end = program.binary_end_offset();
+                                if binary_end < tbf_header_base.header_size as u32
+                                    || binary_end > tbf_header_base.total_size
+                                {
+                                    return Err(types::TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+
+                                program_pointer = Some(program);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         types::TbfHeaderTypes::TbfHeaderWriteableFlashRegions => {
                             // Length must be a multiple of the size of a region definition.
                             if tlv_header.length as usize
@@ -219,6 +249,33 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderSyscallPermissions => {
+                            let permissions_buf = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+
+                            // The length must match the number of entries the
+                            // section says it has.
+                            let permissions: types::TbfHeaderV2SyscallPermissions =
+                                permissions_buf.try_into()?;
+                            if tlv_header.length as usize
+                                != 2 + permissions.len()
+                                    * mem::size_of::<types::TbfHeaderDriverPermission>()
+                            {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                            syscall_permissions_pointer = Some(permissions);
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderStoragePermissions => {
+                            let permissions_buf = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            storage_permissions_pointer = Some(permissions_buf.try_into()?);
+                        }
+
                         _ => {}
                     }
 
@@ -233,9 +290,12 @@ pub fn parse_tbf_header(
                 let tbf_header = types::TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    syscall_permissions: syscall_permissions_pointer,
+                    storage_permissions: storage_permissions_pointer,
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -244,3 +304,300 @@ pub fn parse_tbf_header(
         _ => Err(types::TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+/// Iterate over all of the credentials in the footers of a TBF object.
+///
+/// `footers` is the region of the TBF object after the end of the application
+/// binary, as found with `TbfHeader::get_binary_end()`. A TBF object may have
+/// any number of footers, and they are returned in the order they are stored.
+/// Footer TLV entries of types other than credentials are skipped.
+///
+/// The iterator returns an error and then stops if a footer is malformed. It
+/// also stops at the first footer whose length is zero, so that erased flash
+/// at the end of the footers is not parsed as footers.
+pub fn parse_tbf_footers<'a>(footers: &'a [u8]) -> TbfFooterIter<'a> {
+    TbfFooterIter { remaining: footers }
+}
+
+/// Iterator over the credentials stored in the footers of a TBF object. See
+/// `parse_tbf_footers()`.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterIter<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> TbfFooterIter<'a> {
+    fn next_footer(
+        &mut self,
+    ) -> Result<Option<types::TbfFooterV2Credentials<'a>>, types::TbfParseError> {
+        let tlv_header: types::TbfHeaderTlv = self
+            .remaining
+            .get(0..4)
+            .ok_or(types::TbfParseError::NotEnoughFlash)?
+            .try_into()?;
+        if tlv_header.length == 0 {
+            return Err(types::TbfParseError::NotEnoughFlash);
+        }
+
+        let total_length = 4 + align4!(tlv_header.length as usize);
+        let footer = self
+            .remaining
+            .get(4..4 + tlv_header.length as usize)
+            .ok_or(types::TbfParseError::NotEnoughFlash)?;
+        self.remaining = self.remaining.get(total_length..).unwrap_or(&[]);
+
+        match tlv_header.tipe {
+            types::TbfHeaderTypes::TbfFooterCredentials => Ok(Some(footer.try_into()?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for TbfFooterIter<'a> {
+    type Item = Result<types::TbfFooterV2Credentials<'a>, types::TbfParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining.len() < 4 {
+                return None;
+            }
+
+            match self.next_footer() {
+                Ok(Some(credentials)) => return Some(Ok(credentials)),
+                Ok(None) => continue,
+                Err(types::TbfParseError::NotEnoughFlash) => {
+                    // Running out of footers (or hitting erased flash) is the
+                    // normal way to reach the end.
+                    self.remaining = &[];
+                    return None;
+                }
+                Err(e) => {
+                    self.remaining = &[];
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CommandPermissions, TbfFooterV2CredentialsType, TbfParseError};
+
+    extern crate std;
+    use std::vec::Vec;
+
+    /// Build an app header with a main TLV followed by `tlvs`, and leak it so
+    /// it can be parsed like a header in flash.
+    fn app_header(tlvs: &[u8]) -> &'static [u8] {
+        let mut header = Vec::new();
+        let header_size = 16 + 16 + tlvs.len();
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&(header_size as u16).to_le_bytes());
+        header.extend_from_slice(&4096u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&12u16.to_le_bytes());
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(tlvs);
+
+        let checksum = header
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .fold(0, |checksum, word| checksum ^ word);
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        std::boxed::Box::leak(header.into_boxed_slice())
+    }
+
+    /// Build a syscall permissions TLV that says it has `count` entries, with
+    /// the given entries.
+    fn syscall_permissions(count: u16, perms: &[(u32, u32, u64)]) -> Vec<u8> {
+        let mut tlv = Vec::new();
+        tlv.extend_from_slice(&0x8001u16.to_le_bytes());
+        tlv.extend_from_slice(&((2 + 16 * perms.len()) as u16).to_le_bytes());
+        tlv.extend_from_slice(&count.to_le_bytes());
+        for (driver_number, offset, allowed_commands) in perms {
+            tlv.extend_from_slice(&driver_number.to_le_bytes());
+            tlv.extend_from_slice(&offset.to_le_bytes());
+            tlv.extend_from_slice(&allowed_commands.to_le_bytes());
+        }
+        // Pad the TLV to a multiple of four bytes.
+        tlv.resize(align4!(tlv.len()), 0);
+        tlv
+    }
+
+    /// Append a credentials footer with the given format and data.
+    fn push_credentials(footers: &mut [u8], offset: usize, format: u32, data: &[u8]) -> usize {
+        let length = 4 + data.len();
+        footers[offset..offset + 2].copy_from_slice(&128u16.to_le_bytes());
+        footers[offset + 2..offset + 4].copy_from_slice(&(length as u16).to_le_bytes());
+        footers[offset + 4..offset + 8].copy_from_slice(&format.to_le_bytes());
+        footers[offset + 8..offset + 8 + data.len()].copy_from_slice(data);
+        offset + 4 + align4!(length)
+    }
+
+    #[test]
+    fn padding_header_parses_as_padding() {
+        let header: &'static [u8; 16] =
+            std::boxed::Box::leak(std::boxed::Box::new(types::create_padding_header(4096)));
+
+        let (version, header_length, total_length) =
+            parse_tbf_header_lengths(header[0..8].try_into().unwrap())
+                .ok()
+                .unwrap();
+        assert_eq!((version, header_length, total_length), (2, 16, 4096));
+
+        let parsed = parse_tbf_header(&header[..], version).ok().unwrap();
+        assert!(!parsed.is_app());
+        assert_eq!(parsed.get_binary_end(), 4096);
+    }
+
+    #[test]
+    fn no_syscall_permissions_allow_everything() {
+        let parsed = parse_tbf_header(app_header(&[]), 2).ok().unwrap();
+        assert_eq!(
+            parsed.get_command_permissions(0x1, 0),
+            CommandPermissions::NoPermsAtAll
+        );
+    }
+
+    #[test]
+    fn syscall_permissions_parse() {
+        let tlv = syscall_permissions(3, &[(0x1, 0, 0b101), (0x1, 1, 0b1), (0x2, 0, 0)]);
+        let parsed = parse_tbf_header(app_header(&tlv), 2).ok().unwrap();
+
+        assert_eq!(
+            parsed.get_command_permissions(0x1, 0),
+            CommandPermissions::Mask(0b101)
+        );
+        assert_eq!(
+            parsed.get_command_permissions(0x1, 1),
+            CommandPermissions::Mask(0b1)
+        );
+        // Listed drivers allow no commands in blocks that are not listed.
+        assert_eq!(
+            parsed.get_command_permissions(0x1, 2),
+            CommandPermissions::Mask(0)
+        );
+        assert_eq!(
+            parsed.get_command_permissions(0x2, 0),
+            CommandPermissions::Mask(0)
+        );
+        assert_eq!(
+            parsed.get_command_permissions(0x3, 0),
+            CommandPermissions::NoPermsThisDriver
+        );
+    }
+
+    #[test]
+    fn kernel_version_tlv_is_not_read_as_permissions() {
+        // Upstream Tock uses TLV 8 for the kernel version an app needs.
+        let mut tlv = Vec::new();
+        tlv.extend_from_slice(&8u16.to_le_bytes());
+        tlv.extend_from_slice(&4u16.to_le_bytes());
+        tlv.extend_from_slice(&2u16.to_le_bytes());
+        tlv.extend_from_slice(&0u16.to_le_bytes());
+        let parsed = parse_tbf_header(app_header(&tlv), 2).ok().unwrap();
+        assert_eq!(
+            parsed.get_command_permissions(0x2, 0),
+            CommandPermissions::NoPermsAtAll
+        );
+    }
+
+    #[test]
+    fn syscall_permissions_length_mismatch() {
+        // The TLV holds two entries, but says it has one.
+        let tlv = syscall_permissions(1, &[(0x1, 0, 1), (0x2, 0, 1)]);
+        assert!(matches!(
+            parse_tbf_header(app_header(&tlv), 2),
+            Err(TbfParseError::BadTlvEntry(0x8001))
+        ));
+    }
+
+    #[test]
+    fn syscall_permissions_too_many_entries() {
+        let perms: Vec<(u32, u32, u64)> = (0..9).map(|driver| (driver, 0, 1)).collect();
+        let tlv = syscall_permissions(9, &perms);
+        assert!(matches!(
+            parse_tbf_header(app_header(&tlv), 2),
+            Err(TbfParseError::BadTlvEntry(0x8001))
+        ));
+    }
+
+    #[test]
+    fn permissions_tlv_is_not_enforced() {
+        // TLV 6 lists permissions without a count, and the kernel does not
+        // enforce it.
+        let mut tlv = Vec::new();
+        tlv.extend_from_slice(&6u16.to_le_bytes());
+        tlv.extend_from_slice(&16u16.to_le_bytes());
+        tlv.extend_from_slice(&0x1u32.to_le_bytes());
+        tlv.extend_from_slice(&0u32.to_le_bytes());
+        tlv.extend_from_slice(&1u64.to_le_bytes());
+        let parsed = parse_tbf_header(app_header(&tlv), 2).ok().unwrap();
+        assert_eq!(
+            parsed.get_command_permissions(0x2, 0),
+            CommandPermissions::NoPermsAtAll
+        );
+    }
+
+    #[test]
+    fn footers_iterate_in_order() {
+        let mut footers = [0xffu8; 256];
+        let mut offset = push_credentials(&mut footers, 0, 3, &[0x11; 32]);
+        offset = push_credentials(&mut footers, offset, 0, &[0; 10]);
+        offset = push_credentials(&mut footers, offset, 7, &[0x22; 64]);
+        push_credentials(&mut footers, offset, 0x1000, &[0x33; 5]);
+
+        let mut iter = parse_tbf_footers(&footers);
+
+        let sha = iter.next().unwrap().unwrap();
+        assert_eq!(sha.format(), TbfFooterV2CredentialsType::SHA256);
+        assert_eq!(sha.hash(), Some(&[0x11; 32][..]));
+
+        let reserved = iter.next().unwrap().unwrap();
+        assert_eq!(reserved.format(), TbfFooterV2CredentialsType::Reserved);
+        assert_eq!(reserved.data().len(), 10);
+
+        let ecdsa = iter.next().unwrap().unwrap();
+        assert_eq!(ecdsa.format(), TbfFooterV2CredentialsType::EcdsaNistP256);
+        assert_eq!(ecdsa.signature(), Some(&[0x22; 64][..]));
+
+        let other = iter.next().unwrap().unwrap();
+        assert_eq!(other.format(), TbfFooterV2CredentialsType::Other(0x1000));
+        assert_eq!(other.data(), &[0x33; 5]);
+
+        // The rest is erased flash.
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn rsa_key_and_signature_split() {
+        let mut data = [0u8; 768];
+        data[..384].copy_from_slice(&[1; 384]);
+        data[384..].copy_from_slice(&[2; 384]);
+        let mut footers = [0u8; 776];
+        push_credentials(&mut footers, 0, 1, &data);
+
+        let rsa = parse_tbf_footers(&footers).next().unwrap().unwrap();
+        assert_eq!(rsa.format(), TbfFooterV2CredentialsType::Rsa3072Key);
+        assert_eq!(rsa.public_key(), Some(&[1; 384][..]));
+        assert_eq!(rsa.signature(), Some(&[2; 384][..]));
+    }
+
+    #[test]
+    fn bad_length_stops_iteration() {
+        let mut footers = [0u8; 64];
+        let offset = push_credentials(&mut footers, 0, 5, &[0; 16]);
+        push_credentials(&mut footers, offset, 3, &[0; 32]);
+
+        let mut iter = parse_tbf_footers(&footers);
+        assert!(matches!(
+            iter.next(),
+            Some(Err(TbfParseError::BadTlvEntry(128)))
+        ));
+        assert!(iter.next().is_none());
+    }
+}
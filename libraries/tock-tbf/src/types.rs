@@ -2,6 +2,7 @@
 
 use core::convert::TryInto;
 use core::fmt;
+use core::mem;
 
 /// Error when parsing just the beginning of the TBF header. This is only used
 /// when establishing the linked list structure of apps installed in flash.
@@ -105,6 +106,17 @@ pub enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderStoragePermissions = 7,
+    TbfHeaderProgram = 9,
+
+    /// The system calls the kernel allows an app to make. The type is in the
+    /// out-of-tree range (bit 15 set), so that it does not collide with the
+    /// TLV types of upstream Tock, where `8` is `KernelVersion`.
+    TbfHeaderSyscallPermissions = 0x8001,
+
+    /// Credentials (e.g. a hash or signature) stored in the footer region
+    /// after the application binary.
+    TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -130,6 +142,22 @@ pub struct TbfHeaderV2Main {
     minimum_ram_size: u32,
 }
 
+/// The v2 program section for apps.
+///
+/// This is a superset of the main section. In addition to the main section
+/// fields it specifies where the application binary ends. Everything in the
+/// TBF object after `binary_end_offset` is footer space, which is used for
+/// storing credentials over the binary (e.g. hashes and signatures). If an app
+/// has both a main and a program section the program section is used.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+    binary_end_offset: u32,
+    version: u32,
+}
+
 /// Writeable flash regions only need an offset and size.
 ///
 /// There can be multiple (or zero) flash regions defined, so this is its own
@@ -164,6 +192,208 @@ pub struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// The maximum number of driver permission entries in a TBF header.
+pub const NUM_DRIVER_PERMISSIONS: usize = 8;
+
+/// The commands one process may call on one driver.
+///
+/// Commands are grouped in blocks of 64. `offset` selects the block, and bit
+/// `n` of `allowed_commands` allows command number `offset * 64 + n`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TbfHeaderDriverPermission {
+    driver_number: u32,
+    offset: u32,
+    allowed_commands: u64,
+}
+
+/// The system calls a process is allowed to make.
+///
+/// If an app has a syscall permissions section, it may only use the drivers
+/// listed in it, and may only call the listed commands on those drivers. Apps without
+/// this section may use every driver. To enable a static buffer, an app can
+/// list at most `NUM_DRIVER_PERMISSIONS` entries.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2SyscallPermissions {
+    length: u16,
+    perms: [TbfHeaderDriverPermission; NUM_DRIVER_PERMISSIONS],
+}
+
+/// The commands of a driver that a process is allowed to call, as given by its
+/// TBF header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandPermissions {
+    /// The process does not have a syscall permissions section, so it may
+    /// call any command on any driver.
+    NoPermsAtAll,
+    /// The process has a syscall permissions section that does not include this
+    /// driver. The process may not use this driver at all.
+    NoPermsThisDriver,
+    /// The process may call command `offset * 64 + n` if bit `n` is set.
+    Mask(u64),
+}
+
+/// The maximum number of read or modify IDs in the storage permissions of a
+/// TBF header.
+pub const NUM_STORAGE_PERMISSIONS: usize = 8;
+
+/// Which persistent storage objects an app may access.
+///
+/// Objects in persistent storage are tagged with the storage ID of the app
+/// that wrote them. Unlike the process identifier, the storage ID is assigned
+/// when the app is built, so it stays the same across reboots and app updates.
+/// An app may create objects tagged with its `write_id`, read objects tagged
+/// with its `write_id` or any of `read_ids`, and modify objects tagged with its
+/// `write_id` or any of `modify_ids`.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2StoragePermissions {
+    write_id: Option<core::num::NonZeroU32>,
+    read_length: u16,
+    read_ids: [u32; NUM_STORAGE_PERMISSIONS],
+    modify_length: u16,
+    modify_ids: [u32; NUM_STORAGE_PERMISSIONS],
+}
+
+impl TbfHeaderV2StoragePermissions {
+    /// The storage ID objects written by this app are tagged with, or `None`
+    /// if the app may not create objects.
+    pub fn write_id(&self) -> Option<u32> {
+        self.write_id.map(|id| id.get())
+    }
+
+    /// The storage IDs of other apps whose objects this app may read.
+    pub fn read_ids(&self) -> &[u32] {
+        &self.read_ids[..self.read_length as usize]
+    }
+
+    /// The storage IDs of other apps whose objects this app may modify.
+    pub fn modify_ids(&self) -> &[u32] {
+        &self.modify_ids[..self.modify_length as usize]
+    }
+}
+
+/// Formats of credentials that can be stored in a TBF footer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    /// Reserved space that does not hold a credential. This is used to leave
+    /// room in the footer so credentials can be added later without moving
+    /// the app.
+    Reserved,
+    /// An RSA-3072 public key followed by a PKCS#1 v1.5 signature of the
+    /// SHA-512 hash of the binary made with that key.
+    Rsa3072Key,
+    /// An RSA-4096 public key followed by a PKCS#1 v1.5 signature of the
+    /// SHA-512 hash of the binary made with that key.
+    Rsa4096Key,
+    /// A SHA-256 hash of the application binary.
+    SHA256,
+    /// A SHA-384 hash of the application binary.
+    SHA384,
+    /// A SHA-512 hash of the application binary.
+    SHA512,
+    /// An HMAC-SHA256 of the application binary keyed with a key the board
+    /// holds.
+    HmacSha256,
+    /// An ECDSA signature (`r` followed by `s`) over the SHA-256 hash of the
+    /// binary using the NIST P-256 curve.
+    EcdsaNistP256,
+    /// A credential in a format this library does not know. The value is the
+    /// format identifier stored in the footer. The credential data is
+    /// available as an opaque blob, so that users of this library can support
+    /// their own formats.
+    Other(u32),
+}
+
+impl TbfFooterV2CredentialsType {
+    /// The identifier of this format as stored in a TBF footer.
+    pub fn id(&self) -> u32 {
+        match *self {
+            TbfFooterV2CredentialsType::Reserved => 0,
+            TbfFooterV2CredentialsType::Rsa3072Key => 1,
+            TbfFooterV2CredentialsType::Rsa4096Key => 2,
+            TbfFooterV2CredentialsType::SHA256 => 3,
+            TbfFooterV2CredentialsType::SHA384 => 4,
+            TbfFooterV2CredentialsType::SHA512 => 5,
+            TbfFooterV2CredentialsType::HmacSha256 => 6,
+            TbfFooterV2CredentialsType::EcdsaNistP256 => 7,
+            TbfFooterV2CredentialsType::Other(id) => id,
+        }
+    }
+
+    /// The length in bytes of credentials of this format, or `None` if the
+    /// length can vary.
+    pub fn length(&self) -> Option<usize> {
+        match *self {
+            TbfFooterV2CredentialsType::Reserved => None,
+            TbfFooterV2CredentialsType::Rsa3072Key => Some(384 * 2),
+            TbfFooterV2CredentialsType::Rsa4096Key => Some(512 * 2),
+            TbfFooterV2CredentialsType::SHA256 => Some(32),
+            TbfFooterV2CredentialsType::SHA384 => Some(48),
+            TbfFooterV2CredentialsType::SHA512 => Some(64),
+            TbfFooterV2CredentialsType::HmacSha256 => Some(32),
+            TbfFooterV2CredentialsType::EcdsaNistP256 => Some(64),
+            TbfFooterV2CredentialsType::Other(_) => None,
+        }
+    }
+}
+
+/// A credential stored in the footer of a TBF object.
+///
+/// Credentials cover the TBF object from the beginning of the header up to
+/// the end of the application binary (`binary_end_offset` in the program
+/// header). The `data` slice points directly into the TBF object.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials<'a> {
+    pub(crate) format: TbfFooterV2CredentialsType,
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> TbfFooterV2Credentials<'a> {
+    /// Get the format of this credential.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// Get the raw credential data.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Get the public key stored in this credential, for formats that
+    /// include one.
+    pub fn public_key(&self) -> Option<&'a [u8]> {
+        match self.format {
+            TbfFooterV2CredentialsType::Rsa3072Key | TbfFooterV2CredentialsType::Rsa4096Key => {
+                self.data.get(..self.data.len() / 2)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the signature stored in this credential, for formats that are
+    /// signatures.
+    pub fn signature(&self) -> Option<&'a [u8]> {
+        match self.format {
+            TbfFooterV2CredentialsType::Rsa3072Key | TbfFooterV2CredentialsType::Rsa4096Key => {
+                self.data.get(self.data.len() / 2..)
+            }
+            TbfFooterV2CredentialsType::EcdsaNistP256 => Some(self.data),
+            _ => None,
+        }
+    }
+
+    /// Get the hash stored in this credential, for formats that are hashes
+    /// (or keyed hashes) of the binary.
+    pub fn hash(&self) -> Option<&'a [u8]> {
+        match self.format {
+            TbfFooterV2CredentialsType::SHA256
+            | TbfFooterV2CredentialsType::SHA384
+            | TbfFooterV2CredentialsType::SHA512
+            | TbfFooterV2CredentialsType::HmacSha256 => Some(self.data),
+            _ => None,
+        }
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -200,6 +430,28 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
     }
 }
 
+/// Create the header of a padding entry that is `total_size` bytes long.
+///
+/// Padding entries keep the linked list of TBF objects in flash intact over
+/// space that does not hold an app, for example the space used to align an app
+/// or the space of an app that was removed. `total_size` must be at least 16
+/// bytes, the size of the header itself.
+pub fn create_padding_header(total_size: u32) -> [u8; 16] {
+    let version: u16 = 2;
+    let header_size: u16 = 16;
+    let flags: u32 = 0;
+
+    let first_word = u32::from(version) | (u32::from(header_size) << 16);
+    let checksum = first_word ^ total_size ^ flags;
+
+    let mut header = [0; 16];
+    header[0..4].copy_from_slice(&first_word.to_le_bytes());
+    header[4..8].copy_from_slice(&total_size.to_le_bytes());
+    header[8..12].copy_from_slice(&flags.to_le_bytes());
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+    header
+}
+
 impl core::convert::TryFrom<u16> for TbfHeaderTypes {
     type Error = TbfParseError;
 
@@ -209,6 +461,10 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            7 => Ok(TbfHeaderTypes::TbfHeaderStoragePermissions),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
+            0x8001 => Ok(TbfHeaderTypes::TbfHeaderSyscallPermissions),
+            128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -258,6 +514,47 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Main {
     }
 }
 
+impl TbfHeaderV2Program {
+    /// Offset from the start of the TBF object to the end of the binary.
+    pub(crate) fn binary_end_offset(&self) -> u32 {
+        self.binary_end_offset
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     type Error = TbfParseError;
 
@@ -296,6 +593,176 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderDriverPermission {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderDriverPermission, Self::Error> {
+        Ok(TbfHeaderDriverPermission {
+            driver_number: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            offset: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            allowed_commands: u64::from_le_bytes(
+                b.get(8..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl TbfHeaderV2SyscallPermissions {
+    /// The number of driver permission entries.
+    pub(crate) fn len(&self) -> usize {
+        self.length as usize
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2SyscallPermissions {
+    type Error = TbfParseError;
+
+    /// The section is the number of entries as a `u16`, followed by 16 bytes
+    /// for each `TbfHeaderDriverPermission`.
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2SyscallPermissions, Self::Error> {
+        let number_perms = u16::from_le_bytes(
+            b.get(0..2)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        );
+        if number_perms as usize > NUM_DRIVER_PERMISSIONS {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderSyscallPermissions as usize,
+            ));
+        }
+
+        let perm_len = mem::size_of::<TbfHeaderDriverPermission>();
+        let mut perms: [TbfHeaderDriverPermission; NUM_DRIVER_PERMISSIONS] = Default::default();
+        for i in 0..number_perms as usize {
+            let start = 2 + i * perm_len;
+            perms[i] = b
+                .get(start..start + perm_len)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?;
+        }
+
+        Ok(TbfHeaderV2SyscallPermissions {
+            length: number_perms,
+            perms,
+        })
+    }
+}
+
+/// Parse a list of storage IDs that starts with its length as a `u16`. Returns
+/// the number of IDs, the IDs, and the number of bytes the list used.
+fn parse_storage_ids(
+    b: &[u8],
+) -> Result<(u16, [u32; NUM_STORAGE_PERMISSIONS], usize), TbfParseError> {
+    let length = u16::from_le_bytes(
+        b.get(0..2)
+            .ok_or(TbfParseError::NotEnoughFlash)?
+            .try_into()?,
+    );
+    if length as usize > NUM_STORAGE_PERMISSIONS {
+        return Err(TbfParseError::BadTlvEntry(
+            TbfHeaderTypes::TbfHeaderStoragePermissions as usize,
+        ));
+    }
+
+    let mut ids = [0; NUM_STORAGE_PERMISSIONS];
+    for i in 0..length as usize {
+        let start = 2 + i * 4;
+        ids[i] = u32::from_le_bytes(
+            b.get(start..start + 4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        );
+    }
+
+    Ok((length, ids, 2 + length as usize * 4))
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2StoragePermissions {
+    type Error = TbfParseError;
+
+    /// The section is the `write_id` as a `u32`, then the list of read IDs and
+    /// then the list of modify IDs. Each list is its number of IDs as a `u16`
+    /// followed by that many `u32` IDs.
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2StoragePermissions, Self::Error> {
+        let write_id = core::num::NonZeroU32::new(u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        ));
+
+        let (read_length, read_ids, read_size) =
+            parse_storage_ids(b.get(4..).ok_or(TbfParseError::NotEnoughFlash)?)?;
+        let (modify_length, modify_ids, modify_size) = parse_storage_ids(
+            b.get(4 + read_size..)
+                .ok_or(TbfParseError::NotEnoughFlash)?,
+        )?;
+
+        // The section must not have anything after the modify IDs.
+        if b.len() != 4 + read_size + modify_size {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderStoragePermissions as usize,
+            ));
+        }
+
+        Ok(TbfHeaderV2StoragePermissions {
+            write_id,
+            read_length,
+            read_ids,
+            modify_length,
+            modify_ids,
+        })
+    }
+}
+
+impl From<u32> for TbfFooterV2CredentialsType {
+    fn from(h: u32) -> TbfFooterV2CredentialsType {
+        match h {
+            0 => TbfFooterV2CredentialsType::Reserved,
+            1 => TbfFooterV2CredentialsType::Rsa3072Key,
+            2 => TbfFooterV2CredentialsType::Rsa4096Key,
+            3 => TbfFooterV2CredentialsType::SHA256,
+            4 => TbfFooterV2CredentialsType::SHA384,
+            5 => TbfFooterV2CredentialsType::SHA512,
+            6 => TbfFooterV2CredentialsType::HmacSha256,
+            7 => TbfFooterV2CredentialsType::EcdsaNistP256,
+            id => TbfFooterV2CredentialsType::Other(id),
+        }
+    }
+}
+
+impl<'a> core::convert::TryFrom<&'a [u8]> for TbfFooterV2Credentials<'a> {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'a [u8]) -> Result<TbfFooterV2Credentials<'a>, Self::Error> {
+        let format: TbfFooterV2CredentialsType = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        )
+        .into();
+
+        let data = b.get(4..).ok_or(TbfParseError::NotEnoughFlash)?;
+
+        // Credentials with a known format must be exactly the right length.
+        match format.length() {
+            Some(length) if length != data.len() => Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfFooterCredentials as usize,
+            )),
+            _ => Ok(TbfFooterV2Credentials { format, data }),
+        }
+    }
+}
+
 /// Single header that can contain all parts of a v2 header.
 ///
 /// Note, this struct limits the number of writeable regions an app can have to
@@ -305,9 +772,12 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
 pub struct TbfHeaderV2 {
     pub(crate) base: TbfHeaderV2Base,
     pub(crate) main: Option<TbfHeaderV2Main>,
+    pub(crate) program: Option<TbfHeaderV2Program>,
     pub(crate) package_name: Option<&'static str>,
     pub(crate) writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     pub(crate) fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    pub(crate) syscall_permissions: Option<TbfHeaderV2SyscallPermissions>,
+    pub(crate) storage_permissions: Option<TbfHeaderV2StoragePermissions>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -348,7 +818,10 @@ impl TbfHeader {
     /// needed for this app.
     pub fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => match hd.program {
+                Some(p) => p.minimum_ram_size,
+                None => hd.main.map_or(0, |m| m.minimum_ram_size),
+            },
             _ => 0,
         }
     }
@@ -358,7 +831,11 @@ impl TbfHeader {
     pub fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                let protected_size = match hd.program {
+                    Some(p) => p.protected_size,
+                    None => hd.main.map_or(0, |m| m.protected_size),
+                };
+                protected_size + (hd.base.header_size as u32)
             }
             _ => 0,
         }
@@ -369,12 +846,38 @@ impl TbfHeader {
     pub fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                let init_fn_offset = match hd.program {
+                    Some(p) => p.init_fn_offset,
+                    None => hd.main.map_or(0, |m| m.init_fn_offset),
+                };
+                init_fn_offset + (hd.base.header_size as u32)
             }
             _ => 0,
         }
     }
 
+    /// Get the offset from the beginning of the TBF object to the end of the
+    /// application binary. Everything after this offset is footer space. If
+    /// the app does not have a program header there are no footers and this is
+    /// the total size of the TBF object.
+    pub fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
+    /// Get the version of the application binary from the program header. Apps
+    /// without a program header are version 0.
+    pub fn get_binary_version(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.program.map_or(0, |p| p.version),
+            _ => 0,
+        }
+    }
+
     /// Get the name of the app.
     pub fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -434,4 +937,42 @@ impl TbfHeader {
             start => Some(start),
         }
     }
+
+    /// Get which commands in the block of 64 commands starting at `offset * 64`
+    /// this app may call on driver `driver_num`.
+    pub fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        let permissions = match self {
+            TbfHeader::TbfHeaderV2(TbfHeaderV2 {
+                syscall_permissions: Some(permissions),
+                ..
+            }) => permissions,
+            _ => return CommandPermissions::NoPermsAtAll,
+        };
+
+        let mut found_driver = false;
+        for perm in permissions.perms[..permissions.length as usize].iter() {
+            if perm.driver_number as usize == driver_num {
+                found_driver = true;
+                if perm.offset as usize == offset {
+                    return CommandPermissions::Mask(perm.allowed_commands);
+                }
+            }
+        }
+
+        if found_driver {
+            // The driver is allowed, but none of the commands in this block.
+            CommandPermissions::Mask(0)
+        } else {
+            CommandPermissions::NoPermsThisDriver
+        }
+    }
+
+    /// Get the persistent storage permissions of the app, or `None` if the app
+    /// does not have a storage permissions section.
+    pub fn get_storage_permissions(&self) -> Option<TbfHeaderV2StoragePermissions> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.storage_permissions,
+            _ => None,
+        }
+    }
 }